@@ -1,6 +1,25 @@
+//! Finds DPDK, generates the bindings and links the libraries
+//!
+//! DPDK is looked up with `pkg-config libdpdk`, so `PKG_CONFIG_PATH` selects the install.
+//! If that fails, or `LIBDPDK_NO_PKG_CONFIG` is set, the static library lists below are
+//! linked from the default `/usr/local` install instead. Either way the build stops on a
+//! release outside `DPDK_MIN_VERSION..DPDK_MAX_VERSION`.
+//!
+//! Poll-mode drivers are linked per `pmd-*` cargo feature, see `PMD_FEATURES`. The
+//! `pmd-all` feature links the whole `RTE_PMD_LIBS` list instead.
+//!
+//! The DPDK release is turned into `dpdk_ge_<year>_<month>` cfg flags, one per known release
+//! up to the installed one, e.g. `#[cfg(dpdk_ge_20_11)]`. They apply to this crate and are
+//! handed to dependent build scripts as `DEP_DPDK_CFGS`. The known releases are in
+//! `releases.rs`, which those build scripts include to declare the flags to rustc.
+
 use std::env;
+use std::fs;
 use std::path::{Path, PathBuf};
 
+include!("releases.rs");
+
+// fallback when pkg-config is not available
 const RTE_CORE_LIBS: &[&str] = &[
 	"rte_acl",
 	"rte_bbdev",
@@ -75,6 +94,7 @@ const RTE_CORE_LIBS: &[&str] = &[
 	"rte_vhost",
 ];
 
+// linked as a whole with the `pmd-all` feature
 #[cfg(not(feature = "rustdoc"))]
 static RTE_PMD_LIBS: &[&str] = &[
 	"rte_pmd_af_packet",
@@ -147,54 +167,249 @@ static RTE_PMD_LIBS: &[&str] = &[
 #[cfg(not(feature = "rustdoc"))]
 const RTE_DEPS_LIBS: &[&str] = &["numa", "pcap"];
 
+/// The drivers each `pmd-*` feature links, by the feature's `CARGO_FEATURE_*` suffix.
+/// Driver `x` is `rte_net_x` from 20.11 on and `rte_pmd_x` before.
 #[cfg(not(feature = "rustdoc"))]
-fn bind(path: &Path) {
-	cc::Build::new()
-		.file("src/shim.c")
-		.flag("-march=corei7")
-		.flag("-mavx")
-		.compile("rte_shim");
+const PMD_FEATURES: &[(&str, &[&str])] = &[
+	("PMD_NULL", &["null"]),
+	("PMD_PCAP", &["pcap"]),
+	("PMD_TAP", &["tap"]),
+	("PMD_RING", &["ring"]),
+	("PMD_AF_PACKET", &["af_packet"]),
+	("PMD_VHOST", &["vhost"]),
+	("PMD_MEMIF", &["memif"]),
+	("PMD_INTEL", &["e1000", "ixgbe", "i40e", "iavf", "ice"]),
+];
+
+/// Oldest DPDK release the bindings are written against
+const DPDK_MIN_VERSION: &str = "19.11";
+
+/// First release the bindings don't build against: 21.11 renamed ethdev's offload and RSS
+/// flags and 22.11 `rxmode.max_rx_pkt_len`, 23.11 removed KNI
+const DPDK_MAX_VERSION: &str = "21.11";
+
+/// Release assumed when the installed one can't be determined
+const DPDK_DEFAULT_VERSION: (u32, u32) = (20, 11);
+
+/// Where the fallback looks for the DPDK headers
+const FALLBACK_INCLUDE_DIRS: &[&str] = &[
+	"/usr/local/include",
+	"/usr/local/include/dpdk",
+	"/usr/include/dpdk",
+	"/usr/include",
+];
+
+/// The DPDK install found by the build
+struct Dpdk {
+	include_paths: Vec<PathBuf>,
+	version: Option<(u32, u32)>,
+}
+
+/// Parse "20.11.1" into (20, 11)
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+	let mut parts = version.trim().split('.');
+	let year = parts.next()?.parse().ok()?;
+	let month = parts.next()?.parse().ok()?;
+	Some((year, month))
+}
+
+/// Read RTE_VER_YEAR and RTE_VER_MONTH from rte_version.h
+fn version_from_header(header: &Path) -> Option<(u32, u32)> {
+	let text = fs::read_to_string(header).ok()?;
+	let define = |name: &str| {
+		text.lines().find_map(|line| {
+			let mut words = line.split_whitespace();
+			match (words.next(), words.next(), words.next()) {
+				(Some("#define"), Some(n), Some(v)) if n == name => v.parse::<u32>().ok(),
+				_ => None,
+			}
+		})
+	};
+	Some((define("RTE_VER_YEAR")?, define("RTE_VER_MONTH")?))
+}
+
+/// Find DPDK through pkg-config, which also emits the link flags
+#[cfg(not(feature = "rustdoc"))]
+fn probe_pkg_config() -> Option<Dpdk> {
+	match pkg_config::Config::new()
+		.range_version(DPDK_MIN_VERSION..DPDK_MAX_VERSION)
+		.probe("libdpdk")
+	{
+		Ok(lib) => Some(Dpdk {
+			version: parse_version(&lib.version).or_else(|| {
+				lib.include_paths
+					.iter()
+					.find_map(|p| version_from_header(&p.join("rte_version.h")))
+			}),
+			include_paths: lib.include_paths,
+		}),
+		Err(e) => {
+			println!(
+				"cargo:warning=libdpdk {}..{} not found by pkg-config, linking the static library list: {}",
+				DPDK_MIN_VERSION, DPDK_MAX_VERSION, e
+			);
+			None
+		}
+	}
+}
+
+/// Link the static library list from the default install
+#[cfg(not(feature = "rustdoc"))]
+fn probe_static() -> Dpdk {
+	RTE_CORE_LIBS
+		.iter()
+		.for_each(|lib| println!("cargo:rustc-link-lib=dylib={}", lib));
+	println!("cargo:rustc-link-search=/usr/local/lib/x86_64-linux-gnu/");
+
+	let include_paths = FALLBACK_INCLUDE_DIRS
+		.iter()
+		.map(PathBuf::from)
+		.filter(|p| p.join("rte_version.h").exists())
+		.collect::<Vec<_>>();
+	let version = include_paths
+		.iter()
+		.find_map(|p| version_from_header(&p.join("rte_version.h")));
+	Dpdk {
+		include_paths,
+		version,
+	}
+}
+
+/// Link the drivers of the enabled `pmd-*` features
+#[cfg(not(feature = "rustdoc"))]
+fn link_pmds(version: (u32, u32)) {
+	if env::var_os("CARGO_FEATURE_PMD_ALL").is_some() {
+		RTE_PMD_LIBS
+			.iter()
+			.for_each(|lib| println!("cargo:rustc-link-lib=dylib={}", lib));
+		return;
+	}
+	let prefix = if version >= (20, 11) {
+		"rte_net"
+	} else {
+		"rte_pmd"
+	};
+	PMD_FEATURES
+		.iter()
+		.filter(|(feature, _)| env::var_os(format!("CARGO_FEATURE_{}", feature)).is_some())
+		.flat_map(|(_, drivers)| drivers.iter())
+		.for_each(|driver| println!("cargo:rustc-link-lib=dylib={}_{}", prefix, driver));
+}
+
+/// Stop the build on a release the bindings aren't written for, before bindgen or the
+/// compiler fail on it with less to go on
+fn check_version((year, month): (u32, u32)) {
+	let min = parse_version(DPDK_MIN_VERSION).unwrap();
+	let max = parse_version(DPDK_MAX_VERSION).unwrap();
+	if (year, month) < min || (year, month) >= max {
+		panic!(
+			"DPDK {}.{:02} is not supported, install a release from {} up to but not \
+			 including {} and point PKG_CONFIG_PATH at it",
+			year, month, DPDK_MIN_VERSION, DPDK_MAX_VERSION
+		);
+	}
+}
+
+/// Emit a `dpdk_ge_*` cfg for every known release up to `version`
+/// and return the release the build assumes
+fn emit_version_cfgs(version: Option<(u32, u32)>) -> (u32, u32) {
+	let (year, month) = version.unwrap_or_else(|| {
+		println!(
+			"cargo:warning=could not detect the DPDK version, assuming {}.{:02}",
+			DPDK_DEFAULT_VERSION.0, DPDK_DEFAULT_VERSION.1
+		);
+		DPDK_DEFAULT_VERSION
+	});
+	let cfgs = DPDK_RELEASES
+		.iter()
+		.filter(|release| **release <= (year, month))
+		.map(|release| release_cfg(*release))
+		.collect::<Vec<_>>();
+	for cfg in &cfgs {
+		println!("cargo:rustc-cfg={}", cfg);
+	}
+	// seen by dependent build scripts as DEP_DPDK_VERSION and DEP_DPDK_CFGS
+	println!("cargo:version={}.{:02}", year, month);
+	println!("cargo:cfgs={}", cfgs.join(","));
+	(year, month)
+}
+
+/// Generates the bindings and compiles the C shims
+///
+/// Every allowlisted `static inline` function of the headers in `bindings.h`, e.g.
+/// `rte_pktmbuf_alloc` or `rte_eth_rx_burst`, gets an exported C wrapper generated into
+/// `OUT_DIR/inline_shims.c`. The bindings link to the wrapper under the function's own name,
+/// so exposing another inline function only takes including its header in `bindings.h`.
+/// `shim.c` is left for macros, like `rte_errno`, and our own helpers.
+#[cfg(not(feature = "rustdoc"))]
+fn bind(path: &Path, dpdk: &Dpdk) {
+	let inline_shims = path.join("inline_shims");
 
 	bindgen::Builder::default()
 		.header("src/bindings.h")
 		// .generate_comments(true)
 		.layout_tests(false) // added by Deep
 		.generate_inline_functions(true)
+		.wrap_static_fns(true)
+		.wrap_static_fns_path(&inline_shims)
 		// treat as opaque as per issue w/ combining align/packed:
 		// https://github.com/rust-lang/rust-bindgen/issues/1538
 		.opaque_type(r"rte_arp_ipv4|rte_arp_hdr")
-		.whitelist_type(r"(rte|eth|pcap)_.*")
-		.whitelist_function(r"(_rte|rte|_pkt|eth|numa|pcap)_.*")
-		.whitelist_var(r"(RTE|DEV|ETH|MEMPOOL|PKT|rte)_.*")
+		.allowlist_type(r"(rte|eth|pcap)_.*")
+		.allowlist_function(r"(_rte|rte|_pkt|eth|numa|pcap)_.*")
+		.allowlist_var(r"(RTE|DEV|ETH|MEMPOOL|PKT|rte)_.*")
 		.derive_copy(true)
 		.derive_debug(true)
 		.derive_default(true)
 		.derive_partialeq(true)
 		.default_enum_style(bindgen::EnumVariation::ModuleConsts)
+		.clang_args(
+			dpdk.include_paths
+				.iter()
+				.map(|p| format!("-I{}", p.display())),
+		)
 		.clang_arg("-finline-functions")
 		.clang_arg("-march=corei7")
 		.clang_arg("-mavx")
-		.rustfmt_bindings(true)
+		.formatter(bindgen::Formatter::Rustfmt)
 		.generate()
 		.expect("Unable to generate bindings")
 		.write_to_file(path.join("bindings.rs"))
 		.expect("Couldn't write bindings!");
+
+	// the generated wrappers include "src/bindings.h"
+	let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+	cc::Build::new()
+		.file("src/shim.c")
+		.file(inline_shims.with_extension("c"))
+		.include(&manifest_dir)
+		.includes(&dpdk.include_paths)
+		// some wrapped functions are still experimental
+		.define("ALLOW_EXPERIMENTAL_API", None)
+		.flag("-march=corei7")
+		.flag("-mavx")
+		.compile("rte_shim");
 }
 
 #[cfg(not(feature = "rustdoc"))]
 fn main() {
 	let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
 
-	bind(&out_path);
+	let dpdk = probe_pkg_config().unwrap_or_else(probe_static);
+	declare_release_cfgs();
+	let version = emit_version_cfgs(dpdk.version);
+	check_version(version);
 
-	RTE_CORE_LIBS
+	bind(&out_path, &dpdk);
+
+	link_pmds(version);
+
+	RTE_DEPS_LIBS
 		.iter()
-		.chain(RTE_PMD_LIBS)
-		.chain(RTE_DEPS_LIBS)
 		.for_each(|lib| println!("cargo:rustc-link-lib=dylib={}", lib));
 
 	// re-run build.rs upon changes
 	println!("cargo:rerun-if-changed=build.rs");
+	println!("cargo:rerun-if-changed=releases.rs");
 	println!("cargo:rerun-if-changed=src/");
-	println!("cargo:rustc-link-search=/usr/local/lib/x86_64-linux-gnu/");
-}
\ No newline at end of file
+}
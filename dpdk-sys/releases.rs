@@ -0,0 +1,27 @@
+// The DPDK releases known to the build, shared through `include!` by this crate's build
+// script and those of the crates using its `dpdk_ge_*` cfg flags
+
+/// DPDK releases that get a `dpdk_ge_*` cfg flag, those dpdk-sys builds against
+const DPDK_RELEASES: &[(u32, u32)] = &[
+	(19, 11),
+	(20, 2),
+	(20, 5),
+	(20, 8),
+	(20, 11),
+	(21, 2),
+	(21, 5),
+	(21, 8),
+];
+
+/// The cfg flag of a release, e.g. `dpdk_ge_20_11`
+fn release_cfg((year, month): (u32, u32)) -> String {
+	format!("dpdk_ge_{}_{:02}", year, month)
+}
+
+/// Declare the cfg flag of every known release, set or not, so rustc's `unexpected_cfgs`
+/// lint knows them
+fn declare_release_cfgs() {
+	DPDK_RELEASES
+		.iter()
+		.for_each(|release| println!("cargo:rustc-check-cfg=cfg({})", release_cfg(*release)));
+}
@@ -212,41 +212,13 @@ pub fn eal_init(args: Vec<String>) -> Result<(), EALErrors> {
 	}
 }
 
-/// Cleans up the Environment Abstraction Layer (EAL).
-pub fn eal_cleanup(mempool: &Mempool) -> Result<(), EALErrors> {
+/// Frees `mempool` and cleans up the Environment Abstraction Layer (EAL).
+pub fn eal_cleanup(mempool: Mempool) -> Result<(), EALErrors> {
+	drop(mempool);
 	unsafe {
-		dpdk_sys::rte_mempool_free(mempool.get_ptr());
 		match dpdk_sys::rte_eal_cleanup() {
 			0 => Ok(()),
 			_ => Err(EALErrors::Fault),
 		}
 	}
 }
-
-/// Frees the `rte_mbuf` in bulk.
-pub(crate) fn mbuf_free_bulk(mbufs: Vec<*mut dpdk_sys::rte_mbuf>) {
-	assert!(!mbufs.is_empty());
-
-	let mut to_free = Vec::with_capacity(mbufs.len());
-	let pool = unsafe { (*mbufs[0]).pool };
-
-	for mbuf in mbufs.into_iter() {
-		if pool == unsafe { (*mbuf).pool } {
-			to_free.push(mbuf as *mut raw::c_void);
-		} else {
-			unsafe {
-				let len = to_free.len();
-				dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
-				to_free.set_len(0);
-			}
-
-			to_free.push(mbuf as *mut raw::c_void);
-		}
-	}
-
-	unsafe {
-		let len = to_free.len();
-		dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
-		to_free.set_len(0);
-	}
-}
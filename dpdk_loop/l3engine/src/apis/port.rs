@@ -7,7 +7,10 @@
 
 // use crate::net::MacAddr;
 // use pnet::datalink::MacAddr;
-use std::marker::{Send, Sync};
+use std::{
+	marker::{Send, Sync},
+	mem,
+};
 
 use super::{Mbuf, Mempool, PortError};
 
@@ -159,6 +162,13 @@ impl Port {
 		}
 	}
 
+	/// The port's ethernet address
+	pub fn mac_addr(&self) -> [u8; 6] {
+		let mut addr = dpdk_sys::rte_ether_addr::default();
+		unsafe { dpdk_sys::rte_eth_macaddr_get(self.id, &mut addr) };
+		addr.addr_bytes
+	}
+
 	/// Get user device name
 	pub fn get_name(&self) -> &str {
 		self.device
@@ -179,9 +189,11 @@ impl Port {
 	}
 
 	/// Send packets out of the port
-	pub fn send(&self, pkts: &Vec<Mbuf>, queue_id: u16) -> usize {
-		let len = pkts.len();
-		let mut ptrs = pkts.into_iter().map(Mbuf::get_ptr).collect::<Vec<_>>();
+	///
+	/// The packets sent are taken out of `pkts`, those left over are the caller's to retry
+	/// or drop.
+	pub fn send(&self, pkts: &mut Vec<Mbuf>, queue_id: u16) -> usize {
+		let mut ptrs = pkts.iter().map(Mbuf::get_ptr).collect::<Vec<_>>();
 
 		let count = unsafe {
 			dpdk_sys::_rte_eth_tx_burst(
@@ -192,9 +204,8 @@ impl Port {
 				ptrs.len() as u16,
 			) as usize
 		};
-		if count < len {
-			super::mbuf_free_bulk(ptrs);
-		}
+		// the driver owns the ones it took
+		pkts.drain(..count).for_each(mem::forget);
 		count
 	}
 }
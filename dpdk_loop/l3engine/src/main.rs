@@ -1,130 +1,155 @@
+//! `l3engine` reflects the frames it receives back out of the port they came in on, to
+//! measure the round trip through the engine
+//!
+//! ```text
+//! l3engine [EAL options] -- [--port N] [--swap mac|ip|l4] [--ip ADDR] [--burst N]
+//!          [--dmac own|MAC] [--proto PROTO]... [--dst PREFIX] [--dport PORT[-PORT]]
+//! ```
+//!
+//! What is swapped and which frames are reflected is up to the options, see `reflect`.
+//! Counters are printed every second and once more at the end. Only the one port is used,
+//! switching frames between ports is what the `bridge` policy of `l3enginebin` does.
+
 pub mod apis;
+mod reflect;
 
 pub use apis::*;
+use reflect::{parse_mac, Filter, Reflector, Swap, Verdict};
 
 use crate::{eal_cleanup, eal_init, Mbuf, Mempool, Port};
 use log;
 use std::{
+	env,
 	net::Ipv4Addr,
-	slice::from_raw_parts_mut,
+	process, slice,
 	sync::{
 		atomic::{AtomicBool, Ordering},
 		Arc,
 	},
 };
 
-use pnet::{
-	datalink::MacAddr,
-	packet::{
-		arp::{ArpHardwareTypes, ArpOperations, MutableArpPacket},
-		ethernet::{EtherTypes, MutableEthernetPacket},
-		MutablePacket, Packet,
-	},
-};
-
-use etherparse::{InternetSlice, LinkSlice, ReadError, SlicedPacket, TransportSlice};
-
 const G_MEMPOOL_NAME: &str = "GLOBAL_MEMPOOL";
-const QUEUE_SZ: usize = 32;
-
-const ETHERTYPE_ARP: u16 = 0x0806;
-
-struct FiveTuple {
-	src_mac: [u8; 6],
-	src_ip: Ipv4Addr,
-	dst_mac: [u8; 6],
-	dst_ip: Ipv4Addr,
-	ethertype: u16,
+/// The FCS the NIC appends to every frame
+const ETHER_CRC_LEN: usize = 4;
+
+const USAGE: &str = "usage: l3engine [EAL options] -- [--port N] [--swap mac|ip|l4] [--ip ADDR] \
+	[--burst N] [--dmac own|MAC] [--proto PROTO]... [--dst PREFIX] [--dport PORT[-PORT]]
+
+  --port N          reflect on port N, 0 by default
+  --swap WHAT       swap the MAC addresses only, the IPv4 addresses too or the UDP/TCP
+                    ports as well, l4 by default
+  --ip ADDR         answer ARP requests for ADDR, so the engine can route to us
+  --burst N         most frames received or sent at a time, 32 by default
+filters, every frame is reflected without any:
+  --dmac own|MAC    frames to the port's MAC or to MAC only
+  --proto PROTO     IPv4 packets of PROTO only: udp, tcp, icmp or a number, repeatable
+  --dst PREFIX      IPv4 packets to PREFIX only, e.g. 10.0.1.0/24
+  --dport PORT      UDP/TCP to PORT or a range of them only, e.g. 1024-2047";
+
+/// What comes after the `--`
+#[derive(Debug)]
+struct Args {
+	port: u16,
+	swap: Swap,
+	ip: Option<Ipv4Addr>,
+	burst: usize,
+	/// `--dmac own`, only known once the port is
+	own_mac: bool,
+	filter: Filter,
 }
 
-pub(crate) fn parse_pkt(pkt: &[u8], local_ip: Ipv4Addr, local_mac: [u8; 6]) -> Option<FiveTuple> {
-	let sliced_pkt = SlicedPacket::from_ethernet(pkt).unwrap();
-	let linkslice = sliced_pkt.link.unwrap();
-	let ipslice = sliced_pkt.ip.unwrap();
-	let transportslice = sliced_pkt.transport.unwrap();
-	let link = match linkslice {
-		LinkSlice::Ethernet2(l) => l.to_header(),
-	};
-	let ip = match ipslice {
-		InternetSlice::Ipv4(ip) => ip,
-		_ => return None,
-	};
-	let transport = match transportslice {
-		TransportSlice::Tcp(tcp) => tcp,
-		TransportSlice::Udp(_) => return None,
-	};
-	let src_mac = link.source;
-	let dst_mac = link.destination;
-	let ethertype = link.ether_type;
-	let src_ip = ip.source_addr();
-	let dst_ip = ip.destination_addr();
-	// let dst_port = transport.destination_port();
-	if dst_ip != local_ip {
-		return None;
-	}
-
-	if dst_mac != local_mac {
-		return None;
+impl Args {
+	fn parse(args: &[String]) -> Result<Self, String> {
+		let mut parsed = Args {
+			port: 0,
+			swap: Swap::L4,
+			ip: None,
+			burst: 32,
+			own_mac: false,
+			filter: Filter::default(),
+		};
+		let mut args = args.iter();
+		while let Some(arg) = args.next() {
+			let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+			let with_arg = |e: String| format!("{}: {}", arg, e);
+			match arg.as_str() {
+				"--port" => {
+					let v = value()?;
+					parsed.port = v
+						.parse()
+						.map_err(|_| with_arg(format!("{:?} is not a port", v)))?;
+				}
+				"--swap" => parsed.swap = value()?.parse().map_err(with_arg)?,
+				"--ip" => {
+					let v = value()?;
+					let ip = v
+						.parse()
+						.map_err(|_| with_arg(format!("{:?} is not an address", v)))?;
+					parsed.ip = Some(ip);
+				}
+				"--burst" => {
+					let v = value()?;
+					parsed.burst = match v.parse() {
+						Ok(0) | Err(_) => return Err(with_arg(format!("{:?} is not a number", v))),
+						Ok(burst) => burst,
+					};
+				}
+				"--dmac" => match value()?.as_str() {
+					"own" => parsed.own_mac = true,
+					v => parsed.filter.dst_mac = Some(parse_mac(v).map_err(with_arg)?),
+				},
+				"--proto" => {
+					let proto = Filter::parse_proto(value()?).map_err(with_arg)?;
+					parsed.filter.protos.push(proto);
+				}
+				"--dst" => {
+					parsed.filter.dst = Some(Filter::parse_prefix(value()?).map_err(with_arg)?)
+				}
+				"--dport" => {
+					parsed.filter.dports = Some(Filter::parse_ports(value()?).map_err(with_arg)?)
+				}
+				"-h" | "--help" => return Err(String::new()),
+				_ => return Err(format!("unknown option {:?}", arg)),
+			}
+		}
+		Ok(parsed)
 	}
-
-	// if !ports.contains(&dst_port) {
-	// 	return Err(FiveTupleErr::WrongPort);
-	// }
-
-	Some(FiveTuple {
-		src_ip,
-		src_mac,
-		dst_mac,
-		dst_ip,
-		ethertype,
-	})
 }
 
-fn arr_to_macaddr(mac: [u8; 6]) -> MacAddr {
-	MacAddr::new(mac[0], mac[1], mac[2], mac[3], mac[4], mac[5])
+/// What the reflector has done, in frames and bytes with the FCS
+#[derive(Clone, Copy, Debug, Default)]
+struct Counters {
+	rx: u64,
+	rx_bytes: u64,
+	tx: u64,
+	tx_bytes: u64,
+	arp: u64,
+	filtered: u64,
+	malformed: u64,
+	/// reflected but dropped, the tx queue was full
+	tx_full: u64,
 }
 
-fn handle_arp(
-	local_ip: Ipv4Addr,
-	local_mac: [u8; 6],
-	dst_mac: [u8; 6],
-	dst_ip: Ipv4Addr,
-	mp: &Mempool,
-	ethertype: u16,
-) -> Option<Mbuf> {
-	if local_mac != dst_mac || local_ip != dst_ip {
-		return None;
-	}
-
-	if ethertype.to_be() == ETHERTYPE_ARP {
-		if local_mac == dst_mac || local_ip == dst_ip {
-			let mut eth_buf = [0u8; 42];
-			let mut eth_pkt = MutableEthernetPacket::new(&mut eth_buf)?;
-			eth_pkt.set_destination(MacAddr::broadcast());
-			let source_mac = arr_to_macaddr(local_mac);
-			eth_pkt.set_source(source_mac);
-			eth_pkt.set_ethertype(EtherTypes::Arp);
-			let mut arp_buffer = [0u8; 28];
-			let mut arp_packet = MutableArpPacket::new(&mut arp_buffer)?;
-			arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
-			arp_packet.set_protocol_type(EtherTypes::Ipv4);
-			arp_packet.set_hw_addr_len(6);
-			arp_packet.set_proto_addr_len(4);
-			arp_packet.set_operation(ArpOperations::Request);
-			arp_packet.set_sender_hw_addr(source_mac);
-			arp_packet.set_sender_proto_addr(local_ip);
-			arp_packet.set_target_hw_addr(MacAddr::zero());
-			arp_packet.set_target_proto_addr(dst_ip);
-
-			eth_pkt.set_payload(arp_packet.packet_mut());
-
-			match Mbuf::from_bytes(eth_pkt.packet(), mp) {
-				Ok(out_buf) => return Some(out_buf),
-				Err(_) => {}
-			}
-		}
+impl Counters {
+	/// Print what happened since `last` over `secs` seconds, at `elapsed` seconds
+	fn report(&self, last: &Counters, secs: f64, elapsed: f64) {
+		let rate = |pkts: u64, bytes: u64| (pkts as f64 / secs, bytes as f64 * 8.0 / secs / 1e6);
+		let (rx_pps, rx_mbps) = rate(self.rx - last.rx, self.rx_bytes - last.rx_bytes);
+		let (tx_pps, tx_mbps) = rate(self.tx - last.tx, self.tx_bytes - last.tx_bytes);
+		println!(
+			"{:6.1}s  rx {:>12.0} pps {:>10.1} Mbps  tx {:>12.0} pps {:>10.1} Mbps  \
+			 arp {} filtered {} malformed {} tx full {}",
+			elapsed,
+			rx_pps,
+			rx_mbps,
+			tx_pps,
+			tx_mbps,
+			self.arp - last.arp,
+			self.filtered - last.filtered,
+			self.malformed - last.malformed,
+			self.tx_full - last.tx_full,
+		);
 	}
-	None
 }
 
 /// Handle Ctrl+C
@@ -135,121 +160,147 @@ fn handle_signal(kr: Arc<AtomicBool>) {
 	.expect("Error setting Ctrl-C handler");
 }
 
-fn recv_pkts(port: &[Port; 2], in_pkts: &mut Vec<Mbuf>) -> usize {
-	let queue_id = unsafe { dpdk_sys::_rte_lcore_id() as u16 };
-	in_pkts.extend(port[0].receive(queue_id, in_pkts.len()));
-	// in_pkts.extend(port[1].receive(queue_id, in_pkts.len()));
-
+/// Reflect what queue `queue_id` of `port` has received back out of the same queue
+fn reflect_burst(
+	port: &Port,
+	queue_id: u16,
+	burst: usize,
+	reflector: &Reflector,
+	out_pkts: &mut Vec<Mbuf>,
+	counters: &mut Counters,
+) {
+	let in_pkts = port.receive(queue_id, burst);
 	#[cfg(feature = "debug")]
-	if in_pkts.len() > 0 {
-		println!("got {} pkts", in_pkts.len());
+	if !in_pkts.is_empty() {
+		println!("queue {}: received {} packets", queue_id, in_pkts.len());
 	}
 
-	in_pkts.len()
-}
+	for pkt in in_pkts {
+		let len = pkt.data_len();
+		counters.rx += 1;
+		counters.rx_bytes += (len + ETHER_CRC_LEN) as u64;
+		// the first segment is all that's looked at, headers don't span segments
+		let frame = unsafe { slice::from_raw_parts_mut(pkt.data_address(0), len) };
+		match reflector.reflect(frame) {
+			Verdict::Reflected => out_pkts.push(pkt),
+			Verdict::ArpReply => {
+				counters.arp += 1;
+				out_pkts.push(pkt);
+			}
+			Verdict::Filtered => counters.filtered += 1,
+			Verdict::Malformed => counters.malformed += 1,
+		}
+	}
 
-fn xmit_pkts(port: &Port, out_pkts: &mut Vec<Mbuf>) -> usize {
-	let queue_id = unsafe { dpdk_sys::_rte_lcore_id() as u16 };
-	let num = port.send(out_pkts, queue_id ^ 1);
-	out_pkts.clear(); // deallocate all buffers
-	num
+	let bytes = |pkts: &Vec<Mbuf>| {
+		pkts.iter()
+			.map(|pkt| (pkt.data_len() + ETHER_CRC_LEN) as u64)
+			.sum::<u64>()
+	};
+	let offered = bytes(out_pkts);
+	counters.tx += port.send(out_pkts, queue_id) as u64;
+	counters.tx_bytes += offered - bytes(out_pkts);
+	counters.tx_full += out_pkts.len() as u64;
+	out_pkts.clear(); // dropping the ones left over frees them
 }
 
 fn main() {
+	let argv = env::args().collect::<Vec<_>>();
+	let split = argv.iter().position(|a| a == "--").unwrap_or(argv.len());
+	let args = match Args::parse(argv.get(split + 1..).unwrap_or_default()) {
+		Ok(args) => args,
+		Err(e) => {
+			if !e.is_empty() {
+				eprintln!("{}\n", e);
+			}
+			eprintln!("{}", USAGE);
+			process::exit(2);
+		}
+	};
+
 	log::info!("Initializing DPDK env ...");
-	let args = vec![
-		String::from("-l 0-1"),
-		String::from("-n 4"),
-		String::from("--proc-type=primary"),
-		String::from("--base-virtaddr=0x7f000000000"),
-		String::from("--"),
-		String::from("-p 3"),
-		String::from("-n 2"),
-	];
-	#[cfg(debug)]
-	println!("main process args: {:?}", &args);
-	eal_init(args).unwrap();
+	#[cfg(feature = "debug")]
+	println!("main process args: {:?}", &argv[..split]);
+	if let Err(e) = eal_init(argv[..split].to_vec()) {
+		eprintln!("Failed to initialize EAL: {}", e);
+		process::exit(1);
+	}
 
 	#[cfg(feature = "debug")]
 	println!("environment initialised");
 
-	let cores = vec![0];
-
 	log::info!("setup mempool");
-	let mempool;
-	match Mempool::new(G_MEMPOOL_NAME) {
-		Ok(mp) => {
-			#[cfg(feature = "debug")]
-			println!("mempool address: {:p}", mp.get_ptr());
-			mempool = mp;
-		}
+	let mempool = match Mempool::new(G_MEMPOOL_NAME) {
+		Ok(mp) => mp,
 		Err(e) => panic!("Failed to initialize mempool: {}", e),
-	}
-
-	#[cfg(feature = "debug")]
-	println!("mempool set");
+	};
 
 	log::info!("setup ports");
-	#[cfg(feature = "debug")]
-	println!("setup ports");
-	let eth_dev1 = "port0";
-	let mut port1 = Port::new(eth_dev1, 0u16).unwrap();
-	port1.configure(cores.len() as u16, &mempool).unwrap();
-	port1.start().unwrap();
-	let eth_dev2 = "port1";
-	let mut port2 = Port::new(eth_dev2, 1u16).unwrap();
-	port2.configure(cores.len() as u16, &mempool).unwrap();
-	port2.start().unwrap();
-	let ports = [port1, port2];
-
-	#[cfg(feature = "debug")]
-	println!("ports set");
+	// ports keep their name for the life of the process
+	let name: &'static str = Box::leak(format!("port{}", args.port).into_boxed_str());
+	let mut port = Port::new(name, args.port)
+		.unwrap_or_else(|e| panic!("Failed to find port {}: {}", args.port, e));
+	// one core, but the port always sets up an even number of queues and RSS spreads the
+	// frames over all of them
+	let cores = 1;
+	let queues = cores + cores % 2;
+	port.configure(cores, &mempool)
+		.unwrap_or_else(|e| panic!("Failed to configure port {}: {}", args.port, e));
+	port.start()
+		.unwrap_or_else(|e| panic!("Failed to start port {}: {}", args.port, e));
+
+	let mac = port.mac_addr();
+	let mut filter = args.filter;
+	if args.own_mac {
+		filter.dst_mac = Some(mac);
+	}
+	let reflector = Reflector {
+		mac,
+		ip: args.ip,
+		swap: args.swap,
+		filter,
+	};
+	println!("reflecting on port {} as {}", args.port, reflector);
 
-	// hold packets received from outside and packetiser
-	let mut in_pkts: Vec<Mbuf> = Vec::with_capacity(QUEUE_SZ);
-	// let mut out_pkts: Vec<Mbuf> = Vec::with_capacity(QUEUE_SZ);
+	let mut out_pkts: Vec<Mbuf> = Vec::with_capacity(args.burst);
+	let mut counters = Counters::default();
 
 	// handling Ctrl+C
 	let keep_running = Arc::new(AtomicBool::new(true));
-	// let kr = keep_running.clone();
 	handle_signal(keep_running.clone());
 
-	#[cfg(feature = "debug")]
-	println!("main: secondary started");
+	let hz = unsafe { dpdk_sys::_rte_get_timer_hz() };
+	let start = unsafe { dpdk_sys::_rte_get_tsc_cycles() };
+	let mut last_report = (start, counters);
 	while keep_running.load(Ordering::SeqCst) {
-		let rsz = recv_pkts(&ports, &mut in_pkts);
-		#[cfg(feature = "debug")]
-		if rsz > 0 {
-			println!("Received {} packets", rsz);
+		for queue_id in 0..queues {
+			reflect_burst(
+				&port,
+				queue_id,
+				args.burst,
+				&reflector,
+				&mut out_pkts,
+				&mut counters,
+			);
 		}
-		if rsz > 0 {
-			let _tsz = xmit_pkts(&port2, &mut in_pkts);
-			#[cfg(feature = "debug")]
-			println!("Transmitted {} packets", _tsz);
-			for pkt in &in_pkts {
-				let local_ip = Ipv4Addr::new(192, 168, 1, 2);
-				let mac = [0x90, 0xe2, 0xba, 0x87, 0x6b, 0xe8];
-				let buf =
-					unsafe { from_raw_parts_mut(dpdk_sys::_pkt_raw_addr(pkt.get_ptr()), 1536) };
-				let ft = parse_pkt(buf, local_ip, mac).unwrap();
-				let mut mbuf = vec![handle_arp(
-					local_ip,
-					mac,
-					ft.src_mac,
-					ft.src_ip,
-					&mempool,
-					ft.ethertype,
-				)
-				.unwrap()];
-				xmit_pkts(&port1, &mut mbuf);
-			}
+
+		let now = unsafe { dpdk_sys::_rte_get_tsc_cycles() };
+		if now - last_report.0 >= hz {
+			let secs = (now - last_report.0) as f64 / hz as f64;
+			counters.report(&last_report.1, secs, (now - start) as f64 / hz as f64);
+			last_report = (now, counters);
 		}
 	}
 
+	let now = unsafe { dpdk_sys::_rte_get_tsc_cycles() };
+	let secs = (now - start) as f64 / hz as f64;
+	println!("total over {:.1}s:", secs);
+	counters.report(&Counters::default(), secs, secs);
+
 	#[cfg(feature = "debug")]
 	println!("main: stopping");
 	unsafe { dpdk_sys::_pkt_stop_and_close_ports() };
 	#[cfg(feature = "debug")]
 	println!("main: ports closed");
-	eal_cleanup(&mempool).unwrap();
+	eal_cleanup(mempool).unwrap();
 }
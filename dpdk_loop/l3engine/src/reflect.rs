@@ -0,0 +1,583 @@
+//! Turning received frames around
+//!
+//! Frames are reflected in place: the source MAC becomes the destination and the port's
+//! MAC the source, and depending on `Swap`, the IPv4 addresses and the UDP/TCP ports trade
+//! places too. Swapping pairs of 16 bit words keeps the IPv4, UDP and TCP checksums valid,
+//! so none are recomputed.
+
+use std::{fmt, net::Ipv4Addr, str::FromStr};
+
+const ETHER_HDR_LEN: usize = 14;
+const VLAN_HDR_LEN: usize = 4;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const IPV4_HDR_LEN: usize = 20;
+const IPV4_FRAG_OFFSET_MASK: u16 = 0x1fff;
+const IP_PROTOCOL_ICMP: u8 = 1;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+const ARP_LEN: usize = 28;
+const ARP_OP_REQUEST: u16 = 1;
+const ARP_OP_REPLY: u16 = 2;
+
+/// How much of a frame is turned around
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Swap {
+	/// The MAC addresses only
+	Mac,
+	/// The MAC and IPv4 addresses
+	Ip,
+	/// The MAC and IPv4 addresses and the UDP/TCP ports
+	L4,
+}
+
+impl FromStr for Swap {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, String> {
+		match s {
+			"mac" => Ok(Swap::Mac),
+			"ip" => Ok(Swap::Ip),
+			"l4" | "port" => Ok(Swap::L4),
+			_ => Err(format!("{:?} is not one of mac, ip or l4", s)),
+		}
+	}
+}
+
+/// Parses "aa:bb:cc:dd:ee:ff"
+pub fn parse_mac(s: &str) -> Result<[u8; 6], String> {
+	let mut mac = [0; 6];
+	let mut octets = s.split(':');
+	for b in mac.iter_mut() {
+		*b = octets
+			.next()
+			.and_then(|o| u8::from_str_radix(o, 16).ok())
+			.ok_or_else(|| format!("{:?} is not a MAC address", s))?;
+	}
+	match octets.next() {
+		Some(_) => Err(format!("{:?} is not a MAC address", s)),
+		None => Ok(mac),
+	}
+}
+
+/// Which frames are reflected, all of them by default
+///
+/// A frame has to pass every condition that is set. The IPv4 conditions turn away
+/// everything else, and the port range whatever isn't UDP or TCP. ARP is never reflected,
+/// only answered.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+	/// Destination MAC
+	pub dst_mac: Option<[u8; 6]>,
+	/// IP protocols, any if empty
+	pub protos: Vec<u8>,
+	/// Destination prefix as address and mask
+	pub dst: Option<(u32, u32)>,
+	/// First and last UDP/TCP destination port
+	pub dports: Option<(u16, u16)>,
+}
+
+impl Filter {
+	/// Parses "udp", "tcp", "icmp" or a protocol number
+	pub fn parse_proto(s: &str) -> Result<u8, String> {
+		match s {
+			"icmp" => Ok(IP_PROTOCOL_ICMP),
+			"tcp" => Ok(IP_PROTOCOL_TCP),
+			"udp" => Ok(IP_PROTOCOL_UDP),
+			_ => s
+				.parse()
+				.map_err(|_| format!("{:?} is not udp, tcp, icmp or a protocol number", s)),
+		}
+	}
+
+	/// Parses "10.0.0.0/24" or a single address
+	pub fn parse_prefix(s: &str) -> Result<(u32, u32), String> {
+		let bad = || format!("{:?} is not an IPv4 prefix like \"10.0.0.0/24\"", s);
+		let (addr, len) = match s.find('/') {
+			Some(idx) => (&s[..idx], s[idx + 1..].parse::<u32>().map_err(|_| bad())?),
+			None => (s, 32),
+		};
+		if len > 32 {
+			return Err(bad());
+		}
+		let addr = u32::from(addr.parse::<Ipv4Addr>().map_err(|_| bad())?);
+		let mask = match len {
+			0 => 0,
+			len => u32::MAX << (32 - len),
+		};
+		Ok((addr & mask, mask))
+	}
+
+	/// Parses "80" or "1024-2047"
+	pub fn parse_ports(s: &str) -> Result<(u16, u16), String> {
+		let bad = || format!("{:?} is not a port or a range like \"1024-2047\"", s);
+		let (first, last) = match s.find('-') {
+			Some(idx) => (&s[..idx], &s[idx + 1..]),
+			None => (s, s),
+		};
+		let first = first.parse::<u16>().map_err(|_| bad())?;
+		let last = last.parse::<u16>().map_err(|_| bad())?;
+		if first > last {
+			return Err(bad());
+		}
+		Ok((first, last))
+	}
+
+	/// Whether the filter looks beyond the ethernet header
+	fn wants_ipv4(&self) -> bool {
+		!self.protos.is_empty() || self.dst.is_some() || self.dports.is_some()
+	}
+}
+
+/// What became of a frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+	/// Turned around, send it back
+	Reflected,
+	/// An ARP request for the reflector's address, answered in place
+	ArpReply,
+	/// Turned away by the filter
+	Filtered,
+	/// Too short for the headers it claims to have
+	Malformed,
+}
+
+/// Reflects frames received on a port with MAC `mac`
+#[derive(Clone, Debug)]
+pub struct Reflector {
+	pub mac: [u8; 6],
+	/// Address ARP requests are answered for
+	pub ip: Option<Ipv4Addr>,
+	pub swap: Swap,
+	pub filter: Filter,
+}
+
+impl Reflector {
+	/// Turn `frame` around in place, unless the verdict says otherwise
+	pub fn reflect(&self, frame: &mut [u8]) -> Verdict {
+		if frame.len() < ETHER_HDR_LEN {
+			return Verdict::Malformed;
+		}
+		let mut l3 = ETHER_HDR_LEN;
+		let mut ethertype = be16(frame, 12);
+		if ethertype == ETHERTYPE_VLAN {
+			if frame.len() < ETHER_HDR_LEN + VLAN_HDR_LEN {
+				return Verdict::Malformed;
+			}
+			l3 += VLAN_HDR_LEN;
+			ethertype = be16(frame, 16);
+		}
+
+		// reflected ARP would poison the neighbours' caches
+		if ethertype == ETHERTYPE_ARP {
+			return self.arp_reply(frame, l3);
+		}
+		if let Some(mac) = self.filter.dst_mac {
+			if frame[0..6] != mac {
+				return Verdict::Filtered;
+			}
+		}
+		match ethertype {
+			ETHERTYPE_IPV4 => {
+				let verdict = self.reflect_ipv4(frame, l3);
+				if verdict != Verdict::Reflected {
+					return verdict;
+				}
+			}
+			_ if self.filter.wants_ipv4() => return Verdict::Filtered,
+			_ => {}
+		}
+
+		frame.copy_within(6..12, 0);
+		frame[6..12].copy_from_slice(&self.mac);
+		Verdict::Reflected
+	}
+
+	/// Filter and swap the IPv4 packet at `l3`, leaving the ethernet header to the caller
+	fn reflect_ipv4(&self, frame: &mut [u8], l3: usize) -> Verdict {
+		if frame.len() < l3 + IPV4_HDR_LEN || frame[l3] >> 4 != 4 {
+			return Verdict::Malformed;
+		}
+		let l4 = l3 + (frame[l3] & 0x0f) as usize * 4;
+		if l4 < l3 + IPV4_HDR_LEN || frame.len() < l4 {
+			return Verdict::Malformed;
+		}
+		let proto = frame[l3 + 9];
+		// later fragments carry no UDP/TCP header
+		let ports = match proto {
+			IP_PROTOCOL_TCP | IP_PROTOCOL_UDP
+				if be16(frame, l3 + 6) & IPV4_FRAG_OFFSET_MASK == 0 =>
+			{
+				if frame.len() < l4 + 4 {
+					return Verdict::Malformed;
+				}
+				Some(be16(frame, l4 + 2))
+			}
+			_ => None,
+		};
+
+		let filter = &self.filter;
+		if !filter.protos.is_empty() && !filter.protos.contains(&proto) {
+			return Verdict::Filtered;
+		}
+		if let Some((prefix, mask)) = filter.dst {
+			if be32(frame, l3 + 16) & mask != prefix {
+				return Verdict::Filtered;
+			}
+		}
+		if let Some((first, last)) = filter.dports {
+			match ports {
+				Some(dport) if first <= dport && dport <= last => {}
+				_ => return Verdict::Filtered,
+			}
+		}
+
+		if self.swap >= Swap::Ip {
+			swap(frame, l3 + 12, l3 + 16, 4);
+		}
+		if self.swap >= Swap::L4 && ports.is_some() {
+			swap(frame, l4, l4 + 2, 2);
+		}
+		Verdict::Reflected
+	}
+
+	/// Answer an ARP request for the reflector's address in place, filter any other ARP
+	fn arp_reply(&self, frame: &mut [u8], l3: usize) -> Verdict {
+		let ip = match self.ip {
+			Some(ip) => ip.octets(),
+			None => return Verdict::Filtered,
+		};
+		if frame.len() < l3 + ARP_LEN {
+			return Verdict::Malformed;
+		}
+		let arp = &mut frame[l3..l3 + ARP_LEN];
+		// ethernet and IPv4, 6 and 4 byte addresses
+		if arp[0..6] != [0, 1, 8, 0, 6, 4] || be16(arp, 6) != ARP_OP_REQUEST || arp[24..28] != ip {
+			return Verdict::Filtered;
+		}
+		arp[6..8].copy_from_slice(&ARP_OP_REPLY.to_be_bytes());
+		arp.copy_within(8..18, 18); // the sender becomes the target
+		arp[8..14].copy_from_slice(&self.mac);
+		arp[14..18].copy_from_slice(&ip);
+		frame.copy_within(6..12, 0);
+		frame[6..12].copy_from_slice(&self.mac);
+		Verdict::ArpReply
+	}
+}
+
+impl fmt::Display for Reflector {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let m = self.mac;
+		write!(
+			f,
+			"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x} swapping {:?}",
+			m[0], m[1], m[2], m[3], m[4], m[5], self.swap
+		)?;
+		if let Some(ip) = self.ip {
+			write!(f, ", answering ARP for {}", ip)?;
+		}
+		Ok(())
+	}
+}
+
+#[inline]
+fn be16(buf: &[u8], at: usize) -> u16 {
+	u16::from_be_bytes([buf[at], buf[at + 1]])
+}
+
+#[inline]
+fn be32(buf: &[u8], at: usize) -> u32 {
+	u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+/// Swap the `len` bytes at `a` with those at `b`, the two don't overlap
+#[inline]
+fn swap(buf: &mut [u8], a: usize, b: usize, len: usize) {
+	let (head, tail) = buf.split_at_mut(b);
+	head[a..a + len].swap_with_slice(&mut tail[..len]);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PORT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0xaa];
+	const PEER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0xbb];
+	const PORT_IP: [u8; 4] = [10, 0, 0, 1];
+	const PEER_IP: [u8; 4] = [10, 0, 0, 2];
+
+	fn reflector(swap: Swap) -> Reflector {
+		Reflector {
+			mac: PORT_MAC,
+			ip: Some(Ipv4Addr::from(PORT_IP)),
+			swap,
+			filter: Filter::default(),
+		}
+	}
+
+	/// The internet checksum of `data`, zero when `data` holds a valid checksum
+	fn checksum(data: &[u8]) -> u16 {
+		let mut sum = data
+			.chunks(2)
+			.map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+			.sum::<u32>();
+		while sum > 0xffff {
+			sum = (sum & 0xffff) + (sum >> 16);
+		}
+		!(sum as u16)
+	}
+
+	/// The checksum of the UDP/TCP segment at `l4` with its pseudo header
+	fn l4_checksum(frame: &[u8], l3: usize, l4: usize) -> u16 {
+		let mut pseudo = frame[l3 + 12..l3 + 20].to_vec();
+		pseudo.extend_from_slice(&[0, frame[l3 + 9]]);
+		pseudo.extend_from_slice(&((frame.len() - l4) as u16).to_be_bytes());
+		pseudo.extend_from_slice(&frame[l4..]);
+		checksum(&pseudo)
+	}
+
+	/// A frame from the peer to the port with valid checksums, tagged with `vlan` if set
+	fn frame(proto: u8, vlan: Option<u16>) -> Vec<u8> {
+		let mut frame = PORT_MAC.to_vec();
+		frame.extend_from_slice(&PEER_MAC);
+		if let Some(vid) = vlan {
+			frame.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+			frame.extend_from_slice(&vid.to_be_bytes());
+		}
+		frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+		let l3 = frame.len();
+		let l4_len = match proto {
+			IP_PROTOCOL_TCP => 20,
+			_ => 8,
+		};
+		let payload = b"reflect me";
+		let total = (IPV4_HDR_LEN + l4_len + payload.len()) as u16;
+		frame.extend_from_slice(&[0x45, 0]);
+		frame.extend_from_slice(&total.to_be_bytes());
+		frame.extend_from_slice(&[0x12, 0x34, 0, 0, 64, proto, 0, 0]);
+		frame.extend_from_slice(&PEER_IP);
+		frame.extend_from_slice(&PORT_IP);
+		let cksum = checksum(&frame[l3..]);
+		frame[l3 + 10..l3 + 12].copy_from_slice(&cksum.to_be_bytes());
+
+		let l4 = frame.len();
+		frame.extend_from_slice(&1024u16.to_be_bytes());
+		frame.extend_from_slice(&9u16.to_be_bytes());
+		match proto {
+			IP_PROTOCOL_TCP => {
+				frame.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff]);
+				frame.extend_from_slice(&[0; 4]);
+			}
+			IP_PROTOCOL_UDP => {
+				frame.extend_from_slice(&((l4_len + payload.len()) as u16).to_be_bytes());
+				frame.extend_from_slice(&[0, 0]);
+			}
+			_ => frame.extend_from_slice(&[0; 8]),
+		}
+		frame.extend_from_slice(payload);
+		let cksum = l4_checksum(&frame, l3, l4);
+		match proto {
+			IP_PROTOCOL_TCP => frame[l4 + 16..l4 + 18].copy_from_slice(&cksum.to_be_bytes()),
+			IP_PROTOCOL_UDP => frame[l4 + 6..l4 + 8].copy_from_slice(&cksum.to_be_bytes()),
+			_ => {}
+		}
+		frame
+	}
+
+	/// An ARP request from the peer for `target`
+	fn arp_request(target: [u8; 4]) -> Vec<u8> {
+		let mut frame = vec![0xff; 6];
+		frame.extend_from_slice(&PEER_MAC);
+		frame.extend_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+		frame.extend_from_slice(&[0, 1, 8, 0, 6, 4]);
+		frame.extend_from_slice(&ARP_OP_REQUEST.to_be_bytes());
+		frame.extend_from_slice(&PEER_MAC);
+		frame.extend_from_slice(&PEER_IP);
+		frame.extend_from_slice(&[0; 6]);
+		frame.extend_from_slice(&target);
+		frame
+	}
+
+	#[test]
+	fn every_swap_keeps_the_checksums_valid() {
+		let l3 = ETHER_HDR_LEN;
+		let l4 = l3 + IPV4_HDR_LEN;
+		for &proto in &[IP_PROTOCOL_UDP, IP_PROTOCOL_TCP] {
+			for &swap in &[Swap::Mac, Swap::Ip, Swap::L4] {
+				let mut frame = frame(proto, None);
+				assert_eq!(reflector(swap).reflect(&mut frame), Verdict::Reflected);
+				assert_eq!(frame[0..6], PEER_MAC);
+				assert_eq!(frame[6..12], PORT_MAC);
+				assert_eq!(checksum(&frame[l3..l4]), 0, "{:?} {:?}", proto, swap);
+				assert_eq!(l4_checksum(&frame, l3, l4), 0, "{:?} {:?}", proto, swap);
+
+				let (src, dst) = match swap {
+					Swap::Mac => (PEER_IP, PORT_IP),
+					_ => (PORT_IP, PEER_IP),
+				};
+				assert_eq!(frame[l3 + 12..l3 + 16], src);
+				assert_eq!(frame[l3 + 16..l3 + 20], dst);
+				let (sport, dport) = match swap {
+					Swap::L4 => (9, 1024),
+					_ => (1024, 9),
+				};
+				assert_eq!(be16(&frame, l4), sport);
+				assert_eq!(be16(&frame, l4 + 2), dport);
+			}
+		}
+	}
+
+	#[test]
+	fn reflects_tagged_frames() {
+		let l3 = ETHER_HDR_LEN + VLAN_HDR_LEN;
+		let l4 = l3 + IPV4_HDR_LEN;
+		let mut frame = frame(IP_PROTOCOL_UDP, Some(100));
+		assert_eq!(reflector(Swap::L4).reflect(&mut frame), Verdict::Reflected);
+		assert_eq!(frame[0..6], PEER_MAC);
+		assert_eq!(be16(&frame, 12), ETHERTYPE_VLAN);
+		assert_eq!(be16(&frame, 14), 100, "the tag is kept");
+		assert_eq!(frame[l3 + 12..l3 + 16], PORT_IP);
+		assert_eq!(be16(&frame, l4 + 2), 1024);
+		assert_eq!(checksum(&frame[l3..l4]), 0);
+		assert_eq!(l4_checksum(&frame, l3, l4), 0);
+	}
+
+	#[test]
+	fn answers_arp_for_its_own_address() {
+		let mut frame = arp_request(PORT_IP);
+		assert_eq!(reflector(Swap::L4).reflect(&mut frame), Verdict::ArpReply);
+		let arp = &frame[ETHER_HDR_LEN..];
+		assert_eq!(frame[0..6], PEER_MAC);
+		assert_eq!(frame[6..12], PORT_MAC);
+		assert_eq!(be16(arp, 6), ARP_OP_REPLY);
+		assert_eq!(arp[8..14], PORT_MAC);
+		assert_eq!(arp[14..18], PORT_IP);
+		assert_eq!(arp[18..24], PEER_MAC);
+		assert_eq!(arp[24..28], PEER_IP);
+
+		let mut other = arp_request(PEER_IP);
+		assert_eq!(reflector(Swap::L4).reflect(&mut other), Verdict::Filtered);
+		let mut reply = arp_request(PORT_IP);
+		reply[ETHER_HDR_LEN + 7] = ARP_OP_REPLY as u8;
+		assert_eq!(reflector(Swap::L4).reflect(&mut reply), Verdict::Filtered);
+		let silent = Reflector {
+			ip: None,
+			..reflector(Swap::L4)
+		};
+		let mut frame = arp_request(PORT_IP);
+		assert_eq!(silent.reflect(&mut frame), Verdict::Filtered);
+	}
+
+	#[test]
+	fn filters_turn_frames_away() {
+		let udp = frame(IP_PROTOCOL_UDP, None);
+		let mut ipv6 = udp.clone();
+		ipv6[12..14].copy_from_slice(&0x86ddu16.to_be_bytes()); // IPv6
+		let check = |filter: Filter, frame: &[u8]| {
+			let reflector = Reflector {
+				filter,
+				..reflector(Swap::Mac)
+			};
+			reflector.reflect(&mut frame.to_vec())
+		};
+		let filter = |f: fn(&mut Filter)| {
+			let mut filter = Filter::default();
+			f(&mut filter);
+			filter
+		};
+
+		let to_port = filter(|f| f.dst_mac = Some(PORT_MAC));
+		let to_peer = filter(|f| f.dst_mac = Some(PEER_MAC));
+		assert_eq!(check(to_port, &udp), Verdict::Reflected);
+		assert_eq!(check(to_peer.clone(), &udp), Verdict::Filtered);
+		assert_eq!(check(to_peer, &ipv6), Verdict::Filtered);
+
+		let udp_only = filter(|f| f.protos = vec![IP_PROTOCOL_UDP]);
+		let tcp_only = filter(|f| f.protos = vec![IP_PROTOCOL_TCP]);
+		assert_eq!(check(udp_only.clone(), &udp), Verdict::Reflected);
+		assert_eq!(check(tcp_only, &udp), Verdict::Filtered);
+		assert_eq!(check(udp_only, &ipv6), Verdict::Filtered, "not IPv4");
+
+		let inside = filter(|f| f.dst = Some(Filter::parse_prefix("10.0.0.0/24").unwrap()));
+		let outside = filter(|f| f.dst = Some(Filter::parse_prefix("10.0.1.0/24").unwrap()));
+		assert_eq!(check(inside, &udp), Verdict::Reflected);
+		assert_eq!(check(outside, &udp), Verdict::Filtered);
+
+		let discard = filter(|f| f.dports = Some((9, 9)));
+		let high = filter(|f| f.dports = Some((1024, 65535)));
+		assert_eq!(check(discard.clone(), &udp), Verdict::Reflected);
+		assert_eq!(check(high, &udp), Verdict::Filtered);
+		let icmp = frame(IP_PROTOCOL_ICMP, None);
+		assert_eq!(check(discard.clone(), &icmp), Verdict::Filtered, "no ports");
+		let mut fragment = udp.clone();
+		fragment[ETHER_HDR_LEN + 7] = 1; // a later fragment
+		assert_eq!(check(discard, &fragment), Verdict::Filtered, "no ports");
+
+		assert_eq!(check(Filter::default(), &ipv6), Verdict::Reflected);
+	}
+
+	#[test]
+	fn truncated_frames_are_malformed() {
+		let tagged = frame(IP_PROTOCOL_UDP, Some(100));
+		let reflector = reflector(Swap::L4);
+		let vlan_l3 = ETHER_HDR_LEN + VLAN_HDR_LEN;
+		// short of the ethernet header, the tag, the IPv4 header and the ports
+		for &len in &[
+			0,
+			ETHER_HDR_LEN - 1,
+			vlan_l3 - 1,
+			vlan_l3 + IPV4_HDR_LEN - 1,
+			vlan_l3 + IPV4_HDR_LEN + 3,
+		] {
+			let mut frame = tagged[..len].to_vec();
+			assert_eq!(
+				reflector.reflect(&mut frame),
+				Verdict::Malformed,
+				"{} bytes",
+				len
+			);
+		}
+
+		let mut options = frame(IP_PROTOCOL_UDP, None);
+		options[ETHER_HDR_LEN] = 0x4f; // 60 bytes of IPv4 header
+		assert_eq!(reflector.reflect(&mut options), Verdict::Malformed);
+		let mut short = frame(IP_PROTOCOL_UDP, None);
+		short[ETHER_HDR_LEN] = 0x44; // shorter than an IPv4 header
+		assert_eq!(reflector.reflect(&mut short), Verdict::Malformed);
+		let mut ipv6 = frame(IP_PROTOCOL_UDP, None);
+		ipv6[ETHER_HDR_LEN] = 0x65;
+		assert_eq!(reflector.reflect(&mut ipv6), Verdict::Malformed);
+
+		let arp = arp_request(PORT_IP);
+		let mut frame = arp[..arp.len() - 1].to_vec();
+		assert_eq!(reflector.reflect(&mut frame), Verdict::Malformed);
+	}
+
+	#[test]
+	fn parses_the_options() {
+		assert_eq!("port".parse(), Ok(Swap::L4));
+		assert!("all".parse::<Swap>().is_err());
+		assert_eq!(parse_mac("02:00:00:00:00:aa"), Ok(PORT_MAC));
+		assert!(parse_mac("02:00:00:00:00").is_err());
+		assert!(parse_mac("02:00:00:00:00:aa:bb").is_err());
+		assert_eq!(Filter::parse_proto("tcp"), Ok(IP_PROTOCOL_TCP));
+		assert_eq!(Filter::parse_proto("47"), Ok(47));
+		assert!(Filter::parse_proto("gre").is_err());
+		assert_eq!(
+			Filter::parse_prefix("10.0.0.7/24"),
+			Ok((0x0a00_0000, 0xffff_ff00))
+		);
+		assert_eq!(Filter::parse_prefix("0.0.0.0/0"), Ok((0, 0)));
+		assert_eq!(
+			Filter::parse_prefix("10.0.0.7"),
+			Ok((0x0a00_0007, u32::MAX))
+		);
+		assert!(Filter::parse_prefix("10.0.0.0/33").is_err());
+		assert_eq!(Filter::parse_ports("80"), Ok((80, 80)));
+		assert_eq!(Filter::parse_ports("1024-2047"), Ok((1024, 2047)));
+		assert!(Filter::parse_ports("2047-1024").is_err());
+	}
+}
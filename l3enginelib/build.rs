@@ -0,0 +1,21 @@
+//! Applies the DPDK version cfg flags detected by dpdk-sys, e.g. `#[cfg(dpdk_ge_20_11)]`
+//!
+//! Every known release's flag is declared even without dpdk-sys, as with the `soft` feature,
+//! so code gated on them doesn't trip rustc's `unexpected_cfgs` lint.
+
+use std::env;
+
+include!("../dpdk-sys/releases.rs");
+
+fn main() {
+	declare_release_cfgs();
+	// set by the dpdk-sys build script through its `links = "dpdk"` metadata
+	if let Ok(cfgs) = env::var("DEP_DPDK_CFGS") {
+		cfgs.split(',')
+			.filter(|cfg| !cfg.is_empty())
+			.for_each(|cfg| println!("cargo:rustc-cfg={}", cfg));
+	}
+	println!("cargo:rerun-if-env-changed=DEP_DPDK_CFGS");
+	println!("cargo:rerun-if-changed=build.rs");
+	println!("cargo:rerun-if-changed=../dpdk-sys/releases.rs");
+}
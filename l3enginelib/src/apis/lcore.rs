@@ -0,0 +1,279 @@
+//! Launching Rust closures on DPDK worker lcores
+//!
+//! `launch` hands a closure to `rte_eal_remote_launch`. The closure's return value, or its
+//! panic payload, is handed back through the `LcoreJoinHandle` once the lcore is done.
+//! A panic never unwinds into DPDK; the lcore just reports a failure.
+//!
+//! Threads spawned with `std::thread` have no lcore id. `EalThread` registers such a thread
+//! with the EAL for as long as it lives so it gets an lcore id and the per-lcore mempool caches.
+
+use std::{
+	ffi::c_void,
+	fmt,
+	marker::PhantomData,
+	os::raw,
+	panic::{self, AssertUnwindSafe},
+	sync::{Arc, Mutex},
+	thread,
+};
+
+use super::{Error, LcoreError};
+use crate::dpdk_sys;
+
+/// A logical core known to the EAL
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Lcore {
+	id: u32,
+}
+
+impl Lcore {
+	const LCORE_ID_ANY: u32 = u32::MAX;
+
+	/// The lcore the calling thread runs on, if it is an EAL thread
+	#[inline]
+	pub fn current() -> Option<Self> {
+		match unsafe { dpdk_sys::rte_lcore_id() } {
+			Self::LCORE_ID_ANY => None,
+			id => Some(Self { id }),
+		}
+	}
+
+	/// The lcore that ran `eal_init`
+	#[inline]
+	pub fn main() -> Self {
+		// renamed from rte_get_master_lcore in 20.11
+		#[cfg(dpdk_ge_20_11)]
+		let id = unsafe { dpdk_sys::rte_get_main_lcore() };
+		#[cfg(not(dpdk_ge_20_11))]
+		let id = unsafe { dpdk_sys::rte_get_master_lcore() };
+		Self { id }
+	}
+
+	/// Number of lcores enabled in the EAL, including the main one
+	#[inline]
+	pub fn count() -> usize {
+		unsafe { dpdk_sys::rte_lcore_count() as usize }
+	}
+
+	/// Iterates all enabled lcores, main lcore first
+	pub fn all() -> LcoreIter {
+		LcoreIter {
+			head: Some(Self::main()),
+			prev: raw::c_uint::MAX,
+		}
+	}
+
+	/// Iterates the enabled worker lcores, i.e. all but the main lcore
+	pub fn workers() -> LcoreIter {
+		LcoreIter {
+			head: None,
+			prev: raw::c_uint::MAX,
+		}
+	}
+
+	#[inline]
+	pub fn id(&self) -> u32 {
+		self.id
+	}
+
+	/// The NUMA socket the lcore sits on
+	#[inline]
+	pub fn socket_id(&self) -> u32 {
+		unsafe { dpdk_sys::rte_lcore_to_socket_id(self.id) }
+	}
+
+	#[inline]
+	pub fn is_main(&self) -> bool {
+		self.id == Self::main().id
+	}
+}
+
+impl fmt::Debug for Lcore {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Lcore")
+			.field("id", &self.id)
+			.field("socket", &self.socket_id())
+			.finish()
+	}
+}
+
+/// Iterator over enabled lcores, see `Lcore::all` and `Lcore::workers`
+pub struct LcoreIter {
+	head: Option<Lcore>,
+	prev: raw::c_uint,
+}
+
+impl Iterator for LcoreIter {
+	type Item = Lcore;
+
+	fn next(&mut self) -> Option<Lcore> {
+		if let Some(lcore) = self.head.take() {
+			return Some(lcore);
+		}
+		// starting from `-1` makes rte_get_next_lcore return the first worker
+		let id = unsafe { dpdk_sys::rte_get_next_lcore(self.prev, 1, 0) };
+		if id >= dpdk_sys::RTE_MAX_LCORE {
+			return None;
+		}
+		self.prev = id;
+		Some(Lcore { id })
+	}
+}
+
+/// Registration of a non-EAL thread with the EAL
+///
+/// The thread is unregistered, and its lcore id released, when the guard is dropped.
+/// The guard can't leave the thread it was created on.
+pub struct EalThread {
+	lcore: Lcore,
+	_not_send: PhantomData<*const ()>,
+}
+
+impl EalThread {
+	/// Register the calling thread with the EAL.
+	///
+	/// Fails if the thread is already an EAL thread or if all lcore ids are taken.
+	/// A thread that failed to register can keep working but should allocate
+	/// and free mbufs through a `MempoolCache`.
+	pub fn register() -> Result<Self, Error> {
+		if Lcore::current().is_some() {
+			return Err(Error::new(LcoreError::Registered, "EalThread::register"));
+		}
+		if unsafe { dpdk_sys::rte_thread_register() } != 0 {
+			return Err(Error::rte::<LcoreError>("rte_thread_register"));
+		}
+		match Lcore::current() {
+			Some(lcore) => Ok(Self {
+				lcore,
+				_not_send: PhantomData,
+			}),
+			None => {
+				unsafe { dpdk_sys::rte_thread_unregister() };
+				Err(Error::new(LcoreError::NoLcore, "rte_thread_register"))
+			}
+		}
+	}
+
+	/// The lcore id the thread was given
+	#[inline]
+	pub fn lcore(&self) -> Lcore {
+		self.lcore
+	}
+}
+
+impl Drop for EalThread {
+	fn drop(&mut self) {
+		unsafe { dpdk_sys::rte_thread_unregister() };
+	}
+}
+
+type Slot<T> = Arc<Mutex<Option<thread::Result<T>>>>;
+type Job = Box<dyn FnOnce() -> raw::c_int + Send>;
+
+/// Handle to the closure running on a worker lcore
+pub struct LcoreJoinHandle<T> {
+	lcore: Lcore,
+	slot: Slot<T>,
+}
+
+impl<T> LcoreJoinHandle<T> {
+	/// The lcore the closure runs on
+	#[inline]
+	pub fn lcore(&self) -> Lcore {
+		self.lcore
+	}
+
+	/// Wait for the closure to finish and return its result.
+	///
+	/// Returns `Err` with the panic payload if the closure panicked.
+	pub fn join(self) -> thread::Result<T> {
+		unsafe { dpdk_sys::rte_eal_wait_lcore(self.lcore.id) };
+		let res = match self.slot.lock() {
+			Ok(mut slot) => slot.take(),
+			Err(p_err) => p_err.into_inner().take(),
+		};
+		// the lcore is back in WAIT state so the slot has been filled
+		res.expect("lcore finished without a result")
+	}
+}
+
+/// Run `f` on the worker lcore `lcore`.
+///
+/// Must be called from the main lcore and the worker must be idle.
+pub fn launch<F, T>(lcore: Lcore, f: F) -> Result<LcoreJoinHandle<T>, Error>
+where
+	F: FnOnce() -> T + Send + 'static,
+	T: Send + 'static,
+{
+	if lcore.is_main() {
+		return Err(Error::new(LcoreError::MainLcore, "launch"));
+	}
+
+	let slot: Slot<T> = Arc::new(Mutex::new(None));
+	let res = slot.clone();
+	let job: Job = Box::new(move || {
+		let out = panic::catch_unwind(AssertUnwindSafe(f));
+		let ret = if out.is_ok() { 0 } else { -1 };
+		match res.lock() {
+			Ok(mut r) => *r = Some(out),
+			Err(p_err) => *p_err.into_inner() = Some(out),
+		}
+		ret
+	});
+	let arg = Box::into_raw(Box::new(job)) as *mut c_void;
+
+	match unsafe { dpdk_sys::rte_eal_remote_launch(Some(lcore_trampoline), arg, lcore.id) } {
+		0 => Ok(LcoreJoinHandle { lcore, slot }),
+		e => {
+			// the job never ran, reclaim it
+			drop(unsafe { Box::from_raw(arg as *mut Job) });
+			Err(Error::ret::<LcoreError>(
+				e,
+				format!("rte_eal_remote_launch(lcore {})", lcore.id),
+			))
+		}
+	}
+}
+
+/// Run a copy of `f` on every worker lcore, passing it the lcore it runs on
+///
+/// If a worker can't take its copy, waits for the copies already launched to return before
+/// returning the error, so `f` must return on its own.
+pub fn launch_workers<F, T>(f: F) -> Result<Vec<LcoreJoinHandle<T>>, Error>
+where
+	F: Fn(Lcore) -> T + Clone + Send + 'static,
+	T: Send + 'static,
+{
+	let mut handles = Vec::new();
+	for lcore in Lcore::workers() {
+		let f = f.clone();
+		match launch(lcore, move || f(lcore)) {
+			Ok(handle) => handles.push(handle),
+			Err(e) => {
+				join_all(handles);
+				return Err(e);
+			}
+		}
+	}
+	Ok(handles)
+}
+
+/// Wait for the jobs of `handles` to return, logging those that panicked
+pub(crate) fn join_all<T>(handles: Vec<LcoreJoinHandle<T>>) {
+	for handle in handles {
+		let lcore = handle.lcore();
+		if handle.join().is_err() {
+			tracing::error!(lcore = lcore.id, "job panicked");
+		}
+	}
+}
+
+/// Wait for every worker lcore to finish its job
+pub fn wait_workers() {
+	unsafe { dpdk_sys::rte_eal_mp_wait_lcore() };
+}
+
+unsafe extern "C" fn lcore_trampoline(arg: *mut c_void) -> raw::c_int {
+	let job = Box::from_raw(arg as *mut Job);
+	job()
+}
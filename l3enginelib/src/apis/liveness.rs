@@ -0,0 +1,229 @@
+//! Tracks whether the secondary process serving a channel is still alive
+//!
+//! The secondary sends `MpMessage::Heartbeat` periodically. The engine feeds those to a
+//! `PeerMonitor` from its control handler and polls the monitor from the loop that owns
+//! the channel. Every poll loop uses the rings only while the monitor sees a secondary, and
+//! on an event the owner waits for the others to finish the poll they were in, see
+//! `Engine::quiesce`, before it recovers the channel. The recovery after a secondary dies
+//! or restarts thus never races with the engine's own enqueues.
+
+use std::{
+	sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+	thread,
+	time::{Duration, Instant},
+};
+
+use crate::dpdk_sys;
+
+/// What happened to the secondary since the last poll
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerEvent {
+	/// A secondary (re-)attached and is waiting for the channel to be recovered.
+	/// The owner must drain the channel and then call `PeerMonitor::ready`.
+	Attached(u32),
+	/// The secondary detached or stopped sending heartbeats.
+	/// The owner should drain the channel and stop feeding it. A secondary that resumes
+	/// its heartbeats without having detached is reported as `Attached` again.
+	Lost,
+}
+
+pub struct PeerMonitor {
+	requested: AtomicU32, // attach generation asked for by the secondary
+	ready: AtomicU32,     // attach generation the channel has been recovered for
+	last_seen: AtomicU64, // TSC of the last sign of life, 0 when nobody is attached
+	gone: AtomicBool,     // the secondary detached gracefully
+	known: AtomicBool,    // a secondary attached and hasn't detached since
+	timeout: u64,         // TSC cycles without a heartbeat before the peer is lost
+}
+
+impl PeerMonitor {
+	/// How often a secondary should send a heartbeat
+	pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+	/// How long without a heartbeat before the secondary is declared dead
+	pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+	const ATTACH_POLL: Duration = Duration::from_millis(1);
+
+	pub fn new(timeout: Duration) -> Self {
+		let hz = unsafe { dpdk_sys::rte_get_timer_hz() };
+		Self {
+			requested: AtomicU32::new(0),
+			ready: AtomicU32::new(0),
+			last_seen: AtomicU64::new(0),
+			gone: AtomicBool::new(false),
+			known: AtomicBool::new(false),
+			timeout: (timeout.as_secs_f64() * hz as f64) as u64,
+		}
+	}
+
+	#[inline]
+	fn now() -> u64 {
+		// never return the "not attached" sentinel
+		unsafe { dpdk_sys::rte_get_tsc_cycles() }.max(1)
+	}
+
+	/// Record an attach request from a secondary.
+	///
+	/// Called from the control handler. Blocks until the owner of the channel has
+	/// recovered it or `wait` elapses, and returns whether the channel is ready.
+	pub fn attach(&self, wait: Duration) -> bool {
+		let gen = self.requested.fetch_add(1, Ordering::AcqRel).wrapping_add(1);
+		self.gone.store(false, Ordering::Release);
+		self.known.store(true, Ordering::Release);
+		self.last_seen.store(Self::now(), Ordering::Release);
+
+		let start = Instant::now();
+		while self.ready.load(Ordering::Acquire) != gen {
+			if start.elapsed() >= wait {
+				return false;
+			}
+			thread::sleep(Self::ATTACH_POLL);
+		}
+		true
+	}
+
+	/// Record a heartbeat from the secondary
+	///
+	/// A secondary that was declared lost over a gap in its heartbeats but never detached
+	/// is still running on the same channel, so its next heartbeat asks for the channel to
+	/// be recovered again, like an attach nobody waits for. Returns whether it did.
+	pub fn heartbeat(&self) -> bool {
+		let seen = self.last_seen.load(Ordering::Acquire);
+		if seen != 0
+			&& self
+				.last_seen
+				.compare_exchange(seen, Self::now(), Ordering::AcqRel, Ordering::Acquire)
+				.is_ok()
+		{
+			return false;
+		}
+		if !self.known.load(Ordering::Acquire) {
+			return false;
+		}
+		self.requested.fetch_add(1, Ordering::AcqRel);
+		self.last_seen.store(Self::now(), Ordering::Release);
+		true
+	}
+
+	/// Record a graceful detach of the secondary
+	pub fn detach(&self) {
+		self.known.store(false, Ordering::Release);
+		self.gone.store(true, Ordering::Release);
+	}
+
+	/// Whether a secondary is currently attached and the channel is ready for it
+	#[inline]
+	pub fn is_alive(&self) -> bool {
+		self.last_seen.load(Ordering::Acquire) != 0
+			&& self.requested.load(Ordering::Acquire) == self.ready.load(Ordering::Acquire)
+	}
+
+	/// Check for attach requests and dead secondaries.
+	///
+	/// Must be polled from the loop that owns the channel.
+	pub fn poll(&self) -> Option<PeerEvent> {
+		let gen = self.requested.load(Ordering::Acquire);
+		if gen != self.ready.load(Ordering::Acquire) {
+			return Some(PeerEvent::Attached(gen));
+		}
+
+		let seen = self.last_seen.load(Ordering::Acquire);
+		if seen == 0 {
+			return None;
+		}
+		let expired = Self::now().saturating_sub(seen) > self.timeout;
+		if (self.gone.swap(false, Ordering::AcqRel) || expired)
+			&& self
+				.last_seen
+				.compare_exchange(seen, 0, Ordering::AcqRel, Ordering::Acquire)
+				.is_ok()
+		{
+			return Some(PeerEvent::Lost);
+		}
+		None
+	}
+
+	/// Mark the channel as recovered for attach generation `gen`, releasing the waiting secondary
+	pub fn ready(&self, gen: u32) {
+		self.ready.store(gen, Ordering::Release);
+	}
+}
+
+impl Default for PeerMonitor {
+	fn default() -> Self {
+		Self::new(Self::DEFAULT_TIMEOUT)
+	}
+}
+
+#[cfg(all(test, feature = "soft"))]
+mod tests {
+	use super::*;
+
+	const TIMEOUT: Duration = Duration::from_millis(20);
+
+	/// Attach like the control handler does, recovering the channel like the owner does
+	fn attach(monitor: &PeerMonitor) {
+		thread::scope(|s| {
+			let waiter = s.spawn(|| monitor.attach(Duration::from_secs(5)));
+			let gen = loop {
+				match monitor.poll() {
+					Some(PeerEvent::Attached(gen)) => break gen,
+					_ => thread::sleep(Duration::from_millis(1)),
+				}
+			};
+			monitor.ready(gen);
+			assert!(waiter.join().unwrap());
+		});
+	}
+
+	fn lose(monitor: &PeerMonitor) {
+		thread::sleep(TIMEOUT * 2);
+		assert_eq!(monitor.poll(), Some(PeerEvent::Lost));
+		assert!(!monitor.is_alive());
+	}
+
+	#[test]
+	fn heartbeats_keep_the_peer_alive() {
+		let monitor = PeerMonitor::new(TIMEOUT);
+		assert!(!monitor.heartbeat(), "nobody attached yet");
+		assert!(!monitor.is_alive());
+
+		attach(&monitor);
+		assert!(monitor.is_alive());
+		for _ in 0..4 {
+			thread::sleep(TIMEOUT / 2);
+			assert!(!monitor.heartbeat());
+			assert_eq!(monitor.poll(), None);
+		}
+		lose(&monitor);
+		assert_eq!(monitor.poll(), None, "lost once");
+	}
+
+	#[test]
+	fn heartbeat_after_a_gap_recovers_the_channel() {
+		let monitor = PeerMonitor::new(TIMEOUT);
+		attach(&monitor);
+		lose(&monitor);
+
+		assert!(monitor.heartbeat());
+		assert!(!monitor.is_alive(), "not until the channel is recovered");
+		match monitor.poll() {
+			Some(PeerEvent::Attached(gen)) => monitor.ready(gen),
+			e => panic!("expected a re-attach, got {:?}", e),
+		}
+		assert!(monitor.is_alive());
+		assert!(!monitor.heartbeat());
+	}
+
+	#[test]
+	fn heartbeat_after_detach_is_ignored() {
+		let monitor = PeerMonitor::new(TIMEOUT);
+		attach(&monitor);
+		monitor.detach();
+		assert_eq!(monitor.poll(), Some(PeerEvent::Lost));
+
+		assert!(!monitor.heartbeat());
+		assert_eq!(monitor.poll(), None);
+		assert!(!monitor.is_alive());
+	}
+}
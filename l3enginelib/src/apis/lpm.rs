@@ -0,0 +1,243 @@
+//! The Lpm and Lpm6 structs wrap DPDK's longest prefix match tables
+//!
+//! A table maps prefixes to next hops, small numbers the caller gives meaning to, e.g. an
+//! index into its own table of gateways. DPDK's tables reject a prefix length of 0, so the
+//! wrappers keep the default route themselves.
+//!
+//! Adding and deleting routes takes `&mut self` as DPDK doesn't synchronise them with
+//! lookups. Fill a table before sharing it between lcores.
+
+use std::{
+	marker::{Send, Sync},
+	net::{Ipv4Addr, Ipv6Addr},
+	ptr::NonNull,
+};
+
+use super::{Error, LpmError, WrappedCString};
+use crate::dpdk_sys;
+
+/// An IPv4 longest prefix match table
+#[derive(Debug)]
+pub struct Lpm {
+	raw: NonNull<dpdk_sys::rte_lpm>,
+	default: Option<u32>, // the next hop of 0.0.0.0/0
+}
+
+unsafe impl Sync for Lpm {}
+unsafe impl Send for Lpm {}
+
+impl Lpm {
+	/// The largest next hop the table stores
+	pub const MAX_NEXT_HOP: u32 = (1 << 24) - 1;
+	const TBL8S: u32 = 256;
+
+	/// Create a table of at most `max_rules` routes
+	pub fn new(name: &str, max_rules: u32) -> Result<Self, Error> {
+		let n = WrappedCString::to_cstring(name)?;
+		let config = dpdk_sys::rte_lpm_config {
+			max_rules,
+			number_tbl8s: Self::TBL8S,
+			flags: 0,
+		};
+		let raw = unsafe {
+			dpdk_sys::rte_lpm_create(n.as_ptr(), dpdk_sys::rte_socket_id() as i32, &config)
+		};
+		match NonNull::new(raw) {
+			Some(raw) => Ok(Self { raw, default: None }),
+			None => Err(Error::rte::<LpmError>(format!("rte_lpm_create({})", name))),
+		}
+	}
+
+	/// Route `prefix/len` to `next_hop`, replacing the route already there
+	pub fn add(&mut self, prefix: Ipv4Addr, len: u8, next_hop: u32) -> Result<(), Error> {
+		if len as u32 > dpdk_sys::RTE_LPM_MAX_DEPTH || next_hop > Self::MAX_NEXT_HOP {
+			return Err(Error::new(
+				LpmError::Invalid,
+				format!("Lpm::add({}/{}, {})", prefix, len, next_hop),
+			));
+		}
+		if len == 0 {
+			self.default = Some(next_hop);
+			return Ok(());
+		}
+		match unsafe { dpdk_sys::rte_lpm_add(self.raw.as_ptr(), prefix.into(), len, next_hop) } {
+			0 => Ok(()),
+			e => Err(Error::ret::<LpmError>(
+				e,
+				format!("rte_lpm_add({}/{}, {})", prefix, len, next_hop),
+			)),
+		}
+	}
+
+	/// Remove the route to `prefix/len`
+	pub fn delete(&mut self, prefix: Ipv4Addr, len: u8) -> Result<(), Error> {
+		if len == 0 {
+			return match self.default.take() {
+				Some(_) => Ok(()),
+				None => Err(Error::new(LpmError::NoRoute, "Lpm::delete(0.0.0.0/0)")),
+			};
+		}
+		if !self.contains(prefix, len) {
+			return Err(Error::new(
+				LpmError::NoRoute,
+				format!("Lpm::delete({}/{})", prefix, len),
+			));
+		}
+		match unsafe { dpdk_sys::rte_lpm_delete(self.raw.as_ptr(), prefix.into(), len) } {
+			0 => Ok(()),
+			e => Err(Error::ret::<LpmError>(
+				e,
+				format!("rte_lpm_delete({}/{})", prefix, len),
+			)),
+		}
+	}
+
+	/// Whether there is a route to exactly `prefix/len`
+	pub fn contains(&self, prefix: Ipv4Addr, len: u8) -> bool {
+		if len == 0 {
+			return self.default.is_some();
+		}
+		let mut next_hop = 0;
+		unsafe {
+			dpdk_sys::rte_lpm_is_rule_present(self.raw.as_ptr(), prefix.into(), len, &mut next_hop)
+				== 1
+		}
+	}
+
+	/// Remove every route
+	pub fn clear(&mut self) {
+		self.default = None;
+		unsafe { dpdk_sys::rte_lpm_delete_all(self.raw.as_ptr()) };
+	}
+
+	/// The next hop of the longest prefix `addr` matches
+	#[inline]
+	pub fn lookup(&self, addr: Ipv4Addr) -> Option<u32> {
+		let mut next_hop = 0;
+		match unsafe { dpdk_sys::rte_lpm_lookup(self.raw.as_ptr(), addr.into(), &mut next_hop) } {
+			0 => Some(next_hop),
+			_ => self.default,
+		}
+	}
+}
+
+impl Drop for Lpm {
+	fn drop(&mut self) {
+		unsafe { dpdk_sys::rte_lpm_free(self.raw.as_ptr()) };
+	}
+}
+
+/// An IPv6 longest prefix match table
+#[derive(Debug)]
+pub struct Lpm6 {
+	raw: NonNull<dpdk_sys::rte_lpm6>,
+	default: Option<u32>, // the next hop of ::/0
+}
+
+unsafe impl Sync for Lpm6 {}
+unsafe impl Send for Lpm6 {}
+
+impl Lpm6 {
+	/// The largest next hop the table stores
+	pub const MAX_NEXT_HOP: u32 = (1 << 21) - 1;
+	const TBL8S: u32 = 1 << 16;
+
+	/// Create a table of at most `max_rules` routes
+	pub fn new(name: &str, max_rules: u32) -> Result<Self, Error> {
+		let n = WrappedCString::to_cstring(name)?;
+		let config = dpdk_sys::rte_lpm6_config {
+			max_rules,
+			number_tbl8s: Self::TBL8S,
+			flags: 0,
+		};
+		let raw = unsafe {
+			dpdk_sys::rte_lpm6_create(n.as_ptr(), dpdk_sys::rte_socket_id() as i32, &config)
+		};
+		match NonNull::new(raw) {
+			Some(raw) => Ok(Self { raw, default: None }),
+			None => Err(Error::rte::<LpmError>(format!("rte_lpm6_create({})", name))),
+		}
+	}
+
+	/// Route `prefix/len` to `next_hop`, replacing the route already there
+	pub fn add(&mut self, prefix: Ipv6Addr, len: u8, next_hop: u32) -> Result<(), Error> {
+		if len as u32 > dpdk_sys::RTE_LPM6_MAX_DEPTH || next_hop > Self::MAX_NEXT_HOP {
+			return Err(Error::new(
+				LpmError::Invalid,
+				format!("Lpm6::add({}/{}, {})", prefix, len, next_hop),
+			));
+		}
+		if len == 0 {
+			self.default = Some(next_hop);
+			return Ok(());
+		}
+		let ip = prefix.octets();
+		match unsafe { dpdk_sys::rte_lpm6_add(self.raw.as_ptr(), ip.as_ptr(), len, next_hop) } {
+			0 => Ok(()),
+			e => Err(Error::ret::<LpmError>(
+				e,
+				format!("rte_lpm6_add({}/{}, {})", prefix, len, next_hop),
+			)),
+		}
+	}
+
+	/// Remove the route to `prefix/len`
+	pub fn delete(&mut self, prefix: Ipv6Addr, len: u8) -> Result<(), Error> {
+		if len == 0 {
+			return match self.default.take() {
+				Some(_) => Ok(()),
+				None => Err(Error::new(LpmError::NoRoute, "Lpm6::delete(::/0)")),
+			};
+		}
+		if !self.contains(prefix, len) {
+			return Err(Error::new(
+				LpmError::NoRoute,
+				format!("Lpm6::delete({}/{})", prefix, len),
+			));
+		}
+		let ip = prefix.octets();
+		match unsafe { dpdk_sys::rte_lpm6_delete(self.raw.as_ptr(), ip.as_ptr(), len) } {
+			0 => Ok(()),
+			e => Err(Error::ret::<LpmError>(
+				e,
+				format!("rte_lpm6_delete({}/{})", prefix, len),
+			)),
+		}
+	}
+
+	/// Whether there is a route to exactly `prefix/len`
+	pub fn contains(&self, prefix: Ipv6Addr, len: u8) -> bool {
+		if len == 0 {
+			return self.default.is_some();
+		}
+		let ip = prefix.octets();
+		let mut next_hop = 0;
+		unsafe {
+			dpdk_sys::rte_lpm6_is_rule_present(self.raw.as_ptr(), ip.as_ptr(), len, &mut next_hop)
+				== 1
+		}
+	}
+
+	/// Remove every route
+	pub fn clear(&mut self) {
+		self.default = None;
+		unsafe { dpdk_sys::rte_lpm6_delete_all(self.raw.as_ptr()) };
+	}
+
+	/// The next hop of the longest prefix `addr` matches
+	#[inline]
+	pub fn lookup(&self, addr: Ipv6Addr) -> Option<u32> {
+		let ip = addr.octets();
+		let mut next_hop = 0;
+		match unsafe { dpdk_sys::rte_lpm6_lookup(self.raw.as_ptr(), ip.as_ptr(), &mut next_hop) } {
+			0 => Some(next_hop),
+			_ => self.default,
+		}
+	}
+}
+
+impl Drop for Lpm6 {
+	fn drop(&mut self) {
+		unsafe { dpdk_sys::rte_lpm6_free(self.raw.as_ptr()) };
+	}
+}
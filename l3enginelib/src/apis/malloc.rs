@@ -0,0 +1,151 @@
+//! An allocator backed by DPDK's hugepage heap
+//!
+//! Memory from `rte_malloc` is mapped at the same address in the primary and every secondary,
+//! so data structures built with the `RteAllocator` can be handed to a secondary, e.g. through
+//! a `Memzone` holding a pointer to them.
+//!
+//! It can serve as the `#[global_allocator]`. Until `eal_init` succeeds it defers to the system
+//! allocator, and frees memory through whichever allocator it came from. `eal_cleanup` hands
+//! it back to the system allocator before the heap goes away, so whatever it allocated from
+//! the heap must be freed by then.
+//!
+//! With the `allocator_api` feature, on nightly, it also implements `Allocator` so it can be
+//! handed to individual collections, e.g. `Vec::new_in(RteAllocator::local())`.
+
+use std::{
+	alloc::{GlobalAlloc, Layout, System},
+	ffi::c_void,
+	os::raw,
+	ptr,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::dpdk_sys;
+
+/// Set while the EAL is up and the hugepage heap can be used
+pub(crate) static EAL_READY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RteAllocator {
+	socket_id: raw::c_int,
+}
+
+impl RteAllocator {
+	const SOCKET_ID_ANY: raw::c_int = -1;
+
+	/// Allocate from any socket, preferring the caller's
+	pub const fn new() -> Self {
+		Self {
+			socket_id: Self::SOCKET_ID_ANY,
+		}
+	}
+
+	/// Allocate from the heap of `socket_id`
+	pub const fn on_socket(socket_id: raw::c_int) -> Self {
+		Self { socket_id }
+	}
+
+	/// Allocate from the heap of the caller's socket
+	pub fn local() -> Self {
+		Self::on_socket(unsafe { dpdk_sys::rte_socket_id() } as raw::c_int)
+	}
+
+	#[inline]
+	pub fn socket_id(&self) -> raw::c_int {
+		self.socket_id
+	}
+
+	/// Whether `ptr` lives in DPDK memory
+	#[inline]
+	fn is_rte(ptr: *mut u8) -> bool {
+		!unsafe { dpdk_sys::rte_mem_virt2memseg_list(ptr as *const c_void) }.is_null()
+	}
+
+	#[inline]
+	unsafe fn rte_alloc(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+		let f = if zeroed {
+			dpdk_sys::rte_zmalloc_socket
+		} else {
+			dpdk_sys::rte_malloc_socket
+		};
+		f(
+			ptr::null(),
+			layout.size() as _,
+			layout.align() as raw::c_uint,
+			self.socket_id,
+		) as *mut u8
+	}
+}
+
+impl Default for RteAllocator {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+unsafe impl GlobalAlloc for RteAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		if !EAL_READY.load(Ordering::Acquire) {
+			return System.alloc(layout);
+		}
+		self.rte_alloc(layout, false)
+	}
+
+	unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+		if !EAL_READY.load(Ordering::Acquire) {
+			return System.alloc_zeroed(layout);
+		}
+		self.rte_alloc(layout, true)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		if EAL_READY.load(Ordering::Acquire) && Self::is_rte(ptr) {
+			dpdk_sys::rte_free(ptr as *mut c_void);
+		} else {
+			System.dealloc(ptr, layout);
+		}
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		if EAL_READY.load(Ordering::Acquire) && Self::is_rte(ptr) {
+			return dpdk_sys::rte_realloc_socket(
+				ptr as *mut c_void,
+				new_size as _,
+				layout.align() as raw::c_uint,
+				self.socket_id,
+			) as *mut u8;
+		}
+		if !EAL_READY.load(Ordering::Acquire) {
+			return System.realloc(ptr, layout, new_size);
+		}
+
+		// move memory allocated before the EAL came up into the hugepage heap
+		let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+		let new_ptr = self.rte_alloc(new_layout, false);
+		if !new_ptr.is_null() {
+			ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+			System.dealloc(ptr, layout);
+		}
+		new_ptr
+	}
+}
+
+#[cfg(feature = "allocator_api")]
+unsafe impl std::alloc::Allocator for RteAllocator {
+	fn allocate(&self, layout: Layout) -> Result<ptr::NonNull<[u8]>, std::alloc::AllocError> {
+		if layout.size() == 0 {
+			let dangling = unsafe { ptr::NonNull::new_unchecked(layout.align() as *mut u8) };
+			return Ok(ptr::NonNull::slice_from_raw_parts(dangling, 0));
+		}
+		let raw = unsafe { self.rte_alloc(layout, false) };
+		ptr::NonNull::new(raw)
+			.map(|p| ptr::NonNull::slice_from_raw_parts(p, layout.size()))
+			.ok_or(std::alloc::AllocError)
+	}
+
+	unsafe fn deallocate(&self, ptr: ptr::NonNull<u8>, layout: Layout) {
+		if layout.size() != 0 {
+			dpdk_sys::rte_free(ptr.as_ptr() as *mut c_void);
+		}
+	}
+}
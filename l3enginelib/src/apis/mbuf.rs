@@ -12,6 +12,7 @@ use std::{
 };
 
 use super::{BufError, MemoryError, Mempool};
+use crate::dpdk_sys;
 
 /// A trait for returning the size type in bytes
 ///
@@ -65,7 +66,7 @@ unsafe impl Send for Mbuf {}
 impl Mbuf {
 	pub fn new(mp: &Mempool) -> Result<Self, MemoryError> {
 		let mempool = mp.get_ptr();
-		let r = unsafe { dpdk_sys::_rte_pktmbuf_alloc(mempool) };
+		let r = unsafe { dpdk_sys::rte_pktmbuf_alloc(mempool) };
 		match NonNull::new(r) {
 			Some(raw) => Ok(Self { raw }),
 			None => Err(MemoryError::NoBuf),
@@ -89,6 +90,29 @@ impl Mbuf {
 		}
 	}
 
+	/// Copies the packet into a new buffer from the mempool the packet came from
+	///
+	/// The copy shares nothing with the original, so both can be sent and freed on their own.
+	#[inline]
+	pub fn copy(&self) -> Result<Self, MemoryError> {
+		let raw = self.raw();
+		let r = unsafe { dpdk_sys::rte_pktmbuf_copy(self.get_ptr(), raw.pool, 0, u32::MAX) };
+		match NonNull::new(r) {
+			Some(raw) => Ok(Self { raw }),
+			None => Err(MemoryError::NoBuf),
+		}
+	}
+
+	/// A new, empty buffer from the mempool the packet came from
+	#[inline]
+	pub fn alloc_like(&self) -> Result<Self, MemoryError> {
+		let r = unsafe { dpdk_sys::rte_pktmbuf_alloc(self.raw().pool) };
+		match NonNull::new(r) {
+			Some(raw) => Ok(Self { raw }),
+			None => Err(MemoryError::NoBuf),
+		}
+	}
+
 	/// Returns the raw struct needed for FFI calls
 	#[inline]
 	pub fn raw(&self) -> &dpdk_sys::rte_mbuf {
@@ -245,7 +269,7 @@ impl Mbuf {
 		if offset >= self.data_len() {
 			return Err(BufError::BadOffset(offset, self.data_len()));
 		}
-		if offset + T::size_of() * count > self.data_len() - offset {
+		if offset + T::size_of() * count > self.data_len() {
 			return Err(BufError::OutOfBuffer(
 				T::size_of() * count,
 				self.data_len() - offset,
@@ -299,9 +323,9 @@ impl Mbuf {
 		// };
 
 		let mbufs = unsafe {
-			// dpdk_sys::_rte_pktmbuf_alloc_bulk(mempool, ptrs.as_mut_ptr(), len as raw::c_uint);
+			// dpdk_sys::rte_pktmbuf_alloc_bulk(mempool, ptrs.as_mut_ptr(), len as raw::c_uint);
 			let rb =
-				dpdk_sys::_rte_pktmbuf_alloc_bulk(mempool, ptrs.as_mut_ptr(), len as raw::c_uint);
+				dpdk_sys::rte_pktmbuf_alloc_bulk(mempool, ptrs.as_mut_ptr(), len as raw::c_uint);
 			match rb {
 				0 => {
 					ptrs.set_len(len);
@@ -337,7 +361,7 @@ impl Mbuf {
 			} else {
 				unsafe {
 					let len = to_free.len();
-					dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
+					dpdk_sys::rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
 					to_free.set_len(0);
 				}
 
@@ -347,7 +371,7 @@ impl Mbuf {
 
 		unsafe {
 			let len = to_free.len();
-			dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
+			dpdk_sys::rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
 			to_free.set_len(0);
 		}
 	}
@@ -367,6 +391,34 @@ impl fmt::Debug for Mbuf {
 
 impl Drop for Mbuf {
 	fn drop(&mut self) {
-		unsafe { dpdk_sys::_rte_pktmbuf_free(self.raw_mut()) };
+		unsafe { dpdk_sys::rte_pktmbuf_free(self.raw_mut()) };
+	}
+}
+
+#[cfg(all(test, feature = "soft"))]
+mod tests {
+	use super::*;
+	use crate::apis::testing;
+
+	#[test]
+	fn slices_reach_the_end_of_the_data() {
+		let bytes = (0..60u8).collect::<Vec<_>>();
+		let mut mbuf = Mbuf::from_bytes(&bytes, testing::mempool()).unwrap();
+		// past the middle of the data, up to its end
+		let tail = mbuf.read_data_slice::<u8>(40, 20).unwrap();
+		assert_eq!(unsafe { tail.as_ref() }, &bytes[40..]);
+		mbuf.write_data_slice(50, &[0xff_u8; 10]).unwrap();
+		let last = mbuf.read_data_slice::<u8>(59, 1).unwrap();
+		assert_eq!(unsafe { last.as_ref() }, [0xff]);
+
+		assert!(matches!(
+			mbuf.read_data_slice::<u8>(40, 21),
+			Err(BufError::OutOfBuffer(21, 20))
+		));
+		assert!(matches!(
+			mbuf.read_data_slice::<u8>(60, 1),
+			Err(BufError::BadOffset(60, 60))
+		));
+		assert!(mbuf.write_data_slice(55, &[0_u8; 6]).is_err());
 	}
 }
@@ -1,6 +1,9 @@
 //! The Mempool struct contains a pointer to a DPDK mempool that is guaranteed to be non null
+//!
+//! The MempoolCache is a mempool cache owned by a thread without an lcore id
 
-use super::{MemoryError, WrappedCString};
+use super::{Error, Mbuf, MemoryError, WrappedCString};
+use crate::dpdk_sys;
 use std::{
 	ffi, fmt, mem,
 	ptr::{self, NonNull},
@@ -11,23 +14,38 @@ pub struct Mempool {
 }
 
 impl Mempool {
-	const RX_MBUF_DATA_SIZE: u32 = 2048;
-	const RTE_PKTMBUF_HEADROOM: u32 = 128;
+	pub const RX_MBUF_DATA_SIZE: u32 = 2048;
+	pub const RTE_PKTMBUF_HEADROOM: u32 = 128;
 	const MBUF_OVERHEAD: u32 =
 		mem::size_of::<dpdk_sys::rte_mbuf>() as u32 + Self::RTE_PKTMBUF_HEADROOM;
-	const NUM_MBUFS: u32 = 32767; // 2^15 - 1
-	const MBUF_SIZE: u32 = Self::RX_MBUF_DATA_SIZE + Self::MBUF_OVERHEAD;
-	const MBUF_CACHE_SIZE: u32 = 512;
+	pub const NUM_MBUFS: u32 = 32767; // 2^15 - 1
+	pub const MBUF_CACHE_SIZE: u32 = 512;
 	const NO_FLAGS: u32 = 0;
 
-	pub fn new(name: &str) -> Result<Self, MemoryError> {
+	pub fn new(name: &str) -> Result<Self, Error> {
+		Self::with_size(
+			name,
+			Self::NUM_MBUFS,
+			Self::MBUF_CACHE_SIZE,
+			Self::RX_MBUF_DATA_SIZE,
+		)
+	}
+
+	/// Create a mempool of `num_mbufs` mbufs with `data_size` bytes of room after the headroom
+	/// and a per-lcore cache of `cache_size` mbufs
+	pub fn with_size(
+		name: &str,
+		num_mbufs: u32,
+		cache_size: u32,
+		data_size: u32,
+	) -> Result<Self, Error> {
 		let n = WrappedCString::to_cstring(name)?;
 		let raw = unsafe {
 			dpdk_sys::rte_mempool_create(
 				n.as_ptr(),
-				Self::NUM_MBUFS,
-				Self::MBUF_SIZE,
-				Self::MBUF_CACHE_SIZE,
+				num_mbufs,
+				data_size + Self::MBUF_OVERHEAD,
+				cache_size,
 				mem::size_of::<dpdk_sys::rte_pktmbuf_pool_private>() as u32,
 				Some(dpdk_sys::rte_pktmbuf_pool_init),
 				ptr::null::<ffi::c_void>() as *mut _,
@@ -44,8 +62,9 @@ impl Mempool {
 				Ok(Self { raw: mem })
 			}
 			None => {
-				log::error!("mempool invalid");
-				Err(MemoryError::new())
+				let e = Error::rte::<MemoryError>(format!("rte_mempool_create({})", name));
+				log::error!("mempool invalid: {}", e);
+				Err(e)
 			}
 		}
 	}
@@ -80,12 +99,12 @@ impl Mempool {
 		}
 	}
 
-	pub fn lookup(name: &str) -> Result<Self, MemoryError> {
+	pub fn lookup(name: &str) -> Result<Self, Error> {
 		let nm = WrappedCString::to_cstring(name)?;
 		let r = unsafe { dpdk_sys::rte_mempool_lookup(nm.as_ptr()) };
 		match NonNull::new(r) {
 			Some(raw) => Ok(Self { raw }),
-			None => Err(MemoryError::NoEntries),
+			None => Err(Error::rte::<MemoryError>(format!("rte_mempool_lookup({})", name))),
 		}
 	}
 }
@@ -112,3 +131,92 @@ impl Drop for Mempool {
 		}
 	}
 }
+
+/// A mempool cache for threads that are not registered with the EAL
+///
+/// DPDK only gives per-lcore caches to threads with an lcore id. Other threads
+/// hit the mempool's ring for every mbuf. A thread that can't register with
+/// `EalThread` should own one of these and allocate and free through it.
+///
+/// The cache belongs to a single thread at a time and is flushed back to the
+/// mempool when dropped.
+pub struct MempoolCache {
+	raw: NonNull<dpdk_sys::rte_mempool_cache>,
+	mempool: NonNull<dpdk_sys::rte_mempool>,
+}
+
+impl MempoolCache {
+	const DEFAULT_SIZE: u32 = 256;
+
+	/// Create a cache of the default size for `mp`
+	pub fn new(mp: &Mempool) -> Result<Self, Error> {
+		Self::with_size(mp, Self::DEFAULT_SIZE)
+	}
+
+	/// Create a cache holding up to `size` objects for `mp`
+	pub fn with_size(mp: &Mempool, size: u32) -> Result<Self, Error> {
+		Self::create(mp.raw, size)
+	}
+
+	/// Create a cache of the default size for the mempool `mbuf` was allocated from
+	///
+	/// For threads that only see mbufs handed to them and not the mempools behind them.
+	pub fn for_mbuf(mbuf: &Mbuf) -> Result<Self, Error> {
+		match NonNull::new(mbuf.raw().pool) {
+			Some(mempool) => Self::create(mempool, Self::DEFAULT_SIZE),
+			None => Err(Error::new(MemoryError::Invalid, "MempoolCache::for_mbuf")),
+		}
+	}
+
+	fn create(mempool: NonNull<dpdk_sys::rte_mempool>, size: u32) -> Result<Self, Error> {
+		let socket_id = unsafe { dpdk_sys::rte_socket_id() } as i32;
+		let raw = unsafe { dpdk_sys::rte_mempool_cache_create(size, socket_id) };
+		match NonNull::new(raw) {
+			Some(raw) => Ok(Self { raw, mempool }),
+			None => Err(Error::rte::<MemoryError>("rte_mempool_cache_create")),
+		}
+	}
+
+	/// Allocate an mbuf from the mempool through the cache
+	#[inline]
+	pub fn alloc(&mut self) -> Result<Mbuf, MemoryError> {
+		let r = unsafe { dpdk_sys::_pkt_mbuf_alloc_cache(self.mempool.as_ptr(), self.get_ptr()) };
+		match NonNull::new(r) {
+			Some(raw) => Ok(Mbuf { raw }),
+			None => Err(MemoryError::NoBuf),
+		}
+	}
+
+	/// Free an mbuf back to its mempool through the cache
+	///
+	/// An mbuf from another mempool bypasses the cache, the cache only ever holds objects
+	/// of its own mempool. So must the other segments of a chain.
+	#[inline]
+	pub fn free(&mut self, mbuf: Mbuf) {
+		if mbuf.raw().pool == self.mempool.as_ptr() {
+			unsafe { dpdk_sys::_pkt_mbuf_free_cache(mbuf.into_ptr(), self.get_ptr()) };
+		}
+		// anything else drops back to its own mempool
+	}
+
+	/// Return all cached objects to the mempool
+	#[inline]
+	pub fn flush(&mut self) {
+		unsafe { dpdk_sys::rte_mempool_cache_flush(self.get_ptr(), self.mempool.as_ptr()) };
+	}
+
+	/// Return mutable reference to the C struct for FFI calls
+	#[inline]
+	pub fn get_ptr(&self) -> *mut dpdk_sys::rte_mempool_cache {
+		self.raw.as_ptr()
+	}
+}
+
+unsafe impl Send for MempoolCache {}
+
+impl Drop for MempoolCache {
+	fn drop(&mut self) {
+		self.flush();
+		unsafe { dpdk_sys::rte_mempool_cache_free(self.get_ptr()) };
+	}
+}
@@ -9,17 +9,21 @@ use anyhow::Result;
 // use chashmap::CHashMap;
 use std::{
 	marker::{Send, Sync},
+	mem,
 	os::raw,
 	ptr,
 	ptr::NonNull,
 };
 
-use super::{Mbuf, MemoryError, WrappedCString};
+use super::{Error, Mbuf, MemoryError, WrappedCString};
+use crate::dpdk_sys;
 
 /// The RingType is whether message is being sent from engine to container or from contianer to engine
 pub enum RingType {
 	P2E, // Packetiser to Engine
 	E2P, // Engine to Packetiser
+	/// Any other ring, e.g. the queues of a port from `Port::from_rings`
+	Named(String),
 }
 
 /// A ring is intended to communicate between two DPDK processes by sending/receiving `Mbuf`.
@@ -33,9 +37,8 @@ pub struct Ring {
 }
 
 impl Ring {
-	const RING_F_SP_ENQ: u8 = 0x0001;
-	const RING_F_SC_DEQ: u8 = 0x0002;
-	const RING_FLAGS: u8 = Self::RING_F_SP_ENQ | Self::RING_F_SC_DEQ;
+	// multi-producer and multi-consumer, every engine lcore uses the channel
+	const RING_FLAGS: u8 = 0;
 	const RING_CAPACITY: usize = 512;
 
 	/// Return a Ring created from a pointer if the pointer is not null
@@ -43,25 +46,26 @@ impl Ring {
 		// client_id: u16,
 		rtype: RingType,
 		r: *mut dpdk_sys::rte_ring,
-	) -> Result<Self, MemoryError> {
+	) -> Result<Self, Error> {
 		if let Some(raw) = NonNull::new(r) {
 			Ok(Self {
 				rtype,
 				raw,
 			})
 		} else {
-			Err(MemoryError::NoBuf)
+			Err(Error::new(MemoryError::NoBuf, "Ring::from_ptr"))
 		}
 	}
 
 	pub fn new(
 		rtype: RingType,
 		socket_id: raw::c_int,
-	) -> Result<Self, MemoryError> {
+	) -> Result<Self, Error> {
 		let r;
 		match &rtype {
 			RingType::P2E => r = "C2E",
 			RingType::E2P => r = "E2C",
+			RingType::Named(name) => r = name.as_str(),
 		};
 		let nm = WrappedCString::to_cstring(format!("{}", r))?;
 		match NonNull::new(unsafe {
@@ -76,115 +80,130 @@ impl Ring {
 				rtype,
 				raw,
 			}),
-			None => Err(MemoryError::new()),
+			None => Err(Error::rte::<MemoryError>(format!("rte_ring_create({})", r))),
 		}
 	}
 
 	/// Get the name to lookup with
 	#[inline]
 	pub fn name(&self) -> &str {
-		match self.rtype {
+		match &self.rtype {
 			RingType::P2E => "C2E",
 			RingType::E2P => "E2C",
+			RingType::Named(name) => name,
 		}
 	}
 
 	/// Lookup a Ring
-	pub fn lookup(rtype: RingType) -> Result<Self, MemoryError> {
+	pub fn lookup(rtype: RingType) -> Result<Self, Error> {
 		let r;
 		match &rtype {
 			RingType::P2E => r = "C2E",
 			RingType::E2P => r = "E2C",
+			RingType::Named(name) => r = name.as_str(),
 		};
 		let nm = WrappedCString::to_cstring(format!("{}", r))?;
 		let raw = unsafe { dpdk_sys::rte_ring_lookup(nm.as_ptr()) };
 
 		if raw.is_null() {
-			return Err(MemoryError::NoEntries);
+			return Err(Error::rte::<MemoryError>(format!("rte_ring_lookup({})", r)));
 		}
-		#[cfg(feature = "debug")]
-		println!("ring raw pointer: {:p}", raw);
+		tracing::debug!(ring = r, ptr = ?raw, "found ring");
 		Self::from_ptr(rtype, raw)
 	}
 
 	/// Enqueue a single packet onto the ring
-	pub fn enqueue(&self, pkt: Mbuf) -> Result<(), MemoryError> {
+	pub fn enqueue(&self, pkt: Mbuf) -> Result<(), Error> {
 		match unsafe {
-			dpdk_sys::_rte_ring_enqueue(self.get_ptr(), pkt.into_ptr() as *mut raw::c_void)
+			dpdk_sys::rte_ring_enqueue(self.get_ptr(), pkt.into_ptr() as *mut raw::c_void)
 		} {
 			0 => {
-				#[cfg(feature = "debug")]
-				println!("enqueued packets");
+				tracing::trace!(ring = self.name(), "enqueued packet");
 				Ok(())
 			}
-			_ => Err(MemoryError::new()),
+			e => Err(Error::ret::<MemoryError>(e, "rte_ring_enqueue")),
 		}
 	}
 
 	/// Dequeue a single packet from the ring
-	pub fn dequeue(&self, pkt: &mut Mbuf) -> Result<(), MemoryError> {
+	pub fn dequeue(&self, pkt: &mut Mbuf) -> Result<(), Error> {
 		match unsafe {
-			dpdk_sys::_rte_ring_dequeue(
+			dpdk_sys::rte_ring_dequeue(
 				self.get_ptr(),
 				&mut (pkt.get_ptr() as *mut _ as *mut raw::c_void),
 			)
 		} {
 			0 => Ok(()),
-			_ => Err(MemoryError::new()),
+			e => Err(Error::ret::<MemoryError>(e, "rte_ring_dequeue")),
 		}
 	}
 
-	/// Enqueue a single packet onto the ring
+	/// Enqueue all of `pkts` onto the ring, or none of them
+	///
+	/// Returns how many were enqueued. What didn't fit is left in `pkts`.
 	pub fn enqueue_bulk(&self, pkts: &mut Vec<Mbuf>) -> usize {
-		#[cfg(feature = "debug")]
-		println!("starting channel enqueue");
-		let mut ptrs = Vec::with_capacity(pkts.len());
-		for pkt in pkts.drain(..) {
-			ptrs.push(pkt.into_ptr());
-		}
-		unsafe {
-			dpdk_sys::_rte_ring_enqueue_bulk(
+		let ptrs = pkts.iter().map(Mbuf::get_ptr).collect::<Vec<_>>();
+		let cnt = unsafe {
+			dpdk_sys::rte_ring_enqueue_bulk(
 				self.get_ptr(),
 				ptrs.as_ptr() as *mut *mut raw::c_void,
-				pkts.len() as u32,
+				ptrs.len() as u32,
 				ptr::null::<u32>() as *mut u32,
 			) as usize
-		}
+		};
+		// the ring owns them now
+		pkts.drain(..cnt).for_each(mem::forget);
+		tracing::trace!(ring = self.name(), offered = ptrs.len(), enqueued = cnt, "enqueue bulk");
+		cnt
 	}
 
-	/// Dequeue a single packet from the ring
+	/// Dequeue up to `rx_burst_max` packets from the ring and append them to `pkts`
 	pub fn dequeue_burst(&self, pkts: &mut Vec<Mbuf>, rx_burst_max: usize) -> usize {
-		// get the raw pointers to the mbufs
-		#[cfg(feature = "debug")]
-		println!("dequeue_bulk");
-		let mut ptrs = Vec::with_capacity(rx_burst_max);
-		for pkt in pkts {
-			ptrs.push(pkt.get_ptr());
-		}
-		#[cfg(feature = "debug")]
-		{
+		let mut ptrs = vec![ptr::null_mut::<raw::c_void>(); rx_burst_max];
+		let cnt = unsafe {
+			dpdk_sys::rte_ring_dequeue_burst(
+				self.get_ptr(),
+				ptrs.as_mut_ptr(),
+				ptrs.len() as u32,
+				ptr::null::<u32>() as *mut u32,
+			) as usize
+		};
+		pkts.extend(
+			ptrs[..cnt]
+				.iter()
+				.map(|p| unsafe { Mbuf::from_ptr(*p as *mut dpdk_sys::rte_mbuf) }),
+		);
+		tracing::trace!(ring = self.name(), dequeued = cnt, "dequeue burst");
+		cnt
+	}
+
+	/// Dequeue every packet left on the ring and return them to their mempool
+	///
+	/// Only safe while no other consumer is dequeuing from the ring
+	pub fn drain(&self) -> usize {
+		let mut ptrs = vec![ptr::null_mut::<raw::c_void>(); Self::RING_CAPACITY];
+		let mut total = 0;
+		loop {
 			let cnt = unsafe {
-				// pass the raw pointers
-				dpdk_sys::_rte_ring_dequeue_burst(
+				dpdk_sys::rte_ring_dequeue_burst(
 					self.get_ptr(),
-					ptrs.as_ptr() as *mut *mut raw::c_void,
+					ptrs.as_mut_ptr(),
 					ptrs.len() as u32,
 					ptr::null::<u32>() as *mut u32,
 				) as usize
 			};
-			println!("Dequeued {} pkts", cnt);
-			return cnt;
-		}
-		#[cfg(not(feature = "debug"))]
-		unsafe {
-			// pass the raw pointers
-			dpdk_sys::_rte_ring_dequeue_burst(
-				self.get_ptr(),
-				ptrs.as_ptr() as *mut *mut raw::c_void,
-				ptrs.len() as u32,
-				ptr::null::<u32>() as *mut u32,
-			) as usize
+			if cnt == 0 {
+				break;
+			}
+			Mbuf::mbuf_free_bulk(
+				ptrs[..cnt]
+					.iter()
+					.map(|p| *p as *mut dpdk_sys::rte_mbuf)
+					.collect(),
+			);
+			total += cnt;
 		}
+		total
 	}
 
 	/// Return mutable reference to the C struct for FFI calls
@@ -227,7 +246,7 @@ unsafe impl Send for Channel {}
 unsafe impl Sync for Channel {}
 
 impl Channel {
-	pub fn new() -> Result<Self, MemoryError> {
+	pub fn new() -> Result<Self, Error> {
 		let socket_id = unsafe { dpdk_sys::rte_socket_id() };
 
 		let engine_to_client = Ring::new(RingType::E2P, socket_id as i32)?;
@@ -240,43 +259,44 @@ impl Channel {
 	}
 
 	/// Lookup both C2E and E2C rings for this channel
-	pub fn lookup() -> Result<Self, MemoryError> {
+	pub fn lookup() -> Result<Self, Error> {
 		let to_packetiser = Ring::lookup(RingType::E2P)?;
 		let to_engine = Ring::lookup(RingType::P2E)?;
-		#[cfg(feature = "debug")]
-		{
-			println!(
-				"channel: lookup : to_engine found {:p}",
-				to_engine.get_ptr()
-			);
-			println!(
-				"channel: lookup : to_packetiser found {:p}",
-				to_packetiser.get_ptr()
-			);
-		}
+		tracing::debug!(
+			to_engine = ?to_engine.get_ptr(),
+			to_packetiser = ?to_packetiser.get_ptr(),
+			"found channel"
+		);
 		Ok(Self {
 			to_engine: to_engine,
 			to_packetiser: to_packetiser,
 		})
 	}
 
+	/// Reclaim the mbufs left in both rings, e.g. after the packetiser died
+	///
+	/// Must not run while the packetiser is dequeuing from the channel
+	pub fn reset(&self) -> usize {
+		self.to_packetiser.drain() + self.to_engine.drain()
+	}
+
 	/// Send a packet from engine to packetiser
-	pub fn send_to_engine(&self, pkt: Mbuf) -> Result<(), MemoryError> {
+	pub fn send_to_engine(&self, pkt: Mbuf) -> Result<(), Error> {
 		self.to_engine.enqueue(pkt)
 	}
 
 	/// Send a packet from engine to packetiser
-	pub fn receive_from_engine(&self, pkt: &mut Mbuf) -> Result<(), MemoryError> {
+	pub fn receive_from_engine(&self, pkt: &mut Mbuf) -> Result<(), Error> {
 		self.to_packetiser.dequeue(pkt)
 	}
 
 	/// Send a packet from engine to packetiser
-	pub fn send_to_packetiser(&self, pkt: Mbuf) -> Result<(), MemoryError> {
+	pub fn send_to_packetiser(&self, pkt: Mbuf) -> Result<(), Error> {
 		self.to_packetiser.enqueue(pkt)
 	}
 
 	/// Send a packet from engine to packetiser
-	pub fn receive_from_packetiser(&self, pkt: &mut Mbuf) -> Result<(), MemoryError> {
+	pub fn receive_from_packetiser(&self, pkt: &mut Mbuf) -> Result<(), Error> {
 		self.to_engine.dequeue(pkt)
 	}
 
@@ -297,8 +317,41 @@ impl Channel {
 
 	/// Receive bulk from engine
 	pub fn recv_from_engine_burst(&self, pkts: &mut Vec<Mbuf>, rx_burst_max: usize) -> usize {
-		#[cfg(feature = "debug")]
-		println!("recv_from_engine_bulk");
 		self.to_packetiser.dequeue_burst(pkts, rx_burst_max)
 	}
 }
+
+#[cfg(all(test, feature = "soft"))]
+mod tests {
+	use super::*;
+	use crate::apis::{testing, Mempool};
+
+	const POOL_SIZE: u32 = 1023;
+
+	fn avail(mp: &Mempool) -> u32 {
+		unsafe { dpdk_sys::rte_mempool_avail_count(mp.get_ptr()) }
+	}
+
+	#[test]
+	fn enqueue_bulk_is_all_or_nothing() {
+		testing::mempool();
+		let mp = Mempool::with_size("RING_TEST_POOL", POOL_SIZE, 0, 256).unwrap();
+		let ring = Ring::new(RingType::Named(String::from("RING_TEST_BULK")), 0).unwrap();
+
+		// more than the ring holds, nothing is enqueued and nothing leaks
+		let mut pkts = Mbuf::alloc_bulk(Ring::RING_CAPACITY + 1, &mp).unwrap();
+		assert_eq!(ring.enqueue_bulk(&mut pkts), 0);
+		assert_eq!(pkts.len(), Ring::RING_CAPACITY + 1, "the caller keeps them");
+		drop(pkts);
+		assert_eq!(avail(&mp), POOL_SIZE);
+
+		let mut pkts = Mbuf::alloc_bulk(100, &mp).unwrap();
+		assert_eq!(ring.enqueue_bulk(&mut pkts), 100);
+		assert!(pkts.is_empty(), "the ring owns them");
+		let mut out = Vec::new();
+		assert_eq!(ring.dequeue_burst(&mut out, 64), 64);
+		drop(out);
+		assert_eq!(ring.drain(), 36);
+		assert_eq!(avail(&mp), POOL_SIZE);
+	}
+}
@@ -0,0 +1,213 @@
+//! The Memzone struct places a typed value in a DPDK memzone so it can be shared between processes
+//!
+//! The primary reserves the zone and initializes the value. Secondaries look it up by name
+//! and get a handle to the same value. Hugepages are mapped at the same address in every
+//! process but the heap, stack and binary of each process are not, so only types without
+//! process-local pointers may live in a memzone. The `Shareable` trait marks those types.
+
+use std::{
+	fmt,
+	marker::PhantomData,
+	mem,
+	ops::Deref,
+	ptr::{self, NonNull},
+	sync::atomic::{
+		AtomicBool, AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicU16, AtomicU32, AtomicU64,
+		AtomicU8, AtomicUsize,
+	},
+};
+
+use super::{Error, MemoryError, WrappedCString};
+use crate::dpdk_sys;
+
+/// Types that can be placed in memory shared between processes
+///
+/// # Safety
+///
+/// The type must be `#[repr(C)]` (or a primitive) and must not contain pointers, references,
+/// or anything that owns heap memory such as `Box`, `Vec` or `String`. Those would point into
+/// the address space of the process that wrote them. All fields must be `Shareable` as well.
+///
+/// Values are accessed concurrently from every process that looks the zone up, so shared
+/// mutable state should use atomics.
+pub unsafe trait Shareable: Sync {}
+
+macro_rules! impl_shareable {
+	($($t:ty),*) => {
+		$(unsafe impl Shareable for $t {})*
+	};
+}
+
+impl_shareable!(
+	(),
+	bool,
+	u8,
+	u16,
+	u32,
+	u64,
+	u128,
+	usize,
+	i8,
+	i16,
+	i32,
+	i64,
+	i128,
+	isize,
+	f32,
+	f64,
+	AtomicBool,
+	AtomicU8,
+	AtomicU16,
+	AtomicU32,
+	AtomicU64,
+	AtomicUsize,
+	AtomicI8,
+	AtomicI16,
+	AtomicI32,
+	AtomicI64
+);
+
+unsafe impl<T: Shareable, const N: usize> Shareable for [T; N] {}
+
+pub struct Memzone<T: Shareable> {
+	raw: NonNull<dpdk_sys::rte_memzone>,
+	owner: bool,
+	_marker: PhantomData<T>,
+}
+
+impl<T: Shareable> Memzone<T> {
+	const NO_FLAGS: u32 = 0;
+
+	/// Reserve a memzone named `name` on the caller's socket and move `val` into it
+	///
+	/// Must be called from the primary process. The zone is freed when the returned
+	/// handle is dropped.
+	pub fn reserve(name: &str, val: T) -> Result<Self, Error> {
+		let socket_id = unsafe { dpdk_sys::rte_socket_id() } as i32;
+		Self::reserve_on_socket(name, val, socket_id)
+	}
+
+	/// Reserve a memzone named `name` on `socket_id` and move `val` into it
+	pub fn reserve_on_socket(name: &str, val: T, socket_id: i32) -> Result<Self, Error> {
+		let nm = WrappedCString::to_cstring(name)?;
+		let mz = unsafe {
+			dpdk_sys::rte_memzone_reserve_aligned(
+				nm.as_ptr(),
+				mem::size_of::<T>() as _,
+				socket_id,
+				Self::NO_FLAGS,
+				mem::align_of::<T>().max(dpdk_sys::RTE_CACHE_LINE_SIZE as usize) as u32,
+			)
+		};
+		let raw = NonNull::new(mz as *mut dpdk_sys::rte_memzone).ok_or_else(|| {
+			Error::rte::<MemoryError>(format!("rte_memzone_reserve_aligned({})", name))
+		})?;
+		let zone = Self {
+			raw,
+			owner: true,
+			_marker: PhantomData,
+		};
+		unsafe { ptr::write(zone.as_ptr(), val) };
+		log::info!("reserved memzone: {}", name);
+		Ok(zone)
+	}
+
+	/// Lookup a memzone reserved by another process
+	///
+	/// The handle does not free the zone when dropped.
+	pub fn lookup(name: &str) -> Result<Self, Error> {
+		let nm = WrappedCString::to_cstring(name)?;
+		let mz = unsafe { dpdk_sys::rte_memzone_lookup(nm.as_ptr()) };
+		let raw = match NonNull::new(mz as *mut dpdk_sys::rte_memzone) {
+			Some(raw) => raw,
+			None => {
+				return Err(Error::rte::<MemoryError>(format!(
+					"rte_memzone_lookup({})",
+					name
+				)))
+			}
+		};
+		let zone = Self {
+			raw,
+			owner: false,
+			_marker: PhantomData,
+		};
+		let len = zone.raw().len as usize;
+		if len < mem::size_of::<T>() || zone.as_ptr() as usize & (mem::align_of::<T>() - 1) != 0 {
+			return Err(Error::new(
+				MemoryError::Invalid,
+				format!("memzone {} does not fit the type", name),
+			));
+		}
+		Ok(zone)
+	}
+
+	/// Returns the raw struct needed for FFI calls
+	#[inline]
+	pub fn raw(&self) -> &dpdk_sys::rte_memzone {
+		unsafe { self.raw.as_ref() }
+	}
+
+	/// Returns a pointer to the shared value
+	#[inline]
+	pub fn as_ptr(&self) -> *mut T {
+		// the memzone struct is packed, read the address by value
+		unsafe { self.raw().__bindgen_anon_2.addr as *mut T }
+	}
+
+	/// Returns a mutable reference to the shared value
+	///
+	/// # Safety
+	///
+	/// No other process or thread may access the value while the reference is alive.
+	#[inline]
+	pub unsafe fn get_mut(&mut self) -> &mut T {
+		&mut *self.as_ptr()
+	}
+
+	/// Returns the name of the memzone
+	#[inline]
+	pub fn name(&self) -> String {
+		let name = &self.raw().name;
+		let bytes = name
+			.iter()
+			.take_while(|c| **c != 0)
+			.map(|c| *c as u8)
+			.collect::<Vec<_>>();
+		String::from_utf8_lossy(&bytes).into_owned()
+	}
+}
+
+impl<T: Shareable> Deref for Memzone<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		unsafe { &*self.as_ptr() }
+	}
+}
+
+unsafe impl<T: Shareable> Sync for Memzone<T> {}
+unsafe impl<T: Shareable> Send for Memzone<T> {}
+
+impl<T: Shareable + fmt::Debug> fmt::Debug for Memzone<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let raw = self.raw();
+		f.debug_struct(&self.name())
+			.field("len", &{ raw.len })
+			.field("socket", &{ raw.socket_id })
+			.field("value", &**self)
+			.finish()
+	}
+}
+
+impl<T: Shareable> Drop for Memzone<T> {
+	fn drop(&mut self) {
+		if !self.owner {
+			return;
+		}
+		unsafe {
+			ptr::drop_in_place(self.as_ptr());
+			dpdk_sys::rte_memzone_free(self.raw.as_ptr());
+		}
+	}
+}
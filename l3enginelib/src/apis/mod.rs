@@ -1,28 +1,62 @@
 //! This module defines structures that are required for DPDK based memory operations
+//! including typed state shared between processes through memzones
+//! and an allocator over the hugepage heap
 //! 
-//! Errors related to DPDK operations
+//! Errors related to DPDK operations, carrying the errno and DPDK's description of it
 //! 
-//! DPDK EAL startup and cleanup ops
+//! DPDK EAL startup and cleanup ops and DPDK's logging
+//!
+//! Control messages between the primary and secondary processes
+//! and liveness tracking of the secondary
+//!
+//! Launching closures on worker lcores and registering non-EAL threads
+//!
+//! Packet headers, ARP replies and the other helpers for parsing packets
+//!
+//! Longest prefix match tables for IPv4 and IPv6 routes
 
+mod lcore;
+mod liveness;
+mod lpm;
+mod malloc;
 mod mbuf;
 mod mempool;
 mod memring;
+mod memzone;
+mod mp;
+mod packet;
 mod port;
+mod rtelog;
+#[cfg(all(test, feature = "soft"))]
+pub(crate) mod testing;
 
+pub use lcore::*;
+pub use liveness::*;
+pub use lpm::*;
+pub use malloc::*;
 pub use mbuf::*;
 pub use mempool::*;
 pub use memring::*;
+pub use memzone::*;
+pub use mp::*;
+pub use packet::*;
 pub use port::*;
+pub use rtelog::*;
 
-use dpdk_sys;
+use crate::dpdk_sys;
+use malloc::EAL_READY;
 use libc::{
-	EAGAIN, EALREADY, EEXIST, EFAULT, EINVAL, ENOBUFS, ENODEV, ENOENT, ENOEXEC, ENOMEM, ENOSPC,
-	ENOTSUP, EPROTO,
+	E2BIG, EAGAIN, EALREADY, EBUSY, EEXIST, EFAULT, EINVAL, ENOBUFS, ENODEV, ENOENT, ENOEXEC,
+	ENOMEM, ENOSPC, ENOTSUP, EPROTO, ETIMEDOUT,
 };
 use log;
 use std::{
-	ffi::{CString, NulError},
+	borrow::Cow,
+	error,
+	ffi::{CStr, CString, NulError},
+	fmt,
 	os::raw,
+	sync::atomic::Ordering,
 };
 use thiserror::Error;
 
@@ -44,11 +78,131 @@ impl WrappedCString for String {
 	}
 }
 
-// NOTE: An absolutely horrible thing to to do
-// converting a string conversion error to a memory error
-impl From<NulError> for MemoryError {
-	fn from(_: NulError) -> Self {
-		MemoryError::BadVal
+/// What kind of operation failed, the subsystem errors below
+#[derive(Error, Debug)]
+pub enum ErrorKind {
+	#[error(transparent)]
+	Memory(#[from] MemoryError),
+	#[error(transparent)]
+	Port(#[from] PortError),
+	#[error(transparent)]
+	Eal(#[from] EALErrors),
+	#[error(transparent)]
+	Mp(#[from] MpError),
+	#[error(transparent)]
+	Lcore(#[from] LcoreError),
+	#[error(transparent)]
+	Log(#[from] LogError),
+	#[error(transparent)]
+	Buf(#[from] BufError),
+	#[error(transparent)]
+	Lpm(#[from] LpmError),
+	#[error("name is not a valid C string")]
+	BadName(#[from] NulError),
+}
+
+/// Maps an errno to the kind of error a subsystem reports for it
+pub(crate) trait FromErrno: Into<ErrorKind> {
+	fn from_errno(errno: i32) -> Self;
+}
+
+/// The error returned by the DPDK wrappers
+///
+/// Along with the kind it keeps the errno of the failed call, DPDK's description of it
+/// and the call that failed, e.g. `rte_eth_rx_queue_setup(port 0, queue 1)`.
+#[derive(Debug)]
+pub struct Error {
+	kind: ErrorKind,
+	errno: Option<i32>,
+	context: Cow<'static, str>,
+}
+
+impl Error {
+	/// Error of a kind that does not come from an errno, e.g. a failed lookup
+	pub fn new(kind: impl Into<ErrorKind>, context: impl Into<Cow<'static, str>>) -> Self {
+		Self {
+			kind: kind.into(),
+			errno: None,
+			context: context.into(),
+		}
+	}
+
+	/// Error of a call that failed with `errno`
+	pub(crate) fn from_errno<K: FromErrno>(
+		errno: i32,
+		context: impl Into<Cow<'static, str>>,
+	) -> Self {
+		Self {
+			kind: K::from_errno(errno).into(),
+			errno: Some(errno),
+			context: context.into(),
+		}
+	}
+
+	/// Error of a call that failed and set `rte_errno`
+	#[inline]
+	pub(crate) fn rte<K: FromErrno>(context: impl Into<Cow<'static, str>>) -> Self {
+		Self::from_errno::<K>(unsafe { dpdk_sys::_rte_errno() }, context)
+	}
+
+	/// Error of a call that returned `-errno`, as the ethdev functions do
+	#[inline]
+	pub(crate) fn ret<K: FromErrno>(ret: i32, context: impl Into<Cow<'static, str>>) -> Self {
+		Self::from_errno::<K>(-ret, context)
+	}
+
+	#[inline]
+	pub fn kind(&self) -> &ErrorKind {
+		&self.kind
+	}
+
+	#[inline]
+	pub fn into_kind(self) -> ErrorKind {
+		self.kind
+	}
+
+	/// The errno the failed call reported, if it reported one
+	#[inline]
+	pub fn errno(&self) -> Option<i32> {
+		self.errno
+	}
+
+	/// DPDK's description of the errno, from `rte_strerror`
+	pub fn message(&self) -> Option<String> {
+		// rte_strerror formats unknown codes into a per-thread buffer, copy it out right away
+		self.errno.map(|errno| {
+			unsafe { CStr::from_ptr(dpdk_sys::rte_strerror(errno)) }
+				.to_string_lossy()
+				.into_owned()
+		})
+	}
+
+	/// The call that failed
+	#[inline]
+	pub fn context(&self) -> &str {
+		&self.context
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.context, self.kind)?;
+		match (self.errno, self.message()) {
+			(Some(errno), Some(msg)) => write!(f, " (errno {}: {})", errno, msg),
+			_ => Ok(()),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		error::Error::source(&self.kind)
+	}
+}
+
+impl From<NulError> for Error {
+	fn from(e: NulError) -> Self {
+		Error::new(e, "CString::new")
 	}
 }
 
@@ -90,7 +244,12 @@ impl From<MemoryError> for RingClientMapError {
 
 impl MemoryError {
 	pub fn new() -> Self {
-		let errno = unsafe { dpdk_sys::_rte_errno() };
+		Self::from_errno(unsafe { dpdk_sys::_rte_errno() })
+	}
+}
+
+impl FromErrno for MemoryError {
+	fn from_errno(errno: i32) -> Self {
 		match errno {
 			1001 => MemoryError::SecondaryProcess,
 			1002 => MemoryError::NoConfig,
@@ -136,7 +295,12 @@ pub enum PortError {
 
 impl PortError {
 	pub fn new() -> Self {
-		let errno = unsafe { dpdk_sys::_rte_errno() };
+		Self::from_errno(unsafe { dpdk_sys::_rte_errno() })
+	}
+}
+
+impl FromErrno for PortError {
+	fn from_errno(errno: i32) -> Self {
 		match errno {
 			ENODEV => PortError::NoDevice,
 			EINVAL => PortError::Invalid,
@@ -147,6 +311,125 @@ impl PortError {
 	}
 }
 
+#[derive(Error, Debug)]
+pub enum LpmError {
+	#[error("a table with the same name already exists")]
+	Exists,
+	#[error("bad prefix length or next hop")]
+	Invalid,
+	#[error("no such route")]
+	NoRoute,
+	#[error("the table is full")]
+	NoSpace,
+	#[error("not enough memory")]
+	NoMem,
+	#[error("bad val")]
+	BadVal,
+}
+
+impl FromErrno for LpmError {
+	fn from_errno(errno: i32) -> Self {
+		match errno {
+			EEXIST => LpmError::Exists,
+			EINVAL => LpmError::Invalid,
+			ENOENT => LpmError::NoRoute,
+			ENOSPC => LpmError::NoSpace,
+			ENOMEM => LpmError::NoMem,
+			_ => LpmError::BadVal,
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum MpError {
+	#[error("IPC is not supported in this process (e.g. --no-shconf or in-memory mode)")]
+	NoSupport,
+	#[error("a handler for this action is already registered")]
+	Exists,
+	#[error("message is malformed or too large")]
+	BadMessage,
+	#[error("peer did not reply in time")]
+	NoReply,
+	#[error("not enough memory")]
+	NoMem,
+	#[error("bad val")]
+	BadVal,
+}
+
+impl MpError {
+	pub fn new() -> Self {
+		Self::from_errno(unsafe { dpdk_sys::_rte_errno() })
+	}
+}
+
+impl Default for MpError {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FromErrno for MpError {
+	fn from_errno(errno: i32) -> Self {
+		match errno {
+			ENOTSUP => MpError::NoSupport,
+			EEXIST => MpError::Exists,
+			EINVAL | E2BIG => MpError::BadMessage,
+			ETIMEDOUT => MpError::NoReply,
+			ENOMEM => MpError::NoMem,
+			_ => MpError::BadVal,
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum LogError {
+	#[error("could not open the log stream")]
+	NoStream,
+	#[error("bad log type pattern: {}", _0)]
+	BadPattern(String),
+	#[error("bad log level: {}", _0)]
+	BadLevel(String),
+}
+
+#[derive(Error, Debug)]
+pub enum LcoreError {
+	#[error("jobs can't be launched on the main lcore")]
+	MainLcore,
+	#[error("the lcore is not in the WAIT state")]
+	Busy,
+	#[error("the thread already has an lcore id")]
+	Registered,
+	#[error("no lcore id left for the thread")]
+	NoLcore,
+	#[error("the EAL is not initialized")]
+	NoEal,
+	#[error("bad val")]
+	BadVal,
+}
+
+impl LcoreError {
+	pub fn new() -> Self {
+		Self::from_errno(unsafe { dpdk_sys::_rte_errno() })
+	}
+}
+
+impl Default for LcoreError {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FromErrno for LcoreError {
+	fn from_errno(errno: i32) -> Self {
+		match errno {
+			ENOMEM => LcoreError::NoLcore,
+			EINVAL => LcoreError::NoEal,
+			EBUSY => LcoreError::Busy,
+			_ => LcoreError::BadVal,
+		}
+	}
+}
+
 #[derive(Error, Debug)]
 pub enum EALErrors {
 	#[error("either a bus or system resource was not available, setup may be attempted again")]
@@ -173,7 +456,12 @@ pub enum EALErrors {
 
 impl EALErrors {
 	pub fn new() -> Self {
-		let errno = unsafe { dpdk_sys::_rte_errno() };
+		Self::from_errno(unsafe { dpdk_sys::_rte_errno() })
+	}
+}
+
+impl FromErrno for EALErrors {
+	fn from_errno(errno: i32) -> Self {
 		match errno {
 			EAGAIN => EALErrors::NoRsrc,
 			EALREADY => EALErrors::DuplicateCall,
@@ -190,7 +478,7 @@ impl EALErrors {
 }
 
 /// Initializes the Environment Abstraction Layer (EAL)
-pub fn eal_init(args: Vec<String>) -> Result<(), EALErrors> {
+pub fn eal_init(args: Vec<String>) -> Result<(), Error> {
 	log::info!("Args: {:?}", &args);
 	let len = args.len() as raw::c_int;
 	// the panic is fine here since it's due to wrong arguments
@@ -205,48 +493,30 @@ pub fn eal_init(args: Vec<String>) -> Result<(), EALErrors> {
 		.collect::<Vec<_>>();
 	match unsafe { dpdk_sys::rte_eal_init(len, ptrs.as_mut_ptr()) } {
 		-1 => {
-			log::error!("failed to initialize eal");
-			Err(EALErrors::new())
+			let e = Error::rte::<EALErrors>("rte_eal_init");
+			log::error!("failed to initialize eal: {}", e);
+			Err(e)
+		}
+		_ => {
+			EAL_READY.store(true, Ordering::Release);
+			Ok(())
 		}
-		_ => Ok(()),
 	}
 }
 
-/// Cleans up the Environment Abstraction Layer (EAL).
-pub fn eal_cleanup(mempool: &Mempool) -> Result<(), EALErrors> {
+/// Frees `mempools` and cleans up the Environment Abstraction Layer (EAL).
+///
+/// The mempools are taken so none of them is freed again once the EAL is gone. The
+/// `RteAllocator` goes back to the system allocator first. Nothing it allocated from the
+/// hugepage heap may be freed afterwards.
+pub fn eal_cleanup(mempools: Vec<Mempool>) -> Result<(), Error> {
+	drop(mempools);
 	unsafe {
-		dpdk_sys::rte_mempool_free(mempool.get_ptr());
+		// the heap and the memseg lists `RteAllocator::dealloc` looks pointers up in go away
+		EAL_READY.store(false, Ordering::Release);
 		match dpdk_sys::rte_eal_cleanup() {
 			0 => Ok(()),
-			_ => Err(EALErrors::Fault),
-		}
-	}
-}
-
-/// Frees the `rte_mbuf` in bulk.
-pub(crate) fn mbuf_free_bulk(mbufs: Vec<*mut dpdk_sys::rte_mbuf>) {
-	assert!(!mbufs.is_empty());
-
-	let mut to_free = Vec::with_capacity(mbufs.len());
-	let pool = unsafe { (*mbufs[0]).pool };
-
-	for mbuf in mbufs.into_iter() {
-		if pool == unsafe { (*mbuf).pool } {
-			to_free.push(mbuf as *mut raw::c_void);
-		} else {
-			unsafe {
-				let len = to_free.len();
-				dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
-				to_free.set_len(0);
-			}
-
-			to_free.push(mbuf as *mut raw::c_void);
+			_ => Err(Error::rte::<EALErrors>("rte_eal_cleanup")),
 		}
 	}
-
-	unsafe {
-		let len = to_free.len();
-		dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
-		to_free.set_len(0);
-	}
 }
@@ -0,0 +1,208 @@
+//! Multi-process control channel between the engine (primary) and the mux (secondary)
+//!
+//! This wraps DPDK's `rte_mp_*` IPC which runs over a unix socket that the EAL sets up
+//! for every process sharing the same hugepage prefix. No extra ports or libraries are needed.
+//!
+//! Every message travels under the same action name and carries a one byte tag followed by
+//! the client id it refers to.
+
+use std::{convert::TryFrom, ffi::c_void, mem, os::raw, ptr, time::Duration};
+
+use super::{Error, MpError, WrappedCString};
+use crate::dpdk_sys;
+
+/// The control messages exchanged between the primary and secondary processes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MpMessage {
+	/// A secondary wants to attach to the channel of the client
+	Attach(u16),
+	/// A secondary is going away and releases the channel of the client
+	Detach(u16),
+	/// The channel of the client has been created and can be looked up
+	ChannelReady(u16),
+	/// The sender is shutting down
+	Shutdown,
+	/// The secondary serving the client is still alive
+	Heartbeat(u16),
+}
+
+impl MpMessage {
+	const ATTACH: u8 = 1;
+	const DETACH: u8 = 2;
+	const CHANNEL_READY: u8 = 3;
+	const SHUTDOWN: u8 = 4;
+	const HEARTBEAT: u8 = 5;
+
+	/// Number of bytes a message occupies in `rte_mp_msg::param`
+	const LEN: usize = 3;
+
+	fn encode(&self) -> [u8; Self::LEN] {
+		let (tag, id) = match *self {
+			MpMessage::Attach(id) => (Self::ATTACH, id),
+			MpMessage::Detach(id) => (Self::DETACH, id),
+			MpMessage::ChannelReady(id) => (Self::CHANNEL_READY, id),
+			MpMessage::Shutdown => (Self::SHUTDOWN, 0),
+			MpMessage::Heartbeat(id) => (Self::HEARTBEAT, id),
+		};
+		let id = id.to_le_bytes();
+		[tag, id[0], id[1]]
+	}
+
+	fn decode(buf: &[u8]) -> Result<Self, MpError> {
+		if buf.len() < Self::LEN {
+			return Err(MpError::BadMessage);
+		}
+		let id = u16::from_le_bytes([buf[1], buf[2]]);
+		match buf[0] {
+			Self::ATTACH => Ok(MpMessage::Attach(id)),
+			Self::DETACH => Ok(MpMessage::Detach(id)),
+			Self::CHANNEL_READY => Ok(MpMessage::ChannelReady(id)),
+			Self::SHUTDOWN => Ok(MpMessage::Shutdown),
+			Self::HEARTBEAT => Ok(MpMessage::Heartbeat(id)),
+			_ => Err(MpError::BadMessage),
+		}
+	}
+}
+
+impl TryFrom<&dpdk_sys::rte_mp_msg> for MpMessage {
+	type Error = MpError;
+
+	fn try_from(msg: &dpdk_sys::rte_mp_msg) -> Result<Self, Self::Error> {
+		let len = msg.len_param as usize;
+		if len > msg.param.len() {
+			return Err(MpError::BadMessage);
+		}
+		Self::decode(&msg.param[..len])
+	}
+}
+
+/// The callback invoked for every control message received from a peer.
+///
+/// The returned message, if any, is sent back to the peer as the reply.
+/// Secondaries waiting in `Mp::request` block until the reply arrives, so handlers
+/// that are the target of a request should always answer.
+pub type MpHandler = Box<dyn Fn(MpMessage) -> Option<MpMessage> + Send + Sync>;
+
+static MP_HANDLER: state::Storage<MpHandler> = state::Storage::new();
+
+/// Handle for the control channel
+pub struct Mp;
+
+impl Mp {
+	/// Name of the action all engine control messages are registered under
+	const ACTION: &'static str = "l3engine_ctrl";
+	/// How long a requester waits for the peer to answer by default
+	pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+	/// Register the handler for incoming control messages.
+	///
+	/// Must be called after `eal_init` and only once per process.
+	pub fn register(handler: MpHandler) -> Result<(), Error> {
+		if !MP_HANDLER.set(handler) {
+			return Err(Error::new(MpError::Exists, "Mp::register"));
+		}
+		let name = WrappedCString::to_cstring(Self::ACTION)?;
+		match unsafe { dpdk_sys::rte_mp_action_register(name.as_ptr(), Some(mp_callback)) } {
+			0 => Ok(()),
+			_ => Err(Error::rte::<MpError>("rte_mp_action_register")),
+		}
+	}
+
+	/// Stop receiving control messages
+	pub fn unregister() -> Result<(), Error> {
+		let name = WrappedCString::to_cstring(Self::ACTION)?;
+		unsafe { dpdk_sys::rte_mp_action_unregister(name.as_ptr()) };
+		Ok(())
+	}
+
+	/// Send a message without waiting for an answer.
+	///
+	/// From the primary the message is broadcast to every secondary.
+	/// From a secondary it goes to the primary.
+	pub fn send(msg: MpMessage) -> Result<(), Error> {
+		let mut req = Self::to_raw(msg)?;
+		match unsafe { dpdk_sys::rte_mp_sendmsg(&mut req) } {
+			0 => Ok(()),
+			_ => Err(Error::rte::<MpError>(format!("rte_mp_sendmsg({:?})", msg))),
+		}
+	}
+
+	/// Send a message and block until the peer replies or `timeout` expires.
+	///
+	/// Only the first reply is returned. This is meant to be used from a secondary
+	/// where the only peer is the primary.
+	pub fn request(msg: MpMessage, timeout: Duration) -> Result<MpMessage, Error> {
+		let mut req = Self::to_raw(msg)?;
+		let mut reply = dpdk_sys::rte_mp_reply::default();
+		let ts = dpdk_sys::timespec {
+			tv_sec: timeout.as_secs() as _,
+			tv_nsec: timeout.subsec_nanos() as _,
+		};
+		if unsafe { dpdk_sys::rte_mp_request_sync(&mut req, &mut reply, &ts) } != 0 {
+			return Err(Error::rte::<MpError>(format!("rte_mp_request_sync({:?})", msg)));
+		}
+
+		// the reply array is allocated by the EAL with malloc and belongs to us
+		let res = if reply.nb_received > 0 && !reply.msgs.is_null() {
+			MpMessage::try_from(unsafe { &*reply.msgs })
+		} else {
+			Err(MpError::NoReply)
+		}
+		.map_err(|e| Error::new(e, format!("reply to {:?}", msg)));
+		unsafe { libc::free(reply.msgs as *mut c_void) };
+		res
+	}
+
+	fn to_raw(msg: MpMessage) -> Result<dpdk_sys::rte_mp_msg, Error> {
+		let name = WrappedCString::to_cstring(Self::ACTION)?;
+		let mut req = dpdk_sys::rte_mp_msg::default();
+		let name = name.as_bytes_with_nul();
+		unsafe {
+			ptr::copy_nonoverlapping(
+				name.as_ptr() as *const raw::c_char,
+				req.name.as_mut_ptr(),
+				name.len(),
+			);
+		}
+		let buf = msg.encode();
+		req.param[..buf.len()].copy_from_slice(&buf);
+		req.len_param = buf.len() as raw::c_int;
+		Ok(req)
+	}
+}
+
+/// Trampoline from the EAL's IPC thread into the registered Rust handler
+unsafe extern "C" fn mp_callback(
+	req: *const dpdk_sys::rte_mp_msg,
+	peer: *const c_void,
+) -> raw::c_int {
+	let handler = match MP_HANDLER.try_get() {
+		Some(h) => h,
+		None => return -1,
+	};
+	let req = match req.as_ref() {
+		Some(r) => r,
+		None => return -1,
+	};
+	let msg = match MpMessage::try_from(req) {
+		Ok(m) => m,
+		Err(_) => {
+			log::error!("mp: dropping malformed control message");
+			return -1;
+		}
+	};
+
+	if let Some(answer) = handler(msg) {
+		// the reply must carry the same action name as the request
+		let mut reply: dpdk_sys::rte_mp_msg = mem::zeroed();
+		reply.name = req.name;
+		let buf = answer.encode();
+		reply.param[..buf.len()].copy_from_slice(&buf);
+		reply.len_param = buf.len() as raw::c_int;
+		if dpdk_sys::rte_mp_reply(&mut reply, peer as *const raw::c_char) != 0 {
+			log::error!("mp: failed to reply to {:?}", msg);
+			return -1;
+		}
+	}
+	0
+}
@@ -0,0 +1,925 @@
+//! Packet headers and the helpers the engine uses to parse and answer packets
+//!
+//! The header structs mirror DPDK's `rte_*_hdr` layouts. Like DPDK's, their multi-byte
+//! fields hold the values in network byte order, convert them with `u16::from_be` and
+//! `u32::from_be`. The accessors on `Mbuf` check the packet is long enough and of the
+//! right protocol before handing out a reference, so a short or foreign packet gives
+//! `None` rather than a read past the data.
+
+use std::{error, fmt, mem, net::AddrParseError, net::Ipv4Addr, str::FromStr};
+
+use super::{Mbuf, Mempool, MemoryError, SizeOf};
+
+pub const ETHER_TYPE_IPV4: u16 = 0x0800;
+pub const ETHER_TYPE_ARP: u16 = 0x0806;
+pub const ARP_HRD_ETHER: u16 = 1;
+pub const ARP_OP_REQUEST: u16 = 1;
+pub const ARP_OP_REPLY: u16 = 2;
+pub const IP_PROTOCOL_ICMP: u8 = 1;
+pub const IP_PROTOCOL_TCP: u8 = 6;
+pub const IP_PROTOCOL_UDP: u8 = 17;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_DEST_UNREACH: u8 = 3;
+pub const ICMP_TIME_EXCEEDED: u8 = 11;
+/// Codes of `ICMP_DEST_UNREACH`
+pub const ICMP_NET_UNREACH: u8 = 0;
+pub const ICMP_HOST_UNREACH: u8 = 1;
+pub const ICMP_PORT_UNREACH: u8 = 3;
+/// Code of `ICMP_TIME_EXCEEDED` for a TTL that ran out in transit
+pub const ICMP_EXC_TTL: u8 = 0;
+/// TTL of the packets the engine makes or answers
+pub const IP_DEFAULT_TTL: u8 = 64;
+const IPV4_MORE_FRAGMENTS: u16 = 0x2000;
+const IPV4_FRAG_OFFSET_MASK: u16 = 0x1fff;
+
+macro_rules! impl_size_of {
+	($($t:ty),*) => {
+		$(impl SizeOf for $t {
+			fn size_of() -> usize {
+				mem::size_of::<$t>()
+			}
+		})*
+	};
+}
+
+/// An ethernet address
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+	pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+	pub const ZERO: MacAddr = MacAddr([0; 6]);
+}
+
+impl fmt::Display for MacAddr {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let b = self.0;
+		write!(
+			f,
+			"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+			b[0], b[1], b[2], b[3], b[4], b[5]
+		)
+	}
+}
+
+impl fmt::Debug for MacAddr {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt::Display::fmt(self, f)
+	}
+}
+
+impl FromStr for MacAddr {
+	type Err = MacAddrParseError;
+
+	/// Parses six colon separated pairs of hex digits like "02:00:00:00:00:01"
+	fn from_str(s: &str) -> Result<Self, MacAddrParseError> {
+		let mut mac = [0; 6];
+		let mut bytes = s.trim().split(':');
+		for b in mac.iter_mut() {
+			let hex = bytes
+				.next()
+				.filter(|h| h.len() == 2)
+				.ok_or(MacAddrParseError)?;
+			*b = u8::from_str_radix(hex, 16).map_err(|_| MacAddrParseError)?;
+		}
+		match bytes.next() {
+			Some(_) => Err(MacAddrParseError),
+			None => Ok(MacAddr(mac)),
+		}
+	}
+}
+
+/// The error of parsing a `MacAddr`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacAddrParseError;
+
+impl fmt::Display for MacAddrParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("invalid MAC address syntax")
+	}
+}
+
+impl error::Error for MacAddrParseError {}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub struct EtherHdr {
+	pub d_addr: MacAddr,
+	pub s_addr: MacAddr,
+	pub ether_type: u16,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub struct ArpHdr {
+	pub arp_hardware: u16,
+	pub arp_protocol: u16,
+	pub arp_hlen: u8,
+	pub arp_plen: u8,
+	pub arp_opcode: u16,
+	pub arp_sha: MacAddr,
+	pub arp_sip: u32,
+	pub arp_tha: MacAddr,
+	pub arp_tip: u32,
+}
+
+impl ArpHdr {
+	/// The fields every ARP for IPv4 over ethernet has, the rest zeroed
+	pub const ETHER_IPV4: ArpHdr = ArpHdr {
+		arp_hardware: ARP_HRD_ETHER.to_be(),
+		arp_protocol: ETHER_TYPE_IPV4.to_be(),
+		arp_hlen: mem::size_of::<MacAddr>() as u8,
+		arp_plen: mem::size_of::<u32>() as u8,
+		arp_opcode: 0,
+		arp_sha: MacAddr::ZERO,
+		arp_sip: 0,
+		arp_tha: MacAddr::ZERO,
+		arp_tip: 0,
+	};
+
+	/// Whether the packet is an ARP for IPv4 over ethernet
+	#[inline]
+	pub fn is_ether_ipv4(&self) -> bool {
+		u16::from_be(self.arp_hardware) == ARP_HRD_ETHER
+			&& u16::from_be(self.arp_protocol) == ETHER_TYPE_IPV4
+			&& self.arp_hlen as usize == mem::size_of::<MacAddr>()
+			&& self.arp_plen as usize == mem::size_of::<u32>()
+	}
+
+	/// The reply to `req` announcing `local_mac` for the requested address
+	fn reply(req: &ArpHdr, local_mac: MacAddr) -> ArpHdr {
+		ArpHdr {
+			arp_opcode: ARP_OP_REPLY.to_be(),
+			arp_sha: local_mac,
+			arp_sip: req.arp_tip,
+			arp_tha: req.arp_sha,
+			arp_tip: req.arp_sip,
+			..ArpHdr::ETHER_IPV4
+		}
+	}
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub struct Ipv4Hdr {
+	pub version_ihl: u8,
+	pub type_of_service: u8,
+	pub total_length: u16,
+	pub packet_id: u16,
+	pub fragment_offset: u16,
+	pub time_to_live: u8,
+	pub next_proto_id: u8,
+	pub hdr_checksum: u16,
+	pub src_addr: u32,
+	pub dst_addr: u32,
+}
+
+impl Ipv4Hdr {
+	/// Length of the header including its options, from the IHL field
+	#[inline]
+	pub fn hdr_len(&self) -> usize {
+		(self.version_ihl & 0x0f) as usize * 4
+	}
+
+	/// Decrement the TTL and update the checksum to match, returns the new TTL
+	///
+	/// A TTL of 0 is left alone, the packet should have been dropped already.
+	#[inline]
+	pub fn decrement_ttl(&mut self) -> u8 {
+		if self.time_to_live == 0 {
+			return 0;
+		}
+		let old = u16::from_be_bytes([self.time_to_live, self.next_proto_id]);
+		self.time_to_live -= 1;
+		let new = u16::from_be_bytes([self.time_to_live, self.next_proto_id]);
+		self.hdr_checksum = checksum_update(u16::from_be(self.hdr_checksum), old, new).to_be();
+		self.time_to_live
+	}
+
+	/// Set the TTL and update the checksum to match
+	#[inline]
+	pub fn set_ttl(&mut self, ttl: u8) {
+		let old = u16::from_be_bytes([self.time_to_live, self.next_proto_id]);
+		self.time_to_live = ttl;
+		let new = u16::from_be_bytes([self.time_to_live, self.next_proto_id]);
+		self.hdr_checksum = checksum_update(u16::from_be(self.hdr_checksum), old, new).to_be();
+	}
+
+	/// Whether the packet is a fragment, the first included
+	#[inline]
+	pub fn is_fragment(&self) -> bool {
+		u16::from_be(self.fragment_offset) & (IPV4_MORE_FRAGMENTS | IPV4_FRAG_OFFSET_MASK) != 0
+	}
+
+	/// Whether the packet is a fragment other than the first
+	#[inline]
+	pub fn is_later_fragment(&self) -> bool {
+		u16::from_be(self.fragment_offset) & IPV4_FRAG_OFFSET_MASK != 0
+	}
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub struct TcpHdr {
+	pub src_port: u16,
+	pub dst_port: u16,
+	pub sent_seq: u32,
+	pub recv_ack: u32,
+	pub data_off: u8,
+	pub tcp_flags: u8,
+	pub rx_win: u16,
+	pub cksum: u16,
+	pub tcp_urp: u16,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub struct UdpHdr {
+	pub src_port: u16,
+	pub dst_port: u16,
+	pub dgram_len: u16,
+	pub dgram_cksum: u16,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub struct IcmpHdr {
+	pub icmp_type: u8,
+	pub icmp_code: u8,
+	pub icmp_cksum: u16,
+	pub icmp_ident: u16,
+	pub icmp_seq_nb: u16,
+}
+
+impl_size_of!(MacAddr, EtherHdr, ArpHdr, Ipv4Hdr, TcpHdr, UdpHdr, IcmpHdr);
+
+const ETHER_HDR_LEN: usize = mem::size_of::<EtherHdr>();
+const IPV4_MIN_HDR_LEN: usize = mem::size_of::<Ipv4Hdr>();
+const ICMP_HDR_LEN: usize = mem::size_of::<IcmpHdr>();
+/// Bytes of the offending datagram's payload an ICMP error quotes after its IP header
+const ICMP_ERROR_QUOTE: usize = 8;
+
+/// Parse a dotted quad like "10.0.0.1" into an address in host byte order
+pub fn parse_ip(ip: &str) -> Result<u32, AddrParseError> {
+	ip.trim().parse::<Ipv4Addr>().map(u32::from)
+}
+
+/// The internet checksum (RFC 1071) of `data`, in host byte order
+pub fn checksum(data: &[u8]) -> u16 {
+	let mut sum = data
+		.chunks(2)
+		.map(|c| match c {
+			[hi, lo] => u16::from_be_bytes([*hi, *lo]) as u32,
+			[hi] => u16::from_be_bytes([*hi, 0]) as u32,
+			_ => 0,
+		})
+		.sum::<u32>();
+	while sum > 0xffff {
+		sum = (sum & 0xffff) + (sum >> 16);
+	}
+	!(sum as u16)
+}
+
+/// Update a checksum, in host byte order, for one 16 bit word of the data changing from
+/// `old` to `new` (RFC 1624)
+pub fn checksum_update(cksum: u16, old: u16, new: u16) -> u16 {
+	let mut sum = (!cksum) as u32 + (!old) as u32 + new as u32;
+	sum = (sum & 0xffff) + (sum >> 16);
+	sum = (sum & 0xffff) + (sum >> 16);
+	!(sum as u16)
+}
+
+/// Update the checksum of an ICMP echo request, in host byte order, for the echo reply
+/// made by only changing the type
+pub fn icmp_echo_reply_checksum(cksum: u16) -> u16 {
+	let old = u16::from_be_bytes([ICMP_ECHO_REQUEST, 0]);
+	let new = u16::from_be_bytes([ICMP_ECHO_REPLY, 0]);
+	checksum_update(cksum, old, new)
+}
+
+impl Mbuf {
+	/// The ethernet header, if the packet is long enough to hold one
+	#[inline]
+	pub fn ether_hdr(&self) -> Option<&EtherHdr> {
+		self.header(0)
+	}
+
+	#[inline]
+	pub fn ether_hdr_mut(&mut self) -> Option<&mut EtherHdr> {
+		self.header_mut(0)
+	}
+
+	/// The ethernet type of the packet in host byte order
+	#[inline]
+	pub fn ether_type(&self) -> Option<u16> {
+		self.ether_hdr().map(|eth| u16::from_be(eth.ether_type))
+	}
+
+	/// The ARP header of an ARP packet
+	#[inline]
+	pub fn arp_hdr(&self) -> Option<&ArpHdr> {
+		match self.ether_type()? {
+			ETHER_TYPE_ARP => self.header(ETHER_HDR_LEN),
+			_ => None,
+		}
+	}
+
+	#[inline]
+	pub fn arp_hdr_mut(&mut self) -> Option<&mut ArpHdr> {
+		match self.ether_type()? {
+			ETHER_TYPE_ARP => self.header_mut(ETHER_HDR_LEN),
+			_ => None,
+		}
+	}
+
+	/// The IPv4 header of an IPv4 packet
+	#[inline]
+	pub fn ipv4_hdr(&self) -> Option<&Ipv4Hdr> {
+		match self.ether_type()? {
+			ETHER_TYPE_IPV4 => self.header(ETHER_HDR_LEN),
+			_ => None,
+		}
+	}
+
+	#[inline]
+	pub fn ipv4_hdr_mut(&mut self) -> Option<&mut Ipv4Hdr> {
+		match self.ether_type()? {
+			ETHER_TYPE_IPV4 => self.header_mut(ETHER_HDR_LEN),
+			_ => None,
+		}
+	}
+
+	/// The TCP header of a TCP over IPv4 packet
+	#[inline]
+	pub fn tcp_hdr(&self) -> Option<&TcpHdr> {
+		self.header(self.l4_offset(IP_PROTOCOL_TCP)?)
+	}
+
+	#[inline]
+	pub fn tcp_hdr_mut(&mut self) -> Option<&mut TcpHdr> {
+		let offset = self.l4_offset(IP_PROTOCOL_TCP)?;
+		self.header_mut(offset)
+	}
+
+	/// The UDP header of a UDP over IPv4 packet
+	#[inline]
+	pub fn udp_hdr(&self) -> Option<&UdpHdr> {
+		self.header(self.l4_offset(IP_PROTOCOL_UDP)?)
+	}
+
+	#[inline]
+	pub fn udp_hdr_mut(&mut self) -> Option<&mut UdpHdr> {
+		let offset = self.l4_offset(IP_PROTOCOL_UDP)?;
+		self.header_mut(offset)
+	}
+
+	/// The ICMP header of an ICMP over IPv4 packet
+	#[inline]
+	pub fn icmp_hdr(&self) -> Option<&IcmpHdr> {
+		self.header(self.l4_offset(IP_PROTOCOL_ICMP)?)
+	}
+
+	#[inline]
+	pub fn icmp_hdr_mut(&mut self) -> Option<&mut IcmpHdr> {
+		let offset = self.l4_offset(IP_PROTOCOL_ICMP)?;
+		self.header_mut(offset)
+	}
+
+	/// Whether the packet is an ARP request for `local_ip`, in host byte order
+	pub fn is_arp_request_for(&self, local_ip: u32) -> bool {
+		match self.arp_hdr() {
+			Some(arp) => {
+				u16::from_be(arp.arp_opcode) == ARP_OP_REQUEST
+					&& u32::from_be(arp.arp_tip) == local_ip
+			}
+			None => false,
+		}
+	}
+
+	/// Build the reply to an ARP request, announcing `local_mac` for the requested address
+	///
+	/// Returns `Ok(None)` if the packet is not an ARP request.
+	pub fn arp_reply(&self, local_mac: MacAddr, mp: &Mempool) -> Result<Option<Mbuf>, MemoryError> {
+		let req = match self.arp_hdr() {
+			Some(arp) if u16::from_be(arp.arp_opcode) == ARP_OP_REQUEST => *arp,
+			_ => return Ok(None),
+		};
+
+		let mut reply = Mbuf::new(mp)?;
+		reply.write_arp(req.arp_sha, ArpHdr::reply(&req, local_mac))?;
+		Ok(Some(reply))
+	}
+
+	/// Turn an ARP request into its reply in place, announcing `local_mac` for the
+	/// requested address
+	///
+	/// Returns false, leaving the packet alone, if it is not an ARP request.
+	pub fn to_arp_reply(&mut self, local_mac: MacAddr) -> bool {
+		let req = match self.arp_hdr() {
+			Some(arp) if u16::from_be(arp.arp_opcode) == ARP_OP_REQUEST => *arp,
+			_ => return false,
+		};
+		if let Some(eth) = self.ether_hdr_mut() {
+			eth.d_addr = req.arp_sha;
+			eth.s_addr = local_mac;
+		}
+		if let Some(arp) = self.arp_hdr_mut() {
+			*arp = ArpHdr::reply(&req, local_mac);
+		}
+		true
+	}
+
+	/// Build a broadcast ARP request for `target_ip` from `local_mac` and `local_ip`, the
+	/// addresses in host byte order
+	///
+	/// The request is made in a buffer from the mempool this packet came from, so whatever
+	/// is waiting for the answer can pay for the question.
+	pub fn arp_request(
+		&self,
+		local_mac: MacAddr,
+		local_ip: u32,
+		target_ip: u32,
+	) -> Result<Mbuf, MemoryError> {
+		let mut req = self.alloc_like()?;
+		let arp = ArpHdr {
+			arp_opcode: ARP_OP_REQUEST.to_be(),
+			arp_sha: local_mac,
+			arp_sip: local_ip.to_be(),
+			arp_tha: MacAddr::ZERO,
+			arp_tip: target_ip.to_be(),
+			..ArpHdr::ETHER_IPV4
+		};
+		req.write_arp(MacAddr::BROADCAST, arp)?;
+		Ok(req)
+	}
+
+	/// Fill an empty buffer with an ARP packet to `d_addr`
+	fn write_arp(&mut self, d_addr: MacAddr, arp: ArpHdr) -> Result<(), MemoryError> {
+		self.extend(0, ETHER_HDR_LEN + mem::size_of::<ArpHdr>())
+			.map_err(|_| MemoryError::NoBuf)?;
+		if let Some(eth) = self.ether_hdr_mut() {
+			*eth = EtherHdr {
+				d_addr,
+				s_addr: arp.arp_sha,
+				ether_type: ETHER_TYPE_ARP.to_be(),
+			};
+		}
+		if let Some(hdr) = self.arp_hdr_mut() {
+			*hdr = arp;
+		}
+		Ok(())
+	}
+
+	/// Turn an ICMP echo request into its reply in place
+	///
+	/// The addresses are swapped and the TTL reset, the checksums are updated rather than
+	/// recomputed. Returns false, leaving the packet alone, if it is not an echo request.
+	pub fn to_icmp_echo_reply(&mut self) -> bool {
+		match self.icmp_hdr() {
+			Some(icmp) if icmp.icmp_type == ICMP_ECHO_REQUEST && icmp.icmp_code == 0 => {}
+			_ => return false,
+		}
+		if let Some(eth) = self.ether_hdr_mut() {
+			*eth = EtherHdr {
+				d_addr: eth.s_addr,
+				s_addr: eth.d_addr,
+				..*eth
+			};
+		}
+		if let Some(ip) = self.ipv4_hdr_mut() {
+			*ip = Ipv4Hdr {
+				src_addr: ip.dst_addr,
+				dst_addr: ip.src_addr,
+				..*ip
+			};
+			ip.set_ttl(IP_DEFAULT_TTL);
+		}
+		if let Some(icmp) = self.icmp_hdr_mut() {
+			icmp.icmp_type = ICMP_ECHO_REPLY;
+			icmp.icmp_cksum = icmp_echo_reply_checksum(u16::from_be(icmp.icmp_cksum)).to_be();
+		}
+		true
+	}
+
+	/// Whether RFC 1122 allows answering this packet with an ICMP error
+	///
+	/// Packets that aren't IPv4 or were sent to a link-layer group, the IP broadcast or an IP
+	/// multicast group, fragments other than the first, ICMP errors and packets whose source
+	/// isn't a single host aren't answered. Only the ingress port knows its subnet's
+	/// broadcast address, so the caller checks that one.
+	pub fn may_get_icmp_error(&self) -> bool {
+		let (eth, ip) = match (self.ether_hdr(), self.ipv4_hdr()) {
+			(Some(eth), Some(ip)) if ip.hdr_len() >= IPV4_MIN_HDR_LEN => (eth, ip),
+			_ => return false,
+		};
+		let src = Ipv4Addr::from(u32::from_be(ip.src_addr));
+		let dst = Ipv4Addr::from(u32::from_be(ip.dst_addr));
+		if eth.d_addr.0[0] & 0x01 != 0
+			|| ip.is_later_fragment()
+			|| dst.is_broadcast()
+			|| dst.is_multicast()
+			|| src.is_unspecified()
+			|| src.is_broadcast()
+			|| src.is_multicast()
+			|| src.is_loopback()
+		{
+			return false;
+		}
+		if ip.next_proto_id == IP_PROTOCOL_ICMP {
+			return matches!(
+				self.icmp_hdr(),
+				Some(icmp) if icmp.icmp_type == ICMP_ECHO_REQUEST || icmp.icmp_type == ICMP_ECHO_REPLY
+			);
+		}
+		true
+	}
+
+	/// Build the ICMP error of `icmp_type` and `code` telling the sender of this IPv4 packet
+	/// it wasn't delivered, from `local_mac` and `local_ip`, in host byte order
+	///
+	/// The error quotes the packet's IP header and the first 8 bytes of its payload. Returns
+	/// `Ok(None)` for the packets `may_get_icmp_error` says not to answer. Like
+	/// `arp_request`, the error is made in a buffer from the packet's mempool.
+	pub fn icmp_error(
+		&self,
+		icmp_type: u8,
+		code: u8,
+		local_mac: MacAddr,
+		local_ip: u32,
+	) -> Result<Option<Mbuf>, MemoryError> {
+		if !self.may_get_icmp_error() {
+			return Ok(None);
+		}
+		let (eth, ip) = match (self.ether_hdr(), self.ipv4_hdr()) {
+			(Some(eth), Some(ip)) => (*eth, *ip),
+			_ => return Ok(None),
+		};
+
+		let quoted = (ip.hdr_len() + ICMP_ERROR_QUOTE).min(self.data_len() - ETHER_HDR_LEN);
+		let icmp_offset = ETHER_HDR_LEN + IPV4_MIN_HDR_LEN;
+		let icmp_len = ICMP_HDR_LEN + quoted;
+		let quote = self
+			.bytes(ETHER_HDR_LEN, quoted)
+			.ok_or(MemoryError::NoBuf)?;
+
+		let mut err = self.alloc_like()?;
+		err.extend(0, icmp_offset + icmp_len)
+			.map_err(|_| MemoryError::NoBuf)?;
+		err.write_data_slice(icmp_offset + ICMP_HDR_LEN, quote)
+			.map_err(|_| MemoryError::NoBuf)?;
+		if let Some(hdr) = err.ether_hdr_mut() {
+			*hdr = EtherHdr {
+				d_addr: eth.s_addr,
+				s_addr: local_mac,
+				ether_type: ETHER_TYPE_IPV4.to_be(),
+			};
+		}
+		if let Some(hdr) = err.header_mut::<IcmpHdr>(icmp_offset) {
+			*hdr = IcmpHdr {
+				icmp_type,
+				icmp_code: code,
+				icmp_cksum: 0,
+				icmp_ident: 0,
+				icmp_seq_nb: 0,
+			};
+		}
+		let cksum = err.bytes(icmp_offset, icmp_len).map_or(0, checksum);
+		if let Some(hdr) = err.header_mut::<IcmpHdr>(icmp_offset) {
+			hdr.icmp_cksum = cksum.to_be();
+		}
+		if let Some(hdr) = err.header_mut::<Ipv4Hdr>(ETHER_HDR_LEN) {
+			*hdr = Ipv4Hdr {
+				version_ihl: 0x45,
+				type_of_service: 0,
+				total_length: ((IPV4_MIN_HDR_LEN + icmp_len) as u16).to_be(),
+				packet_id: 0,
+				fragment_offset: 0,
+				time_to_live: IP_DEFAULT_TTL,
+				next_proto_id: IP_PROTOCOL_ICMP,
+				hdr_checksum: 0,
+				src_addr: local_ip.to_be(),
+				dst_addr: ip.src_addr,
+			};
+		}
+		let cksum = err
+			.bytes(ETHER_HDR_LEN, IPV4_MIN_HDR_LEN)
+			.map_or(0, checksum);
+		if let Some(hdr) = err.header_mut::<Ipv4Hdr>(ETHER_HDR_LEN) {
+			hdr.hdr_checksum = cksum.to_be();
+		}
+		Ok(Some(err))
+	}
+
+	/// Offset of the layer 4 header of an IPv4 packet carrying `proto`
+	fn l4_offset(&self, proto: u8) -> Option<usize> {
+		let ip = self.ipv4_hdr()?;
+		if ip.next_proto_id != proto || ip.hdr_len() < IPV4_MIN_HDR_LEN {
+			return None;
+		}
+		Some(ETHER_HDR_LEN + ip.hdr_len())
+	}
+
+	#[inline]
+	fn header<T: SizeOf>(&self, offset: usize) -> Option<&T> {
+		// headers are packed so any address is aligned for them
+		self.read_data::<T>(offset)
+			.ok()
+			.map(|hdr| unsafe { &*hdr.as_ptr() })
+	}
+
+	#[inline]
+	fn bytes(&self, offset: usize, len: usize) -> Option<&[u8]> {
+		self.read_data_slice::<u8>(offset, len)
+			.ok()
+			.map(|bytes| unsafe { &*bytes.as_ptr() })
+	}
+
+	#[inline]
+	fn header_mut<T: SizeOf>(&mut self, offset: usize) -> Option<&mut T> {
+		self.read_data::<T>(offset)
+			.ok()
+			.map(|hdr| unsafe { &mut *hdr.as_ptr() })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// the ICMP header of an echo request, checksum zeroed
+	const ICMP_ECHO: [u8; 8] = [0x08, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x01];
+
+	#[test]
+	fn parses_dotted_quads() {
+		assert_eq!(parse_ip("10.0.0.1"), Ok(0x0a00_0001));
+		assert_eq!(parse_ip(" 192.168.1.254 "), Ok(0xc0a8_01fe));
+		assert!(parse_ip("10.0.0").is_err());
+		assert!(parse_ip("10.0.0.256").is_err());
+	}
+
+	#[test]
+	fn parses_mac_addresses() {
+		assert_eq!(
+			"02:00:00:0a:Bc:ff".parse(),
+			Ok(MacAddr([0x02, 0, 0, 0x0a, 0xbc, 0xff]))
+		);
+		assert_eq!(" ff:ff:ff:ff:ff:ff ".parse(), Ok(MacAddr::BROADCAST));
+		let mac = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+		assert_eq!(mac.to_string().parse(), Ok(mac));
+		for bad in &[
+			"",
+			"02:00:00:00:00",
+			"02:00:00:00:00:01:02",
+			"2:00:00:00:00:01",
+			"02:00:00:00:00:0g",
+			"02-00-00-00-00-01",
+		] {
+			assert_eq!(bad.parse::<MacAddr>(), Err(MacAddrParseError), "{:?}", bad);
+		}
+	}
+
+	#[test]
+	fn checksum_verifies_to_zero() {
+		let mut icmp = ICMP_ECHO;
+		let sum = checksum(&icmp);
+		icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+		assert_eq!(checksum(&icmp), 0);
+	}
+
+	#[test]
+	fn ttl_decrement_checksum_matches_a_full_recompute() {
+		let mut bytes = [
+			0x45, 0x00, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 2,
+			10, 1, 0, 1,
+		];
+		let sum = checksum(&bytes);
+		bytes[10..12].copy_from_slice(&sum.to_be_bytes());
+		let mut ip = unsafe { *(bytes.as_ptr() as *const Ipv4Hdr) };
+		for ttl in (0..64).rev() {
+			assert_eq!(ip.decrement_ttl(), ttl);
+			let hdr = unsafe { std::slice::from_raw_parts(&ip as *const _ as *const u8, 20) };
+			assert_eq!(checksum(hdr), 0);
+		}
+		assert_eq!(ip.decrement_ttl(), 0);
+	}
+
+	#[test]
+	fn echo_reply_checksum_matches_a_full_recompute() {
+		let mut request = ICMP_ECHO;
+		let cksum = checksum(&request);
+		request[0] = ICMP_ECHO_REPLY;
+		assert_eq!(icmp_echo_reply_checksum(cksum), checksum(&request));
+	}
+
+	#[cfg(feature = "soft")]
+	mod mbuf {
+		use super::super::*;
+		use crate::apis::testing::mempool;
+
+		const LOCAL_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+		const PEER_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x02]);
+
+		fn arp_request(tip: [u8; 4]) -> Vec<u8> {
+			let mut pkt = Vec::new();
+			pkt.extend_from_slice(&[0xff; 6]);
+			pkt.extend_from_slice(&PEER_MAC.0);
+			pkt.extend_from_slice(&[0x08, 0x06, 0x00, 0x01, 0x08, 0x00, 6, 4, 0x00, 0x01]);
+			pkt.extend_from_slice(&PEER_MAC.0);
+			pkt.extend_from_slice(&[10, 0, 0, 2]);
+			pkt.extend_from_slice(&[0; 6]);
+			pkt.extend_from_slice(&tip);
+			pkt
+		}
+
+		fn udp_packet(ihl: u8) -> Vec<u8> {
+			let mut pkt = Vec::new();
+			pkt.extend_from_slice(&LOCAL_MAC.0);
+			pkt.extend_from_slice(&PEER_MAC.0);
+			pkt.extend_from_slice(&[0x08, 0x00]);
+			pkt.extend_from_slice(&[0x40 | ihl, 0, 0, 0, 0, 0, 0, 0, 64, IP_PROTOCOL_UDP, 0, 0]);
+			pkt.extend_from_slice(&[10, 0, 0, 2, 10, 0, 0, 1]);
+			pkt.resize(pkt.len() + (ihl as usize - 5) * 4, 0);
+			pkt.extend_from_slice(&[0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00]);
+			pkt
+		}
+
+		fn echo_request() -> Vec<u8> {
+			let mut icmp = super::ICMP_ECHO.to_vec();
+			icmp.extend_from_slice(b"ping");
+			let sum = checksum(&icmp);
+			icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+			let len = (IPV4_MIN_HDR_LEN + icmp.len()) as u8;
+			let mut ip = vec![0x45, 0, 0, len, 0, 0, 0x40, 0, 9, IP_PROTOCOL_ICMP, 0, 0];
+			ip.extend_from_slice(&[10, 0, 0, 2, 10, 0, 0, 1]);
+			let sum = checksum(&ip);
+			ip[10..12].copy_from_slice(&sum.to_be_bytes());
+
+			let mut pkt = Vec::new();
+			pkt.extend_from_slice(&LOCAL_MAC.0);
+			pkt.extend_from_slice(&PEER_MAC.0);
+			pkt.extend_from_slice(&[0x08, 0x00]);
+			pkt.extend(ip);
+			pkt.extend(icmp);
+			pkt
+		}
+
+		#[test]
+		fn answers_arp_requests_for_the_local_address() {
+			let mp = mempool();
+			let req = Mbuf::from_bytes(&arp_request([10, 0, 0, 1]), mp).unwrap();
+			assert!(req.is_arp_request_for(0x0a00_0001));
+			assert!(!req.is_arp_request_for(0x0a00_0003));
+			assert!(req.ipv4_hdr().is_none());
+
+			let reply = req.arp_reply(LOCAL_MAC, mp).unwrap().unwrap();
+			let eth = *reply.ether_hdr().unwrap();
+			assert_eq!({ eth.d_addr }, PEER_MAC);
+			assert_eq!({ eth.s_addr }, LOCAL_MAC);
+			let arp = *reply.arp_hdr().unwrap();
+			assert_eq!(u16::from_be(arp.arp_opcode), ARP_OP_REPLY);
+			assert_eq!({ arp.arp_sha }, LOCAL_MAC);
+			assert_eq!(u32::from_be(arp.arp_sip), 0x0a00_0001);
+			assert_eq!({ arp.arp_tha }, PEER_MAC);
+			assert_eq!(u32::from_be(arp.arp_tip), 0x0a00_0002);
+			assert!(reply.arp_reply(LOCAL_MAC, mp).unwrap().is_none());
+		}
+
+		#[test]
+		fn answers_arp_requests_in_place_and_asks_for_next_hops() {
+			let mp = mempool();
+			let mut pkt = Mbuf::from_bytes(&arp_request([10, 0, 0, 1]), mp).unwrap();
+			assert!(pkt.arp_hdr().unwrap().is_ether_ipv4());
+			assert!(pkt.to_arp_reply(LOCAL_MAC));
+			let eth = *pkt.ether_hdr().unwrap();
+			assert_eq!({ eth.d_addr }, PEER_MAC);
+			assert_eq!({ eth.s_addr }, LOCAL_MAC);
+			let arp = *pkt.arp_hdr().unwrap();
+			assert_eq!(u16::from_be(arp.arp_opcode), ARP_OP_REPLY);
+			assert_eq!(u32::from_be(arp.arp_sip), 0x0a00_0001);
+			assert_eq!(u32::from_be(arp.arp_tip), 0x0a00_0002);
+			assert!(!pkt.to_arp_reply(LOCAL_MAC));
+
+			let req = pkt
+				.arp_request(LOCAL_MAC, 0x0a00_0001, 0x0a00_0003)
+				.unwrap();
+			assert_eq!({ req.ether_hdr().unwrap().d_addr }, MacAddr::BROADCAST);
+			assert!(req.is_arp_request_for(0x0a00_0003));
+			let arp = *req.arp_hdr().unwrap();
+			assert!(arp.is_ether_ipv4());
+			assert_eq!({ arp.arp_sha }, LOCAL_MAC);
+			assert_eq!(u32::from_be(arp.arp_sip), 0x0a00_0001);
+		}
+
+		#[test]
+		fn answers_echo_requests_in_place() {
+			let mp = mempool();
+			let mut pkt = Mbuf::from_bytes(&echo_request(), mp).unwrap();
+			assert!(pkt.to_icmp_echo_reply());
+			let eth = *pkt.ether_hdr().unwrap();
+			assert_eq!({ eth.d_addr }, PEER_MAC);
+			assert_eq!({ eth.s_addr }, LOCAL_MAC);
+			let ip = *pkt.ipv4_hdr().unwrap();
+			assert_eq!(u32::from_be(ip.src_addr), 0x0a00_0001);
+			assert_eq!(u32::from_be(ip.dst_addr), 0x0a00_0002);
+			assert_eq!(ip.time_to_live, IP_DEFAULT_TTL);
+			assert_eq!(
+				checksum(pkt.bytes(ETHER_HDR_LEN, IPV4_MIN_HDR_LEN).unwrap()),
+				0
+			);
+			let icmp = *pkt.icmp_hdr().unwrap();
+			assert_eq!(icmp.icmp_type, ICMP_ECHO_REPLY);
+			assert_eq!(u16::from_be(icmp.icmp_ident), 0x1234);
+			assert_eq!(checksum(pkt.bytes(34, ICMP_HDR_LEN + 4).unwrap()), 0);
+			assert!(!pkt.to_icmp_echo_reply());
+		}
+
+		#[test]
+		fn quotes_the_offending_packet_in_icmp_errors() {
+			let mp = mempool();
+			let bytes = udp_packet(6);
+			let pkt = Mbuf::from_bytes(&bytes, mp).unwrap();
+			let err = pkt
+				.icmp_error(ICMP_DEST_UNREACH, ICMP_PORT_UNREACH, LOCAL_MAC, 0x0a00_0001)
+				.unwrap()
+				.unwrap();
+			let eth = *err.ether_hdr().unwrap();
+			assert_eq!({ eth.d_addr }, PEER_MAC);
+			assert_eq!({ eth.s_addr }, LOCAL_MAC);
+			let ip = *err.ipv4_hdr().unwrap();
+			assert_eq!(u32::from_be(ip.src_addr), 0x0a00_0001);
+			assert_eq!(u32::from_be(ip.dst_addr), 0x0a00_0002);
+			assert_eq!(
+				u16::from_be(ip.total_length) as usize,
+				err.data_len() - ETHER_HDR_LEN
+			);
+			assert_eq!(
+				checksum(err.bytes(ETHER_HDR_LEN, IPV4_MIN_HDR_LEN).unwrap()),
+				0
+			);
+			let icmp = *err.icmp_hdr().unwrap();
+			assert_eq!(icmp.icmp_type, ICMP_DEST_UNREACH);
+			assert_eq!(icmp.icmp_code, ICMP_PORT_UNREACH);
+			// the IP header with its option and the UDP header
+			let quoted = err.bytes(42, err.data_len() - 42).unwrap();
+			assert_eq!(quoted, &bytes[14..14 + 24 + 8]);
+			assert_eq!(checksum(err.bytes(34, err.data_len() - 34).unwrap()), 0);
+
+			// errors about errors could loop forever
+			let none = err.icmp_error(ICMP_TIME_EXCEEDED, ICMP_EXC_TTL, LOCAL_MAC, 0x0a00_0001);
+			assert!(none.unwrap().is_none());
+			let echo = Mbuf::from_bytes(&echo_request(), mp).unwrap();
+			let some = echo.icmp_error(ICMP_TIME_EXCEEDED, ICMP_EXC_TTL, LOCAL_MAC, 0x0a00_0001);
+			assert!(some.unwrap().is_some());
+
+			let mut fragment = udp_packet(5);
+			fragment[20..22].copy_from_slice(&[0x00, 0x10]);
+			let pkt = Mbuf::from_bytes(&fragment, mp).unwrap();
+			assert!(!pkt.may_get_icmp_error());
+			assert!(pkt.icmp_error(3, 3, LOCAL_MAC, 1).unwrap().is_none());
+			let mut broadcast = udp_packet(5);
+			broadcast[26..30].copy_from_slice(&[255; 4]);
+			let pkt = Mbuf::from_bytes(&broadcast, mp).unwrap();
+			assert!(!pkt.may_get_icmp_error());
+			assert!(pkt.icmp_error(3, 3, LOCAL_MAC, 1).unwrap().is_none());
+			// unicast frames can still carry broadcast and multicast datagrams
+			for group in &[[255; 4], [224, 0, 0, 1]] {
+				let mut to_group = udp_packet(5);
+				to_group[30..34].copy_from_slice(group);
+				let pkt = Mbuf::from_bytes(&to_group, mp).unwrap();
+				assert!(!pkt.may_get_icmp_error());
+				assert!(pkt.icmp_error(3, 3, LOCAL_MAC, 1).unwrap().is_none());
+			}
+			assert!(!err.may_get_icmp_error());
+			assert!(echo.may_get_icmp_error());
+		}
+
+		#[test]
+		fn finds_the_l4_header_after_ip_options() {
+			let mp = mempool();
+			for ihl in &[5, 6] {
+				let pkt = Mbuf::from_bytes(&udp_packet(*ihl), mp).unwrap();
+				let udp = *pkt.udp_hdr().unwrap();
+				assert_eq!(u16::from_be(udp.src_port), 12345);
+				assert_eq!(u16::from_be(udp.dst_port), 53);
+				assert!(pkt.tcp_hdr().is_none());
+				assert!(pkt.icmp_hdr().is_none());
+			}
+		}
+
+		#[test]
+		fn rejects_truncated_packets() {
+			let mp = mempool();
+			let mut bytes = udp_packet(5);
+			bytes.truncate(bytes.len() - 2);
+			let pkt = Mbuf::from_bytes(&bytes, mp).unwrap();
+			assert!(pkt.ipv4_hdr().is_some());
+			assert!(pkt.udp_hdr().is_none());
+
+			let pkt = Mbuf::from_bytes(&bytes[..10], mp).unwrap();
+			assert!(pkt.ether_hdr().is_none());
+			assert!(!pkt.is_arp_request_for(0x0a00_0001));
+		}
+	}
+}
@@ -1,15 +1,18 @@
 //! The Port structure is a wrapper around physical NIC ports
 
-// TODO: It might be a good idea for Port to hold a mapping between core id and send and receive queues. This is currently done in `txbin` with `queue_id ^ 1` for the transmit queue and by ensuring that every Port always sets up an even number of queues.
-
 // DEVFLAGS: development flags - remove in production
 #![allow(dead_code)]
 
 // use crate::net::MacAddr;
 // use pnet::datalink::MacAddr;
-use std::marker::{Send, Sync};
+use std::{
+	marker::{Send, Sync},
+	mem,
+	os::raw,
+};
 
-use super::{Mbuf, Mempool, PortError};
+use super::{Error, MacAddr, Mbuf, Mempool, PortError, Ring, WrappedCString};
+use crate::dpdk_sys;
 
 #[derive(Clone, Copy)]
 pub struct Port {
@@ -21,6 +24,57 @@ pub struct Port {
 unsafe impl Sync for Port {}
 unsafe impl Send for Port {}
 
+/// How `Port::configure_with` sets a port up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PortConf {
+	/// rx and tx queue pairs
+	pub queues: u16,
+	/// descriptors per rx queue
+	pub rx_desc: u16,
+	/// descriptors per tx queue
+	pub tx_desc: u16,
+	/// largest L3 packet received, jumbo frames are turned on above 1500
+	pub mtu: u16,
+	/// `DEV_RX_OFFLOAD_*` flags, only those the device supports are used
+	pub rx_offloads: u64,
+	/// `DEV_TX_OFFLOAD_*` flags, only those the device supports are used
+	pub tx_offloads: u64,
+	pub promiscuous: bool,
+}
+
+impl PortConf {
+	pub const ETHER_OVERHEAD: u16 = 18; // ethernet header and CRC
+	pub const DEFAULT_MTU: u16 = 1500;
+
+	/// The defaults for `queues` queue pairs
+	pub fn new(queues: u16) -> Self {
+		Self {
+			queues,
+			rx_desc: Port::RTE_MP_RX_DESC_DEFAULT,
+			tx_desc: Port::RTE_MP_TX_DESC_DEFAULT,
+			mtu: Self::DEFAULT_MTU,
+			rx_offloads: dpdk_sys::DEV_RX_OFFLOAD_CHECKSUM as u64,
+			tx_offloads: (dpdk_sys::DEV_TX_OFFLOAD_IPV4_CKSUM
+				| dpdk_sys::DEV_TX_OFFLOAD_UDP_CKSUM
+				| dpdk_sys::DEV_TX_OFFLOAD_TCP_CKSUM
+				| dpdk_sys::DEV_TX_OFFLOAD_MBUF_FAST_FREE) as u64,
+			promiscuous: true,
+		}
+	}
+
+	/// The largest frame received, MTU plus ethernet overhead
+	#[inline]
+	pub fn max_rx_pkt_len(&self) -> u32 {
+		self.mtu as u32 + Self::ETHER_OVERHEAD as u32
+	}
+}
+
+impl Default for PortConf {
+	fn default() -> Self {
+		Self::new(1)
+	}
+}
+
 impl Port {
 	const PORTMASK: u8 = 0x03;
 	const DEFAULT_RSS_HF: u64 = (dpdk_sys::ETH_RSS_IP
@@ -40,7 +94,7 @@ impl Port {
 		0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a,
 	];
 
-	pub fn new(device: &'static str, id: u16) -> Result<Self, PortError> {
+	pub fn new(device: &'static str, id: u16) -> Result<Self, Error> {
 		let mut dev_info = dpdk_sys::rte_eth_dev_info::default();
 		match unsafe { dpdk_sys::rte_eth_dev_info_get(id, &mut dev_info) } {
 			0 => Ok(Self {
@@ -48,17 +102,73 @@ impl Port {
 				device,
 				dev_info,
 			}),
-			_ => Err(PortError::new()),
+			e => Err(Error::ret::<PortError>(
+				e,
+				format!("rte_eth_dev_info_get(port {})", id),
+			)),
+		}
+	}
+
+	/// Create a port named `name` that receives from `rx_rings` and sends to `tx_rings`
+	///
+	/// Queue `n` of the port is backed by ring `n` of each, so whatever is enqueued on an rx
+	/// ring is received like traffic from a NIC and whatever is sent can be dequeued from the
+	/// tx ring. Needs the ring driver, see dpdk-sys' `pmd-ring` feature. The port is
+	/// configured with no more queues than there are rings before it's started.
+	///
+	/// # Safety
+	///
+	/// The port only keeps the rings' pointers. The rings must not be dropped while the port,
+	/// or any copy of it, may still receive or send.
+	pub unsafe fn from_rings(
+		name: &'static str,
+		rx_rings: &[Ring],
+		tx_rings: &[Ring],
+	) -> Result<Self, Error> {
+		let nm = WrappedCString::to_cstring(name)?;
+		let rx = rx_rings.iter().map(Ring::get_ptr).collect::<Vec<_>>();
+		let tx = tx_rings.iter().map(Ring::get_ptr).collect::<Vec<_>>();
+		let id = dpdk_sys::rte_eth_from_rings(
+			nm.as_ptr(),
+			rx.as_ptr(),
+			rx.len() as raw::c_uint,
+			tx.as_ptr(),
+			tx.len() as raw::c_uint,
+			dpdk_sys::rte_socket_id(),
+		);
+		if id < 0 {
+			return Err(Error::rte::<PortError>(format!(
+				"rte_eth_from_rings({})",
+				name
+			)));
 		}
+		tracing::debug!(
+			port = id,
+			name,
+			rx = rx.len(),
+			tx = tx.len(),
+			"created ring port"
+		);
+		Self::new(name, id as u16)
 	}
 
-	pub fn configure(&mut self, num_cores: u16, mempool: &Mempool) -> Result<(), PortError> {
+	/// Set the port up with `num_cores` queue pairs and the default `PortConf`
+	pub fn configure(&mut self, num_cores: u16, mempool: &Mempool) -> Result<(), Error> {
+		self.configure_with(&PortConf::new(num_cores), mempool)
+	}
+
+	/// Set the port up as described by `pconf`, receiving into `mempool`
+	pub fn configure_with(&mut self, pconf: &PortConf, mempool: &Mempool) -> Result<(), Error> {
 		let mut conf = dpdk_sys::rte_eth_conf::default();
 
 		conf.rxmode.mq_mode = dpdk_sys::rte_eth_rx_mq_mode::ETH_MQ_RX_RSS;
-		conf.rxmode.max_rx_pkt_len = dpdk_sys::RTE_ETHER_MAX_LEN;
+		conf.rxmode.max_rx_pkt_len = pconf.max_rx_pkt_len();
 		conf.rxmode.split_hdr_size = 0;
-		conf.rxmode.offloads = dpdk_sys::DEV_RX_OFFLOAD_CHECKSUM as u64;
+		// DPDK refuses offloads the device can't do, software does without them
+		conf.rxmode.offloads = pconf.rx_offloads & self.dev_info.rx_offload_capa;
+		if conf.rxmode.max_rx_pkt_len > dpdk_sys::RTE_ETHER_MAX_LEN {
+			conf.rxmode.offloads |= dpdk_sys::DEV_RX_OFFLOAD_JUMBO_FRAME as u64;
+		}
 
 		conf.rx_adv_conf.rss_conf.rss_hf =
 			Self::DEFAULT_RSS_HF & self.dev_info.flow_type_rss_offloads;
@@ -66,45 +176,42 @@ impl Port {
 		conf.rx_adv_conf.rss_conf.rss_key = rss_symmetric_key;
 
 		conf.txmode.mq_mode = 0;
-		conf.txmode.offloads = 0x00000002 | 0x00000004 | 0x00000008;
-
-		// turns on optimization for fast release of mbufs.
-		if self.dev_info.tx_offload_capa & dpdk_sys::DEV_TX_OFFLOAD_MBUF_FAST_FREE as u64 > 0 {
-			conf.txmode.offloads |= dpdk_sys::DEV_TX_OFFLOAD_MBUF_FAST_FREE as u64;
-		}
+		// including the fast release of mbufs
+		conf.txmode.offloads = pconf.tx_offloads & self.dev_info.tx_offload_capa;
 
 		// configure the device
-		match unsafe { dpdk_sys::rte_eth_dev_configure(self.id, num_cores, num_cores, &conf) } {
+		let n_queues = pconf.queues;
+		match unsafe { dpdk_sys::rte_eth_dev_configure(self.id, n_queues, n_queues, &conf) } {
 			0 => {}
-			_ => return Err(PortError::new()),
+			e => {
+				return Err(Error::ret::<PortError>(
+					e,
+					format!("rte_eth_dev_configure(port {})", self.id),
+				))
+			}
 		};
 
 		// queue set up
 		let rx_conf = &self.dev_info.default_rxconf;
 		let tx_conf = &self.dev_info.default_txconf;
 
-		let mut n_cores = num_cores;
-
-		if num_cores % 2 == 1 {
-			n_cores += 1;
-		} else {
-			n_cores = num_cores;
-		}
-
-		for i in 0..n_cores {
+		for i in 0..n_queues {
 			unsafe {
 				match dpdk_sys::rte_eth_rx_queue_setup(
 					self.id,
 					i,
-					Self::RTE_MP_RX_DESC_DEFAULT,
+					pconf.rx_desc,
 					dpdk_sys::rte_eth_dev_socket_id(self.id) as u32,
 					rx_conf,
 					mempool.get_ptr(),
 				) {
 					0 => {}
-					_ => {
-						let e = PortError::new();
-						log::error!("main: couldn't set up rx queue for port {}: {}", self.id, e);
+					e => {
+						let e = Error::ret::<PortError>(
+							e,
+							format!("rte_eth_rx_queue_setup(port {}, queue {})", self.id, i),
+						);
+						log::error!("main: couldn't set up rx queue: {}", e);
 						return Err(e);
 					}
 				}
@@ -112,38 +219,65 @@ impl Port {
 				match dpdk_sys::rte_eth_tx_queue_setup(
 					self.id,
 					i,
-					Self::RTE_MP_TX_DESC_DEFAULT,
+					pconf.tx_desc,
 					dpdk_sys::rte_eth_dev_socket_id(self.id) as u32,
 					tx_conf,
 				) {
 					0 => {}
-					_ => {
-						let e = PortError::new();
-						log::error!("main: couldn't set up tx queue for port {}: {}", self.id, e);
+					e => {
+						let e = Error::ret::<PortError>(
+							e,
+							format!("rte_eth_tx_queue_setup(port {}, queue {})", self.id, i),
+						);
+						log::error!("main: couldn't set up tx queue: {}", e);
 						return Err(e);
 					}
 				}
 			}
 		}
 
+		if !pconf.promiscuous {
+			return Ok(());
+		}
+
 		// sets the port's promiscuous mode
 		match unsafe { dpdk_sys::rte_eth_promiscuous_enable(self.id) } {
 			0 => {}
-			_ => return Err(PortError::new()),
+			e => {
+				return Err(Error::ret::<PortError>(
+					e,
+					format!("rte_eth_promiscuous_enable(port {})", self.id),
+				))
+			}
 		};
 		Ok(())
 	}
 
 	/// Start the port
-	pub fn start(&self) -> Result<(), PortError> {
+	pub fn start(&self) -> Result<(), Error> {
 		unsafe {
 			match dpdk_sys::rte_eth_dev_start(self.id) {
 				0 => Ok(()),
-				_ => Err(PortError::new()),
+				e => Err(Error::ret::<PortError>(
+					e,
+					format!("rte_eth_dev_start(port {})", self.id),
+				)),
 			}
 		}
 	}
 
+	/// The port's ethernet address
+	pub fn mac_addr(&self) -> Result<MacAddr, Error> {
+		let mut addr = dpdk_sys::rte_ether_addr::default();
+		match unsafe { dpdk_sys::rte_eth_macaddr_get(self.id, &mut addr) } {
+			0 => Ok(MacAddr(addr.addr_bytes)),
+			e => Err(Error::ret::<PortError>(
+				e,
+				format!("rte_eth_macaddr_get(port {})", self.id),
+			)),
+		}
+	}
+
 	/// Get user device in PCI notation
 	pub fn get_name(&self) -> &str {
 		self.device
@@ -154,8 +288,11 @@ impl Port {
 		let mut ptrs = Vec::with_capacity(sz);
 
 		let len = unsafe {
-			dpdk_sys::_rte_eth_rx_burst(self.id, queue_id, ptrs.as_mut_ptr(), sz as u16)
+			dpdk_sys::rte_eth_rx_burst(self.id, queue_id, ptrs.as_mut_ptr(), sz as u16)
 		};
+		if len > 0 {
+			tracing::trace!(port = self.id, queue = queue_id, received = len, "rx burst");
+		}
 
 		unsafe {
 			ptrs.set_len(len as usize);
@@ -166,12 +303,15 @@ impl Port {
 	}
 
 	/// Send packets out of the port
-	pub fn send(&self, pkts: &Vec<Mbuf>, queue_id: u16) -> usize {
+	///
+	/// The sent packets are taken off the front of `pkts`, the driver frees them once they
+	/// are out. Those the tx queue had no room for are left in `pkts`.
+	pub fn send(&self, pkts: &mut Vec<Mbuf>, queue_id: u16) -> usize {
 		let len = pkts.len();
-		let mut ptrs = pkts.into_iter().map(Mbuf::get_ptr).collect::<Vec<_>>();
+		let mut ptrs = pkts.iter().map(Mbuf::get_ptr).collect::<Vec<_>>();
 
 		let count = unsafe {
-			dpdk_sys::_rte_eth_tx_burst(
+			dpdk_sys::rte_eth_tx_burst(
 				self.id,
 				queue_id,
 				// ptrs.as_ptr() as *mut *mut dpdk_sys::rte_mbuf,
@@ -179,9 +319,10 @@ impl Port {
 				ptrs.len() as u16,
 			) as usize
 		};
-		if count < len {
-			super::mbuf_free_bulk(ptrs);
+		if count > 0 || len > 0 {
+			tracing::trace!(port = self.id, queue = queue_id, sent = count, offered = len, "tx burst");
 		}
+		pkts.drain(..count).for_each(mem::forget);
 		count
 	}
 }
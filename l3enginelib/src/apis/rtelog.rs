@@ -0,0 +1,199 @@
+//! Forwards DPDK's own log messages into the `log` crate
+//!
+//! DPDK writes through a `FILE` stream. `RteLog::install` replaces that stream with one
+//! created by `fopencookie` whose write callback hands every line to `log` at the level
+//! of the message being printed. The component prefix DPDK puts in front of its messages
+//! ("EAL", "MBUF", ...) becomes the record's target, under `dpdk::`.
+//!
+//! Install it before `eal_init` to capture the EAL's start up messages too.
+
+use std::{
+	ffi::c_void,
+	os::raw,
+	ptr, slice,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{Error, LogError, WrappedCString};
+use crate::dpdk_sys;
+
+// glibc's fopencookie is not exposed by the libc crate
+type CookieRead = unsafe extern "C" fn(*mut c_void, *mut raw::c_char, usize) -> isize;
+type CookieWrite = unsafe extern "C" fn(*mut c_void, *const raw::c_char, usize) -> isize;
+type CookieSeek = unsafe extern "C" fn(*mut c_void, *mut i64, raw::c_int) -> raw::c_int;
+type CookieClose = unsafe extern "C" fn(*mut c_void) -> raw::c_int;
+
+#[repr(C)]
+struct CookieIoFunctions {
+	read: Option<CookieRead>,
+	write: Option<CookieWrite>,
+	seek: Option<CookieSeek>,
+	close: Option<CookieClose>,
+}
+
+extern "C" {
+	fn fopencookie(
+		cookie: *mut c_void,
+		mode: *const raw::c_char,
+		funcs: CookieIoFunctions,
+	) -> *mut libc::FILE;
+}
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Bridge between DPDK's logging and the `log` crate
+pub struct RteLog;
+
+impl RteLog {
+	// DPDK log levels, see rte_log.h
+	const RTE_LOG_EMERG: u32 = 1;
+	const RTE_LOG_ALERT: u32 = 2;
+	const RTE_LOG_CRIT: u32 = 3;
+	const RTE_LOG_ERR: u32 = 4;
+	const RTE_LOG_WARNING: u32 = 5;
+	const RTE_LOG_NOTICE: u32 = 6;
+	const RTE_LOG_INFO: u32 = 7;
+	const RTE_LOG_DEBUG: u32 = 8;
+
+	/// Send DPDK's log messages to the `log` crate
+	///
+	/// DPDK's global level is aligned with `log::max_level()` so messages that would
+	/// be filtered out anyway are not formatted.
+	pub fn install() -> Result<(), Error> {
+		if INSTALLED.swap(true, Ordering::AcqRel) {
+			return Ok(());
+		}
+		let mode = WrappedCString::to_cstring("w")?;
+		let funcs = CookieIoFunctions {
+			read: None,
+			write: Some(log_write),
+			seek: None,
+			close: None,
+		};
+		let stream = unsafe { fopencookie(ptr::null_mut(), mode.as_ptr(), funcs) };
+		if stream.is_null() {
+			INSTALLED.store(false, Ordering::Release);
+			return Err(Error::new(LogError::NoStream, "fopencookie"));
+		}
+		// flush every message as soon as DPDK prints it
+		unsafe { libc::setvbuf(stream, ptr::null_mut(), libc::_IOLBF, 0) };
+		if unsafe { dpdk_sys::rte_openlog_stream(stream as *mut dpdk_sys::FILE) } != 0 {
+			INSTALLED.store(false, Ordering::Release);
+			return Err(Error::new(LogError::NoStream, "rte_openlog_stream"));
+		}
+		Self::set_global_level(log::max_level());
+		Ok(())
+	}
+
+	/// Set the level of every DPDK log type
+	pub fn set_global_level(level: log::LevelFilter) {
+		unsafe { dpdk_sys::rte_log_set_global_level(Self::to_rte(level)) };
+	}
+
+	/// Set the level of the DPDK log types matching the glob `pattern`, e.g. `"pmd.net.*"`
+	pub fn set_level(pattern: &str, level: log::LevelFilter) -> Result<(), Error> {
+		let pat = WrappedCString::to_cstring(pattern)?;
+		match unsafe { dpdk_sys::rte_log_set_level_pattern(pat.as_ptr(), Self::to_rte(level)) } {
+			0 => Ok(()),
+			_ => Err(Error::new(
+				LogError::BadPattern(pattern.to_owned()),
+				"rte_log_set_level_pattern",
+			)),
+		}
+	}
+
+	/// Set the levels from a spec like `"eal=debug,pmd.net.*=warn"`
+	///
+	/// A bare level, without a pattern, sets the global level.
+	pub fn parse_levels(spec: &str) -> Result<(), Error> {
+		for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+			let mut parts = directive.splitn(2, '=');
+			let (pattern, level) = match (parts.next(), parts.next()) {
+				(Some(p), Some(l)) => (Some(p), l),
+				(Some(l), None) => (None, l),
+				_ => {
+					return Err(Error::new(
+						LogError::BadPattern(directive.to_owned()),
+						"RteLog::parse_levels",
+					))
+				}
+			};
+			let level = level
+				.parse::<log::LevelFilter>()
+				.map_err(|_| {
+					Error::new(LogError::BadLevel(level.to_owned()), "RteLog::parse_levels")
+				})?;
+			match pattern {
+				Some(p) => Self::set_level(p, level)?,
+				None => Self::set_global_level(level),
+			}
+		}
+		Ok(())
+	}
+
+	fn to_rte(level: log::LevelFilter) -> u32 {
+		match level {
+			// DPDK has no "off", keep the emergencies
+			log::LevelFilter::Off => Self::RTE_LOG_EMERG,
+			log::LevelFilter::Error => Self::RTE_LOG_ERR,
+			log::LevelFilter::Warn => Self::RTE_LOG_WARNING,
+			log::LevelFilter::Info => Self::RTE_LOG_INFO,
+			log::LevelFilter::Debug | log::LevelFilter::Trace => Self::RTE_LOG_DEBUG,
+		}
+	}
+
+	fn from_rte(level: i32) -> log::Level {
+		match level as u32 {
+			Self::RTE_LOG_EMERG | Self::RTE_LOG_ALERT | Self::RTE_LOG_CRIT | Self::RTE_LOG_ERR => {
+				log::Level::Error
+			}
+			Self::RTE_LOG_WARNING => log::Level::Warn,
+			Self::RTE_LOG_NOTICE | Self::RTE_LOG_INFO => log::Level::Info,
+			_ => log::Level::Debug,
+		}
+	}
+}
+
+/// Split DPDK's "COMPONENT: message" into the component and the message
+fn split_component(line: &str) -> (&str, &str) {
+	match line.find(": ") {
+		Some(idx)
+			if idx > 0
+				&& line[..idx]
+					.chars()
+					.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') =>
+		{
+			(&line[..idx], &line[idx + 2..])
+		}
+		_ => ("", line),
+	}
+}
+
+unsafe extern "C" fn log_write(_cookie: *mut c_void, buf: *const raw::c_char, size: usize) -> isize {
+	if buf.is_null() || size == 0 {
+		return 0;
+	}
+	let bytes = slice::from_raw_parts(buf as *const u8, size);
+	let text = String::from_utf8_lossy(bytes);
+	let level = RteLog::from_rte(dpdk_sys::rte_log_cur_msg_loglevel());
+	if level > log::max_level() {
+		return size as isize;
+	}
+
+	for line in text.lines().filter(|l| !l.trim().is_empty()) {
+		let (component, msg) = split_component(line);
+		let target = if component.is_empty() {
+			String::from("dpdk")
+		} else {
+			format!("dpdk::{}", component.to_ascii_lowercase())
+		};
+		log::logger().log(
+			&log::Record::builder()
+				.args(format_args!("{}", msg))
+				.level(level)
+				.target(&target)
+				.build(),
+		);
+	}
+	size as isize
+}
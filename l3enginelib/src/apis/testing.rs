@@ -0,0 +1,18 @@
+//! What the unit tests on the soft backend share
+
+use std::sync::Once;
+
+use super::{eal_init, Mempool};
+
+/// The EAL and mempool every test shares, whichever test runs first sets them up
+///
+/// Tests that count what's left in a mempool make one of their own after calling this.
+pub(crate) fn mempool() -> &'static Mempool {
+	static INIT: Once = Once::new();
+	static POOL: state::Storage<Mempool> = state::Storage::new();
+	INIT.call_once(|| {
+		eal_init(vec![String::from("l3enginelib-tests")]).unwrap();
+		POOL.set(Mempool::new("TEST_POOL").unwrap());
+	});
+	POOL.get()
+}
@@ -0,0 +1,1393 @@
+//! The engine's setup, read from a TOML file
+//!
+//! Every section and key is optional, whatever is left out takes the value `Config::default()`
+//! has. `l3engine.toml` next to the crate's manifest lists every key.
+//!
+//! ```toml
+//! [eal]
+//! lcores = "0-1"
+//!
+//! [[mempool]]
+//! name = "GLOBAL_MEMPOOL"
+//! size = 32767
+//!
+//! [[port]]
+//! name = "port0"
+//! devargs = "net_pcap0,rx_pcap=in.pcap,tx_pcap=out.pcap"
+//! mtu = 1500
+//! tx_offloads = ["ipv4_cksum", "udp_cksum", "tcp_cksum"]
+//!
+//! [channel]
+//! port = "port0"
+//! ```
+//!
+//! The whole file is checked before anything is set up and every mistake in it is reported
+//! with the key it was found at, e.g. `port[1].mempool: no mempool named "pool1"`.
+
+use std::{
+	collections::{BTreeSet, HashSet},
+	convert::TryFrom,
+	env, fmt, fs, io,
+	net::Ipv4Addr,
+	path::{Path, PathBuf},
+	str::FromStr,
+	time::Duration,
+};
+
+use thiserror::Error;
+use toml::{value::Table, Value};
+
+use crate::{
+	dpdk_sys,
+	engine::{ArpTable, Icmp, RouteTable, Service},
+	MacAddr, Mempool, PortConf,
+};
+
+/// Where the engine and the mux read the configuration from when it isn't given on the
+/// command line
+pub const CONFIG_ENV: &str = "L3ENGINE_CONFIG";
+
+/// Longest mempool name DPDK takes, `RTE_RING_NAMESIZE` less the "MP_" prefix
+const MEMPOOL_NAMESIZE: usize = 29;
+/// `RTE_MEMPOOL_CACHE_MAX_SIZE`
+const MEMPOOL_CACHE_MAX_SIZE: u32 = 512;
+/// `RTE_MAX_QUEUES_PER_PORT`
+const MAX_QUEUES_PER_PORT: u16 = 1024;
+/// Smallest MTU an IPv4 link may have
+const MIN_MTU: u16 = 68;
+
+const RX_OFFLOADS: &[(&str, u32)] = &[
+	("vlan_strip", dpdk_sys::DEV_RX_OFFLOAD_VLAN_STRIP),
+	("ipv4_cksum", dpdk_sys::DEV_RX_OFFLOAD_IPV4_CKSUM),
+	("udp_cksum", dpdk_sys::DEV_RX_OFFLOAD_UDP_CKSUM),
+	("tcp_cksum", dpdk_sys::DEV_RX_OFFLOAD_TCP_CKSUM),
+	("checksum", dpdk_sys::DEV_RX_OFFLOAD_CHECKSUM),
+	("scatter", dpdk_sys::DEV_RX_OFFLOAD_SCATTER),
+	("rss_hash", dpdk_sys::DEV_RX_OFFLOAD_RSS_HASH),
+];
+
+const TX_OFFLOADS: &[(&str, u32)] = &[
+	("vlan_insert", dpdk_sys::DEV_TX_OFFLOAD_VLAN_INSERT),
+	("ipv4_cksum", dpdk_sys::DEV_TX_OFFLOAD_IPV4_CKSUM),
+	("udp_cksum", dpdk_sys::DEV_TX_OFFLOAD_UDP_CKSUM),
+	("tcp_cksum", dpdk_sys::DEV_TX_OFFLOAD_TCP_CKSUM),
+	("tcp_tso", dpdk_sys::DEV_TX_OFFLOAD_TCP_TSO),
+	("multi_segs", dpdk_sys::DEV_TX_OFFLOAD_MULTI_SEGS),
+	("mbuf_fast_free", dpdk_sys::DEV_TX_OFFLOAD_MBUF_FAST_FREE),
+];
+
+/// Why a configuration couldn't be loaded
+#[derive(Error, Debug)]
+pub enum ConfigError {
+	#[error("couldn't read {}: {source}", path.display())]
+	Io {
+		path: PathBuf,
+		#[source]
+		source: io::Error,
+	},
+	#[error("not valid TOML: {0}")]
+	Syntax(#[from] toml::de::Error),
+	#[error("{} mistake(s) in the configuration:{}", .0.len(), list(.0))]
+	Invalid(Vec<Invalid>),
+}
+
+/// A value that is out of place, with the key it was found at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invalid {
+	/// e.g. `port[0].queues`
+	pub key: String,
+	pub reason: String,
+}
+
+impl fmt::Display for Invalid {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.key, self.reason)
+	}
+}
+
+fn list(invalid: &[Invalid]) -> String {
+	invalid.iter().map(|i| format!("\n  {}", i)).collect()
+}
+
+/// The EAL options, the ports' `devargs` are added to them
+#[derive(Debug, Clone, PartialEq)]
+pub struct EalConfig {
+	/// `-l`, e.g. "0-3,8"; the first lcore is the main lcore
+	pub lcores: String,
+	/// `-n`
+	pub memory_channels: u32,
+	/// `--base-virtaddr`, so secondaries can map the hugepages at the same address
+	pub base_virtaddr: Option<u64>,
+	/// `--file-prefix`, to run more than one primary on a machine
+	pub file_prefix: Option<String>,
+	/// Passed to the EAL as they are, after everything else
+	pub args: Vec<String>,
+}
+
+impl Default for EalConfig {
+	fn default() -> Self {
+		Self {
+			lcores: String::from("0-1"),
+			memory_channels: 4,
+			base_virtaddr: Some(0x7f000000000),
+			file_prefix: None,
+			args: Vec::new(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolConfig {
+	pub name: String,
+	/// mbufs in the pool
+	pub size: u32,
+	/// mbufs cached per lcore
+	pub cache_size: u32,
+	/// bytes of packet data an mbuf holds past its headroom
+	pub data_room: u32,
+}
+
+impl MempoolConfig {
+	fn new(name: &str) -> Self {
+		Self {
+			name: name.to_owned(),
+			size: Mempool::NUM_MBUFS,
+			cache_size: Mempool::MBUF_CACHE_SIZE,
+			data_room: Mempool::RX_MBUF_DATA_SIZE,
+		}
+	}
+
+	/// Create the mempool
+	pub fn create(&self) -> Result<Mempool, crate::Error> {
+		Mempool::with_size(&self.name, self.size, self.cache_size, self.data_room)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortConfig {
+	pub name: String,
+	/// DPDK's port id, the order the ports were probed in
+	pub id: u16,
+	/// The device and its arguments, e.g. "0000:3b:00.0" or "net_tap0,iface=tap0"
+	///
+	/// `net_*` devices are created with `--vdev`, anything else is allowed with `-a`.
+	pub devargs: Option<String>,
+	/// The mempool the rx queues receive into
+	pub mempool: String,
+	pub conf: PortConf,
+	/// The port's side of the engine, for the roles policy
+	pub role: Option<Role>,
+	/// The port's address and the prefix length of its subnet, ARP for it is answered
+	pub ip: Option<(Ipv4Addr, u8)>,
+}
+
+impl PortConfig {
+	/// The EAL arguments that probe the port's device
+	fn eal_args(&self) -> Option<[String; 2]> {
+		self.devargs.as_ref().map(|devargs| {
+			let opt = if devargs.starts_with("net_") {
+				"--vdev"
+			} else {
+				"-a"
+			};
+			[opt.to_owned(), devargs.clone()]
+		})
+	}
+}
+
+/// The channel to the mux
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelConfig {
+	/// The mux's client id
+	pub client: u16,
+	/// The port whose packets go through the channel
+	pub port: String,
+	/// Most packets moved between the port and the channel at a time
+	pub burst: usize,
+	/// How long an attach waits for the channel to be reset
+	pub attach_wait: Duration,
+}
+
+/// How the engine forwards what its ports receive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+	/// The channel's port and the mux exchange packets, the other ports drop what they receive
+	Mux,
+	/// What one port of a pair receives goes out of the other
+	Pairs,
+	/// Downlinks forward to the uplink, the uplink to its downlink or by the routes
+	Roles,
+	/// Every port forwards by the routes
+	Routes,
+	/// The ports are bridged, packets go where their destination MAC was heard
+	Bridge,
+	/// IPv4 is routed between the subnets of the ports
+	Router,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+	Uplink,
+	Downlink,
+}
+
+/// An IPv4 prefix and the port that reaches it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteConfig {
+	pub prefix: Ipv4Addr,
+	pub len: u8,
+	pub port: String,
+	/// The gateway the router hands packets to, none when the prefix is on the port's link
+	pub via: Option<Ipv4Addr>,
+}
+
+/// A neighbour the router knows the MAC address of from the start
+#[derive(Debug, Clone, PartialEq)]
+pub struct NeighborConfig {
+	pub ip: Ipv4Addr,
+	pub mac: MacAddr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardConfig {
+	pub policy: Policy,
+	/// Port names, for the pairs policy
+	pub pairs: Vec<(String, String)>,
+	pub routes: Vec<RouteConfig>,
+	/// How long the bridge remembers a quiet station
+	pub mac_aging: Duration,
+	/// Most stations the bridge remembers
+	pub mac_table_size: usize,
+	/// Permanent ARP entries
+	pub neighbors: Vec<NeighborConfig>,
+	/// How long a learned neighbour is remembered after its last ARP
+	pub arp_aging: Duration,
+	/// Most routes the router's table holds, the ports' subnets included
+	pub max_routes: u32,
+}
+
+impl Default for ForwardConfig {
+	fn default() -> Self {
+		Self {
+			policy: Policy::Mux,
+			pairs: Vec::new(),
+			routes: Vec::new(),
+			mac_aging: Duration::from_secs(300),
+			mac_table_size: 4096,
+			neighbors: Vec::new(),
+			arp_aging: ArpTable::DEFAULT_AGING,
+			max_routes: RouteTable::DEFAULT_MAX_ROUTES,
+		}
+	}
+}
+
+/// What the engine answers with ICMP
+#[derive(Debug, Clone, PartialEq)]
+pub struct IcmpConfig {
+	/// Whether pings to the ports' addresses are answered
+	pub echo: bool,
+	/// Most ICMP errors sent a second, none if 0
+	pub errors_per_sec: u32,
+	/// The UDP and TCP ports served at the ports' addresses, none to hand the mux whatever
+	/// arrives
+	pub services: Option<Vec<Service>>,
+}
+
+impl Default for IcmpConfig {
+	fn default() -> Self {
+		Self {
+			echo: true,
+			errors_per_sec: Icmp::DEFAULT_ERRORS_PER_SEC,
+			services: None,
+		}
+	}
+}
+
+/// Everything `l3enginebin` sets up
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+	pub eal: EalConfig,
+	pub mempools: Vec<MempoolConfig>,
+	pub ports: Vec<PortConfig>,
+	pub channel: ChannelConfig,
+	pub forward: ForwardConfig,
+	pub icmp: IcmpConfig,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		let mempool = MempoolConfig::new("GLOBAL_MEMPOOL");
+		let port = PortConfig {
+			name: String::from("port0"),
+			id: 0,
+			devargs: None,
+			mempool: mempool.name.clone(),
+			conf: PortConf::new(2), // a queue pair per lcore
+			role: None,
+			ip: None,
+		};
+		let channel = ChannelConfig {
+			client: 0,
+			port: port.name.clone(),
+			burst: 32,
+			attach_wait: Duration::from_secs(1),
+		};
+		Self {
+			eal: EalConfig::default(),
+			mempools: vec![mempool],
+			ports: vec![port],
+			channel,
+			forward: ForwardConfig::default(),
+			icmp: IcmpConfig::default(),
+		}
+	}
+}
+
+impl Config {
+	/// Read and check the configuration at `path`
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+		let path = path.as_ref();
+		fs::read_to_string(path)
+			.map_err(|source| ConfigError::Io {
+				path: path.to_owned(),
+				source,
+			})?
+			.parse()
+	}
+
+	/// The path named by the process' first argument or `L3ENGINE_CONFIG`, if either is set
+	pub fn path_from_env() -> Option<String> {
+		env::args().nth(1).or_else(|| env::var(CONFIG_ENV).ok())
+	}
+
+	/// The arguments for `eal_init`
+	pub fn eal_args(&self) -> Vec<String> {
+		let eal = &self.eal;
+		let mut args = vec![
+			String::from("l3enginebin"),
+			String::from("-l"),
+			eal.lcores.clone(),
+			String::from("-n"),
+			eal.memory_channels.to_string(),
+			String::from("--proc-type=primary"),
+		];
+		if let Some(addr) = eal.base_virtaddr {
+			args.push(format!("--base-virtaddr={:#x}", addr));
+		}
+		if let Some(prefix) = &eal.file_prefix {
+			args.push(format!("--file-prefix={}", prefix));
+		}
+		args.extend(self.ports.iter().filter_map(PortConfig::eal_args).flatten());
+		args.extend(eal.args.iter().cloned());
+		args
+	}
+
+	/// The arguments for the mux's `eal_init`, a secondary of the engine running on `lcores`
+	///
+	/// The secondary finds the engine's hugepages and devices through the engine's runtime
+	/// directory, so it takes the same file prefix.
+	pub fn secondary_eal_args(&self, lcores: &str) -> Vec<String> {
+		let eal = &self.eal;
+		let mut args = vec![
+			String::from("l3enginemux"),
+			String::from("-l"),
+			lcores.to_owned(),
+			String::from("-n"),
+			eal.memory_channels.to_string(),
+			String::from("--proc-type=secondary"),
+		];
+		if let Some(prefix) = &eal.file_prefix {
+			args.push(format!("--file-prefix={}", prefix));
+		}
+		args
+	}
+
+	/// The lcores `eal.lcores` enables, in order
+	pub fn lcores(&self) -> BTreeSet<u32> {
+		parse_lcores(&self.eal.lcores).unwrap_or_default()
+	}
+
+	pub fn mempool(&self, name: &str) -> Option<&MempoolConfig> {
+		self.mempools.iter().find(|mp| mp.name == name)
+	}
+
+	pub fn port(&self, name: &str) -> Option<&PortConfig> {
+		self.ports.iter().find(|p| p.name == name)
+	}
+
+	/// Check the values against each other and against what DPDK accepts
+	fn validate(&self, errs: &mut Vec<Invalid>) {
+		let mut err = |key: String, reason: String| errs.push(Invalid { key, reason });
+
+		match parse_lcores(&self.eal.lcores) {
+			Ok(l) if l.is_empty() => err("eal.lcores".into(), "no lcores enabled".into()),
+			Ok(_) => {}
+			Err(e) => err("eal.lcores".into(), e),
+		}
+		if self.eal.memory_channels == 0 {
+			err("eal.memory_channels".into(), "must be at least 1".into());
+		}
+		if let Some(prefix) = &self.eal.file_prefix {
+			if prefix.is_empty() || prefix.contains(|c: char| c == '/' || c.is_whitespace()) {
+				err(
+					"eal.file_prefix".into(),
+					format!("{:?} must be a non-empty file name", prefix),
+				);
+			}
+		}
+
+		if self.mempools.is_empty() {
+			err("mempool".into(), "at least one mempool is needed".into());
+		}
+		let mut names = HashSet::new();
+		for (i, mp) in self.mempools.iter().enumerate() {
+			let key = |k: &str| format!("mempool[{}].{}", i, k);
+			if mp.name.is_empty() || mp.name.len() >= MEMPOOL_NAMESIZE {
+				err(
+					key("name"),
+					format!("must be 1 to {} bytes long", MEMPOOL_NAMESIZE - 1),
+				);
+			} else if !names.insert(&mp.name) {
+				err(key("name"), format!("{:?} is already taken", mp.name));
+			}
+			if mp.size == 0 {
+				err(key("size"), "must be at least 1".into());
+			}
+			if mp.cache_size > MEMPOOL_CACHE_MAX_SIZE {
+				err(
+					key("cache_size"),
+					format!("must be at most {}", MEMPOOL_CACHE_MAX_SIZE),
+				);
+			} else if mp.cache_size as u64 * 3 / 2 > mp.size as u64 {
+				// DPDK flushes a cache at 1.5 times its size
+				err(
+					key("cache_size"),
+					format!(
+						"must be at most 2/3 of the size, {}",
+						mp.size as u64 * 2 / 3
+					),
+				);
+			}
+			let room = mp.data_room.checked_add(Mempool::RTE_PKTMBUF_HEADROOM);
+			if mp.data_room == 0 || room.filter(|room| *room <= u16::MAX as u32).is_none() {
+				err(
+					key("data_room"),
+					format!(
+						"must be 1 to {}",
+						u16::MAX as u32 - Mempool::RTE_PKTMBUF_HEADROOM
+					),
+				);
+			}
+		}
+
+		if self.ports.is_empty() {
+			err("port".into(), "at least one port is needed".into());
+		}
+		let lcores = self.lcores().len();
+		let mut names = HashSet::new();
+		let mut ids = HashSet::new();
+		for (i, port) in self.ports.iter().enumerate() {
+			let key = |k: &str| format!("port[{}].{}", i, k);
+			if port.name.is_empty() {
+				err(key("name"), "must not be empty".into());
+			} else if !names.insert(&port.name) {
+				err(key("name"), format!("{:?} is already taken", port.name));
+			}
+			if !ids.insert(port.id) {
+				err(key("id"), format!("port {} is already configured", port.id));
+			}
+			if let Some(devargs) = &port.devargs {
+				if devargs.is_empty() || devargs.starts_with(',') {
+					err(key("devargs"), "must start with the device".into());
+				}
+			}
+			let conf = &port.conf;
+			if conf.queues == 0 || conf.queues > MAX_QUEUES_PER_PORT {
+				err(
+					key("queues"),
+					format!("must be 1 to {}", MAX_QUEUES_PER_PORT),
+				);
+			} else if (conf.queues as usize) < lcores {
+				// every lcore polls its own queue pair of every port
+				err(
+					key("queues"),
+					format!(
+						"{} queue pair(s) but eal.lcores enables {} lcores, each needs one",
+						conf.queues, lcores
+					),
+				);
+			}
+			if conf.rx_desc == 0 {
+				err(key("rx_desc"), "must be at least 1".into());
+			}
+			if conf.tx_desc == 0 {
+				err(key("tx_desc"), "must be at least 1".into());
+			}
+			if conf.mtu < MIN_MTU {
+				err(key("mtu"), format!("must be at least {}", MIN_MTU));
+			}
+			match self.mempool(&port.mempool) {
+				Some(mp) => {
+					let scatter = conf.rx_offloads & dpdk_sys::DEV_RX_OFFLOAD_SCATTER as u64 != 0;
+					if !scatter && conf.max_rx_pkt_len() > mp.data_room {
+						err(
+							key("mtu"),
+							format!(
+								"{} byte frames don't fit the {} byte mbufs of mempool {:?}, \
+								 lower the MTU or turn on the scatter rx offload",
+								conf.max_rx_pkt_len(),
+								mp.data_room,
+								mp.name
+							),
+						);
+					}
+				}
+				None => err(
+					key("mempool"),
+					format!("no mempool named {:?}", port.mempool),
+				),
+			}
+		}
+
+		if self.port(&self.channel.port).is_none() {
+			err(
+				"channel.port".into(),
+				format!("no port named {:?}", self.channel.port),
+			);
+		}
+		if self.channel.burst == 0 {
+			err("channel.burst".into(), "must be at least 1".into());
+		}
+
+		self.validate_forward(&mut err);
+	}
+
+	fn validate_forward(&self, err: &mut impl FnMut(String, String)) {
+		let fwd = &self.forward;
+		let mut paired = HashSet::new();
+		for (i, (a, b)) in fwd.pairs.iter().enumerate() {
+			for (j, name) in [a, b].iter().enumerate() {
+				let key = format!("forward.pairs[{}][{}]", i, j);
+				if self.port(name).is_none() {
+					err(key, format!("no port named {:?}", name));
+				} else if !paired.insert(*name) {
+					err(key, format!("port {:?} is already in a pair", name));
+				}
+			}
+		}
+		for (i, route) in fwd.routes.iter().enumerate() {
+			let port = match self.port(&route.port) {
+				Some(port) => port,
+				None => {
+					err(
+						format!("forward.routes[{}].port", i),
+						format!("no port named {:?}", route.port),
+					);
+					continue;
+				}
+			};
+			match (route.via, port.ip) {
+				(Some(via), Some((addr, len))) if !same_subnet(via, addr, len) => err(
+					format!("forward.routes[{}].via", i),
+					format!(
+						"{} is not on the subnet of port {:?}, {}/{}",
+						via, route.port, addr, len
+					),
+				),
+				_ => {}
+			}
+		}
+
+		match fwd.policy {
+			Policy::Mux => {}
+			Policy::Pairs if fwd.pairs.is_empty() => err(
+				"forward.pairs".into(),
+				"the pairs policy needs at least one pair".into(),
+			),
+			Policy::Pairs => {}
+			Policy::Roles => {
+				let mut uplinks = 0;
+				let mut downlinks = 0;
+				for (i, port) in self.ports.iter().enumerate() {
+					match port.role {
+						Some(Role::Uplink) => uplinks += 1,
+						Some(Role::Downlink) => downlinks += 1,
+						None => err(
+							format!("port[{}].role", i),
+							"the roles policy needs a role for every port".into(),
+						),
+					}
+				}
+				if uplinks != 1 {
+					err(
+						"forward.policy".into(),
+						format!("the roles policy needs one uplink, found {}", uplinks),
+					);
+				}
+				if downlinks == 0 {
+					err(
+						"forward.policy".into(),
+						"the roles policy needs at least one downlink".into(),
+					);
+				} else if downlinks > 1 && fwd.routes.is_empty() {
+					err(
+						"forward.routes".into(),
+						format!(
+							"the uplink needs routes to tell the {} downlinks apart",
+							downlinks
+						),
+					);
+				}
+			}
+			Policy::Routes if fwd.routes.is_empty() => err(
+				"forward.routes".into(),
+				"the routes policy needs at least one route".into(),
+			),
+			Policy::Routes => {}
+			Policy::Bridge if self.ports.len() < 2 => err(
+				"forward.policy".into(),
+				"the bridge policy needs at least two ports".into(),
+			),
+			Policy::Bridge => {}
+			Policy::Router => {
+				for (i, port) in self.ports.iter().enumerate() {
+					if port.ip.is_none() {
+						err(
+							format!("port[{}].ip", i),
+							"the router policy needs an address for every port".into(),
+						);
+					}
+				}
+			}
+		}
+		if fwd.mac_aging.as_secs() == 0 {
+			err("forward.mac_aging_secs".into(), "must be at least 1".into());
+		}
+		if fwd.mac_table_size == 0 {
+			err("forward.mac_table_size".into(), "must be at least 1".into());
+		}
+		if fwd.arp_aging.as_secs() == 0 {
+			err("forward.arp_aging_secs".into(), "must be at least 1".into());
+		}
+		if fwd.max_routes == 0 {
+			err("forward.max_routes".into(), "must be at least 1".into());
+		}
+	}
+}
+
+impl FromStr for Config {
+	type Err = ConfigError;
+
+	/// Parse and check a configuration
+	fn from_str(s: &str) -> Result<Self, ConfigError> {
+		let root = s.parse::<Value>()?;
+		let mut errs = Vec::new();
+		let config = {
+			let mut r = Reader::new(&mut errs);
+			let root = r.table(&root, "").unwrap_or_default();
+			let mut config = Config::default();
+			r.read_config(&root, &mut config);
+			config
+		};
+		config.validate(&mut errs);
+		if errs.is_empty() {
+			Ok(config)
+		} else {
+			Err(ConfigError::Invalid(errs))
+		}
+	}
+}
+
+/// Parses "0-3,8" style lcore lists, every lcore must be below `RTE_MAX_LCORE`
+fn parse_lcores(list: &str) -> Result<BTreeSet<u32>, String> {
+	let bad = || format!("{:?} is not a list of lcores like \"0-3,8\"", list);
+	let mut lcores = BTreeSet::new();
+	for part in list.split(',').map(str::trim) {
+		let (lo, hi) = match part.find('-') {
+			Some(idx) => (&part[..idx], &part[idx + 1..]),
+			None => (part, part),
+		};
+		let (lo, hi) = (
+			lo.trim().parse::<u32>().map_err(|_| bad())?,
+			hi.trim().parse::<u32>().map_err(|_| bad())?,
+		);
+		if lo > hi {
+			return Err(bad());
+		}
+		// checked before the range is expanded, "0-4000000000" would take a while
+		if hi >= dpdk_sys::RTE_MAX_LCORE {
+			return Err(format!("lcores go up to {}", dpdk_sys::RTE_MAX_LCORE - 1));
+		}
+		lcores.extend(lo..=hi);
+	}
+	Ok(lcores)
+}
+
+/// Parses "10.0.0.1/24" style addresses with the prefix length of their subnet
+fn parse_cidr(cidr: &str) -> Option<(Ipv4Addr, u8)> {
+	let idx = cidr.find('/')?;
+	let addr = cidr[..idx].trim().parse::<Ipv4Addr>().ok()?;
+	match cidr[idx + 1..].trim().parse::<u8>() {
+		Ok(len) if len <= 32 => Some((addr, len)),
+		_ => None,
+	}
+}
+
+/// Parses "10.0.0.0/8" style prefixes, the host bits must be clear
+fn parse_prefix(prefix: &str) -> Result<(Ipv4Addr, u8), String> {
+	let (addr, len) = parse_cidr(prefix)
+		.ok_or_else(|| format!("{:?} is not a prefix like \"10.0.0.0/8\"", prefix))?;
+	let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+	if u32::from(addr) & !mask != 0 {
+		return Err(format!(
+			"{:?} has host bits set, the prefix is {}/{}",
+			prefix,
+			Ipv4Addr::from(u32::from(addr) & mask),
+			len
+		));
+	}
+	Ok((addr, len))
+}
+
+fn same_subnet(a: Ipv4Addr, b: Ipv4Addr, len: u8) -> bool {
+	let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+	u32::from(a) & mask == u32::from(b) & mask
+}
+
+/// Reads the TOML into a `Config`, noting every value of the wrong type or unknown key
+struct Reader<'a> {
+	errs: &'a mut Vec<Invalid>,
+}
+
+impl<'a> Reader<'a> {
+	fn new(errs: &'a mut Vec<Invalid>) -> Self {
+		Self { errs }
+	}
+
+	fn err(&mut self, key: &str, reason: String) {
+		self.errs.push(Invalid {
+			key: key.to_owned(),
+			reason,
+		});
+	}
+
+	fn read_config(&mut self, root: &Table, config: &mut Config) {
+		self.known(
+			root,
+			"",
+			&["eal", "mempool", "port", "channel", "forward", "icmp"],
+		);
+
+		if let Some(eal) = root.get("eal").and_then(|v| self.table(v, "eal")) {
+			self.read_eal(&eal, &mut config.eal);
+		}
+		if let Some(pools) = root.get("mempool").and_then(|v| self.tables(v, "mempool")) {
+			config.mempools = pools
+				.iter()
+				.enumerate()
+				.map(|(i, t)| self.read_mempool(t, &format!("mempool[{}]", i)))
+				.collect();
+		}
+		// ports receive into the first mempool and have a queue pair per lcore unless they
+		// say otherwise
+		let queues = config.lcores().len().clamp(1, u16::MAX as usize) as u16;
+		let mempool = config
+			.mempools
+			.first()
+			.map(|mp| mp.name.clone())
+			.unwrap_or_default();
+		if let Some(ports) = root.get("port").and_then(|v| self.tables(v, "port")) {
+			config.ports = ports
+				.iter()
+				.enumerate()
+				.map(|(i, t)| {
+					let path = format!("port[{}]", i);
+					self.read_port(t, &path, i as u16, &mempool, queues)
+				})
+				.collect();
+		} else {
+			for port in &mut config.ports {
+				port.mempool = mempool.clone();
+				port.conf.queues = queues;
+			}
+		}
+		config.channel.port = config
+			.ports
+			.first()
+			.map(|p| p.name.clone())
+			.unwrap_or_default();
+		if let Some(channel) = root.get("channel").and_then(|v| self.table(v, "channel")) {
+			self.read_channel(&channel, &mut config.channel);
+		}
+		if let Some(forward) = root.get("forward").and_then(|v| self.table(v, "forward")) {
+			self.read_forward(&forward, &mut config.forward);
+		}
+		if let Some(icmp) = root.get("icmp").and_then(|v| self.table(v, "icmp")) {
+			self.read_icmp(&icmp, &mut config.icmp);
+		}
+	}
+
+	fn read_eal(&mut self, t: &Table, eal: &mut EalConfig) {
+		self.known(
+			t,
+			"eal",
+			&[
+				"lcores",
+				"memory_channels",
+				"base_virtaddr",
+				"file_prefix",
+				"args",
+			],
+		);
+		self.read(t, "eal", "lcores", &mut eal.lcores);
+		self.read(t, "eal", "memory_channels", &mut eal.memory_channels);
+		if let Some(v) = t.get("base_virtaddr") {
+			// hex addresses are easier written as strings
+			eal.base_virtaddr = match v {
+				Value::String(s) => {
+					let s = s.trim();
+					let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+						Some(hex) => u64::from_str_radix(hex, 16),
+						None => s.parse(),
+					};
+					parsed.ok().or_else(|| {
+						self.err("eal.base_virtaddr", format!("{:?} is not an address", s));
+						None
+					})
+				}
+				v => self.value(v, "eal.base_virtaddr"),
+			};
+		}
+		if let Some(v) = t.get("file_prefix") {
+			eal.file_prefix = self.value(v, "eal.file_prefix");
+		}
+		self.read(t, "eal", "args", &mut eal.args);
+	}
+
+	fn read_mempool(&mut self, t: &Table, path: &str) -> MempoolConfig {
+		self.known(t, path, &["name", "size", "cache_size", "data_room"]);
+		let mut mp = MempoolConfig::new("");
+		self.read(t, path, "name", &mut mp.name);
+		self.read(t, path, "size", &mut mp.size);
+		self.read(t, path, "cache_size", &mut mp.cache_size);
+		self.read(t, path, "data_room", &mut mp.data_room);
+		mp
+	}
+
+	fn read_port(
+		&mut self,
+		t: &Table,
+		path: &str,
+		id: u16,
+		mempool: &str,
+		queues: u16,
+	) -> PortConfig {
+		self.known(
+			t,
+			path,
+			&[
+				"name",
+				"id",
+				"devargs",
+				"mempool",
+				"queues",
+				"rx_desc",
+				"tx_desc",
+				"mtu",
+				"rx_offloads",
+				"tx_offloads",
+				"promiscuous",
+				"role",
+				"ip",
+			],
+		);
+		let mut port = PortConfig {
+			name: String::new(),
+			id,
+			devargs: None,
+			mempool: mempool.to_owned(),
+			conf: PortConf::new(queues),
+			role: None,
+			ip: None,
+		};
+		self.read(t, path, "name", &mut port.name);
+		self.read(t, path, "id", &mut port.id);
+		if let Some(v) = t.get("devargs") {
+			port.devargs = self.value(v, &format!("{}.devargs", path));
+		}
+		if let Some(v) = t.get("role") {
+			port.role = self.value(v, &join(path, "role"));
+		}
+		if let Some(v) = t.get("ip") {
+			let key = join(path, "ip");
+			if let Some(ip) = self.value::<String>(v, &key) {
+				port.ip = parse_cidr(&ip);
+				if port.ip.is_none() {
+					self.err(
+						&key,
+						format!("{:?} is not an address like \"10.0.0.1/24\"", ip),
+					);
+				}
+			}
+		}
+		self.read(t, path, "mempool", &mut port.mempool);
+		let conf = &mut port.conf;
+		self.read(t, path, "queues", &mut conf.queues);
+		self.read(t, path, "rx_desc", &mut conf.rx_desc);
+		self.read(t, path, "tx_desc", &mut conf.tx_desc);
+		self.read(t, path, "mtu", &mut conf.mtu);
+		self.read(t, path, "promiscuous", &mut conf.promiscuous);
+		if let Some(flags) = self.offloads(t, path, "rx_offloads", RX_OFFLOADS) {
+			conf.rx_offloads = flags;
+		}
+		if let Some(flags) = self.offloads(t, path, "tx_offloads", TX_OFFLOADS) {
+			conf.tx_offloads = flags;
+		}
+		port
+	}
+
+	fn read_channel(&mut self, t: &Table, channel: &mut ChannelConfig) {
+		self.known(t, "channel", &["client", "port", "burst", "attach_wait_ms"]);
+		self.read(t, "channel", "client", &mut channel.client);
+		self.read(t, "channel", "port", &mut channel.port);
+		self.read(t, "channel", "burst", &mut channel.burst);
+		let mut ms = channel.attach_wait.as_millis() as u64;
+		if self.read(t, "channel", "attach_wait_ms", &mut ms) {
+			channel.attach_wait = Duration::from_millis(ms);
+		}
+	}
+
+	fn read_forward(&mut self, t: &Table, fwd: &mut ForwardConfig) {
+		self.known(
+			t,
+			"forward",
+			&[
+				"policy",
+				"pairs",
+				"routes",
+				"mac_aging_secs",
+				"mac_table_size",
+				"neighbors",
+				"arp_aging_secs",
+				"max_routes",
+			],
+		);
+		self.read(t, "forward", "policy", &mut fwd.policy);
+		let mut secs = fwd.mac_aging.as_secs();
+		if self.read(t, "forward", "mac_aging_secs", &mut secs) {
+			fwd.mac_aging = Duration::from_secs(secs);
+		}
+		self.read(t, "forward", "mac_table_size", &mut fwd.mac_table_size);
+		let mut secs = fwd.arp_aging.as_secs();
+		if self.read(t, "forward", "arp_aging_secs", &mut secs) {
+			fwd.arp_aging = Duration::from_secs(secs);
+		}
+		self.read(t, "forward", "max_routes", &mut fwd.max_routes);
+		if let Some(v) = t.get("pairs") {
+			if let Some(pairs) = self.value::<Vec<Vec<String>>>(v, "forward.pairs") {
+				for (i, pair) in pairs.into_iter().enumerate() {
+					match <[String; 2]>::try_from(pair) {
+						Ok([a, b]) => fwd.pairs.push((a, b)),
+						Err(_) => self.err(
+							&format!("forward.pairs[{}]", i),
+							"expected two port names".into(),
+						),
+					}
+				}
+			}
+		}
+		if let Some(routes) = t
+			.get("routes")
+			.and_then(|v| self.tables(v, "forward.routes"))
+		{
+			for (i, route) in routes.iter().enumerate() {
+				let path = format!("forward.routes[{}]", i);
+				self.known(route, &path, &["prefix", "port", "via"]);
+				let mut prefix = String::new();
+				let mut port = String::new();
+				if !self.read(route, &path, "prefix", &mut prefix) {
+					self.err(&join(&path, "prefix"), "missing".into());
+				}
+				if !self.read(route, &path, "port", &mut port) {
+					self.err(&join(&path, "port"), "missing".into());
+				}
+				let via = route
+					.get("via")
+					.and_then(|v| self.value(v, &join(&path, "via")));
+				match parse_prefix(&prefix) {
+					Ok((prefix, len)) => fwd.routes.push(RouteConfig {
+						prefix,
+						len,
+						port,
+						via,
+					}),
+					Err(reason) if !prefix.is_empty() => self.err(&join(&path, "prefix"), reason),
+					Err(_) => {}
+				}
+			}
+		}
+		if let Some(neighbors) = t
+			.get("neighbors")
+			.and_then(|v| self.tables(v, "forward.neighbors"))
+		{
+			for (i, neighbor) in neighbors.iter().enumerate() {
+				let path = format!("forward.neighbors[{}]", i);
+				self.known(neighbor, &path, &["ip", "mac"]);
+				let ip = self.required(neighbor, &path, "ip");
+				let mac = self.required(neighbor, &path, "mac");
+				if let (Some(ip), Some(mac)) = (ip, mac) {
+					fwd.neighbors.push(NeighborConfig { ip, mac });
+				}
+			}
+		}
+	}
+
+	fn read_icmp(&mut self, t: &Table, icmp: &mut IcmpConfig) {
+		self.known(t, "icmp", &["echo", "errors_per_sec", "services"]);
+		self.read(t, "icmp", "echo", &mut icmp.echo);
+		self.read(t, "icmp", "errors_per_sec", &mut icmp.errors_per_sec);
+		if let Some(v) = t.get("services") {
+			if let Some(services) = self.value::<Vec<String>>(v, "icmp.services") {
+				let mut parsed = Vec::with_capacity(services.len());
+				for (i, service) in services.iter().enumerate() {
+					match service.parse() {
+						Ok(service) => parsed.push(service),
+						Err(reason) => self.err(&format!("icmp.services[{}]", i), reason),
+					}
+				}
+				icmp.services = Some(parsed);
+			}
+		}
+	}
+
+	/// Overwrite `out` with the value at `key` if there is one of the right type
+	///
+	/// Returns whether the key was there.
+	fn read<T: FromValue>(&mut self, t: &Table, path: &str, key: &str, out: &mut T) -> bool {
+		match t.get(key) {
+			Some(v) => {
+				if let Some(v) = self.value(v, &join(path, key)) {
+					*out = v;
+				}
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// The value at `key`, reporting it missing if it isn't there
+	fn required<T: FromValue>(&mut self, t: &Table, path: &str, key: &str) -> Option<T> {
+		match t.get(key) {
+			Some(v) => self.value(v, &join(path, key)),
+			None => {
+				self.err(&join(path, key), "missing".into());
+				None
+			}
+		}
+	}
+
+	fn value<T: FromValue>(&mut self, v: &Value, key: &str) -> Option<T> {
+		match T::from_value(v) {
+			Ok(v) => Some(v),
+			Err(reason) => {
+				self.err(key, reason);
+				None
+			}
+		}
+	}
+
+	/// A list of offload names as `DEV_*_OFFLOAD_*` flags
+	fn offloads(&mut self, t: &Table, path: &str, key: &str, known: &[(&str, u32)]) -> Option<u64> {
+		let v = t.get(key)?;
+		let key = join(path, key);
+		let names: Vec<String> = self.value(v, &key)?;
+		let mut flags = 0;
+		for (i, name) in names.iter().enumerate() {
+			match known.iter().find(|(n, _)| n == name) {
+				Some((_, flag)) => flags |= *flag as u64,
+				None => {
+					let names = known.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+					self.err(
+						&format!("{}[{}]", key, i),
+						format!(
+							"unknown offload {:?}, expected one of {}",
+							name,
+							names.join(", ")
+						),
+					)
+				}
+			}
+		}
+		Some(flags)
+	}
+
+	fn table(&mut self, v: &Value, key: &str) -> Option<Table> {
+		match v {
+			Value::Table(t) => Some(t.clone()),
+			v => {
+				self.err(key, format!("expected a table, found {}", v.type_str()));
+				None
+			}
+		}
+	}
+
+	/// An array of tables, e.g. `[[port]]`
+	fn tables(&mut self, v: &Value, key: &str) -> Option<Vec<Table>> {
+		match v {
+			Value::Array(a) => a
+				.iter()
+				.enumerate()
+				.map(|(i, v)| self.table(v, &format!("{}[{}]", key, i)))
+				.collect(),
+			v => {
+				self.err(
+					key,
+					format!("expected an array of tables, found {}", v.type_str()),
+				);
+				None
+			}
+		}
+	}
+
+	/// Report keys that aren't in `known`, typos would otherwise go unnoticed
+	fn known(&mut self, t: &Table, path: &str, known: &[&str]) {
+		for key in t.keys().filter(|k| !known.contains(&k.as_str())) {
+			self.err(
+				&join(path, key),
+				format!("unknown key, expected one of {}", known.join(", ")),
+			);
+		}
+	}
+}
+
+fn join(path: &str, key: &str) -> String {
+	if path.is_empty() {
+		key.to_owned()
+	} else {
+		format!("{}.{}", path, key)
+	}
+}
+
+/// A TOML value converted to the type of a config field
+trait FromValue: Sized {
+	fn from_value(v: &Value) -> Result<Self, String>;
+}
+
+fn expected(what: &str, v: &Value) -> String {
+	format!("expected {}, found {}", what, v.type_str())
+}
+
+impl FromValue for String {
+	fn from_value(v: &Value) -> Result<Self, String> {
+		v.as_str()
+			.map(str::to_owned)
+			.ok_or_else(|| expected("a string", v))
+	}
+}
+
+impl FromValue for bool {
+	fn from_value(v: &Value) -> Result<Self, String> {
+		v.as_bool().ok_or_else(|| expected("a boolean", v))
+	}
+}
+
+macro_rules! impl_from_value_int {
+	($($t:ty),*) => {
+		$(impl FromValue for $t {
+			fn from_value(v: &Value) -> Result<Self, String> {
+				let i = v.as_integer().ok_or_else(|| expected("an integer", v))?;
+				<$t>::try_from(i)
+					.map_err(|_| format!("{} is out of range, must be 0 to {}", i, <$t>::MAX))
+			}
+		})*
+	};
+}
+
+impl_from_value_int!(u16, u32, u64, usize);
+
+impl FromValue for Vec<String> {
+	fn from_value(v: &Value) -> Result<Self, String> {
+		match v {
+			Value::Array(a) => a.iter().map(String::from_value).collect(),
+			v => Err(expected("an array of strings", v)),
+		}
+	}
+}
+
+impl FromValue for Vec<Vec<String>> {
+	fn from_value(v: &Value) -> Result<Self, String> {
+		match v {
+			Value::Array(a) => a.iter().map(Vec::<String>::from_value).collect(),
+			v => Err(expected("an array of arrays of strings", v)),
+		}
+	}
+}
+
+impl FromValue for Ipv4Addr {
+	fn from_value(v: &Value) -> Result<Self, String> {
+		let s = String::from_value(v)?;
+		s.parse()
+			.map_err(|_| format!("{:?} is not an IPv4 address", s))
+	}
+}
+
+impl FromValue for MacAddr {
+	fn from_value(v: &Value) -> Result<Self, String> {
+		let s = String::from_value(v)?;
+		s.parse()
+			.map_err(|_| format!("{:?} is not a MAC address like \"02:00:00:00:00:01\"", s))
+	}
+}
+
+impl FromValue for Policy {
+	fn from_value(v: &Value) -> Result<Self, String> {
+		match String::from_value(v)?.as_str() {
+			"mux" => Ok(Policy::Mux),
+			"pairs" => Ok(Policy::Pairs),
+			"roles" => Ok(Policy::Roles),
+			"routes" => Ok(Policy::Routes),
+			"bridge" => Ok(Policy::Bridge),
+			"router" => Ok(Policy::Router),
+			p => Err(format!(
+				"unknown policy {:?}, expected one of mux, pairs, roles, routes, bridge, router",
+				p
+			)),
+		}
+	}
+}
+
+impl FromValue for Role {
+	fn from_value(v: &Value) -> Result<Self, String> {
+		match String::from_value(v)?.as_str() {
+			"uplink" => Ok(Role::Uplink),
+			"downlink" => Ok(Role::Downlink),
+			r => Err(format!("unknown role {:?}, expected uplink or downlink", r)),
+		}
+	}
+}
+
+#[cfg(all(test, feature = "soft"))]
+mod tests {
+	use super::*;
+
+	/// The mistakes `toml` is rejected for, as they are reported
+	fn mistakes(toml: &str) -> Vec<String> {
+		match toml.parse::<Config>() {
+			Ok(_) => panic!("accepted {:?}", toml),
+			Err(ConfigError::Invalid(errs)) => errs.iter().map(Invalid::to_string).collect(),
+			Err(e) => panic!("{}", e),
+		}
+	}
+
+	#[test]
+	fn everything_is_optional() {
+		assert_eq!("".parse::<Config>().unwrap(), Config::default());
+	}
+
+	#[test]
+	fn rejects_bad_lcores() {
+		let too_high = format!(
+			"eal.lcores: lcores go up to {}",
+			dpdk_sys::RTE_MAX_LCORE - 1
+		);
+		assert_eq!(
+			mistakes("eal.lcores = \"0-4000000000\""),
+			[too_high.as_str()]
+		);
+		assert_eq!(
+			mistakes(&format!("eal.lcores = \"0,{}\"", dpdk_sys::RTE_MAX_LCORE)),
+			[too_high]
+		);
+		for lcores in &["3-1", "0-", "a", ""] {
+			assert_eq!(
+				mistakes(&format!("eal.lcores = {:?}", lcores)),
+				[format!(
+					"eal.lcores: {:?} is not a list of lcores like \"0-3,8\"",
+					lcores
+				)]
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_an_unknown_mempool() {
+		assert_eq!(
+			mistakes("[[port]]\nname = \"port0\"\nmempool = \"pool1\""),
+			["port[0].mempool: no mempool named \"pool1\""]
+		);
+	}
+
+	#[test]
+	fn rejects_duplicate_ports() {
+		let toml = "[channel]\nport = \"a\"\n\
+		            [[port]]\nname = \"a\"\n\
+		            [[port]]\nname = \"a\"\nid = 0";
+		assert_eq!(
+			mistakes(toml),
+			[
+				"port[1].name: \"a\" is already taken",
+				"port[1].id: port 0 is already configured"
+			]
+		);
+	}
+
+	#[test]
+	fn rejects_devargs_without_a_device() {
+		assert_eq!(
+			mistakes("[[port]]\nname = \"port0\"\ndevargs = \",iface=eth0\""),
+			["port[0].devargs: must start with the device"]
+		);
+	}
+
+	#[test]
+	fn rejects_a_bad_mtu() {
+		assert_eq!(
+			mistakes("[[port]]\nname = \"port0\"\nmtu = 67"),
+			["port[0].mtu: must be at least 68"]
+		);
+		let mtu = (Mempool::RX_MBUF_DATA_SIZE - PortConf::ETHER_OVERHEAD as u32 + 1) as u16;
+		assert_eq!(
+			mistakes(&format!("[[port]]\nname = \"port0\"\nmtu = {}", mtu)),
+			[format!(
+				"port[0].mtu: {} byte frames don't fit the {} byte mbufs of mempool \
+				 \"GLOBAL_MEMPOOL\", lower the MTU or turn on the scatter rx offload",
+				Mempool::RX_MBUF_DATA_SIZE + 1,
+				Mempool::RX_MBUF_DATA_SIZE
+			)]
+		);
+	}
+
+	#[test]
+	fn rejects_a_data_room_that_overflows() {
+		let max = u16::MAX as u32 - Mempool::RTE_PKTMBUF_HEADROOM;
+		let mistake = format!("mempool[0].data_room: must be 1 to {}", max);
+		let toml = |room: u32| format!("[[mempool]]\nname = \"GLOBAL\"\ndata_room = {}", room);
+		for &room in &[max + 1, u32::MAX] {
+			assert_eq!(mistakes(&toml(room)), [mistake.as_str()]);
+		}
+		// the port's frames don't fit either
+		assert_eq!(mistakes(&toml(0))[0], mistake);
+	}
+
+	#[test]
+	fn the_mux_shares_the_engines_runtime_directory() {
+		let config = "[eal]\nmemory_channels = 2\nfile_prefix = \"second\""
+			.parse::<Config>()
+			.unwrap();
+		assert_eq!(
+			config.secondary_eal_args("2-3"),
+			[
+				"l3enginemux",
+				"-l",
+				"2-3",
+				"-n",
+				"2",
+				"--proc-type=secondary",
+				"--file-prefix=second"
+			]
+		);
+		assert!(config
+			.eal_args()
+			.contains(&String::from("--file-prefix=second")));
+	}
+}
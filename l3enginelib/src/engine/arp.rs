@@ -0,0 +1,377 @@
+//! The neighbours the engine sends IPv4 to
+//!
+//! Neighbours are learned from ARP replies, gratuitous ARPs and requests addressed to the
+//! engine, and forgotten when they haven't been heard from for the aging time. Configured
+//! neighbours are permanent. Packets to a next hop that isn't known yet wait in a short
+//! queue of their own while the engine asks for it, and are dropped if it never answers.
+
+use std::{
+	collections::HashMap,
+	fmt,
+	net::Ipv4Addr,
+	sync::{Mutex, RwLock},
+	time::Duration,
+};
+
+use super::Interface;
+use crate::{dpdk_sys, MacAddr, Mbuf};
+
+#[derive(Debug)]
+struct Neighbor {
+	mac: MacAddr,
+	seen: u64, // TSC of the last ARP from the neighbour
+	permanent: bool,
+}
+
+/// Packets waiting for their next hop to answer
+struct Pending {
+	port: usize,
+	pkts: Vec<Mbuf>,
+	sent: u64, // TSC of the last request
+	requests: u32,
+}
+
+impl fmt::Debug for Pending {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Pending")
+			.field("port", &self.port)
+			.field("pkts", &self.pkts.len())
+			.field("requests", &self.requests)
+			.finish()
+	}
+}
+
+/// A neighbour and its MAC address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArpEntry {
+	pub ip: Ipv4Addr,
+	pub mac: MacAddr,
+	/// configured rather than learned, never ages out
+	pub permanent: bool,
+}
+
+/// What became of a packet handed to `ArpTable::hold`
+pub enum Hold {
+	/// queued, this request for the next hop is to be sent
+	Request(Mbuf),
+	/// queued behind a request that was sent already
+	Queued,
+	/// dropped, the next hop's queue is full; the request for it is to be sent if it was
+	/// time to ask again
+	Full(Option<Mbuf>),
+}
+
+/// The MAC address of every known neighbour and the packets waiting for the others
+#[derive(Debug)]
+pub struct ArpTable {
+	entries: RwLock<HashMap<Ipv4Addr, Neighbor>>,
+	pending: Mutex<HashMap<Ipv4Addr, Pending>>,
+	aging: u64, // TSC cycles without an ARP before a neighbour is forgotten
+	retry: u64, // TSC cycles between requests for a next hop
+}
+
+impl Default for ArpTable {
+	fn default() -> Self {
+		Self::new(Self::DEFAULT_AGING)
+	}
+}
+
+impl ArpTable {
+	/// Forget neighbours after 5 minutes
+	pub const DEFAULT_AGING: Duration = Duration::from_secs(300);
+	/// Most packets waiting for one next hop
+	pub const PENDING_PKTS: usize = 16;
+	/// Requests sent for a next hop before its packets are dropped, a second apart
+	pub const REQUESTS: u32 = 3;
+
+	/// A table that forgets learned neighbours `aging` after their last ARP
+	pub fn new(aging: Duration) -> Self {
+		let hz = unsafe { dpdk_sys::rte_get_timer_hz() };
+		Self {
+			entries: RwLock::new(HashMap::new()),
+			pending: Mutex::new(HashMap::new()),
+			aging: (aging.as_secs_f64() * hz as f64) as u64,
+			retry: hz,
+		}
+	}
+
+	#[inline]
+	fn now() -> u64 {
+		unsafe { dpdk_sys::rte_get_tsc_cycles() }
+	}
+
+	#[inline]
+	fn expired(&self, neighbor: &Neighbor, now: u64) -> bool {
+		!neighbor.permanent && now.saturating_sub(neighbor.seen) > self.aging
+	}
+
+	/// Record that `ip` is permanently at `mac`
+	///
+	/// Returns the port and the packets that were waiting for `ip`, their destination MAC
+	/// is left for the caller to set.
+	pub fn insert(&self, ip: Ipv4Addr, mac: MacAddr) -> Option<(usize, Vec<Mbuf>)> {
+		let neighbor = Neighbor {
+			mac,
+			seen: Self::now(),
+			permanent: true,
+		};
+		self.entries.write().unwrap().insert(ip, neighbor);
+		self.release(ip)
+	}
+
+	/// Record that an ARP from `ip` said it is at `mac`
+	///
+	/// Only neighbours the table knows or waits for are updated unless `create` is set,
+	/// permanent ones are left alone. Returns the port and the packets that were waiting for
+	/// `ip`, their destination MAC is left for the caller to set.
+	pub fn learn(&self, ip: Ipv4Addr, mac: MacAddr, create: bool) -> Option<(usize, Vec<Mbuf>)> {
+		let now = Self::now();
+		{
+			let mut entries = self.entries.write().unwrap();
+			match entries.get_mut(&ip) {
+				Some(neighbor) if neighbor.permanent => return None,
+				Some(neighbor) => {
+					neighbor.mac = mac;
+					neighbor.seen = now;
+				}
+				None if create || self.pending.lock().unwrap().contains_key(&ip) => {
+					let neighbor = Neighbor {
+						mac,
+						seen: now,
+						permanent: false,
+					};
+					entries.insert(ip, neighbor);
+				}
+				None => return None,
+			}
+		}
+		self.release(ip)
+	}
+
+	fn release(&self, ip: Ipv4Addr) -> Option<(usize, Vec<Mbuf>)> {
+		let pending = self.pending.lock().unwrap().remove(&ip)?;
+		Some((pending.port, pending.pkts))
+	}
+
+	/// Forget `ip`, returns where it was
+	pub fn remove(&self, ip: Ipv4Addr) -> Option<MacAddr> {
+		self.entries.write().unwrap().remove(&ip).map(|n| n.mac)
+	}
+
+	/// Where `ip` is, unless it hasn't been heard from for longer than the aging time
+	#[inline]
+	pub fn lookup(&self, ip: Ipv4Addr) -> Option<MacAddr> {
+		let entries = self.entries.read().unwrap();
+		let neighbor = entries.get(&ip)?;
+		if self.expired(neighbor, Self::now()) {
+			None
+		} else {
+			Some(neighbor.mac)
+		}
+	}
+
+	/// Queue `pkt` to go out of `port`, whose address is `from`, once `ip` answers
+	///
+	/// A next hop is asked for again every second while packets arrive for it, even when
+	/// they are dropped for want of room. Once it has been asked `REQUESTS` times, the
+	/// packets waiting for it are dropped and it's asked for afresh. Requests are made from
+	/// the mempool of the packet that prompted them.
+	pub fn hold(&self, ip: Ipv4Addr, port: usize, from: &Interface, pkt: Mbuf) -> Hold {
+		let now = Self::now();
+		let mut pending = self.pending.lock().unwrap();
+		let waiting = pending.entry(ip).or_insert_with(|| Pending {
+			port,
+			pkts: Vec::new(),
+			sent: now,
+			requests: 0,
+		});
+		let req = if waiting.requests == 0 || now.saturating_sub(waiting.sent) > self.retry {
+			if waiting.requests >= Self::REQUESTS {
+				// it's not answering, don't send it anything stale
+				waiting.pkts.clear();
+				waiting.requests = 0;
+			}
+			waiting.requests += 1;
+			waiting.sent = now;
+			// asked again a second from now if there's no mbuf for it
+			pkt.arp_request(from.mac, from.addr.into(), ip.into()).ok()
+		} else {
+			None
+		};
+		if waiting.pkts.len() >= Self::PENDING_PKTS {
+			return Hold::Full(req);
+		}
+		waiting.port = port;
+		waiting.pkts.push(pkt);
+		match req {
+			Some(req) => Hold::Request(req),
+			None => Hold::Queued,
+		}
+	}
+
+	/// Forget the neighbours that have aged out and drop the packets of next hops that
+	/// didn't answer, returns how many neighbours and packets there were
+	pub fn age(&self) -> (usize, usize) {
+		let now = Self::now();
+		let aged = {
+			let mut entries = self.entries.write().unwrap();
+			let before = entries.len();
+			entries.retain(|_, neighbor| !self.expired(neighbor, now));
+			before - entries.len()
+		};
+		let mut dropped = 0;
+		let mut pending = self.pending.lock().unwrap();
+		pending.retain(|_, waiting| {
+			// packets still arriving for it would have asked again by now
+			let asking = now.saturating_sub(waiting.sent) <= 2 * self.retry;
+			if !asking {
+				dropped += waiting.pkts.len();
+			}
+			asking
+		});
+		(aged, dropped)
+	}
+
+	/// Every neighbour, including those that aged out but haven't been forgotten yet
+	pub fn entries(&self) -> Vec<ArpEntry> {
+		let entries = self.entries.read().unwrap();
+		entries
+			.iter()
+			.map(|(ip, neighbor)| ArpEntry {
+				ip: *ip,
+				mac: neighbor.mac,
+				permanent: neighbor.permanent,
+			})
+			.collect()
+	}
+
+	/// How many next hops the engine is asking for
+	pub fn pending(&self) -> usize {
+		self.pending.lock().unwrap().len()
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.entries.read().unwrap().len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+#[cfg(all(test, feature = "soft"))]
+mod tests {
+	use super::*;
+	use crate::apis::testing;
+	use std::thread;
+
+	const NEXT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 254);
+	const NEXT_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0xfe]);
+	const WAIT: Duration = Duration::from_millis(20);
+	const FROM: Interface = Interface {
+		addr: Ipv4Addr::new(10, 0, 0, 1),
+		len: 24,
+		mac: MacAddr([0x02, 0, 0, 0, 0, 0x01]),
+	};
+
+	/// A table that asks again, and forgets neighbours, after `WAIT`
+	fn table() -> ArpTable {
+		testing::mempool();
+		let mut arp = ArpTable::new(WAIT);
+		arp.retry = arp.aging;
+		arp
+	}
+
+	fn pkt() -> Mbuf {
+		Mbuf::from_bytes(&[0; 60], testing::mempool()).unwrap()
+	}
+
+	fn requested(hold: Hold) -> Mbuf {
+		match hold {
+			Hold::Request(req) => req,
+			_ => panic!("no request"),
+		}
+	}
+
+	#[test]
+	fn holds_packets_behind_one_request() {
+		let arp = table();
+		let req = requested(arp.hold(NEXT, 1, &FROM, pkt()));
+		let hdr = *req.arp_hdr().unwrap();
+		assert_eq!(req.ether_hdr().unwrap().d_addr, MacAddr::BROADCAST);
+		assert_eq!(u32::from_be(hdr.arp_tip), u32::from(NEXT));
+		assert_eq!(u32::from_be(hdr.arp_sip), u32::from(FROM.addr));
+		assert!(matches!(arp.hold(NEXT, 1, &FROM, pkt()), Hold::Queued));
+		assert_eq!(arp.pending(), 1);
+
+		// a reply from anybody else isn't learned
+		assert!(arp
+			.learn(Ipv4Addr::new(10, 0, 0, 9), NEXT_MAC, false)
+			.is_none());
+		let (port, pkts) = arp.learn(NEXT, NEXT_MAC, false).unwrap();
+		assert_eq!((port, pkts.len()), (1, 2));
+		assert_eq!(arp.lookup(NEXT), Some(NEXT_MAC));
+		assert_eq!(arp.pending(), 0);
+	}
+
+	#[test]
+	fn a_full_queue_drops_but_still_asks() {
+		let arp = table();
+		requested(arp.hold(NEXT, 1, &FROM, pkt()));
+		for _ in 1..ArpTable::PENDING_PKTS {
+			assert!(matches!(arp.hold(NEXT, 1, &FROM, pkt()), Hold::Queued));
+		}
+		assert!(matches!(arp.hold(NEXT, 1, &FROM, pkt()), Hold::Full(None)));
+		thread::sleep(WAIT * 2);
+		assert!(matches!(
+			arp.hold(NEXT, 1, &FROM, pkt()),
+			Hold::Full(Some(_))
+		));
+		let (_, pkts) = arp.learn(NEXT, NEXT_MAC, false).unwrap();
+		assert_eq!(pkts.len(), ArpTable::PENDING_PKTS);
+	}
+
+	#[test]
+	fn gives_up_on_a_silent_next_hop() {
+		let arp = table();
+		for _ in 0..ArpTable::REQUESTS {
+			requested(arp.hold(NEXT, 1, &FROM, pkt()));
+			thread::sleep(WAIT * 2);
+		}
+		// asked afresh, with only this packet waiting
+		requested(arp.hold(NEXT, 2, &FROM, pkt()));
+		let (port, pkts) = arp.learn(NEXT, NEXT_MAC, false).unwrap();
+		assert_eq!((port, pkts.len()), (2, 1));
+
+		requested(arp.hold(Ipv4Addr::new(10, 0, 0, 9), 1, &FROM, pkt()));
+		arp.hold(Ipv4Addr::new(10, 0, 0, 9), 1, &FROM, pkt());
+		thread::sleep(WAIT * 3);
+		// nobody asked again, NEXT's entry aged out too
+		assert_eq!(arp.age(), (1, 2));
+		assert_eq!(arp.pending(), 0);
+	}
+
+	#[test]
+	fn forgets_learned_neighbours() {
+		let arp = table();
+		let gw = Ipv4Addr::new(10, 0, 0, 2);
+		arp.insert(gw, MacAddr([0x02, 0, 0, 0, 0, 0x02]));
+		assert!(arp.learn(NEXT, NEXT_MAC, true).is_none());
+		assert_eq!(arp.lookup(NEXT), Some(NEXT_MAC));
+		// configured neighbours aren't overridden
+		arp.learn(gw, NEXT_MAC, true);
+		assert_eq!(arp.lookup(gw), Some(MacAddr([0x02, 0, 0, 0, 0, 0x02])));
+
+		thread::sleep(WAIT * 2);
+		assert_eq!(arp.lookup(NEXT), None, "lookups ignore aged neighbours");
+		assert_eq!(arp.len(), 2, "until they are aged");
+		assert_eq!(arp.age(), (1, 0));
+		let entries = arp.entries();
+		assert_eq!(entries.len(), 1);
+		assert!(entries[0].permanent);
+		assert_eq!(arp.remove(gw), Some(MacAddr([0x02, 0, 0, 0, 0, 0x02])));
+		assert!(arp.is_empty());
+	}
+}
@@ -0,0 +1,221 @@
+//! The MAC table of the engine's learning bridge
+//!
+//! Every lcore learns the source MACs of what it receives and looks up the destinations
+//! in the one shared table. Known stations are refreshed under the read lock, only new or
+//! moved stations take the write lock.
+
+use std::{
+	collections::HashMap,
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		RwLock,
+	},
+	time::Duration,
+};
+
+use crate::{dpdk_sys, MacAddr};
+
+#[derive(Debug)]
+struct Station {
+	port: AtomicUsize,
+	seen: AtomicU64, // TSC of the last packet from the station
+}
+
+/// The port every station was last heard on
+#[derive(Debug)]
+pub struct MacTable {
+	stations: RwLock<HashMap<MacAddr, Station>>,
+	capacity: usize,
+	aging: u64,   // TSC cycles without a packet before a station is forgotten
+	refresh: u64, // TSC cycles between refreshes of a station's last seen time
+}
+
+impl MacTable {
+	/// A table of at most `capacity` stations, each forgotten `aging` after its last packet
+	pub fn new(capacity: usize, aging: Duration) -> Self {
+		let hz = unsafe { dpdk_sys::rte_get_timer_hz() };
+		let aging = (aging.as_secs_f64() * hz as f64) as u64;
+		Self {
+			stations: RwLock::new(HashMap::with_capacity(capacity)),
+			capacity,
+			aging,
+			// a lost refresh costs a little of the station's age, not the station
+			refresh: aging / 16,
+		}
+	}
+
+	#[inline]
+	fn now() -> u64 {
+		unsafe { dpdk_sys::rte_get_tsc_cycles() }
+	}
+
+	#[inline]
+	fn expired(&self, station: &Station, now: u64) -> bool {
+		now.saturating_sub(station.seen.load(Ordering::Relaxed)) > self.aging
+	}
+
+	/// Record that `mac` was heard on `port`
+	///
+	/// Group addresses aren't stations and are ignored. A full table learns nothing new
+	/// until stations age out.
+	pub fn learn(&self, mac: MacAddr, port: usize) {
+		if is_group(mac) {
+			return;
+		}
+		let now = Self::now();
+		{
+			let stations = self.stations.read().unwrap();
+			if let Some(station) = stations.get(&mac) {
+				if now.saturating_sub(station.seen.load(Ordering::Relaxed)) > self.refresh {
+					station.seen.store(now, Ordering::Relaxed);
+				}
+				if station.port.load(Ordering::Relaxed) != port {
+					// the station moved
+					station.port.store(port, Ordering::Relaxed);
+					station.seen.store(now, Ordering::Relaxed);
+				}
+				return;
+			}
+		}
+		let mut stations = self.stations.write().unwrap();
+		if stations.len() >= self.capacity {
+			stations.retain(|_, station| !self.expired(station, now));
+			if stations.len() >= self.capacity {
+				return;
+			}
+		}
+		stations.insert(
+			mac,
+			Station {
+				port: AtomicUsize::new(port),
+				seen: AtomicU64::new(now),
+			},
+		);
+	}
+
+	/// The port `mac` was last heard on, unless it's gone quiet for longer than the aging time
+	#[inline]
+	pub fn lookup(&self, mac: MacAddr) -> Option<usize> {
+		let stations = self.stations.read().unwrap();
+		let station = stations.get(&mac)?;
+		if self.expired(station, Self::now()) {
+			None
+		} else {
+			Some(station.port.load(Ordering::Relaxed))
+		}
+	}
+
+	/// TSC cycles without a packet before a station is forgotten
+	#[inline]
+	pub(crate) fn aging(&self) -> u64 {
+		self.aging
+	}
+
+	/// Forget the stations that have aged out, returns how many there were
+	pub fn age(&self) -> usize {
+		let now = Self::now();
+		let mut stations = self.stations.write().unwrap();
+		let before = stations.len();
+		stations.retain(|_, station| !self.expired(station, now));
+		before - stations.len()
+	}
+
+	/// Forget every station heard on `port`, e.g. when its link went down
+	pub fn flush_port(&self, port: usize) {
+		let mut stations = self.stations.write().unwrap();
+		stations.retain(|_, station| station.port.load(Ordering::Relaxed) != port);
+	}
+
+	/// The stations and the ports they were last heard on, including those that aged out
+	/// but haven't been forgotten yet
+	pub fn stations(&self) -> Vec<(MacAddr, usize)> {
+		let stations = self.stations.read().unwrap();
+		stations
+			.iter()
+			.map(|(mac, station)| (*mac, station.port.load(Ordering::Relaxed)))
+			.collect()
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.stations.read().unwrap().len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+/// Broadcast and multicast addresses have the group bit set
+#[inline]
+pub fn is_group(mac: MacAddr) -> bool {
+	mac.0[0] & 1 != 0
+}
+
+#[cfg(all(test, feature = "soft"))]
+mod tests {
+	use super::*;
+	use crate::apis::testing;
+	use std::thread;
+
+	const A: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x0a]);
+	const B: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x0b]);
+	const C: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x0c]);
+	const AGING: Duration = Duration::from_millis(20);
+
+	fn table(capacity: usize) -> MacTable {
+		testing::mempool();
+		MacTable::new(capacity, AGING)
+	}
+
+	#[test]
+	fn learns_where_stations_are_and_where_they_move() {
+		let macs = table(16);
+		assert_eq!(macs.lookup(A), None);
+		macs.learn(A, 1);
+		macs.learn(B, 2);
+		assert_eq!(macs.lookup(A), Some(1));
+		assert_eq!(macs.lookup(B), Some(2));
+		macs.learn(A, 3);
+		assert_eq!(macs.lookup(A), Some(3));
+		assert_eq!(macs.len(), 2);
+
+		macs.learn(MacAddr([0xff; 6]), 1);
+		macs.learn(MacAddr([0x01, 0x00, 0x5e, 0, 0, 1]), 1);
+		assert_eq!(macs.len(), 2, "group addresses aren't stations");
+
+		macs.flush_port(3);
+		assert_eq!(macs.stations(), [(B, 2)]);
+	}
+
+	#[test]
+	fn forgets_quiet_stations() {
+		let macs = table(16);
+		macs.learn(A, 1);
+		thread::sleep(AGING * 2);
+		macs.learn(B, 2);
+		assert_eq!(macs.lookup(A), None, "lookups ignore aged stations");
+		assert_eq!(macs.lookup(B), Some(2));
+		assert_eq!(macs.len(), 2, "until they are aged");
+		assert_eq!(macs.age(), 1);
+		assert_eq!(macs.stations(), [(B, 2)]);
+	}
+
+	#[test]
+	fn a_full_table_learns_once_stations_age_out() {
+		let macs = table(2);
+		macs.learn(A, 1);
+		macs.learn(B, 1);
+		macs.learn(C, 1);
+		assert_eq!(macs.lookup(C), None);
+		// known stations still move
+		macs.learn(B, 2);
+		assert_eq!(macs.lookup(B), Some(2));
+
+		thread::sleep(AGING * 2);
+		macs.learn(C, 1);
+		assert_eq!(macs.lookup(C), Some(1));
+		assert_eq!(macs.len(), 1);
+	}
+}
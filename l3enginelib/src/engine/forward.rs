@@ -0,0 +1,473 @@
+//! Where the engine sends what its ports receive
+//!
+//! Every port has an `Egress`: the mux, another port, whichever port the route table
+//! picks for the packet's IPv4 destination, whichever port the bridge has heard the
+//! packet's destination MAC on, or, when routing, the port towards the next hop with the
+//! packet rewritten for it. Ports with an address handle ARP themselves, answer pings to
+//! the engine's addresses and hand the mux the rest of the engine's own traffic.
+
+use std::net::Ipv4Addr;
+
+use super::{is_group, ArpTable, Icmp, Interface, MacTable, NextHop, RouteTable, Service};
+use crate::{
+	config::{Config, Policy, Role},
+	Error, Mbuf, Port, ETHER_TYPE_ARP, ICMP_DEST_UNREACH, ICMP_EXC_TTL, ICMP_NET_UNREACH,
+	ICMP_PORT_UNREACH, ICMP_TIME_EXCEEDED, IP_PROTOCOL_ICMP, IP_PROTOCOL_TCP, IP_PROTOCOL_UDP,
+};
+
+/// Where the packets received on a port go
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Egress {
+	/// to the mux over the channel
+	Mux,
+	/// out of the port at this index
+	Port(usize),
+	/// out of the port the route table has for the IPv4 destination
+	Route,
+	/// out of the port the destination MAC was heard on, learning the source MAC
+	Bridge,
+	/// out of every port but the one it came in on
+	Flood,
+	/// routed to the next hop, with the TTL decremented and the MACs rewritten
+	Router,
+	/// answered or learned from by the engine's ARP
+	Arp,
+	/// out of the port at this index once the next hop at this address answers ARP
+	Resolve(usize, Ipv4Addr),
+	/// dropped, and the sender told why with an ICMP error of this type and code
+	Icmp(u8, u8),
+	Drop,
+}
+
+/// The engine's forwarding policy, the egress of every port by index
+#[derive(Debug)]
+pub struct Forwarding {
+	ingress: Vec<Egress>,
+	routes: RouteTable,
+	macs: Option<MacTable>,
+	interfaces: Vec<Option<Interface>>,
+	arp: ArpTable,
+	icmp: Icmp,
+}
+
+impl Forwarding {
+	/// `ingress[i]` is where the packets received on port `i` go
+	///
+	/// Ports that bridge need `macs`, ports that route need an entry in `interfaces`.
+	pub fn new(
+		ingress: Vec<Egress>,
+		routes: RouteTable,
+		macs: Option<MacTable>,
+		interfaces: Vec<Option<Interface>>,
+		arp: ArpTable,
+		icmp: Icmp,
+	) -> Self {
+		assert!(macs.is_some() || !ingress.contains(&Egress::Bridge));
+		assert!(ingress
+			.iter()
+			.enumerate()
+			.all(|(i, e)| *e != Egress::Router || matches!(interfaces.get(i), Some(Some(_)))));
+		Self {
+			ingress,
+			routes,
+			macs,
+			interfaces,
+			arp,
+			icmp,
+		}
+	}
+
+	/// The policy `config.forward` describes, ports are indexed as in `config.ports`
+	///
+	/// The configuration must have been checked, as `Config::load` does. `ports` are the
+	/// configured ports, the router takes their MAC addresses.
+	pub fn from_config(config: &Config, ports: &[Port]) -> Result<Self, Error> {
+		let index = |name: &str| {
+			config
+				.ports
+				.iter()
+				.position(|p| p.name == name)
+				.expect("port names are checked when the configuration is loaded")
+		};
+		let mut ingress = vec![Egress::Drop; config.ports.len()];
+		match config.forward.policy {
+			Policy::Mux => ingress[index(&config.channel.port)] = Egress::Mux,
+			Policy::Pairs => {
+				for (a, b) in &config.forward.pairs {
+					let (a, b) = (index(a), index(b));
+					ingress[a] = Egress::Port(b);
+					ingress[b] = Egress::Port(a);
+				}
+			}
+			Policy::Roles => {
+				let with_role = |role| {
+					config
+						.ports
+						.iter()
+						.enumerate()
+						.filter(move |(_, p)| p.role == Some(role))
+						.map(|(i, _)| i)
+				};
+				let uplink = with_role(Role::Uplink).next().unwrap();
+				let downlinks = with_role(Role::Downlink).collect::<Vec<_>>();
+				for &i in &downlinks {
+					ingress[i] = Egress::Port(uplink);
+				}
+				// more than one downlink, the routes tell them apart
+				ingress[uplink] = match downlinks[..] {
+					[only] => Egress::Port(only),
+					_ => Egress::Route,
+				};
+			}
+			Policy::Routes => ingress.iter_mut().for_each(|e| *e = Egress::Route),
+			Policy::Bridge => ingress.iter_mut().for_each(|e| *e = Egress::Bridge),
+			Policy::Router => ingress.iter_mut().for_each(|e| *e = Egress::Router),
+		}
+
+		let mut interfaces = Vec::with_capacity(config.ports.len());
+		for (pc, port) in config.ports.iter().zip(ports) {
+			interfaces.push(match pc.ip {
+				Some((addr, len)) => Some(Interface {
+					addr,
+					len,
+					mac: port.mac_addr()?,
+				}),
+				None => None,
+			});
+		}
+
+		let mut routes = RouteTable::new(config.forward.max_routes)?;
+		// the subnets of the ports are reached directly
+		for (port, iface) in interfaces.iter().enumerate() {
+			if let Some(iface) = iface {
+				let hop = NextHop {
+					port,
+					gateway: None,
+				};
+				routes.add(iface.subnet(), iface.len, hop)?;
+			}
+		}
+		for route in &config.forward.routes {
+			let hop = NextHop {
+				port: index(&route.port),
+				gateway: route.via,
+			};
+			routes.add(route.prefix, route.len, hop)?;
+		}
+
+		let macs = match config.forward.policy {
+			Policy::Bridge => Some(MacTable::new(
+				config.forward.mac_table_size,
+				config.forward.mac_aging,
+			)),
+			_ => None,
+		};
+		let arp = ArpTable::new(config.forward.arp_aging);
+		let icmp = Icmp::new(
+			config.icmp.echo,
+			config.icmp.errors_per_sec,
+			config.icmp.services.clone(),
+		);
+		let forwarding = Self::new(ingress, routes, macs, interfaces, arp, icmp);
+		for neighbor in &config.forward.neighbors {
+			forwarding.arp.insert(neighbor.ip, neighbor.mac);
+		}
+		Ok(forwarding)
+	}
+
+	#[inline]
+	pub fn routes(&self) -> &RouteTable {
+		&self.routes
+	}
+
+	/// The bridge's MAC table, if any port bridges
+	#[inline]
+	pub fn macs(&self) -> Option<&MacTable> {
+		self.macs.as_ref()
+	}
+
+	/// The address of the port at index `port`, if it has one
+	#[inline]
+	pub fn interface(&self, port: usize) -> Option<&Interface> {
+		self.interfaces.get(port)?.as_ref()
+	}
+
+	/// The neighbours the engine sends IPv4 to
+	#[inline]
+	pub fn arp(&self) -> &ArpTable {
+		&self.arp
+	}
+
+	/// What the engine answers with ICMP
+	#[inline]
+	pub fn icmp(&self) -> &Icmp {
+		&self.icmp
+	}
+
+	/// Where a packet received on port `ingress` goes, never `Egress::Route`,
+	/// `Egress::Bridge` or `Egress::Router`
+	///
+	/// Routed packets are rewritten for their next hop.
+	#[inline]
+	pub fn egress(&self, ingress: usize, pkt: &mut Mbuf) -> Egress {
+		match self.ingress.get(ingress) {
+			Some(Egress::Bridge) => self.bridge(ingress, pkt),
+			Some(_) if self.is_arp_for(ingress, pkt) => Egress::Arp,
+			Some(_) if self.is_local_for(ingress, pkt) => self.local(ingress, pkt),
+			Some(Egress::Router) => self.route(ingress, pkt),
+			Some(Egress::Route) => pkt
+				.ipv4_hdr()
+				.and_then(|ip| {
+					self.routes
+						.lookup(Ipv4Addr::from(u32::from_be(ip.dst_addr)))
+				})
+				.map_or(Egress::Drop, |hop| Egress::Port(hop.port)),
+			Some(e) => *e,
+			None => Egress::Drop,
+		}
+	}
+
+	/// Where a packet the mux sends goes, none if the routes don't reach its destination
+	/// through a port with an address and it leaves by the channel's port as it is
+	///
+	/// Routed packets are rewritten for their next hop, their TTL is left alone.
+	#[inline]
+	pub fn originate(&self, pkt: &mut Mbuf) -> Option<Egress> {
+		let dst = Ipv4Addr::from(u32::from_be(pkt.ipv4_hdr()?.dst_addr));
+		let hop = self.routes.lookup(dst)?;
+		self.interface(hop.port)?;
+		Some(self.next_hop(dst, hop, pkt))
+	}
+
+	/// Whether `pkt` is an ARP the port at index `ingress` handles, it does if it has an
+	/// address
+	#[inline]
+	fn is_arp_for(&self, ingress: usize, pkt: &Mbuf) -> bool {
+		self.interface(ingress).is_some() && pkt.ether_type() == Some(ETHER_TYPE_ARP)
+	}
+
+	/// Whether `pkt` is IPv4 to one of the engine's addresses, sent to the port at index
+	/// `ingress`
+	#[inline]
+	fn is_local_for(&self, ingress: usize, pkt: &Mbuf) -> bool {
+		let (iface, eth, ip) = match (self.interface(ingress), pkt.ether_hdr(), pkt.ipv4_hdr()) {
+			(Some(iface), Some(eth), Some(ip)) => (iface, eth, ip),
+			_ => return false,
+		};
+		let dst = Ipv4Addr::from(u32::from_be(ip.dst_addr));
+		eth.d_addr == iface.mac && self.interfaces.iter().flatten().any(|i| i.addr == dst)
+	}
+
+	/// Answer pings and refuse packets to services the engine doesn't run, the mux gets
+	/// the rest
+	#[inline]
+	fn local(&self, ingress: usize, pkt: &mut Mbuf) -> Egress {
+		let ip = match pkt.ipv4_hdr() {
+			// the mux reassembles
+			Some(ip) if !ip.is_fragment() => *ip,
+			_ => return Egress::Mux,
+		};
+		match ip.next_proto_id {
+			IP_PROTOCOL_ICMP if self.icmp.echo() && pkt.to_icmp_echo_reply() => {
+				Egress::Port(ingress)
+			}
+			IP_PROTOCOL_UDP => match pkt.udp_hdr() {
+				Some(udp) if !self.icmp.serves(Service::udp(u16::from_be(udp.dst_port))) => {
+					Egress::Icmp(ICMP_DEST_UNREACH, ICMP_PORT_UNREACH)
+				}
+				_ => Egress::Mux,
+			},
+			IP_PROTOCOL_TCP => match pkt.tcp_hdr() {
+				Some(tcp) if !self.icmp.serves(Service::tcp(u16::from_be(tcp.dst_port))) => {
+					Egress::Drop
+				}
+				_ => Egress::Mux,
+			},
+			_ => Egress::Mux,
+		}
+	}
+
+	/// Learn where the source lives and find where the destination does
+	#[inline]
+	fn bridge(&self, ingress: usize, pkt: &Mbuf) -> Egress {
+		let (macs, eth) = match (&self.macs, pkt.ether_hdr()) {
+			(Some(macs), Some(eth)) => (macs, *eth),
+			_ => return Egress::Drop,
+		};
+		macs.learn(eth.s_addr, ingress);
+		if is_group(eth.d_addr) {
+			return Egress::Flood;
+		}
+		match macs.lookup(eth.d_addr) {
+			// both stations are on the ingress segment, it has seen the packet already
+			Some(egress) if egress == ingress => Egress::Drop,
+			Some(egress) => Egress::Port(egress),
+			None => Egress::Flood,
+		}
+	}
+
+	/// Forward a packet to its next hop, or say which error to send back
+	#[inline]
+	fn route(&self, ingress: usize, pkt: &mut Mbuf) -> Egress {
+		let (iface, eth) = match (self.interface(ingress), pkt.ether_hdr()) {
+			(Some(iface), Some(eth)) => (iface, *eth),
+			_ => return Egress::Drop,
+		};
+		if eth.d_addr != iface.mac {
+			// promiscuous ports see other stations' unicast
+			return Egress::Drop;
+		}
+
+		let dst = match pkt.ipv4_hdr() {
+			Some(ip) if ip.version_ihl >> 4 == 4 && ip.hdr_len() >= 20 => {
+				if ip.time_to_live <= 1 {
+					return Egress::Icmp(ICMP_TIME_EXCEEDED, ICMP_EXC_TTL);
+				}
+				Ipv4Addr::from(u32::from_be(ip.dst_addr))
+			}
+			_ => return Egress::Drop,
+		};
+		let hop = match self.routes.lookup(dst) {
+			Some(hop) if self.interface(hop.port).is_some() => hop,
+			Some(_) => return Egress::Drop,
+			None => return Egress::Icmp(ICMP_DEST_UNREACH, ICMP_NET_UNREACH),
+		};
+		if let Some(ip) = pkt.ipv4_hdr_mut() {
+			ip.decrement_ttl();
+		}
+		self.next_hop(dst, hop, pkt)
+	}
+
+	/// Address `pkt` from the port of `hop` to the next hop towards `dst`, or say which
+	/// next hop to resolve first
+	///
+	/// The port of `hop` must have an address.
+	#[inline]
+	fn next_hop(&self, dst: Ipv4Addr, hop: NextHop, pkt: &mut Mbuf) -> Egress {
+		let next = hop.gateway.unwrap_or(dst);
+		let s_addr = self.interface(hop.port).unwrap().mac;
+		let d_addr = self.arp.lookup(next);
+		if let Some(eth) = pkt.ether_hdr_mut() {
+			eth.s_addr = s_addr;
+			if let Some(d_addr) = d_addr {
+				eth.d_addr = d_addr;
+			}
+		}
+		match d_addr {
+			Some(_) => Egress::Port(hop.port),
+			None => Egress::Resolve(hop.port, next),
+		}
+	}
+}
+
+#[cfg(all(test, feature = "soft"))]
+mod tests {
+	use super::*;
+	use crate::{apis::testing, MacAddr, IP_PROTOCOL_UDP};
+
+	/// The forwarding of `toml`'s ports port0, port1 and so on, none has an address
+	fn forwarding(toml: &str) -> Forwarding {
+		testing::mempool();
+		let config = toml.parse::<Config>().unwrap();
+		Forwarding::from_config(&config, &[]).unwrap()
+	}
+
+	fn ports(n: usize) -> String {
+		(0..n)
+			.map(|i| format!("[[port]]\nname = \"port{}\"\n", i))
+			.collect()
+	}
+
+	/// A UDP datagram to `dst`
+	fn to(dst: [u8; 4]) -> Mbuf {
+		let mut pkt = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00];
+		pkt.extend_from_slice(&[0x45, 0, 0, 28, 0, 0, 0, 0, 64, IP_PROTOCOL_UDP, 0, 0]);
+		pkt.extend_from_slice(&[192, 168, 0, 2]);
+		pkt.extend_from_slice(&dst);
+		pkt.extend_from_slice(&[0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00]);
+		Mbuf::from_bytes(&pkt, testing::mempool()).unwrap()
+	}
+
+	#[test]
+	fn mux_hands_the_channels_port_to_the_mux() {
+		let fwd = forwarding(&format!("{}[channel]\nport = \"port1\"", ports(2)));
+		assert_eq!(fwd.egress(0, &mut to([10, 0, 0, 1])), Egress::Drop);
+		assert_eq!(fwd.egress(1, &mut to([10, 0, 0, 1])), Egress::Mux);
+		assert_eq!(fwd.egress(2, &mut to([10, 0, 0, 1])), Egress::Drop);
+	}
+
+	#[test]
+	fn pairs_cross_over() {
+		let fwd = forwarding(&format!(
+			"{}[forward]\npolicy = \"pairs\"\npairs = [[\"port0\", \"port2\"]]",
+			ports(3)
+		));
+		assert_eq!(fwd.egress(0, &mut to([10, 0, 0, 1])), Egress::Port(2));
+		assert_eq!(fwd.egress(2, &mut to([10, 0, 0, 1])), Egress::Port(0));
+		assert_eq!(fwd.egress(1, &mut to([10, 0, 0, 1])), Egress::Drop);
+	}
+
+	#[test]
+	fn roles_send_downlinks_to_the_uplink() {
+		let roles = "[[port]]\nname = \"port0\"\nrole = \"downlink\"\n\
+		             [[port]]\nname = \"port1\"\nrole = \"uplink\"\n";
+		let fwd = forwarding(&format!("{}[forward]\npolicy = \"roles\"", roles));
+		assert_eq!(fwd.egress(0, &mut to([10, 0, 0, 1])), Egress::Port(1));
+		assert_eq!(fwd.egress(1, &mut to([10, 0, 0, 1])), Egress::Port(0));
+
+		// the routes tell the downlinks apart
+		let fwd = forwarding(&format!(
+			"{}[[port]]\nname = \"port2\"\nrole = \"downlink\"\n\
+			 [forward]\npolicy = \"roles\"\nroutes = [\
+			 {{ prefix = \"10.0.0.0/8\", port = \"port0\" }}, \
+			 {{ prefix = \"10.2.0.0/16\", port = \"port2\" }}]",
+			roles
+		));
+		assert_eq!(fwd.egress(0, &mut to([8, 8, 8, 8])), Egress::Port(1));
+		assert_eq!(fwd.egress(2, &mut to([8, 8, 8, 8])), Egress::Port(1));
+		assert_eq!(fwd.egress(1, &mut to([10, 1, 0, 1])), Egress::Port(0));
+		assert_eq!(fwd.egress(1, &mut to([10, 2, 0, 1])), Egress::Port(2));
+		assert_eq!(fwd.egress(1, &mut to([8, 8, 8, 8])), Egress::Drop);
+	}
+
+	#[test]
+	fn bridge_floods_until_it_learns() {
+		let fwd = forwarding(&format!("{}[forward]\npolicy = \"bridge\"", ports(3)));
+		let reply = || {
+			let mut pkt = to([192, 168, 0, 2]);
+			let eth = pkt.ether_hdr_mut().unwrap();
+			std::mem::swap(&mut eth.s_addr, &mut eth.d_addr);
+			pkt
+		};
+		assert_eq!(fwd.egress(1, &mut to([10, 0, 0, 1])), Egress::Flood);
+		assert_eq!(fwd.egress(2, &mut reply()), Egress::Port(1));
+		assert_eq!(fwd.egress(1, &mut to([10, 0, 0, 1])), Egress::Port(2));
+		// both stations are on port 2 now
+		assert_eq!(fwd.egress(2, &mut to([10, 0, 0, 1])), Egress::Drop);
+
+		let mut broadcast = to([10, 0, 0, 255]);
+		broadcast.ether_hdr_mut().unwrap().d_addr = MacAddr([0xff; 6]);
+		assert_eq!(fwd.egress(0, &mut broadcast), Egress::Flood);
+		assert_eq!(fwd.macs().unwrap().len(), 2);
+	}
+
+	#[test]
+	fn routes_pick_the_longest_prefix() {
+		let fwd = forwarding(&format!(
+			"{}[forward]\npolicy = \"routes\"\nroutes = [\
+			 {{ prefix = \"10.0.0.0/8\", port = \"port1\" }}, \
+			 {{ prefix = \"10.1.0.0/16\", port = \"port2\" }}, \
+			 {{ prefix = \"0.0.0.0/0\", port = \"port0\" }}]",
+			ports(3)
+		));
+		for ingress in 0..3 {
+			assert_eq!(fwd.egress(ingress, &mut to([10, 1, 2, 3])), Egress::Port(2));
+			assert_eq!(fwd.egress(ingress, &mut to([10, 9, 9, 9])), Egress::Port(1));
+			assert_eq!(fwd.egress(ingress, &mut to([8, 8, 8, 8])), Egress::Port(0));
+		}
+		// not IPv4
+		let mut arp = to([10, 1, 2, 3]);
+		arp.ether_hdr_mut().unwrap().ether_type = ETHER_TYPE_ARP.to_be();
+		assert_eq!(fwd.egress(0, &mut arp), Egress::Drop);
+	}
+}
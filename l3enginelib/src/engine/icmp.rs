@@ -0,0 +1,178 @@
+//! The engine's ICMP
+//!
+//! Echo requests to the addresses of the engine's ports are answered by the engine. So are
+//! the packets it can't deliver: those whose TTL runs out, those the routes don't reach
+//! and, when the engine knows which services it runs, those to a UDP port nobody listens
+//! on. Errors are rate limited across the lcores so a flood of bad packets doesn't become
+//! a flood of errors.
+
+use std::{
+	collections::HashSet,
+	fmt,
+	str::FromStr,
+	sync::{Mutex, RwLock},
+};
+
+use crate::{dpdk_sys, IP_PROTOCOL_TCP, IP_PROTOCOL_UDP};
+
+/// A UDP or TCP port at the engine's addresses
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Service {
+	/// `IP_PROTOCOL_UDP` or `IP_PROTOCOL_TCP`
+	pub proto: u8,
+	pub port: u16,
+}
+
+impl Service {
+	#[inline]
+	pub fn udp(port: u16) -> Self {
+		Self {
+			proto: IP_PROTOCOL_UDP,
+			port,
+		}
+	}
+
+	#[inline]
+	pub fn tcp(port: u16) -> Self {
+		Self {
+			proto: IP_PROTOCOL_TCP,
+			port,
+		}
+	}
+}
+
+impl fmt::Display for Service {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.proto {
+			IP_PROTOCOL_UDP => write!(f, "udp/{}", self.port),
+			IP_PROTOCOL_TCP => write!(f, "tcp/{}", self.port),
+			proto => write!(f, "{}/{}", proto, self.port),
+		}
+	}
+}
+
+impl FromStr for Service {
+	type Err = String;
+
+	/// Parses "udp/53" or "tcp/80"
+	fn from_str(s: &str) -> Result<Self, String> {
+		let bad = || format!("{:?} is not a service like \"udp/53\" or \"tcp/80\"", s);
+		let idx = s.find('/').ok_or_else(bad)?;
+		let port = match s[idx + 1..].trim().parse() {
+			Ok(0) | Err(_) => return Err(bad()),
+			Ok(port) => port,
+		};
+		match s[..idx].trim() {
+			"udp" => Ok(Self::udp(port)),
+			"tcp" => Ok(Self::tcp(port)),
+			_ => Err(bad()),
+		}
+	}
+}
+
+#[derive(Debug)]
+struct Bucket {
+	credit: u64, // TSC cycles of errors that may be sent
+	last: u64,   // TSC of the last refill
+}
+
+/// What the engine answers with ICMP
+#[derive(Debug)]
+pub struct Icmp {
+	echo: bool,
+	services: Option<RwLock<HashSet<Service>>>,
+	bucket: Mutex<Bucket>,
+	cost: u64,  // TSC cycles of credit an error takes, 0 when errors are off
+	depth: u64, // most credit the bucket holds, a second's worth
+}
+
+impl Default for Icmp {
+	fn default() -> Self {
+		Self::new(true, Self::DEFAULT_ERRORS_PER_SEC, None)
+	}
+}
+
+impl Icmp {
+	/// Send at most 100 errors a second
+	pub const DEFAULT_ERRORS_PER_SEC: u32 = 100;
+
+	/// Answer pings if `echo` is set and send at most `errors_per_sec` errors a second,
+	/// none if it's 0
+	///
+	/// With `services`, UDP to any other port of the engine's addresses is answered with
+	/// port unreachable and TCP is dropped. Without, the mux gets whatever arrives.
+	pub fn new(echo: bool, errors_per_sec: u32, services: Option<Vec<Service>>) -> Self {
+		let hz = unsafe { dpdk_sys::rte_get_timer_hz() };
+		let cost = match errors_per_sec {
+			0 => 0,
+			rate => (hz / rate as u64).max(1),
+		};
+		Self {
+			echo,
+			services: services.map(|s| RwLock::new(s.into_iter().collect())),
+			bucket: Mutex::new(Bucket {
+				credit: hz,
+				last: unsafe { dpdk_sys::rte_get_tsc_cycles() },
+			}),
+			cost,
+			depth: hz,
+		}
+	}
+
+	/// Whether echo requests to the engine's addresses are answered
+	#[inline]
+	pub fn echo(&self) -> bool {
+		self.echo
+	}
+
+	/// Whether packets to `service` are for the mux, they all are if the engine doesn't
+	/// know its services
+	#[inline]
+	pub fn serves(&self, service: Service) -> bool {
+		match &self.services {
+			Some(services) => services.read().unwrap().contains(&service),
+			None => true,
+		}
+	}
+
+	/// Start serving `service`, returns false if it was served already or the engine
+	/// doesn't know its services
+	pub fn register(&self, service: Service) -> bool {
+		match &self.services {
+			Some(services) => services.write().unwrap().insert(service),
+			None => false,
+		}
+	}
+
+	/// Stop serving `service`, returns whether it was served
+	pub fn unregister(&self, service: Service) -> bool {
+		match &self.services {
+			Some(services) => services.write().unwrap().remove(&service),
+			None => false,
+		}
+	}
+
+	/// Every service, none if the engine doesn't know them
+	pub fn services(&self) -> Option<Vec<Service>> {
+		let services = self.services.as_ref()?.read().unwrap();
+		Some(services.iter().copied().collect())
+	}
+
+	/// Whether an error may be sent now, taking its share of the rate if so
+	#[inline]
+	pub fn allow_error(&self) -> bool {
+		if self.cost == 0 {
+			return false;
+		}
+		let now = unsafe { dpdk_sys::rte_get_tsc_cycles() };
+		let mut bucket = self.bucket.lock().unwrap();
+		let elapsed = now.saturating_sub(bucket.last);
+		bucket.credit = bucket.credit.saturating_add(elapsed).min(self.depth);
+		bucket.last = now;
+		if bucket.credit < self.cost {
+			return false;
+		}
+		bucket.credit -= self.cost;
+		true
+	}
+}
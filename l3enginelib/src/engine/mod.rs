@@ -0,0 +1,573 @@
+//! The engine's poll loops
+//!
+//! Every lcore runs a `Poller` over its own queue pair of every port. What a port receives
+//! is handed to the mux or sent out of one or more ports, as the engine's `Forwarding`
+//! says. What the mux sends goes out of the port the routes pick for it, or the channel's
+//! port. Packets waiting for their next hop to answer ARP are sent by whichever lcore
+//! receives the answer. Packets that can't be delivered are answered with ICMP errors out
+//! of the port they came in on.
+
+mod arp;
+mod bridge;
+mod forward;
+mod icmp;
+mod route;
+
+pub use arp::*;
+pub use bridge::*;
+pub use forward::*;
+pub use icmp::*;
+pub use route::*;
+
+use std::{
+	fmt, hint,
+	net::Ipv4Addr,
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Arc,
+	},
+};
+
+use crate::{
+	dpdk_sys, join_all, launch, Channel, Error, Lcore, LcoreJoinHandle, MacAddr, Mbuf, PeerEvent,
+	PeerMonitor, Port,
+};
+
+/// Packets one lcore's poll loop has moved, aligned so the lcores don't share cache lines
+#[derive(Default, Debug)]
+#[repr(align(64))]
+pub struct Counters {
+	/// received from the ports
+	pub rx: AtomicU64,
+	/// sent out of the ports
+	pub tx: AtomicU64,
+	/// handed to the mux
+	pub to_mux: AtomicU64,
+	/// taken from the mux
+	pub from_mux: AtomicU64,
+	/// ARP packets answered or learned from
+	pub arp: AtomicU64,
+	/// ICMP errors sent
+	pub icmp: AtomicU64,
+	/// forwarded nowhere, or there was no room for them
+	pub dropped: AtomicU64,
+}
+
+impl Counters {
+	#[inline]
+	fn add(counter: &AtomicU64, n: usize) {
+		// only the owning lcore writes, others only read
+		counter.fetch_add(n as u64, Ordering::Relaxed);
+	}
+}
+
+impl fmt::Display for Counters {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"rx {}, tx {}, to mux {}, from mux {}, arp {}, icmp {}, dropped {}",
+			self.rx.load(Ordering::Relaxed),
+			self.tx.load(Ordering::Relaxed),
+			self.to_mux.load(Ordering::Relaxed),
+			self.from_mux.load(Ordering::Relaxed),
+			self.arp.load(Ordering::Relaxed),
+			self.icmp.load(Ordering::Relaxed),
+			self.dropped.load(Ordering::Relaxed)
+		)
+	}
+}
+
+/// The quiescence epoch a poll loop last started a poll in, see `Engine::quiesce`
+#[derive(Default)]
+#[repr(align(64))]
+struct Ack(AtomicU64);
+
+/// What the lcores' poll loops share
+///
+/// The channel's rings are multi-producer and multi-consumer so every lcore can use it.
+pub struct Engine {
+	ports: Vec<Port>,
+	forwarding: Forwarding,
+	channel: Channel,
+	channel_port: usize,
+	monitor: Arc<PeerMonitor>,
+	running: AtomicBool,
+	burst: usize,
+	counters: Vec<Counters>,
+	epoch: AtomicU64,
+	acks: Vec<Ack>, // by queue
+}
+
+impl Engine {
+	/// An engine for `queues` poll loops over `ports`
+	///
+	/// The mux's packets go out of `ports[channel_port]`. The loops only use the channel
+	/// while `monitor` sees a mux.
+	pub fn new(
+		ports: Vec<Port>,
+		forwarding: Forwarding,
+		channel: Channel,
+		channel_port: usize,
+		monitor: Arc<PeerMonitor>,
+		burst: usize,
+		queues: u16,
+	) -> Self {
+		Self {
+			ports,
+			forwarding,
+			channel,
+			channel_port,
+			monitor,
+			running: AtomicBool::new(true),
+			burst,
+			counters: (0..queues).map(|_| Counters::default()).collect(),
+			epoch: AtomicU64::new(0),
+			acks: (0..queues).map(|_| Ack::default()).collect(),
+		}
+	}
+
+	#[inline]
+	pub fn ports(&self) -> &[Port] {
+		&self.ports
+	}
+
+	#[inline]
+	pub fn channel(&self) -> &Channel {
+		&self.channel
+	}
+
+	#[inline]
+	pub fn forwarding(&self) -> &Forwarding {
+		&self.forwarding
+	}
+
+	/// The counters of every queue pair's poll loop
+	#[inline]
+	pub fn counters(&self) -> &[Counters] {
+		&self.counters
+	}
+
+	#[inline]
+	pub fn is_running(&self) -> bool {
+		self.running.load(Ordering::Relaxed)
+	}
+
+	/// Make the poll loops return
+	#[inline]
+	pub fn stop(&self) {
+		self.running.store(false, Ordering::Relaxed);
+	}
+
+	/// Wait until every poll loop but `queue`'s has finished the poll it was in
+	///
+	/// The loop that owns the channel calls this on a `PeerEvent` before it resets the
+	/// channel. The monitor already stopped the loops from using the channel, so once they
+	/// are past the poll they were in, none of them can enqueue behind the reset. Loops that
+	/// returned are not waited for.
+	pub fn quiesce(&self, queue: u16) {
+		let epoch = self.epoch.fetch_add(1, Ordering::AcqRel) + 1;
+		for (q, ack) in self.acks.iter().enumerate() {
+			if q == queue as usize {
+				continue;
+			}
+			while ack.0.load(Ordering::Acquire) < epoch && self.is_running() {
+				hint::spin_loop();
+			}
+		}
+	}
+
+	/// A poll loop over queue pair `queue` of every port
+	pub fn poller(self: &Arc<Self>, queue: u16) -> Poller {
+		assert!((queue as usize) < self.counters.len());
+		Poller {
+			engine: self.clone(),
+			queue,
+			to_mux: Vec::with_capacity(self.burst),
+			from_mux: Vec::with_capacity(self.burst),
+			out_pkts: self
+				.ports
+				.iter()
+				.map(|_| Vec::with_capacity(self.burst))
+				.collect(),
+		}
+	}
+
+	/// Run the poll loops of queues 1 and up, queue `n` on the `n`th lcore
+	///
+	/// Must be called from the main lcore, whose thread then runs queue 0's, see `run_main`.
+	/// If an lcore can't take its loop, the engine is stopped and the loops already launched
+	/// are waited for before the error is returned.
+	pub fn launch_pollers(self: &Arc<Self>) -> Result<Vec<LcoreJoinHandle<()>>, Error> {
+		let mut pollers = Vec::new();
+		for (queue, lcore) in Lcore::all().enumerate().skip(1) {
+			let engine = self.clone();
+			match launch(lcore, move || engine.poller(queue as u16).run()) {
+				Ok(poller) => pollers.push(poller),
+				Err(e) => {
+					self.stop();
+					join_all(pollers);
+					return Err(e);
+				}
+			}
+		}
+		Ok(pollers)
+	}
+
+	/// Run queue 0's poll loop, which owns the channel, until the engine stops
+	///
+	/// When the monitor sees the mux attach or go, the loop waits for the others to be past
+	/// their poll and recovers the channel. It also forgets the bridge's quiet stations
+	/// every aging period, lookups already ignore them, and gives up on silent next hops
+	/// every second.
+	pub fn run_main(self: &Arc<Self>) {
+		let mut poller = self.poller(0);
+		let hz = unsafe { dpdk_sys::rte_get_timer_hz() };
+		let mut last_aged = unsafe { dpdk_sys::rte_get_tsc_cycles() };
+		let mut last_arp_aged = last_aged;
+
+		let _span = tracing::info_span!("engine", queue = 0).entered();
+		while self.is_running() {
+			match self.monitor.poll() {
+				Some(PeerEvent::Attached(gen)) => {
+					self.quiesce(0);
+					let n = self.channel.reset();
+					tracing::debug!(reclaimed = n, "secondary attached");
+					self.monitor.ready(gen);
+				}
+				Some(PeerEvent::Lost) => {
+					self.quiesce(0);
+					let n = self.channel.reset();
+					tracing::error!(reclaimed = n, "secondary lost");
+				}
+				None => {}
+			}
+			let now = unsafe { dpdk_sys::rte_get_tsc_cycles() };
+			if let Some(macs) = self.forwarding.macs() {
+				if now - last_aged > macs.aging() {
+					let n = macs.age();
+					tracing::debug!(aged = n, stations = macs.len(), "mac table aged");
+					last_aged = now;
+				}
+			}
+			if now - last_arp_aged > hz {
+				let arp = self.forwarding.arp();
+				let (aged, dropped) = arp.age();
+				if aged > 0 || dropped > 0 {
+					tracing::debug!(aged, dropped, neighbors = arp.len(), "arp table aged");
+				}
+				last_arp_aged = now;
+			}
+			poller.poll();
+		}
+	}
+}
+
+/// An lcore's poll loop over its own queue pair of every port
+pub struct Poller {
+	engine: Arc<Engine>,
+	queue: u16,
+	to_mux: Vec<Mbuf>,
+	from_mux: Vec<Mbuf>,
+	out_pkts: Vec<Vec<Mbuf>>, // by egress port
+}
+
+impl Poller {
+	/// Receive from every port and sort the packets by where they go
+	fn recv_pkts(&mut self) {
+		let engine = &self.engine;
+		let counters = &engine.counters[self.queue as usize];
+		let mux_alive = engine.monitor.is_alive();
+		let mut dropped = 0;
+		let mut arp = 0;
+		let mut icmp = 0;
+		for (ingress, port) in engine.ports.iter().enumerate() {
+			let pkts = port.receive(self.queue, engine.burst);
+			Counters::add(&counters.rx, pkts.len());
+			for mut pkt in pkts {
+				match engine.forwarding.egress(ingress, &mut pkt) {
+					Egress::Mux if mux_alive => self.to_mux.push(pkt),
+					Egress::Port(egress) => self.out_pkts[egress].push(pkt),
+					Egress::Flood => dropped += Self::flood(&mut self.out_pkts, ingress, pkt),
+					Egress::Arp => match Self::arp(engine, &mut self.out_pkts, ingress, pkt) {
+						true => arp += 1,
+						false => dropped += 1,
+					},
+					Egress::Resolve(egress, next) => {
+						dropped += Self::resolve(engine, &mut self.out_pkts, egress, next, pkt)
+					}
+					Egress::Icmp(icmp_type, code) => {
+						if Self::icmp_error(
+							engine,
+							&mut self.out_pkts,
+							ingress,
+							icmp_type,
+							code,
+							&pkt,
+						) {
+							icmp += 1;
+						}
+						// the packet itself goes no further
+						dropped += 1;
+					}
+					_ => dropped += 1,
+				}
+			}
+		}
+		Counters::add(&counters.arp, arp);
+		Counters::add(&counters.icmp, icmp);
+		Counters::add(&counters.dropped, dropped);
+	}
+
+	/// Answer an ARP request for the address of port `ingress` and learn the sender,
+	/// returns whether the packet was an ARP for IPv4 over ethernet
+	///
+	/// The packets that were waiting for the sender are queued on their port.
+	fn arp(engine: &Engine, out_pkts: &mut [Vec<Mbuf>], ingress: usize, mut pkt: Mbuf) -> bool {
+		let (iface, arp) = match (engine.forwarding.interface(ingress), pkt.arp_hdr()) {
+			(Some(iface), Some(arp)) if arp.is_ether_ipv4() => (*iface, *arp),
+			_ => return false,
+		};
+		let sip = Ipv4Addr::from(u32::from_be(arp.arp_sip));
+		let tip = Ipv4Addr::from(u32::from_be(arp.arp_tip));
+		let sha = arp.arp_sha;
+		if !sip.is_unspecified() && !is_group(sha) {
+			// whoever asks for us or announces itself is new, others only refresh
+			let create = tip == iface.addr || sip == tip;
+			if let Some((port, pkts)) = engine.forwarding.arp().learn(sip, sha, create) {
+				Self::release(out_pkts, port, sha, pkts);
+			}
+		}
+		if tip == iface.addr && pkt.to_arp_reply(iface.mac) {
+			out_pkts[ingress].push(pkt);
+		}
+		true
+	}
+
+	/// Queue an ICMP error about `pkt` on port `ingress` if the rate allows, from the port's
+	/// address, returns whether one was queued
+	fn icmp_error(
+		engine: &Engine,
+		out_pkts: &mut [Vec<Mbuf>],
+		ingress: usize,
+		icmp_type: u8,
+		code: u8,
+		pkt: &Mbuf,
+	) -> bool {
+		let forwarding = &engine.forwarding;
+		let iface = match forwarding.interface(ingress) {
+			Some(iface) => iface,
+			None => return false,
+		};
+		// the packet can't tell a directed broadcast to the ingress subnet from a unicast
+		let to_broadcast = match (pkt.ipv4_hdr(), iface.broadcast()) {
+			(Some(ip), Some(bcast)) => Ipv4Addr::from(u32::from_be(ip.dst_addr)) == bcast,
+			_ => false,
+		};
+		// packets that aren't answered don't use up the rate, those over it cost no mbuf
+		if to_broadcast || !pkt.may_get_icmp_error() || !forwarding.icmp().allow_error() {
+			return false;
+		}
+		match pkt.icmp_error(icmp_type, code, iface.mac, iface.addr.into()) {
+			Ok(Some(err)) => {
+				out_pkts[ingress].push(err);
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// Queue the packets that were waiting for a next hop at `mac` on `port`
+	fn release(out_pkts: &mut [Vec<Mbuf>], port: usize, mac: MacAddr, pkts: Vec<Mbuf>) {
+		for mut pkt in pkts {
+			if let Some(eth) = pkt.ether_hdr_mut() {
+				eth.d_addr = mac;
+			}
+			out_pkts[port].push(pkt);
+		}
+	}
+
+	/// Hold `pkt` until the next hop at `next` answers and ask for it out of `port` when
+	/// it's time to, returns how many packets were dropped
+	fn resolve(
+		engine: &Engine,
+		out_pkts: &mut [Vec<Mbuf>],
+		port: usize,
+		next: Ipv4Addr,
+		pkt: Mbuf,
+	) -> usize {
+		let iface = match engine.forwarding.interface(port) {
+			Some(iface) => iface,
+			None => return 1,
+		};
+		match engine.forwarding.arp().hold(next, port, iface, pkt) {
+			Hold::Request(req) => {
+				out_pkts[port].push(req);
+				0
+			}
+			Hold::Queued => 0,
+			Hold::Full(req) => {
+				out_pkts[port].extend(req);
+				1
+			}
+		}
+	}
+
+	/// Queue a copy of `pkt` on every port but `ingress`, returns how many copies couldn't
+	/// be made
+	fn flood(out_pkts: &mut [Vec<Mbuf>], ingress: usize, pkt: Mbuf) -> usize {
+		let mut egress = (0..out_pkts.len()).filter(|&port| port != ingress);
+		let last = match egress.next_back() {
+			Some(last) => last,
+			None => return 1,
+		};
+		let mut failed = 0;
+		for port in egress {
+			match pkt.copy() {
+				Ok(copy) => out_pkts[port].push(copy),
+				Err(_) => failed += 1,
+			}
+		}
+		// the last port gets the original
+		out_pkts[last].push(pkt);
+		failed
+	}
+
+	/// Hand the mux its packets and take the ones it sends
+	fn xchg_mux(&mut self) {
+		let engine = &self.engine;
+		let counters = &engine.counters[self.queue as usize];
+		if !engine.monitor.is_alive() {
+			// nobody to hand packets to, the mux went away since they were received
+			Counters::add(&counters.dropped, self.to_mux.len());
+			self.to_mux.clear();
+			return;
+		}
+		if !self.to_mux.is_empty() {
+			let n = engine.channel.send_to_packetiser_bulk(&mut self.to_mux);
+			Counters::add(&counters.to_mux, n);
+			// the mux is falling behind
+			Counters::add(&counters.dropped, self.to_mux.len());
+			self.to_mux.clear();
+		}
+		if engine.forwarding.routes().is_empty() {
+			// nothing to route, it all leaves by the channel's port
+			let out = &mut self.out_pkts[engine.channel_port];
+			let room = engine.burst.saturating_sub(out.len());
+			if room > 0 {
+				let n = engine.channel.recv_from_packetiser_bulk(out, room);
+				Counters::add(&counters.from_mux, n);
+			}
+			return;
+		}
+		let n = engine
+			.channel
+			.recv_from_packetiser_bulk(&mut self.from_mux, engine.burst);
+		Counters::add(&counters.from_mux, n);
+		let mut dropped = 0;
+		for mut pkt in self.from_mux.drain(..) {
+			match engine.forwarding.originate(&mut pkt) {
+				None => self.out_pkts[engine.channel_port].push(pkt),
+				Some(Egress::Port(egress)) => self.out_pkts[egress].push(pkt),
+				Some(Egress::Resolve(egress, next)) => {
+					dropped += Self::resolve(engine, &mut self.out_pkts, egress, next, pkt)
+				}
+				Some(_) => dropped += 1,
+			}
+		}
+		Counters::add(&counters.dropped, dropped);
+	}
+
+	/// Send what's been forwarded to every port
+	///
+	/// What a full tx queue refuses is dropped, like the mux's packets when the channel is
+	/// full. Holding it for the next poll would only hold up the receive side, which has a
+	/// burst of its own to send by then.
+	fn xmit_pkts(&mut self) {
+		let engine = &self.engine;
+		let counters = &engine.counters[self.queue as usize];
+		for (port, out) in engine.ports.iter().zip(self.out_pkts.iter_mut()) {
+			if out.is_empty() {
+				continue;
+			}
+			let n = port.send(out, self.queue);
+			Counters::add(&counters.tx, n);
+			// the tx queue is full
+			Counters::add(&counters.dropped, out.len());
+			out.clear();
+		}
+	}
+
+	/// Poll every port and the mux once
+	#[inline]
+	pub fn poll(&mut self) {
+		// done with the previous poll, see `Engine::quiesce`
+		let epoch = self.engine.epoch.load(Ordering::Acquire);
+		let ack = &self.engine.acks[self.queue as usize].0;
+		if ack.load(Ordering::Relaxed) != epoch {
+			ack.store(epoch, Ordering::Release);
+		}
+		self.recv_pkts();
+		self.xchg_mux();
+		self.xmit_pkts();
+	}
+
+	/// Poll until the engine stops
+	pub fn run(mut self) {
+		let _span = tracing::info_span!("engine", queue = self.queue).entered();
+		while self.engine.is_running() {
+			self.poll();
+		}
+	}
+}
+
+impl Drop for Poller {
+	fn drop(&mut self) {
+		// a loop that returned, or panicked, never touches the channel again
+		self.engine.acks[self.queue as usize]
+			.0
+			.store(u64::MAX, Ordering::Release);
+	}
+}
+
+#[cfg(all(test, feature = "soft"))]
+mod tests {
+	use super::*;
+	use crate::apis::testing;
+	use std::{thread, time::Duration};
+
+	#[test]
+	fn quiesce_waits_for_the_other_loops() {
+		testing::mempool();
+		let forwarding = Forwarding::new(
+			Vec::new(),
+			RouteTable::new(16).unwrap(),
+			None,
+			Vec::new(),
+			ArpTable::new(Duration::from_secs(60)),
+			Icmp::new(false, 0, None),
+		);
+		let engine = Arc::new(Engine::new(
+			Vec::new(),
+			forwarding,
+			Channel::new().unwrap(),
+			0,
+			Arc::new(PeerMonitor::default()),
+			32,
+			3,
+		));
+		let mut busy = engine.poller(1);
+		// a loop that returned isn't waited for
+		drop(engine.poller(2));
+
+		thread::scope(|s| {
+			let waiter = s.spawn(|| engine.quiesce(0));
+			thread::sleep(Duration::from_millis(20));
+			assert!(!waiter.is_finished(), "loop 1 never finished a poll");
+			while !waiter.is_finished() {
+				busy.poll();
+			}
+		});
+		drop(busy);
+		engine.quiesce(0);
+	}
+}
@@ -0,0 +1,243 @@
+//! The engine's IPv4 routes and the addresses of its ports
+//!
+//! Routes live in an `Lpm` whose next hops index a list of the distinct `NextHop`s, so
+//! many routes through the same gateway share an entry.
+
+use std::{
+	collections::BTreeMap,
+	net::Ipv4Addr,
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{Error, Lpm, LpmError, MacAddr};
+
+/// Where a route sends packets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NextHop {
+	/// the egress port's index
+	pub port: usize,
+	/// the router to hand packets to, none when the destination is on the port's subnet
+	pub gateway: Option<Ipv4Addr>,
+}
+
+/// The address of a port the router forwards between
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interface {
+	pub addr: Ipv4Addr,
+	/// prefix length of the port's subnet
+	pub len: u8,
+	pub mac: MacAddr,
+}
+
+impl Interface {
+	/// The port's subnet
+	#[inline]
+	pub fn subnet(&self) -> Ipv4Addr {
+		Ipv4Addr::from(u32::from(self.addr) & mask(self.len))
+	}
+
+	/// Whether `ip` is on the port's subnet
+	#[inline]
+	pub fn contains(&self, ip: Ipv4Addr) -> bool {
+		u32::from(ip) & mask(self.len) == u32::from(self.subnet())
+	}
+
+	/// The subnet's broadcast address, point-to-point /31s and host /32s have none
+	#[inline]
+	pub fn broadcast(&self) -> Option<Ipv4Addr> {
+		if self.len >= 31 {
+			return None;
+		}
+		Some(Ipv4Addr::from(u32::from(self.addr) | !mask(self.len)))
+	}
+}
+
+#[inline]
+fn mask(len: u8) -> u32 {
+	u32::MAX.checked_shl(32 - len as u32).unwrap_or(0)
+}
+
+/// IPv4 prefixes and their next hops, the longest matching prefix wins
+#[derive(Debug)]
+pub struct RouteTable {
+	lpm: Lpm,
+	hops: Vec<NextHop>,
+	routes: BTreeMap<(u32, u8), usize>, // prefix and length to index into `hops`
+}
+
+impl RouteTable {
+	/// Room for 1024 routes
+	pub const DEFAULT_MAX_ROUTES: u32 = 1024;
+
+	/// A table of at most `max_routes` routes
+	pub fn new(max_routes: u32) -> Result<Self, Error> {
+		// the LPM tables are named, every route table needs its own
+		static TABLES: AtomicUsize = AtomicUsize::new(0);
+		let name = format!("routes{}", TABLES.fetch_add(1, Ordering::Relaxed));
+		Ok(Self {
+			lpm: Lpm::new(&name, max_routes)?,
+			hops: Vec::new(),
+			routes: BTreeMap::new(),
+		})
+	}
+
+	/// Route `prefix/len` to `hop`, replacing an existing route
+	pub fn add(&mut self, prefix: Ipv4Addr, len: u8, hop: NextHop) -> Result<(), Error> {
+		if len > 32 {
+			return Err(Error::new(
+				LpmError::Invalid,
+				format!("RouteTable::add({}/{})", prefix, len),
+			));
+		}
+		let prefix = u32::from(prefix) & mask(len);
+		let idx = match self.hops.iter().position(|h| *h == hop) {
+			Some(idx) => idx,
+			None => {
+				self.hops.push(hop);
+				self.hops.len() - 1
+			}
+		};
+		self.lpm.add(prefix.into(), len, idx as u32)?;
+		self.routes.insert((prefix, len), idx);
+		Ok(())
+	}
+
+	/// Remove the route to `prefix/len`
+	pub fn delete(&mut self, prefix: Ipv4Addr, len: u8) -> Result<(), Error> {
+		let prefix = u32::from(prefix) & mask(len.min(32));
+		self.lpm.delete(prefix.into(), len)?;
+		self.routes.remove(&(prefix, len));
+		Ok(())
+	}
+
+	/// The next hop towards `dst`
+	#[inline]
+	pub fn lookup(&self, dst: Ipv4Addr) -> Option<NextHop> {
+		self.lpm
+			.lookup(dst)
+			.and_then(|idx| self.hops.get(idx as usize))
+			.copied()
+	}
+
+	/// Every route, by prefix
+	pub fn routes(&self) -> impl Iterator<Item = (Ipv4Addr, u8, NextHop)> + '_ {
+		self.routes
+			.iter()
+			.map(move |(&(prefix, len), &idx)| (prefix.into(), len, self.hops[idx]))
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.routes.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.routes.is_empty()
+	}
+}
+
+#[cfg(all(test, feature = "soft"))]
+mod tests {
+	use super::*;
+	use crate::apis::testing;
+
+	fn ip(addr: &str) -> Ipv4Addr {
+		addr.parse().unwrap()
+	}
+
+	fn via(port: usize, gateway: &str) -> NextHop {
+		NextHop {
+			port,
+			gateway: Some(ip(gateway)),
+		}
+	}
+
+	fn table() -> RouteTable {
+		testing::mempool();
+		RouteTable::new(RouteTable::DEFAULT_MAX_ROUTES).unwrap()
+	}
+
+	#[test]
+	fn the_longest_prefix_wins() {
+		let mut routes = table();
+		let link = NextHop {
+			port: 0,
+			gateway: None,
+		};
+		routes.add(ip("10.0.0.0"), 8, via(1, "10.0.0.254")).unwrap();
+		routes.add(ip("10.1.0.0"), 16, link).unwrap();
+		routes.add(ip("10.1.2.3"), 32, via(2, "10.1.0.1")).unwrap();
+		assert_eq!(routes.lookup(ip("10.9.9.9")), Some(via(1, "10.0.0.254")));
+		assert_eq!(routes.lookup(ip("10.1.9.9")), Some(link));
+		assert_eq!(routes.lookup(ip("10.1.2.3")), Some(via(2, "10.1.0.1")));
+		assert_eq!(routes.lookup(ip("192.168.0.1")), None);
+	}
+
+	#[test]
+	fn the_default_route_catches_the_rest() {
+		let mut routes = table();
+		routes.add(ip("10.0.0.0"), 8, via(1, "10.0.0.254")).unwrap();
+		routes.add(ip("0.0.0.0"), 0, via(0, "192.168.0.1")).unwrap();
+		assert_eq!(routes.lookup(ip("10.0.0.1")), Some(via(1, "10.0.0.254")));
+		assert_eq!(routes.lookup(ip("8.8.8.8")), Some(via(0, "192.168.0.1")));
+
+		routes.delete(ip("0.0.0.0"), 0).unwrap();
+		assert_eq!(routes.lookup(ip("8.8.8.8")), None);
+		assert!(routes
+			.add(ip("0.0.0.0"), 33, via(0, "192.168.0.1"))
+			.is_err());
+	}
+
+	#[test]
+	fn deleting_falls_back_to_the_shorter_prefix() {
+		let mut routes = table();
+		routes.add(ip("10.0.0.0"), 8, via(1, "10.0.0.254")).unwrap();
+		routes
+			.add(ip("10.1.0.0"), 16, via(2, "10.1.0.254"))
+			.unwrap();
+		// the host bits don't matter
+		routes.delete(ip("10.1.2.3"), 16).unwrap();
+		assert_eq!(routes.lookup(ip("10.1.0.1")), Some(via(1, "10.0.0.254")));
+		assert!(routes.delete(ip("10.1.0.0"), 16).is_err());
+		assert_eq!(routes.len(), 1);
+	}
+
+	#[test]
+	fn routes_share_their_next_hops() {
+		let mut routes = table();
+		let gw = via(1, "10.0.0.254");
+		routes.add(ip("10.1.0.0"), 16, gw).unwrap();
+		routes.add(ip("10.2.0.0"), 16, gw).unwrap();
+		routes
+			.add(ip("10.3.0.0"), 16, via(2, "10.0.0.253"))
+			.unwrap();
+		assert_eq!(routes.hops.len(), 2);
+		// a route is replaced, not added twice
+		routes.add(ip("10.3.0.0"), 16, gw).unwrap();
+		assert_eq!(routes.lookup(ip("10.3.0.1")), Some(gw));
+		assert_eq!(
+			routes.routes().collect::<Vec<_>>(),
+			[
+				(ip("10.1.0.0"), 16, gw),
+				(ip("10.2.0.0"), 16, gw),
+				(ip("10.3.0.0"), 16, gw)
+			]
+		);
+	}
+
+	#[test]
+	fn interfaces_know_their_subnet() {
+		let iface = Interface {
+			addr: ip("10.1.2.3"),
+			len: 16,
+			mac: MacAddr([0x02, 0, 0, 0, 0, 1]),
+		};
+		assert_eq!(iface.subnet(), ip("10.1.0.0"));
+		assert!(iface.contains(ip("10.1.255.255")));
+		assert!(!iface.contains(ip("10.2.0.1")));
+		assert_eq!(iface.broadcast(), Some(ip("10.1.255.255")));
+		let p2p = Interface { len: 31, ..iface };
+		assert_eq!(p2p.broadcast(), None);
+	}
+}
@@ -3,7 +3,29 @@
 //!
 //! These structures and functions enable the user to interact with DPDK in a safe manner
 //! and without also having to manually figure out certain interaction semantics
+//!
+//! The `dpdk` feature, on by default, binds to DPDK through `dpdk-sys`. The `soft` feature
+//! swaps in a pure-Rust backend, see `soft`, so the crate can be built and tested on a
+//! machine without DPDK; it wins when both are enabled. Either way the bindings are reachable
+//! as `l3enginelib::dpdk_sys`.
+//!
+//! `config` reads the setup of `l3enginebin` from a TOML file, `engine` runs its poll loops.
+
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+#[cfg(not(any(feature = "dpdk", feature = "soft")))]
+compile_error!("enable either the `dpdk` or the `soft` feature");
+
+#[cfg(feature = "soft")]
+pub mod soft;
+
+#[cfg(feature = "soft")]
+pub use soft as dpdk_sys;
+#[cfg(not(feature = "soft"))]
+pub use ::dpdk_sys;
 
 pub mod apis;
+pub mod config;
+pub mod engine;
 
 pub use apis::*;
\ No newline at end of file
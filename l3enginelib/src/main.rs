@@ -1,134 +1,191 @@
-use l3enginelib::{eal_cleanup, eal_init, Channel, Mbuf, Mempool, Port};
+use l3enginelib::{
+	config::Config,
+	dpdk_sys, eal_cleanup, eal_init,
+	engine::{Engine, Forwarding},
+	Channel, Lcore, Mempool, Mp, MpMessage, PeerMonitor, Port, RteLog,
+};
 use log;
-use std::{sync::{
-	atomic::{AtomicBool, Ordering},
-	Arc,
-}};
-use zmq::Context;
-
-const G_MEMPOOL_NAME: &str = "GLOBAL_MEMPOOL";
-const QUEUE_SZ: usize = 32;
-const PACKETISER_ZMQ_PORT: &str = "tcp://*:5555";
+use std::{process, sync::Arc, time::Duration};
+use tracing_subscriber::EnvFilter;
 
 /// Handle Ctrl+C
-fn handle_signal(kr: Arc<AtomicBool>) {
+fn handle_signal(engine: Arc<Engine>) {
 	ctrlc::set_handler(move || {
-		kr.store(false, Ordering::SeqCst);
+		engine.stop();
 	})
 	.expect("Error setting Ctrl-C handler");
 }
 
-fn recv_pkts(port: &Port, in_pkts: &mut Vec<Mbuf>, ch: &Channel) -> usize {
-	let len = in_pkts.capacity() - in_pkts.len();
-	if len == 0 {
-		return 0usize;
-	}
-
-	let queue_id = unsafe { dpdk_sys::_rte_lcore_id() as u16 };
-	// let bufs = port.receive(queue_id, len);
-	// for pkt in bufs {
-	// 	in_pkts.push(pkt);
-	// }
-	// NOTE: Would this work?
-	in_pkts.extend(port.receive(queue_id, len));
-
-	let len = ch.send_to_packetiser_bulk(in_pkts);
-
-	len
+/// Answer the mux's control messages
+///
+/// An attach is only answered once the main loop has reclaimed whatever a previous
+/// instance of the mux left in the channel.
+fn handle_mp(monitor: Arc<PeerMonitor>, client: u16, attach_wait: Duration) {
+	Mp::register(Box::new(move |msg| match msg {
+		MpMessage::Attach(id) if id == client => {
+			if monitor.attach(attach_wait) {
+				log::info!("client {} attached", id);
+				Some(MpMessage::ChannelReady(id))
+			} else {
+				log::error!("client {} attach timed out", id);
+				None
+			}
+		}
+		MpMessage::Detach(id) if id == client => {
+			log::info!("client {} detached", id);
+			monitor.detach();
+			None
+		}
+		MpMessage::Heartbeat(id) if id == client => {
+			if monitor.heartbeat() {
+				log::info!("client {} is back after a gap in its heartbeats", id);
+			}
+			None
+		}
+		m => {
+			log::error!("unexpected control message: {:?}", m);
+			None
+		}
+	}))
+	.expect("Error registering control message handler");
 }
 
-fn xmit_pkts(port: &Port, out_pkts: &mut Vec<Mbuf>, ch: &Channel) -> usize {
-	let len = out_pkts.capacity() - out_pkts.len();
-	if len > 0 {
-		let mut pkts = Vec::with_capacity(len);
-		ch.recv_from_packetiser_bulk(&mut pkts, len);
-		out_pkts.extend(pkts);
+/// Send our events and DPDK's logs through `tracing`, filtered by `RUST_LOG`,
+/// e.g. `RUST_LOG=info,l3enginelib=trace,dpdk::eal=debug`
+///
+/// `DPDK_LOG` can raise or lower individual DPDK log types, e.g. `DPDK_LOG=pmd.net.*=debug`
+fn init_logging() {
+	tracing_subscriber::fmt()
+		.with_env_filter(EnvFilter::from_default_env())
+		.init();
+	if let Err(e) = RteLog::install() {
+		log::error!("failed to forward DPDK logs: {}", e);
+	}
+	if let Ok(spec) = std::env::var("DPDK_LOG") {
+		if let Err(e) = RteLog::parse_levels(&spec) {
+			log::error!("bad DPDK_LOG: {}", e);
+		}
 	}
+}
 
-	let queue_id = unsafe { dpdk_sys::_rte_lcore_id() as u16 };
-	let num = port.send(out_pkts, queue_id ^ 1);
-	out_pkts.clear(); // deallocate all buffers
-	num
+/// Load the configuration named by the first argument or `L3ENGINE_CONFIG`, or use the
+/// defaults if neither is set
+///
+/// Exits when the configuration can't be used, before anything is set up.
+fn load_config() -> Config {
+	let path = match Config::path_from_env() {
+		Some(path) => path,
+		None => {
+			log::info!("no configuration given, using the defaults");
+			return Config::default();
+		}
+	};
+	match Config::load(&path) {
+		Ok(config) => {
+			log::info!("loaded configuration from {}", path);
+			config
+		}
+		Err(e) => {
+			log::error!("{}: {}", path, e);
+			process::exit(1);
+		}
+	}
 }
 
 fn main() {
-	log::info!("Initializing DPDK env ...");
-	let args = vec![
-		String::from("-l 0-1"),
-		String::from("-n 4"),
-		String::from("--proc-type=primary"),
-		String::from("--base-virtaddr=0x7f000000000"),
-		String::from("--"),
-		String::from("-p 3"),
-		String::from("-n 2"),
-	];
-	#[cfg(debug)]
-	println!("main process args: {:?}", &args);
-	eal_init(args).unwrap();
-
-	#[cfg(feature = "debug")]
-	println!("environment initialised");
+	init_logging();
+	// ports keep their device name for the life of the process
+	let config: &'static Config = Box::leak(Box::new(load_config()));
 
-	let cores = vec![0];
+	log::info!("Initializing DPDK env ...");
+	eal_init(config.eal_args()).unwrap();
+	tracing::debug!("environment initialised");
+
+	log::info!("setup mempools");
+	let mempools = config
+		.mempools
+		.iter()
+		.map(|mpc| match mpc.create() {
+			Ok(mp) => {
+				tracing::debug!(mempool = ?mp.get_ptr(), name = %mpc.name, "mempool set");
+				mp
+			}
+			Err(e) => panic!("Failed to initialize mempool {}: {}", mpc.name, e),
+		})
+		.collect::<Vec<Mempool>>();
 
-	log::info!("setup mempool");
-	let mempool;
-	match Mempool::new(G_MEMPOOL_NAME) {
-		Ok(mp) => {
-			#[cfg(feature = "debug")]
-			println!("mempool address: {:p}", mp.get_ptr());
-			mempool = mp;
+	log::info!("setup ports");
+	let ports = config
+		.ports
+		.iter()
+		.map(|pc| {
+			let mempool = config
+				.mempools
+				.iter()
+				.position(|mpc| mpc.name == pc.mempool)
+				.map(|i| &mempools[i])
+				.unwrap(); // checked when the configuration was loaded
+			let mut port = Port::new(&pc.name, pc.id)
+				.unwrap_or_else(|e| panic!("Failed to find port {}: {}", pc.name, e));
+			port.configure_with(&pc.conf, mempool)
+				.unwrap_or_else(|e| panic!("Failed to configure port {}: {}", pc.name, e));
+			port.start().unwrap();
+			port
+		})
+		.collect::<Vec<Port>>();
+	let channel_port = config
+		.ports
+		.iter()
+		.position(|pc| pc.name == config.channel.port)
+		.unwrap(); // checked when the configuration was loaded
+
+	tracing::debug!(ports = ports.len(), "ports set");
+
+	// track the mux so a dead one doesn't leave us filling the channel
+	let monitor = Arc::new(PeerMonitor::default());
+	let client = config.channel.client;
+	handle_mp(monitor.clone(), client, config.channel.attach_wait);
+
+	let forwarding = Forwarding::from_config(config, &ports)
+		.unwrap_or_else(|e| panic!("Failed to set up forwarding: {}", e));
+	for (i, pc) in config.ports.iter().enumerate() {
+		if let Some(iface) = forwarding.interface(i) {
+			tracing::debug!(port = %pc.name, ip = %iface.addr, mac = %iface.mac, "interface set");
 		}
-		Err(e) => panic!("Failed to initialize mempool: {}", e),
 	}
-
-	#[cfg(feature = "debug")]
-	println!("mempool set");
-
-	log::info!("setup ports");
-	let eth_devs = "port0";
-	let mut port = Port::new(eth_devs, 0u16).unwrap();
-	port.configure(cores.len() as u16, &mempool).unwrap();
-	port.start().unwrap();
-
-	#[cfg(feature = "debug")]
-	println!("ports set");
-
-	#[cfg(feature = "debug")]
-	println!("main: waiting for secondary");
-	let context = Context::new();
-	let responder = context.socket(zmq::REP).unwrap(); // fatal error
-	assert!(responder.bind(PACKETISER_ZMQ_PORT).is_ok());
-	let mut msg = zmq::Message::new();
-	responder.recv(&mut msg, 0).unwrap();
-
-	// start the channel
-	let channel = Channel::new().unwrap(); // we can't work otherwise!
-
-	// hold packets received from outside and packetiser
-	let mut in_pkts: Vec<Mbuf> = Vec::with_capacity(QUEUE_SZ);
-	let mut out_pkts: Vec<Mbuf> = Vec::with_capacity(QUEUE_SZ);
+	tracing::debug!(policy = ?config.forward.policy, routes = forwarding.routes().len(), "forwarding set");
+	let engine = Arc::new(Engine::new(
+		ports,
+		forwarding,
+		Channel::new().unwrap(), // we can't work otherwise!
+		channel_port,
+		monitor.clone(),
+		config.channel.burst,
+		Lcore::count() as u16,
+	));
 
 	// handling Ctrl+C
-	let keep_running = Arc::new(AtomicBool::new(true));
-	// let kr = keep_running.clone();
-	handle_signal(keep_running.clone());
+	handle_signal(engine.clone());
+
+	// every lcore polls its own queue pair, the main lcore polls queue 0 and owns the channel
+	let workers = engine.launch_pollers().unwrap();
+	tracing::debug!(lcores = Lcore::count(), "waiting for secondary");
+	engine.run_main();
 
-	#[cfg(feature = "debug")]
-	println!("main: secondary started");
-	while keep_running.load(Ordering::SeqCst) {
-		let _rsz = recv_pkts(&port, &mut in_pkts, &channel);
-		#[cfg(feature = "debug")]
-		println!("Received {} packets", _rsz);
-		let _tsz = xmit_pkts(&port, &mut out_pkts, &channel);
-		#[cfg(feature = "debug")]
-		println!("Received {} packets", _rsz);
+	for worker in workers {
+		let lcore = worker.lcore();
+		if worker.join().is_err() {
+			log::error!("poll loop on lcore {} panicked", lcore.id());
+		}
+	}
+	for (queue, counters) in engine.counters().iter().enumerate() {
+		log::info!("queue {}: {}", queue, counters);
 	}
 
-	#[cfg(feature = "debug")]
-	println!("main: stopping");
+	tracing::debug!("stopping");
+	if let Err(e) = Mp::send(MpMessage::Shutdown) {
+		log::error!("failed to notify secondaries of shutdown: {}", e);
+	}
 	unsafe { dpdk_sys::_pkt_stop_and_close_ports() };
-	#[cfg(feature = "debug")]
-	println!("main: ports closed");
-	eal_cleanup(&mempool).unwrap();
+	eal_cleanup(mempools).unwrap();
 }
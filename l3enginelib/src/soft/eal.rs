@@ -0,0 +1,566 @@
+//! EAL start up, lcores, timers, logging, heap, memzones and control messages
+//!
+//! Lcores are plain threads. The thread calling `rte_eal_init` becomes the main lcore
+//! and `rte_eal_remote_launch` spawns a thread per launched job. The heap and memzones
+//! come from the process heap, and control messages are delivered to the action
+//! registered in the same process.
+
+use std::{
+	cell::Cell,
+	collections::{BTreeSet, HashMap},
+	ffi::{c_void, CStr},
+	os::raw,
+	ptr,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Mutex, MutexGuard,
+	},
+	thread::{self, JoinHandle},
+	time::Instant,
+};
+
+use super::{copy_name, lock, name_of, set_errno, Registry, FILE, RTE_MAX_LCORE};
+
+pub const RTE_MEMZONE_NAMESIZE: usize = 32;
+pub const RTE_MP_MAX_NAME_LEN: usize = 64;
+pub const RTE_MP_MAX_PARAM_LEN: usize = 256;
+pub const RTE_MP_MAX_FD_NUM: usize = 8;
+const LCORE_ID_ANY: raw::c_uint = raw::c_uint::MAX;
+const RTE_LOG_DEBUG: raw::c_int = 8;
+
+pub type lcore_function_t = unsafe extern "C" fn(arg: *mut c_void) -> raw::c_int;
+pub type rte_mp_t =
+	Option<unsafe extern "C" fn(msg: *const rte_mp_msg, peer: *const c_void) -> raw::c_int>;
+
+static EAL_INIT: AtomicBool = AtomicBool::new(false);
+static LCORES: state::Storage<Mutex<Lcores>> = state::Storage::new();
+static MEMZONES: Registry = Registry::new();
+static MP_ACTIONS: Registry = Registry::new();
+static START: state::Storage<Instant> = state::Storage::new();
+
+thread_local! {
+	static LCORE_ID: Cell<raw::c_uint> = const { Cell::new(LCORE_ID_ANY) };
+}
+
+#[derive(Default)]
+struct Lcores {
+	enabled: BTreeSet<raw::c_uint>,
+	main: raw::c_uint,
+	registered: BTreeSet<raw::c_uint>,
+	running: HashMap<raw::c_uint, JoinHandle<raw::c_int>>,
+}
+
+fn lcores() -> MutexGuard<'static, Lcores> {
+	let lcores = LCORES.get_or_set(|| Mutex::new(Lcores::default()));
+	match lcores.lock() {
+		Ok(guard) => guard,
+		Err(p_err) => p_err.into_inner(),
+	}
+}
+
+/// Parse an lcore list like "0-3,6"
+fn parse_lcore_list(list: &str) -> Option<BTreeSet<raw::c_uint>> {
+	let mut out = BTreeSet::new();
+	for range in list.split(',').map(str::trim) {
+		let mut ends = range.splitn(2, '-');
+		let first = ends.next()?.trim().parse::<raw::c_uint>().ok()?;
+		let last = match ends.next() {
+			Some(l) => l.trim().parse::<raw::c_uint>().ok()?,
+			None => first,
+		};
+		if first > last || last >= RTE_MAX_LCORE {
+			return None;
+		}
+		out.extend(first..=last);
+	}
+	Some(out)
+}
+
+/// Parse a hexadecimal coremask like "0xf"
+fn parse_coremask(mask: &str) -> Option<BTreeSet<raw::c_uint>> {
+	let mask = mask.trim();
+	let mask = mask.strip_prefix("0x").unwrap_or(mask);
+	let bits = u128::from_str_radix(mask, 16).ok()?;
+	Some(
+		(0..RTE_MAX_LCORE)
+			.filter(|i| bits & (1 << i) != 0)
+			.collect(),
+	)
+}
+
+/// Initialize the software EAL
+///
+/// Understands `-l`, `-c` and `--vdev` and ignores the other EAL options. Like DPDK, the
+/// first argument is the program name and parsing stops at `--`.
+pub unsafe fn rte_eal_init(argc: raw::c_int, argv: *mut *mut raw::c_char) -> raw::c_int {
+	if EAL_INIT.swap(true, Ordering::AcqRel) {
+		set_errno(libc::EALREADY);
+		return -1;
+	}
+	let args = (0..argc.max(0) as usize)
+		.map(|i| CStr::from_ptr(*argv.add(i)).to_string_lossy().into_owned())
+		.collect::<Vec<_>>();
+
+	let mut enabled = None;
+	let mut vdevs = Vec::new();
+	let mut parsed = 0;
+	// options and their values may come in one argument, e.g. "-l 0-1"
+	let mut words = args.iter().skip(1).flat_map(|a| a.split_whitespace());
+	while let Some(word) = words.next() {
+		if word == "--" {
+			break;
+		}
+		parsed += 1;
+		let (opt, inline) = match word.find('=') {
+			Some(idx) if word.starts_with("--") => (&word[..idx], Some(&word[idx + 1..])),
+			_ => (word, None),
+		};
+		let mut value = || {
+			inline
+				.map(str::to_owned)
+				.or_else(|| words.next().map(str::to_owned))
+		};
+		let res = match opt {
+			"-l" | "--lcores" => value()
+				.and_then(|v| parse_lcore_list(&v))
+				.map(|l| enabled = Some(l)),
+			"-c" => value()
+				.and_then(|v| parse_coremask(&v))
+				.map(|l| enabled = Some(l)),
+			"--vdev" => value().map(|v| vdevs.push(v)),
+			"-n" | "-m" | "-w" | "-a" | "-b" | "--file-prefix" | "--proc-type"
+			| "--base-virtaddr" | "--socket-mem" | "--log-level" => value().map(|_| ()),
+			_ => Some(()),
+		};
+		if res.is_none() {
+			EAL_INIT.store(false, Ordering::Release);
+			set_errno(libc::EINVAL);
+			return -1;
+		}
+	}
+
+	let enabled = match enabled {
+		Some(l) if !l.is_empty() => l,
+		Some(_) => {
+			EAL_INIT.store(false, Ordering::Release);
+			set_errno(libc::EINVAL);
+			return -1;
+		}
+		None => {
+			let n = thread::available_parallelism().map_or(1, |n| n.get()) as raw::c_uint;
+			(0..n.min(RTE_MAX_LCORE)).collect()
+		}
+	};
+	if let Err(e) = super::ethdev::probe(&vdevs) {
+		tracing::error!(error = %e, "failed to create soft ports");
+		EAL_INIT.store(false, Ordering::Release);
+		set_errno(libc::EINVAL);
+		return -1;
+	}
+
+	START.set(Instant::now());
+	let mut lcores = lcores();
+	lcores.main = *enabled.iter().next().unwrap_or(&0);
+	lcores.enabled = enabled;
+	LCORE_ID.with(|id| id.set(lcores.main));
+	parsed
+}
+
+pub unsafe fn rte_eal_cleanup() -> raw::c_int {
+	rte_eal_mp_wait_lcore();
+	0
+}
+
+pub unsafe fn rte_lcore_id() -> raw::c_uint {
+	LCORE_ID.with(|id| id.get())
+}
+
+pub unsafe fn rte_socket_id() -> raw::c_uint {
+	0
+}
+
+pub unsafe fn rte_get_main_lcore() -> raw::c_uint {
+	lcores().main
+}
+
+pub unsafe fn rte_get_master_lcore() -> raw::c_uint {
+	rte_get_main_lcore()
+}
+
+pub unsafe fn rte_lcore_count() -> raw::c_uint {
+	lcores().enabled.len() as raw::c_uint
+}
+
+pub unsafe fn rte_lcore_to_socket_id(_lcore_id: raw::c_uint) -> raw::c_uint {
+	0
+}
+
+pub unsafe fn rte_get_next_lcore(
+	i: raw::c_uint,
+	skip_main: raw::c_int,
+	wrap: raw::c_int,
+) -> raw::c_uint {
+	let lcores = lcores();
+	let start = i.wrapping_add(1);
+	let after = lcores.enabled.range(start..);
+	let before = lcores.enabled.range(..start).filter(|_| wrap != 0);
+	after
+		.chain(before)
+		.find(|id| skip_main == 0 || **id != lcores.main)
+		.copied()
+		.unwrap_or(RTE_MAX_LCORE)
+}
+
+pub unsafe fn rte_eal_remote_launch(
+	f: Option<lcore_function_t>,
+	arg: *mut c_void,
+	worker_id: raw::c_uint,
+) -> raw::c_int {
+	let mut lcores = lcores();
+	let f = match f {
+		Some(f) if lcores.enabled.contains(&worker_id) && worker_id != lcores.main => f,
+		_ => return -libc::EINVAL,
+	};
+	if lcores.running.contains_key(&worker_id) {
+		return -libc::EBUSY;
+	}
+	let arg = arg as usize;
+	let spawned = thread::Builder::new()
+		.name(format!("lcore-worker-{}", worker_id))
+		.spawn(move || {
+			LCORE_ID.with(|id| id.set(worker_id));
+			unsafe { f(arg as *mut c_void) }
+		});
+	match spawned {
+		Ok(handle) => {
+			lcores.running.insert(worker_id, handle);
+			0
+		}
+		Err(_) => -libc::ENOEXEC,
+	}
+}
+
+/// Wait for the job on `worker_id` and return its return value, 0 if it was idle
+pub unsafe fn rte_eal_wait_lcore(worker_id: raw::c_uint) -> raw::c_int {
+	// don't hold the lock while joining, the job may use the lcore API
+	let handle = lcores().running.remove(&worker_id);
+	match handle.map(JoinHandle::join) {
+		Some(Ok(ret)) => ret,
+		Some(Err(_)) => -1,
+		None => 0,
+	}
+}
+
+pub unsafe fn rte_eal_mp_wait_lcore() {
+	let running = lcores().running.keys().copied().collect::<Vec<_>>();
+	for worker_id in running {
+		rte_eal_wait_lcore(worker_id);
+	}
+}
+
+/// Give the calling thread the lowest lcore id not used by the EAL
+pub unsafe fn rte_thread_register() -> raw::c_int {
+	if rte_lcore_id() != LCORE_ID_ANY {
+		return 0;
+	}
+	let mut lcores = lcores();
+	let free = (0..RTE_MAX_LCORE)
+		.find(|id| !lcores.enabled.contains(id) && !lcores.registered.contains(id));
+	match free {
+		Some(id) => {
+			lcores.registered.insert(id);
+			LCORE_ID.with(|lid| lid.set(id));
+			0
+		}
+		None => {
+			set_errno(libc::ENOMEM);
+			-1
+		}
+	}
+}
+
+pub unsafe fn rte_thread_unregister() {
+	let id = rte_lcore_id();
+	if lcores().registered.remove(&id) {
+		LCORE_ID.with(|lid| lid.set(LCORE_ID_ANY));
+	}
+}
+
+/// Nanoseconds since `rte_eal_init`
+pub unsafe fn rte_get_tsc_cycles() -> u64 {
+	START.get_or_set(Instant::now).elapsed().as_nanos() as u64
+}
+
+pub unsafe fn rte_get_timer_hz() -> u64 {
+	1_000_000_000
+}
+
+pub unsafe fn rte_openlog_stream(_f: *mut FILE) -> raw::c_int {
+	0
+}
+
+pub unsafe fn rte_log_set_global_level(_level: u32) {}
+
+pub unsafe fn rte_log_set_level_pattern(_pattern: *const raw::c_char, _level: u32) -> raw::c_int {
+	0
+}
+
+pub unsafe fn rte_log_cur_msg_loglevel() -> raw::c_int {
+	RTE_LOG_DEBUG
+}
+
+/// Opaque, the software heap has no memory segments
+#[repr(C)]
+pub struct rte_memseg_list {
+	_private: [u8; 0],
+}
+
+pub unsafe fn rte_malloc_socket(
+	_type_: *const raw::c_char,
+	size: usize,
+	align: raw::c_uint,
+	_socket: raw::c_int,
+) -> *mut c_void {
+	let align = (align as usize)
+		.max(std::mem::size_of::<usize>())
+		.next_power_of_two();
+	let mut out = ptr::null_mut();
+	match libc::posix_memalign(&mut out, align, size.max(1)) {
+		0 => out,
+		_ => ptr::null_mut(),
+	}
+}
+
+pub unsafe fn rte_zmalloc_socket(
+	type_: *const raw::c_char,
+	size: usize,
+	align: raw::c_uint,
+	socket: raw::c_int,
+) -> *mut c_void {
+	let out = rte_malloc_socket(type_, size, align, socket);
+	if !out.is_null() {
+		ptr::write_bytes(out as *mut u8, 0, size);
+	}
+	out
+}
+
+pub unsafe fn rte_realloc_socket(
+	p: *mut c_void,
+	size: usize,
+	align: raw::c_uint,
+	socket: raw::c_int,
+) -> *mut c_void {
+	let out = rte_malloc_socket(ptr::null(), size, align, socket);
+	if !out.is_null() && !p.is_null() {
+		ptr::copy_nonoverlapping(
+			p as *const u8,
+			out as *mut u8,
+			libc::malloc_usable_size(p).min(size),
+		);
+		libc::free(p);
+	}
+	out
+}
+
+pub unsafe fn rte_free(p: *mut c_void) {
+	libc::free(p);
+}
+
+/// The heap is the process heap, no address belongs to a memory segment
+pub unsafe fn rte_mem_virt2memseg_list(_addr: *const c_void) -> *mut rte_memseg_list {
+	ptr::null_mut()
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union rte_memzone__bindgen_ty_2 {
+	pub addr: *mut c_void,
+	pub addr_64: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct rte_memzone {
+	pub name: [raw::c_char; RTE_MEMZONE_NAMESIZE],
+	pub iova: u64,
+	pub __bindgen_anon_2: rte_memzone__bindgen_ty_2,
+	pub len: usize,
+	pub hugepage_sz: u64,
+	pub socket_id: i32,
+	pub flags: u32,
+}
+
+pub unsafe fn rte_memzone_reserve_aligned(
+	name: *const raw::c_char,
+	len: usize,
+	socket_id: raw::c_int,
+	flags: raw::c_uint,
+	align: raw::c_uint,
+) -> *const rte_memzone {
+	let name = name_of(name);
+	let mut zones = lock(&MEMZONES);
+	if zones.contains_key(&name) {
+		set_errno(libc::EEXIST);
+		return ptr::null();
+	}
+	let addr = rte_zmalloc_socket(ptr::null(), len, align, socket_id);
+	if addr.is_null() {
+		set_errno(libc::ENOMEM);
+		return ptr::null();
+	}
+	let mz = Box::into_raw(Box::new(rte_memzone {
+		name: copy_name(&name),
+		iova: 0,
+		__bindgen_anon_2: rte_memzone__bindgen_ty_2 { addr },
+		len,
+		hugepage_sz: 0,
+		socket_id,
+		flags,
+	}));
+	zones.insert(name, mz as usize);
+	mz
+}
+
+pub unsafe fn rte_memzone_lookup(name: *const raw::c_char) -> *const rte_memzone {
+	match lock(&MEMZONES).get(&name_of(name)) {
+		Some(mz) => *mz as *const rte_memzone,
+		None => {
+			set_errno(libc::ENOENT);
+			ptr::null()
+		}
+	}
+}
+
+pub unsafe fn rte_memzone_free(mz: *const rte_memzone) -> raw::c_int {
+	if mz.is_null() {
+		return -libc::EINVAL;
+	}
+	let name = name_of((*mz).name.as_ptr());
+	let mut zones = lock(&MEMZONES);
+	if zones.get(&name) != Some(&(mz as usize)) {
+		return -libc::EINVAL;
+	}
+	zones.remove(&name);
+	let mz = Box::from_raw(mz as *mut rte_memzone);
+	rte_free(mz.__bindgen_anon_2.addr);
+	0
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct rte_mp_msg {
+	pub name: [raw::c_char; RTE_MP_MAX_NAME_LEN],
+	pub len_param: raw::c_int,
+	pub num_fds: raw::c_int,
+	pub param: [u8; RTE_MP_MAX_PARAM_LEN],
+	pub fds: [raw::c_int; RTE_MP_MAX_FD_NUM],
+}
+
+impl Default for rte_mp_msg {
+	fn default() -> Self {
+		Self {
+			name: [0; RTE_MP_MAX_NAME_LEN],
+			len_param: 0,
+			num_fds: 0,
+			param: [0; RTE_MP_MAX_PARAM_LEN],
+			fds: [0; RTE_MP_MAX_FD_NUM],
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct rte_mp_reply {
+	pub nb_sent: raw::c_int,
+	pub nb_received: raw::c_int,
+	pub msgs: *mut rte_mp_msg,
+}
+
+impl Default for rte_mp_reply {
+	fn default() -> Self {
+		Self {
+			nb_sent: 0,
+			nb_received: 0,
+			msgs: ptr::null_mut(),
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct timespec {
+	pub tv_sec: libc::time_t,
+	pub tv_nsec: raw::c_long,
+}
+
+pub unsafe fn rte_mp_action_register(name: *const raw::c_char, action: rte_mp_t) -> raw::c_int {
+	let action = match action {
+		Some(a) => a,
+		None => {
+			set_errno(libc::EINVAL);
+			return -1;
+		}
+	};
+	let name = name_of(name);
+	let mut actions = lock(&MP_ACTIONS);
+	if actions.contains_key(&name) {
+		set_errno(libc::EEXIST);
+		return -1;
+	}
+	actions.insert(name, action as usize);
+	0
+}
+
+pub unsafe fn rte_mp_action_unregister(name: *const raw::c_char) {
+	lock(&MP_ACTIONS).remove(&name_of(name));
+}
+
+/// Run the action registered for `msg`, outside the registry's lock
+unsafe fn dispatch(msg: *const rte_mp_msg, peer: *const c_void) -> Option<raw::c_int> {
+	let action = *lock(&MP_ACTIONS).get(&name_of((*msg).name.as_ptr()))?;
+	let action: unsafe extern "C" fn(*const rte_mp_msg, *const c_void) -> raw::c_int =
+		std::mem::transmute(action);
+	Some(action(msg, peer))
+}
+
+/// Deliver `msg` to the action registered in this process, there are no other peers
+pub unsafe fn rte_mp_sendmsg(msg: *mut rte_mp_msg) -> raw::c_int {
+	dispatch(msg, ptr::null());
+	0
+}
+
+pub unsafe fn rte_mp_request_sync(
+	req: *mut rte_mp_msg,
+	reply: *mut rte_mp_reply,
+	_ts: *const timespec,
+) -> raw::c_int {
+	// the peer is where `rte_mp_reply` leaves the answer
+	let mut answer: Option<rte_mp_msg> = None;
+	let peer = &mut answer as *mut Option<rte_mp_msg> as *const c_void;
+	*reply = rte_mp_reply::default();
+	if dispatch(req, peer).is_none() {
+		return 0;
+	}
+	(*reply).nb_sent = 1;
+	if let Some(msg) = answer {
+		let msgs = libc::malloc(std::mem::size_of::<rte_mp_msg>()) as *mut rte_mp_msg;
+		if msgs.is_null() {
+			set_errno(libc::ENOMEM);
+			return -1;
+		}
+		ptr::write(msgs, msg);
+		(*reply).msgs = msgs;
+		(*reply).nb_received = 1;
+	}
+	0
+}
+
+pub unsafe fn rte_mp_reply(msg: *mut rte_mp_msg, peer: *const raw::c_char) -> raw::c_int {
+	// messages sent with rte_mp_sendmsg have no one waiting for the answer
+	if peer.is_null() {
+		set_errno(libc::EINVAL);
+		return -1;
+	}
+	*(peer as *mut Option<rte_mp_msg>) = Some(*msg);
+	0
+}
@@ -0,0 +1,696 @@
+//! Software ethernet ports created from the EAL's `--vdev` arguments
+//!
+//! - `net_pcap*` ports have a queue per `rx_pcap` and `tx_pcap` argument, as DPDK's do.
+//!   Rx queue `n` delivers the packets of the `n`th `rx_pcap` file, tx queue `n` appends
+//!   what it sends to the `n`th `tx_pcap` file. Like DPDK's, they read the `rx_pcap` files
+//!   as they need their packets, so packets appended before they get there still arrive.
+//! - `net_null*` ports never receive and drop what they send
+//! - every other port loops what it sends on a tx queue back to the rx queue of the same id
+//! - ports from `rte_eth_from_rings` receive from and send to their rings, queue `n` on
+//!   ring `n`
+//!
+//! Port `n` has the locally administered MAC address 02:00:00:00:nn:nn.
+
+use std::{
+	collections::VecDeque,
+	ffi::c_void,
+	fs::File,
+	io::{self, BufWriter, Write},
+	os::{raw, unix::fs::FileExt},
+	ptr, slice,
+	sync::{Mutex, MutexGuard, RwLock},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+	_pkt_raw_addr, name_of, rte_mbuf, rte_mempool, rte_pktmbuf_alloc, rte_pktmbuf_free, rte_ring,
+	rte_ring_dequeue_burst, rte_ring_enqueue_burst, set_errno,
+};
+
+pub const ETH_RSS_IP: u32 = 0xa38c;
+pub const ETH_RSS_TCP: u32 = 0x10410;
+pub const ETH_RSS_UDP: u32 = 0x20820;
+pub const ETH_RSS_SCTP: u32 = 0x1040;
+pub const ETH_RSS_L2_PAYLOAD: u32 = 0x4000;
+pub const DEV_RX_OFFLOAD_VLAN_STRIP: u32 = 0x1;
+pub const DEV_RX_OFFLOAD_IPV4_CKSUM: u32 = 0x2;
+pub const DEV_RX_OFFLOAD_UDP_CKSUM: u32 = 0x4;
+pub const DEV_RX_OFFLOAD_TCP_CKSUM: u32 = 0x8;
+pub const DEV_RX_OFFLOAD_CHECKSUM: u32 = 0xe;
+pub const DEV_RX_OFFLOAD_JUMBO_FRAME: u32 = 0x800;
+pub const DEV_RX_OFFLOAD_SCATTER: u32 = 0x2000;
+pub const DEV_RX_OFFLOAD_RSS_HASH: u32 = 0x80000;
+pub const DEV_TX_OFFLOAD_VLAN_INSERT: u32 = 0x1;
+pub const DEV_TX_OFFLOAD_IPV4_CKSUM: u32 = 0x2;
+pub const DEV_TX_OFFLOAD_UDP_CKSUM: u32 = 0x4;
+pub const DEV_TX_OFFLOAD_TCP_CKSUM: u32 = 0x8;
+pub const DEV_TX_OFFLOAD_TCP_TSO: u32 = 0x20;
+pub const DEV_TX_OFFLOAD_MULTI_SEGS: u32 = 0x8000;
+pub const DEV_TX_OFFLOAD_MBUF_FAST_FREE: u32 = 0x10000;
+
+const MAX_QUEUES: u16 = 64;
+/// Frames are copied in up to the snap length, there is nothing else to offload
+const RX_OFFLOAD_CAPA: u64 = DEV_RX_OFFLOAD_JUMBO_FRAME as u64;
+const TX_OFFLOAD_CAPA: u64 = 0;
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+const PCAP_SNAPLEN: u32 = 65535;
+
+#[allow(non_snake_case)]
+pub mod rte_eth_rx_mq_mode {
+	pub type Type = u32;
+	pub const ETH_MQ_RX_NONE: Type = 0;
+	pub const ETH_MQ_RX_RSS: Type = 1;
+}
+
+#[repr(C, packed(2))]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_ether_addr {
+	pub addr_bytes: [u8; 6],
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_eth_rxconf {
+	pub rx_free_thresh: u16,
+	pub rx_drop_en: u8,
+	pub rx_deferred_start: u8,
+	pub offloads: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_eth_txconf {
+	pub tx_rs_thresh: u16,
+	pub tx_free_thresh: u16,
+	pub tx_deferred_start: u8,
+	pub offloads: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_eth_dev_info {
+	pub if_index: raw::c_uint,
+	pub min_rx_bufsize: u32,
+	pub max_rx_pktlen: u32,
+	pub max_rx_queues: u16,
+	pub max_tx_queues: u16,
+	pub rx_offload_capa: u64,
+	pub tx_offload_capa: u64,
+	pub flow_type_rss_offloads: u64,
+	pub default_rxconf: rte_eth_rxconf,
+	pub default_txconf: rte_eth_txconf,
+	pub hash_key_size: u8,
+	pub reta_size: u16,
+	pub nb_rx_queues: u16,
+	pub nb_tx_queues: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_eth_rxmode {
+	pub mq_mode: rte_eth_rx_mq_mode::Type,
+	pub max_rx_pkt_len: u32,
+	pub max_lro_pkt_size: u32,
+	pub split_hdr_size: u16,
+	pub offloads: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_eth_txmode {
+	pub mq_mode: u32,
+	pub offloads: u64,
+	pub pvid: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct rte_eth_rss_conf {
+	pub rss_key: *mut u8,
+	pub rss_key_len: u8,
+	pub rss_hf: u64,
+}
+
+impl Default for rte_eth_rss_conf {
+	fn default() -> Self {
+		Self {
+			rss_key: ptr::null_mut(),
+			rss_key_len: 0,
+			rss_hf: 0,
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_eth_conf__bindgen_ty_1 {
+	pub rss_conf: rte_eth_rss_conf,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_eth_conf {
+	pub link_speeds: u32,
+	pub rxmode: rte_eth_rxmode,
+	pub txmode: rte_eth_txmode,
+	pub lpbk_mode: u32,
+	pub rx_adv_conf: rte_eth_conf__bindgen_ty_1,
+}
+
+enum Driver {
+	Loopback,
+	Null,
+	/// `rx_pcap` and `tx_pcap` files, by queue
+	Pcap {
+		input: Vec<PcapReader>,
+		output: Vec<BufWriter<File>>,
+	},
+	/// `rte_ring` pointers, by queue
+	Rings {
+		rx: Vec<usize>,
+		tx: Vec<usize>,
+	},
+}
+
+impl Driver {
+	/// Most rx and tx queues the port can be configured with
+	fn max_queues(&self) -> (u16, u16) {
+		match self {
+			Driver::Rings { rx, tx } => (rx.len() as u16, tx.len() as u16),
+			Driver::Pcap { input, output } => {
+				(input.len().max(1) as u16, output.len().max(1) as u16)
+			}
+			_ => (MAX_QUEUES, MAX_QUEUES),
+		}
+	}
+}
+
+#[derive(Default)]
+struct RxQueue {
+	pool: usize,
+	nb_desc: usize,
+	pkts: VecDeque<usize>,
+}
+
+struct SoftPort {
+	name: String,
+	driver: Mutex<Driver>,
+	rxq: Vec<Mutex<RxQueue>>,
+	nb_tx: u16,
+	started: bool,
+}
+
+static PORTS: state::Storage<RwLock<Vec<SoftPort>>> = state::Storage::new();
+
+fn ports() -> &'static RwLock<Vec<SoftPort>> {
+	PORTS.get_or_set(|| RwLock::new(Vec::new()))
+}
+
+fn guard<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+	match m.lock() {
+		Ok(g) => g,
+		Err(p_err) => p_err.into_inner(),
+	}
+}
+
+/// Create one port per `--vdev` argument, or a single loopback port without any
+pub(crate) fn probe(vdevs: &[String]) -> io::Result<()> {
+	let mut created = Vec::new();
+	for vdev in vdevs {
+		let mut parts = vdev.split(',');
+		let name = parts.next().unwrap_or_default().to_owned();
+		let kvs = parts
+			.filter_map(|kv| {
+				let mut kv = kv.splitn(2, '=');
+				Some((kv.next()?, kv.next()?))
+			})
+			.collect::<Vec<_>>();
+		let args = |key: &str| {
+			kvs.iter()
+				.filter(|(k, _)| *k == key)
+				.map(|(_, v)| *v)
+				.collect::<Vec<_>>()
+		};
+
+		let driver = if name.starts_with("net_pcap") {
+			let input = args("rx_pcap")
+				.into_iter()
+				.map(PcapReader::open)
+				.collect::<io::Result<_>>()?;
+			let output = args("tx_pcap")
+				.into_iter()
+				.map(create_pcap)
+				.collect::<io::Result<_>>()?;
+			Driver::Pcap { input, output }
+		} else if name.starts_with("net_null") {
+			Driver::Null
+		} else {
+			Driver::Loopback
+		};
+		created.push(SoftPort::new(name, driver));
+	}
+	if created.is_empty() {
+		created.push(SoftPort::new(String::from("net_loop0"), Driver::Loopback));
+	}
+	*ports().write().unwrap_or_else(|p_err| p_err.into_inner()) = created;
+	Ok(())
+}
+
+/// The packets of a pcap file, read one at a time as libpcap does
+struct PcapReader {
+	file: File,
+	swapped: bool,
+	off: u64,              // of the next packet's record
+	next: Option<Vec<u8>>, // read but not yet received
+}
+
+impl PcapReader {
+	fn open(path: &str) -> io::Result<Self> {
+		let file = File::open(path)?;
+		let mut hdr = [0; 24];
+		let magic = match file.read_exact_at(&mut hdr, 0) {
+			Ok(()) => u32::from_le_bytes([hdr[0], hdr[1], hdr[2], hdr[3]]),
+			Err(_) => 0,
+		};
+		let swapped = match magic {
+			PCAP_MAGIC => false,
+			m if m.swap_bytes() == PCAP_MAGIC => true,
+			_ => {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					format!("{}: not a pcap file", path),
+				))
+			}
+		};
+		Ok(Self {
+			file,
+			swapped,
+			off: hdr.len() as u64,
+			next: None,
+		})
+	}
+
+	/// The next packet, none until all of it has been written
+	fn peek(&mut self) -> Option<&[u8]> {
+		if self.next.is_none() {
+			let mut rec = [0; 16];
+			self.file.read_exact_at(&mut rec, self.off).ok()?;
+			let incl = [rec[8], rec[9], rec[10], rec[11]];
+			let incl = match self.swapped {
+				false => u32::from_le_bytes(incl),
+				true => u32::from_be_bytes(incl),
+			};
+			let mut data = vec![0; incl as usize];
+			self.file
+				.read_exact_at(&mut data, self.off + rec.len() as u64)
+				.ok()?;
+			self.off += (rec.len() + data.len()) as u64;
+			self.next = Some(data);
+		}
+		self.next.as_deref()
+	}
+
+	/// Move past the packet `peek` returned
+	fn pop(&mut self) {
+		self.next = None;
+	}
+}
+
+fn create_pcap(path: &str) -> io::Result<BufWriter<File>> {
+	let mut out = BufWriter::new(File::create(path)?);
+	out.write_all(&PCAP_MAGIC.to_le_bytes())?;
+	out.write_all(&2u16.to_le_bytes())?;
+	out.write_all(&4u16.to_le_bytes())?;
+	out.write_all(&0i32.to_le_bytes())?;
+	out.write_all(&0u32.to_le_bytes())?;
+	out.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+	out.write_all(&PCAP_LINKTYPE_ETHERNET.to_le_bytes())?;
+	Ok(out)
+}
+
+fn write_pcap(out: &mut BufWriter<File>, data: &[u8]) -> io::Result<()> {
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap_or_default();
+	out.write_all(&(now.as_secs() as u32).to_le_bytes())?;
+	out.write_all(&now.subsec_micros().to_le_bytes())?;
+	out.write_all(&(data.len() as u32).to_le_bytes())?;
+	out.write_all(&(data.len() as u32).to_le_bytes())?;
+	out.write_all(data)?;
+	out.flush()
+}
+
+impl SoftPort {
+	fn new(name: String, driver: Driver) -> Self {
+		Self {
+			name,
+			driver: Mutex::new(driver),
+			rxq: Vec::new(),
+			nb_tx: 0,
+			started: false,
+		}
+	}
+}
+
+unsafe fn mbuf_data<'a>(m: *mut rte_mbuf) -> &'a [u8] {
+	slice::from_raw_parts(_pkt_raw_addr(m), (*m).data_len as usize)
+}
+
+/// Adds a port backed by the rings, returns its id or -1 with `rte_errno` set
+pub unsafe fn rte_eth_from_rings(
+	name: *const raw::c_char,
+	rx_queues: *const *mut rte_ring,
+	nb_rx_queues: raw::c_uint,
+	tx_queues: *const *mut rte_ring,
+	nb_tx_queues: raw::c_uint,
+	_numa_node: raw::c_uint,
+) -> raw::c_int {
+	// every queue needs a ring
+	let rings = |queues: *const *mut rte_ring, n: raw::c_uint| match n {
+		0 => Some(Vec::new()),
+		_ if queues.is_null() || n > MAX_QUEUES as raw::c_uint => None,
+		_ => {
+			let rings = slice::from_raw_parts(queues, n as usize);
+			match rings.iter().any(|r| r.is_null()) {
+				true => None,
+				false => Some(rings.iter().map(|&r| r as usize).collect::<Vec<_>>()),
+			}
+		}
+	};
+	let (rx, tx) = match (
+		rings(rx_queues, nb_rx_queues),
+		rings(tx_queues, nb_tx_queues),
+	) {
+		(Some(rx), Some(tx)) if !name.is_null() => (rx, tx),
+		_ => {
+			set_errno(libc::EINVAL);
+			return -1;
+		}
+	};
+	let name = name_of(name);
+	let mut ports = ports().write().unwrap_or_else(|p_err| p_err.into_inner());
+	if ports.iter().any(|p| p.name == name) {
+		set_errno(libc::EEXIST);
+		return -1;
+	}
+	ports.push(SoftPort::new(name, Driver::Rings { rx, tx }));
+	(ports.len() - 1) as raw::c_int
+}
+
+pub unsafe fn rte_eth_dev_count_avail() -> u16 {
+	ports().read().map(|p| p.len()).unwrap_or(0) as u16
+}
+
+pub unsafe fn rte_eth_dev_info_get(port_id: u16, dev_info: *mut rte_eth_dev_info) -> raw::c_int {
+	let ports = ports().read().unwrap_or_else(|p_err| p_err.into_inner());
+	let port = match ports.get(port_id as usize) {
+		Some(p) => p,
+		None => return -libc::ENODEV,
+	};
+	let (max_rx_queues, max_tx_queues) = guard(&port.driver).max_queues();
+	*dev_info = rte_eth_dev_info {
+		if_index: 0,
+		min_rx_bufsize: 0,
+		max_rx_pktlen: PCAP_SNAPLEN,
+		max_rx_queues,
+		max_tx_queues,
+		rx_offload_capa: RX_OFFLOAD_CAPA,
+		tx_offload_capa: TX_OFFLOAD_CAPA,
+		flow_type_rss_offloads: (ETH_RSS_IP | ETH_RSS_TCP | ETH_RSS_UDP) as u64,
+		default_rxconf: rte_eth_rxconf::default(),
+		default_txconf: rte_eth_txconf::default(),
+		hash_key_size: 40,
+		reta_size: 0,
+		nb_rx_queues: port.rxq.len() as u16,
+		nb_tx_queues: port.nb_tx,
+	};
+	0
+}
+
+pub unsafe fn rte_eth_dev_configure(
+	port_id: u16,
+	nb_rx_queue: u16,
+	nb_tx_queue: u16,
+	eth_conf: *const rte_eth_conf,
+) -> raw::c_int {
+	// like DPDK, refuse offloads the port can't do
+	if let Some(conf) = eth_conf.as_ref() {
+		if conf.rxmode.offloads & !RX_OFFLOAD_CAPA != 0
+			|| conf.txmode.offloads & !TX_OFFLOAD_CAPA != 0
+		{
+			return -libc::EINVAL;
+		}
+	}
+	let mut ports = ports().write().unwrap_or_else(|p_err| p_err.into_inner());
+	let port = match ports.get_mut(port_id as usize) {
+		Some(p) => p,
+		None => return -libc::ENODEV,
+	};
+	if port.started {
+		return -libc::EBUSY;
+	}
+	let (max_rx_queues, max_tx_queues) = guard(&port.driver).max_queues();
+	if nb_rx_queue > max_rx_queues || nb_tx_queue > max_tx_queues {
+		return -libc::EINVAL;
+	}
+	port.rxq = (0..nb_rx_queue).map(|_| Mutex::default()).collect();
+	port.nb_tx = nb_tx_queue;
+	0
+}
+
+pub unsafe fn rte_eth_rx_queue_setup(
+	port_id: u16,
+	rx_queue_id: u16,
+	nb_rx_desc: u16,
+	_socket_id: raw::c_uint,
+	_rx_conf: *const rte_eth_rxconf,
+	mb_pool: *mut rte_mempool,
+) -> raw::c_int {
+	let ports = ports().read().unwrap_or_else(|p_err| p_err.into_inner());
+	let port = match ports.get(port_id as usize) {
+		Some(p) => p,
+		None => return -libc::ENODEV,
+	};
+	match port.rxq.get(rx_queue_id as usize) {
+		Some(q) if !mb_pool.is_null() => {
+			let mut q = guard(q);
+			q.pool = mb_pool as usize;
+			q.nb_desc = nb_rx_desc as usize;
+			0
+		}
+		_ => -libc::EINVAL,
+	}
+}
+
+pub unsafe fn rte_eth_tx_queue_setup(
+	port_id: u16,
+	tx_queue_id: u16,
+	_nb_tx_desc: u16,
+	_socket_id: raw::c_uint,
+	_tx_conf: *const rte_eth_txconf,
+) -> raw::c_int {
+	let ports = ports().read().unwrap_or_else(|p_err| p_err.into_inner());
+	match ports.get(port_id as usize) {
+		Some(p) if tx_queue_id < p.nb_tx => 0,
+		Some(_) => -libc::EINVAL,
+		None => -libc::ENODEV,
+	}
+}
+
+pub unsafe fn rte_eth_dev_socket_id(port_id: u16) -> raw::c_int {
+	match (port_id as usize) < rte_eth_dev_count_avail() as usize {
+		true => 0,
+		false => -1,
+	}
+}
+
+pub unsafe fn rte_eth_macaddr_get(port_id: u16, mac_addr: *mut rte_ether_addr) -> raw::c_int {
+	if mac_addr.is_null() {
+		return -libc::EINVAL;
+	}
+	if rte_eth_dev_socket_id(port_id) != 0 {
+		return -libc::ENODEV;
+	}
+	let [hi, lo] = port_id.to_be_bytes();
+	(*mac_addr).addr_bytes = [0x02, 0, 0, 0, hi, lo];
+	0
+}
+
+pub unsafe fn rte_eth_promiscuous_enable(port_id: u16) -> raw::c_int {
+	match rte_eth_dev_socket_id(port_id) {
+		0 => 0,
+		_ => -libc::ENODEV,
+	}
+}
+
+pub unsafe fn rte_eth_dev_start(port_id: u16) -> raw::c_int {
+	let mut ports = ports().write().unwrap_or_else(|p_err| p_err.into_inner());
+	match ports.get_mut(port_id as usize) {
+		Some(p) => {
+			p.started = true;
+			0
+		}
+		None => -libc::ENODEV,
+	}
+}
+
+pub unsafe fn rte_eth_dev_stop(port_id: u16) -> raw::c_int {
+	let mut ports = ports().write().unwrap_or_else(|p_err| p_err.into_inner());
+	match ports.get_mut(port_id as usize) {
+		Some(p) => {
+			p.started = false;
+			0
+		}
+		None => -libc::ENODEV,
+	}
+}
+
+/// Stops the port and frees the packets still waiting in its queues
+pub unsafe fn rte_eth_dev_close(port_id: u16) -> raw::c_int {
+	let mut ports = ports().write().unwrap_or_else(|p_err| p_err.into_inner());
+	let port = match ports.get_mut(port_id as usize) {
+		Some(p) => p,
+		None => return -libc::ENODEV,
+	};
+	port.started = false;
+	for q in port.rxq.drain(..) {
+		for m in guard(&q).pkts.drain(..) {
+			rte_pktmbuf_free(m as *mut rte_mbuf);
+		}
+	}
+	if let Driver::Pcap { output, .. } = &mut *guard(&port.driver) {
+		for out in output {
+			let _ = out.flush();
+		}
+	}
+	tracing::debug!(port = port_id, name = %port.name, "closed soft port");
+	0
+}
+
+pub unsafe fn rte_eth_rx_burst(
+	port_id: u16,
+	queue_id: u16,
+	rx_pkts: *mut *mut rte_mbuf,
+	nb_pkts: u16,
+) -> u16 {
+	let ports = ports().read().unwrap_or_else(|p_err| p_err.into_inner());
+	let port = match ports.get(port_id as usize) {
+		Some(p) if p.started => p,
+		_ => return 0,
+	};
+	let mut q = match port.rxq.get(queue_id as usize) {
+		Some(q) => guard(q),
+		None => return 0,
+	};
+
+	if let Driver::Rings { rx, .. } = &*guard(&port.driver) {
+		return match rx.get(queue_id as usize) {
+			Some(&r) => rte_ring_dequeue_burst(
+				r as *mut rte_ring,
+				rx_pkts as *mut *mut c_void,
+				nb_pkts as raw::c_uint,
+				ptr::null_mut(),
+			) as u16,
+			None => 0,
+		};
+	}
+
+	// pcap input is read on demand, into buffers of the queue's mempool
+	if q.pool != 0 {
+		if let Driver::Pcap { input, .. } = &mut *guard(&port.driver) {
+			let input = match input.get_mut(queue_id as usize) {
+				Some(input) => input,
+				None => return 0,
+			};
+			while q.pkts.len() < nb_pkts as usize {
+				let data = match input.peek() {
+					Some(d) => d,
+					None => break,
+				};
+				let m = rte_pktmbuf_alloc(q.pool as *mut rte_mempool);
+				if m.is_null() {
+					break;
+				}
+				let len = data.len().min(((*m).buf_len - (*m).data_off) as usize);
+				ptr::copy_nonoverlapping(data.as_ptr(), _pkt_raw_addr(m), len);
+				(*m).data_len = len as u16;
+				(*m).pkt_len = len as u32;
+				(*m).port = port_id;
+				q.pkts.push_back(m as usize);
+				input.pop();
+			}
+		}
+	}
+
+	let mut cnt = 0;
+	while cnt < nb_pkts {
+		match q.pkts.pop_front() {
+			Some(m) => *rx_pkts.add(cnt as usize) = m as *mut rte_mbuf,
+			None => break,
+		}
+		cnt += 1;
+	}
+	cnt
+}
+
+pub unsafe fn rte_eth_tx_burst(
+	port_id: u16,
+	queue_id: u16,
+	tx_pkts: *mut *mut rte_mbuf,
+	nb_pkts: u16,
+) -> u16 {
+	let ports = ports().read().unwrap_or_else(|p_err| p_err.into_inner());
+	let port = match ports.get(port_id as usize) {
+		Some(p) if p.started && queue_id < p.nb_tx => p,
+		_ => return 0,
+	};
+	let pkts = slice::from_raw_parts(tx_pkts, nb_pkts as usize);
+
+	let mut driver = guard(&port.driver);
+	match &mut *driver {
+		Driver::Loopback => {
+			let mut q = match port.rxq.get(queue_id as usize) {
+				Some(q) => guard(q),
+				None => return 0,
+			};
+			let room = q.nb_desc.saturating_sub(q.pkts.len()).min(pkts.len());
+			for &m in &pkts[..room] {
+				(*m).port = port_id;
+				q.pkts.push_back(m as usize);
+			}
+			room as u16
+		}
+		Driver::Null => {
+			pkts.iter().for_each(|&m| rte_pktmbuf_free(m));
+			nb_pkts
+		}
+		Driver::Pcap { output, .. } => {
+			for &m in pkts {
+				if let Some(out) = output.get_mut(queue_id as usize) {
+					if let Err(e) = write_pcap(out, mbuf_data(m)) {
+						tracing::error!(port = port_id, error = %e, "failed to write tx_pcap");
+					}
+				}
+				rte_pktmbuf_free(m);
+			}
+			nb_pkts
+		}
+		Driver::Rings { tx, .. } => match tx.get(queue_id as usize) {
+			Some(&r) => rte_ring_enqueue_burst(
+				r as *mut rte_ring,
+				tx_pkts as *const *mut c_void,
+				nb_pkts as raw::c_uint,
+				ptr::null_mut(),
+			) as u16,
+			None => 0,
+		},
+	}
+}
+
+pub unsafe fn _pkt_stop_and_close_ports() {
+	for port_id in 0..rte_eth_dev_count_avail() {
+		rte_eth_dev_stop(port_id);
+		rte_eth_dev_close(port_id);
+	}
+}
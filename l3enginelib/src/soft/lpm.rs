@@ -0,0 +1,435 @@
+//! Longest prefix match tables like `rte_lpm` and `rte_lpm6`
+//!
+//! The rules of every prefix length sit in their own hash map and a lookup tries the
+//! lengths from the longest down. Like DPDK's, the tables take prefix lengths from 1 up,
+//! next hops of 24 bits for IPv4 and 21 bits for IPv6, and add and delete rules without
+//! synchronising with lookups.
+
+use std::{collections::HashMap, os::raw, ptr};
+
+use super::{copy_name, lock, name_of, set_errno, Registry};
+
+pub const RTE_LPM_NAMESIZE: usize = 32;
+pub const RTE_LPM_MAX_DEPTH: u32 = 32;
+pub const RTE_LPM6_NAMESIZE: usize = 32;
+pub const RTE_LPM6_MAX_DEPTH: u32 = 128;
+
+const LPM_NEXT_HOP_MASK: u32 = (1 << 24) - 1;
+const LPM6_NEXT_HOP_MASK: u32 = (1 << 21) - 1;
+
+static TABLES: Registry = Registry::new();
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_lpm_config {
+	pub max_rules: u32,
+	pub number_tbl8s: u32,
+	pub flags: raw::c_int,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_lpm6_config {
+	pub max_rules: u32,
+	pub number_tbl8s: u32,
+	pub flags: raw::c_int,
+}
+
+/// A table of rules keyed by the masked prefix, one map per prefix length
+struct Rules<K> {
+	by_depth: Vec<HashMap<K, u32>>,
+	count: usize,
+	max: usize,
+}
+
+impl<K: std::hash::Hash + Eq + Copy> Rules<K> {
+	fn new(max_depth: u32, max: u32) -> Self {
+		Self {
+			by_depth: (0..=max_depth).map(|_| HashMap::new()).collect(),
+			count: 0,
+			max: max as usize,
+		}
+	}
+
+	fn add(&mut self, key: K, depth: u8, next_hop: u32) -> raw::c_int {
+		let rules = &mut self.by_depth[depth as usize];
+		if !rules.contains_key(&key) {
+			if self.count >= self.max {
+				return -libc::ENOSPC;
+			}
+			self.count += 1;
+		}
+		rules.insert(key, next_hop);
+		0
+	}
+
+	fn delete(&mut self, key: K, depth: u8) -> raw::c_int {
+		match self.by_depth[depth as usize].remove(&key) {
+			Some(_) => {
+				self.count -= 1;
+				0
+			}
+			None => -libc::EINVAL,
+		}
+	}
+
+	fn get(&self, key: K, depth: u8) -> Option<u32> {
+		self.by_depth[depth as usize].get(&key).copied()
+	}
+
+	fn clear(&mut self) {
+		self.by_depth.iter_mut().for_each(HashMap::clear);
+		self.count = 0;
+	}
+}
+
+pub struct rte_lpm {
+	name: [raw::c_char; RTE_LPM_NAMESIZE],
+	rules: Rules<u32>,
+}
+
+pub struct rte_lpm6 {
+	name: [raw::c_char; RTE_LPM6_NAMESIZE],
+	rules: Rules<u128>,
+}
+
+#[inline]
+fn mask4(ip: u32, depth: u8) -> u32 {
+	ip & u32::MAX.checked_shl(32 - depth as u32).unwrap_or(0)
+}
+
+#[inline]
+fn mask6(ip: u128, depth: u8) -> u128 {
+	ip & u128::MAX.checked_shl(128 - depth as u32).unwrap_or(0)
+}
+
+unsafe fn ip6(ip: *const u8) -> u128 {
+	let mut octets = [0u8; 16];
+	ptr::copy_nonoverlapping(ip, octets.as_mut_ptr(), 16);
+	u128::from_be_bytes(octets)
+}
+
+unsafe fn register(name: *const raw::c_char, table: usize) -> bool {
+	let mut tables = lock(&TABLES);
+	let name = name_of(name);
+	if tables.contains_key(&name) {
+		set_errno(libc::EEXIST);
+		return false;
+	}
+	tables.insert(name, table);
+	true
+}
+
+unsafe fn unregister(name: *const raw::c_char) {
+	lock(&TABLES).remove(&name_of(name));
+}
+
+pub unsafe fn rte_lpm_create(
+	name: *const raw::c_char,
+	_socket_id: raw::c_int,
+	config: *const rte_lpm_config,
+) -> *mut rte_lpm {
+	if name.is_null() || config.is_null() || (*config).max_rules == 0 {
+		set_errno(libc::EINVAL);
+		return ptr::null_mut();
+	}
+	let lpm = Box::into_raw(Box::new(rte_lpm {
+		name: copy_name(&name_of(name)),
+		rules: Rules::new(RTE_LPM_MAX_DEPTH, (*config).max_rules),
+	}));
+	if !register(name, lpm as usize) {
+		drop(Box::from_raw(lpm));
+		return ptr::null_mut();
+	}
+	lpm
+}
+
+pub unsafe fn rte_lpm_free(lpm: *mut rte_lpm) {
+	if lpm.is_null() {
+		return;
+	}
+	unregister((*lpm).name.as_ptr());
+	drop(Box::from_raw(lpm));
+}
+
+pub unsafe fn rte_lpm_add(lpm: *mut rte_lpm, ip: u32, depth: u8, next_hop: u32) -> raw::c_int {
+	if lpm.is_null() || depth < 1 || depth as u32 > RTE_LPM_MAX_DEPTH {
+		return -libc::EINVAL;
+	}
+	(*lpm)
+		.rules
+		.add(mask4(ip, depth), depth, next_hop & LPM_NEXT_HOP_MASK)
+}
+
+pub unsafe fn rte_lpm_is_rule_present(
+	lpm: *mut rte_lpm,
+	ip: u32,
+	depth: u8,
+	next_hop: *mut u32,
+) -> raw::c_int {
+	if lpm.is_null() || next_hop.is_null() || depth < 1 || depth as u32 > RTE_LPM_MAX_DEPTH {
+		return -libc::EINVAL;
+	}
+	match (*lpm).rules.get(mask4(ip, depth), depth) {
+		Some(nh) => {
+			*next_hop = nh;
+			1
+		}
+		None => 0,
+	}
+}
+
+pub unsafe fn rte_lpm_delete(lpm: *mut rte_lpm, ip: u32, depth: u8) -> raw::c_int {
+	if lpm.is_null() || depth < 1 || depth as u32 > RTE_LPM_MAX_DEPTH {
+		return -libc::EINVAL;
+	}
+	(*lpm).rules.delete(mask4(ip, depth), depth)
+}
+
+pub unsafe fn rte_lpm_delete_all(lpm: *mut rte_lpm) {
+	(*lpm).rules.clear();
+}
+
+pub unsafe fn rte_lpm_lookup(lpm: *mut rte_lpm, ip: u32, next_hop: *mut u32) -> raw::c_int {
+	if lpm.is_null() || next_hop.is_null() {
+		return -libc::EINVAL;
+	}
+	for depth in (1..=RTE_LPM_MAX_DEPTH as u8).rev() {
+		if let Some(nh) = (*lpm).rules.get(mask4(ip, depth), depth) {
+			*next_hop = nh;
+			return 0;
+		}
+	}
+	-libc::ENOENT
+}
+
+pub unsafe fn rte_lpm6_create(
+	name: *const raw::c_char,
+	_socket_id: raw::c_int,
+	config: *const rte_lpm6_config,
+) -> *mut rte_lpm6 {
+	if name.is_null() || config.is_null() || (*config).max_rules == 0 {
+		set_errno(libc::EINVAL);
+		return ptr::null_mut();
+	}
+	let lpm = Box::into_raw(Box::new(rte_lpm6 {
+		name: copy_name(&name_of(name)),
+		rules: Rules::new(RTE_LPM6_MAX_DEPTH, (*config).max_rules),
+	}));
+	if !register(name, lpm as usize) {
+		drop(Box::from_raw(lpm));
+		return ptr::null_mut();
+	}
+	lpm
+}
+
+pub unsafe fn rte_lpm6_free(lpm: *mut rte_lpm6) {
+	if lpm.is_null() {
+		return;
+	}
+	unregister((*lpm).name.as_ptr());
+	drop(Box::from_raw(lpm));
+}
+
+pub unsafe fn rte_lpm6_add(
+	lpm: *mut rte_lpm6,
+	ip: *const u8,
+	depth: u8,
+	next_hop: u32,
+) -> raw::c_int {
+	if lpm.is_null() || ip.is_null() || depth < 1 || depth as u32 > RTE_LPM6_MAX_DEPTH {
+		return -libc::EINVAL;
+	}
+	(*lpm)
+		.rules
+		.add(mask6(ip6(ip), depth), depth, next_hop & LPM6_NEXT_HOP_MASK)
+}
+
+pub unsafe fn rte_lpm6_is_rule_present(
+	lpm: *mut rte_lpm6,
+	ip: *const u8,
+	depth: u8,
+	next_hop: *mut u32,
+) -> raw::c_int {
+	if lpm.is_null()
+		|| ip.is_null()
+		|| next_hop.is_null()
+		|| depth < 1
+		|| depth as u32 > RTE_LPM6_MAX_DEPTH
+	{
+		return -libc::EINVAL;
+	}
+	match (*lpm).rules.get(mask6(ip6(ip), depth), depth) {
+		Some(nh) => {
+			*next_hop = nh;
+			1
+		}
+		None => 0,
+	}
+}
+
+pub unsafe fn rte_lpm6_delete(lpm: *mut rte_lpm6, ip: *const u8, depth: u8) -> raw::c_int {
+	if lpm.is_null() || ip.is_null() || depth < 1 || depth as u32 > RTE_LPM6_MAX_DEPTH {
+		return -libc::EINVAL;
+	}
+	(*lpm).rules.delete(mask6(ip6(ip), depth), depth)
+}
+
+pub unsafe fn rte_lpm6_delete_all(lpm: *mut rte_lpm6) {
+	(*lpm).rules.clear();
+}
+
+pub unsafe fn rte_lpm6_lookup(
+	lpm: *const rte_lpm6,
+	ip: *const u8,
+	next_hop: *mut u32,
+) -> raw::c_int {
+	if lpm.is_null() || ip.is_null() || next_hop.is_null() {
+		return -libc::EINVAL;
+	}
+	let ip = ip6(ip);
+	for depth in (1..=RTE_LPM6_MAX_DEPTH as u8).rev() {
+		if let Some(nh) = (*lpm).rules.get(mask6(ip, depth), depth) {
+			*next_hop = nh;
+			return 0;
+		}
+	}
+	-libc::ENOENT
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{ffi::CString, net::Ipv4Addr};
+
+	/// A table of `max_rules` rules under a name no other test uses
+	fn lpm(name: &str, max_rules: u32) -> *mut rte_lpm {
+		let name = CString::new(name).unwrap();
+		let config = rte_lpm_config {
+			max_rules,
+			..Default::default()
+		};
+		let lpm = unsafe { rte_lpm_create(name.as_ptr(), 0, &config) };
+		assert!(!lpm.is_null());
+		lpm
+	}
+
+	fn ip(addr: &str) -> u32 {
+		addr.parse::<Ipv4Addr>().unwrap().into()
+	}
+
+	fn lookup(lpm: *mut rte_lpm, addr: &str) -> Option<u32> {
+		let mut next_hop = 0;
+		match unsafe { rte_lpm_lookup(lpm, ip(addr), &mut next_hop) } {
+			0 => Some(next_hop),
+			e => {
+				assert_eq!(e, -libc::ENOENT);
+				None
+			}
+		}
+	}
+
+	#[test]
+	fn the_longest_prefix_wins() {
+		let lpm = lpm("lpm_longest", 16);
+		unsafe {
+			assert_eq!(rte_lpm_add(lpm, ip("10.0.0.0"), 8, 1), 0);
+			assert_eq!(rte_lpm_add(lpm, ip("10.1.0.0"), 16, 2), 0);
+			assert_eq!(rte_lpm_add(lpm, ip("10.1.2.3"), 32, 3), 0);
+			// host bits are ignored
+			assert_eq!(rte_lpm_add(lpm, ip("10.1.2.99"), 24, 4), 0);
+		}
+		assert_eq!(lookup(lpm, "10.9.9.9"), Some(1));
+		assert_eq!(lookup(lpm, "10.1.9.9"), Some(2));
+		assert_eq!(lookup(lpm, "10.1.2.4"), Some(4));
+		assert_eq!(lookup(lpm, "10.1.2.3"), Some(3));
+		assert_eq!(lookup(lpm, "11.0.0.0"), None);
+		unsafe { rte_lpm_free(lpm) };
+	}
+
+	#[test]
+	fn deleting_falls_back_to_the_shorter_prefix() {
+		let lpm = lpm("lpm_delete", 16);
+		let mut next_hop = 0;
+		unsafe {
+			rte_lpm_add(lpm, ip("10.0.0.0"), 8, 1);
+			rte_lpm_add(lpm, ip("10.1.0.0"), 16, 2);
+			assert_eq!(
+				rte_lpm_is_rule_present(lpm, ip("10.1.0.0"), 16, &mut next_hop),
+				1
+			);
+			assert_eq!(next_hop, 2);
+			assert_eq!(rte_lpm_delete(lpm, ip("10.1.255.255"), 16), 0);
+			assert_eq!(
+				rte_lpm_is_rule_present(lpm, ip("10.1.0.0"), 16, &mut next_hop),
+				0
+			);
+			assert_eq!(rte_lpm_delete(lpm, ip("10.1.0.0"), 16), -libc::EINVAL);
+		}
+		assert_eq!(lookup(lpm, "10.1.0.1"), Some(1));
+		unsafe { rte_lpm_delete_all(lpm) };
+		assert_eq!(lookup(lpm, "10.1.0.1"), None);
+		unsafe { rte_lpm_free(lpm) };
+	}
+
+	#[test]
+	fn takes_what_dpdk_takes() {
+		let lpm = lpm("lpm_limits", 2);
+		unsafe {
+			// the default route is the wrappers' business
+			assert_eq!(rte_lpm_add(lpm, 0, 0, 1), -libc::EINVAL);
+			assert_eq!(rte_lpm_add(lpm, 0, 33, 1), -libc::EINVAL);
+			assert_eq!(rte_lpm_add(lpm, ip("10.0.0.0"), 8, u32::MAX), 0);
+			assert_eq!(rte_lpm_add(lpm, ip("11.0.0.0"), 8, 2), 0);
+			assert_eq!(rte_lpm_add(lpm, ip("12.0.0.0"), 8, 3), -libc::ENOSPC);
+			// replacing a rule takes no room
+			assert_eq!(rte_lpm_add(lpm, ip("11.0.0.0"), 8, 4), 0);
+		}
+		assert_eq!(lookup(lpm, "10.0.0.1"), Some(LPM_NEXT_HOP_MASK));
+		assert_eq!(lookup(lpm, "11.0.0.1"), Some(4));
+
+		let name = CString::new("lpm_limits").unwrap();
+		let config = rte_lpm_config {
+			max_rules: 2,
+			..Default::default()
+		};
+		assert!(unsafe { rte_lpm_create(name.as_ptr(), 0, &config) }.is_null());
+		unsafe { rte_lpm_free(lpm) };
+		let lpm = unsafe { rte_lpm_create(name.as_ptr(), 0, &config) };
+		assert!(!lpm.is_null(), "the name is free again");
+		unsafe { rte_lpm_free(lpm) };
+	}
+
+	#[test]
+	fn ipv6_the_longest_prefix_wins() {
+		let name = CString::new("lpm6_longest").unwrap();
+		let config = rte_lpm6_config {
+			max_rules: 16,
+			..Default::default()
+		};
+		let lpm = unsafe { rte_lpm6_create(name.as_ptr(), 0, &config) };
+		assert!(!lpm.is_null());
+		let ip = |addr: &str| addr.parse::<std::net::Ipv6Addr>().unwrap().octets();
+		let lookup = |addr: &str| {
+			let mut next_hop = 0;
+			match unsafe { rte_lpm6_lookup(lpm, ip(addr).as_ptr(), &mut next_hop) } {
+				0 => Some(next_hop),
+				_ => None,
+			}
+		};
+		unsafe {
+			assert_eq!(rte_lpm6_add(lpm, ip("2001:db8::").as_ptr(), 32, 1), 0);
+			assert_eq!(rte_lpm6_add(lpm, ip("2001:db8:1::").as_ptr(), 48, 2), 0);
+			assert_eq!(rte_lpm6_add(lpm, ip("2001:db8:1::1").as_ptr(), 128, 3), 0);
+		}
+		assert_eq!(lookup("2001:db8:2::1"), Some(1));
+		assert_eq!(lookup("2001:db8:1::2"), Some(2));
+		assert_eq!(lookup("2001:db8:1::1"), Some(3));
+		assert_eq!(lookup("2001:db9::1"), None);
+		unsafe {
+			assert_eq!(rte_lpm6_delete(lpm, ip("2001:db8:1::").as_ptr(), 48), 0);
+		}
+		assert_eq!(lookup("2001:db8:1::2"), Some(1));
+		unsafe { rte_lpm6_free(lpm) };
+	}
+}
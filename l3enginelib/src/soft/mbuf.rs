@@ -0,0 +1,403 @@
+//! Mempools of `rte_mbuf` laid out buffers
+//!
+//! A mempool is one heap allocation cut into `size` objects of `elt_size` bytes. The free
+//! objects sit in a ring. Mempool caches pass straight through to their mempool.
+
+use std::{
+	alloc::{self, Layout},
+	cmp,
+	ffi::c_void,
+	mem,
+	os::raw,
+	ptr,
+	sync::atomic::{AtomicU16, Ordering},
+};
+
+use super::{copy_name, lock, name_of, rte_ring, set_errno, Registry, RTE_CACHE_LINE_SIZE};
+
+pub const RTE_MEMPOOL_NAMESIZE: usize = 32;
+pub const RTE_PKTMBUF_HEADROOM: u16 = 128;
+const RTE_MBUF_PORT_INVALID: u16 = u16::MAX;
+
+static MEMPOOLS: Registry = Registry::new();
+
+pub type rte_mempool_ctor_t = Option<unsafe extern "C" fn(mp: *mut rte_mempool, arg: *mut c_void)>;
+pub type rte_mempool_obj_cb_t = Option<
+	unsafe extern "C" fn(
+		mp: *mut rte_mempool,
+		opaque: *mut c_void,
+		obj: *mut c_void,
+		idx: raw::c_uint,
+	),
+>;
+
+/// The packet buffer header, with the fields of DPDK's `rte_mbuf` the wrappers use
+///
+/// Like DPDK's, the reference count is atomic, lcores on other threads may free references
+/// to the same buffer.
+#[repr(C, align(64))]
+#[derive(Debug)]
+pub struct rte_mbuf {
+	pub buf_addr: *mut c_void,
+	pub buf_iova: u64,
+	pub data_off: u16,
+	pub refcnt: AtomicU16,
+	pub nb_segs: u16,
+	pub port: u16,
+	pub ol_flags: u64,
+	pub packet_type: u32,
+	pub pkt_len: u32,
+	pub data_len: u16,
+	pub vlan_tci: u16,
+	pub rss: u32,
+	pub vlan_tci_outer: u16,
+	pub buf_len: u16,
+	pub pool: *mut rte_mempool,
+	pub next: *mut rte_mbuf,
+	pub tx_offload: u64,
+	pub priv_size: u16,
+	pub timesync: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct rte_pktmbuf_pool_private {
+	pub mbuf_data_room_size: u16,
+	pub mbuf_priv_size: u16,
+	pub flags: u32,
+}
+
+#[repr(C)]
+pub struct rte_mempool {
+	pub name: [raw::c_char; RTE_MEMPOOL_NAMESIZE],
+	pub pool_data: *mut c_void,
+	pub flags: raw::c_uint,
+	pub socket_id: raw::c_int,
+	pub size: u32,
+	pub cache_size: u32,
+	pub elt_size: u32,
+	pub private_data_size: u32,
+	pub pool_private: rte_pktmbuf_pool_private,
+	objs: *mut u8,
+	layout: Layout,
+}
+
+impl rte_mempool {
+	#[inline]
+	fn ring(&self) -> &rte_ring {
+		unsafe { &*(self.pool_data as *const rte_ring) }
+	}
+}
+
+/// A mempool cache, which holds nothing in the software backend
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct rte_mempool_cache {
+	pub size: u32,
+	pub len: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn rte_mempool_create(
+	name: *const raw::c_char,
+	n: raw::c_uint,
+	elt_size: raw::c_uint,
+	cache_size: raw::c_uint,
+	private_data_size: raw::c_uint,
+	mp_init: rte_mempool_ctor_t,
+	mp_init_arg: *mut c_void,
+	obj_init: rte_mempool_obj_cb_t,
+	obj_init_arg: *mut c_void,
+	socket_id: raw::c_int,
+	flags: raw::c_uint,
+) -> *mut rte_mempool {
+	let name = name_of(name);
+	let mut pools = lock(&MEMPOOLS);
+	if pools.contains_key(&name) {
+		set_errno(libc::EEXIST);
+		return ptr::null_mut();
+	}
+
+	let align = RTE_CACHE_LINE_SIZE as usize;
+	let stride = (elt_size as usize + align - 1) & !(align - 1);
+	let layout = match Layout::from_size_align(stride * n.max(1) as usize, align) {
+		Ok(l) if elt_size as usize >= mem::size_of::<rte_mbuf>() && n > 0 => l,
+		_ => {
+			set_errno(libc::EINVAL);
+			return ptr::null_mut();
+		}
+	};
+	let ring = match rte_ring::with_count(&name, n, super::RING_F_EXACT_SZ) {
+		Some(r) => Box::into_raw(r),
+		None => {
+			set_errno(libc::EINVAL);
+			return ptr::null_mut();
+		}
+	};
+	let objs = alloc::alloc_zeroed(layout);
+	if objs.is_null() {
+		drop(Box::from_raw(ring));
+		set_errno(libc::ENOMEM);
+		return ptr::null_mut();
+	}
+
+	let mp = Box::into_raw(Box::new(rte_mempool {
+		name: copy_name(&name),
+		pool_data: ring as *mut c_void,
+		flags,
+		socket_id,
+		size: n,
+		cache_size,
+		elt_size,
+		private_data_size,
+		pool_private: rte_pktmbuf_pool_private::default(),
+		objs,
+		layout,
+	}));
+	if let Some(init) = mp_init {
+		init(mp, mp_init_arg);
+	}
+	for idx in 0..n {
+		let obj = objs.add(idx as usize * stride) as *mut c_void;
+		if let Some(init) = obj_init {
+			init(mp, obj_init_arg, obj, idx);
+		}
+		(*ring).enqueue(&obj, 1, true);
+	}
+	pools.insert(name, mp as usize);
+	mp
+}
+
+pub unsafe fn rte_mempool_lookup(name: *const raw::c_char) -> *mut rte_mempool {
+	match lock(&MEMPOOLS).get(&name_of(name)) {
+		Some(mp) => *mp as *mut rte_mempool,
+		None => {
+			set_errno(libc::ENOENT);
+			ptr::null_mut()
+		}
+	}
+}
+
+pub unsafe fn rte_mempool_free(mp: *mut rte_mempool) {
+	if mp.is_null() {
+		return;
+	}
+	let name = name_of((*mp).name.as_ptr());
+	let mut pools = lock(&MEMPOOLS);
+	if pools.get(&name) != Some(&(mp as usize)) {
+		return;
+	}
+	pools.remove(&name);
+	let mp = Box::from_raw(mp);
+	drop(Box::from_raw(mp.pool_data as *mut rte_ring));
+	alloc::dealloc(mp.objs, mp.layout);
+}
+
+pub unsafe fn rte_mempool_get(mp: *mut rte_mempool, obj_p: *mut *mut c_void) -> raw::c_int {
+	rte_mempool_get_bulk(mp, obj_p, 1)
+}
+
+pub unsafe fn rte_mempool_get_bulk(
+	mp: *mut rte_mempool,
+	obj_table: *mut *mut c_void,
+	n: raw::c_uint,
+) -> raw::c_int {
+	match (*mp).ring().dequeue(obj_table, n, true) {
+		0 if n > 0 => -libc::ENOENT,
+		_ => 0,
+	}
+}
+
+pub unsafe fn rte_mempool_put(mp: *mut rte_mempool, obj: *mut c_void) {
+	rte_mempool_put_bulk(mp, &obj, 1);
+}
+
+pub unsafe fn rte_mempool_put_bulk(
+	mp: *mut rte_mempool,
+	obj_table: *const *mut c_void,
+	n: raw::c_uint,
+) {
+	// the ring holds every object of the pool, putting them back always fits
+	(*mp).ring().enqueue(obj_table, n, false);
+}
+
+pub unsafe fn rte_mempool_avail_count(mp: *const rte_mempool) -> raw::c_uint {
+	(*mp).ring().count()
+}
+
+pub unsafe fn rte_mempool_cache_create(
+	size: u32,
+	_socket_id: raw::c_int,
+) -> *mut rte_mempool_cache {
+	Box::into_raw(Box::new(rte_mempool_cache { size, len: 0 }))
+}
+
+pub unsafe fn rte_mempool_cache_free(cache: *mut rte_mempool_cache) {
+	if !cache.is_null() {
+		drop(Box::from_raw(cache));
+	}
+}
+
+pub unsafe fn rte_mempool_cache_flush(_cache: *mut rte_mempool_cache, _mp: *mut rte_mempool) {}
+
+/// Records the data room of the buffers in the pool's private area
+pub unsafe extern "C" fn rte_pktmbuf_pool_init(mp: *mut rte_mempool, _opaque_arg: *mut c_void) {
+	let room = (*mp).elt_size as usize - mem::size_of::<rte_mbuf>();
+	(*mp).pool_private = rte_pktmbuf_pool_private {
+		mbuf_data_room_size: room.min(u16::MAX as usize) as u16,
+		mbuf_priv_size: 0,
+		flags: 0,
+	};
+}
+
+/// Lays out the `rte_mbuf` header of an object, the data buffer follows it
+pub unsafe extern "C" fn rte_pktmbuf_init(
+	mp: *mut rte_mempool,
+	_opaque_arg: *mut c_void,
+	obj: *mut c_void,
+	_i: raw::c_uint,
+) {
+	let hdr = mem::size_of::<rte_mbuf>();
+	let buf_len = ((*mp).elt_size as usize - hdr).min(u16::MAX as usize) as u16;
+	ptr::write(
+		obj as *mut rte_mbuf,
+		rte_mbuf {
+			buf_addr: (obj as *mut u8).add(hdr) as *mut c_void,
+			buf_iova: 0,
+			data_off: cmp::min(RTE_PKTMBUF_HEADROOM, buf_len),
+			refcnt: AtomicU16::new(1),
+			nb_segs: 1,
+			port: RTE_MBUF_PORT_INVALID,
+			ol_flags: 0,
+			packet_type: 0,
+			pkt_len: 0,
+			data_len: 0,
+			vlan_tci: 0,
+			rss: 0,
+			vlan_tci_outer: 0,
+			buf_len,
+			pool: mp,
+			next: ptr::null_mut(),
+			tx_offload: 0,
+			priv_size: 0,
+			timesync: 0,
+		},
+	);
+}
+
+pub unsafe fn rte_pktmbuf_reset(m: *mut rte_mbuf) {
+	let m = &mut *m;
+	m.next = ptr::null_mut();
+	m.pkt_len = 0;
+	m.data_len = 0;
+	m.nb_segs = 1;
+	m.port = RTE_MBUF_PORT_INVALID;
+	m.ol_flags = 0;
+	m.packet_type = 0;
+	m.tx_offload = 0;
+	m.vlan_tci = 0;
+	m.refcnt.store(1, Ordering::Relaxed);
+	m.data_off = cmp::min(RTE_PKTMBUF_HEADROOM, m.buf_len);
+}
+
+pub unsafe fn rte_pktmbuf_alloc(mp: *mut rte_mempool) -> *mut rte_mbuf {
+	let mut m = ptr::null_mut();
+	if rte_mempool_get(mp, &mut m) != 0 {
+		return ptr::null_mut();
+	}
+	let m = m as *mut rte_mbuf;
+	rte_pktmbuf_reset(m);
+	m
+}
+
+pub unsafe fn rte_pktmbuf_alloc_bulk(
+	pool: *mut rte_mempool,
+	mbufs: *mut *mut rte_mbuf,
+	count: raw::c_uint,
+) -> raw::c_int {
+	let ret = rte_mempool_get_bulk(pool, mbufs as *mut *mut c_void, count);
+	if ret == 0 {
+		for i in 0..count as usize {
+			rte_pktmbuf_reset(*mbufs.add(i));
+		}
+	}
+	ret
+}
+
+pub unsafe fn rte_pktmbuf_free(m: *mut rte_mbuf) {
+	let mut m = m;
+	while !m.is_null() {
+		let next = (*m).next;
+		// the last reference needs no atomic update, like `rte_pktmbuf_prefree_seg`
+		let last = (*m).refcnt.load(Ordering::Acquire) == 1
+			|| (*m).refcnt.fetch_sub(1, Ordering::AcqRel) == 1;
+		if last {
+			(*m).refcnt.store(1, Ordering::Relaxed);
+			(*m).next = ptr::null_mut();
+			(*m).nb_segs = 1;
+			rte_mempool_put((*m).pool, m as *mut c_void);
+		}
+		m = next;
+	}
+}
+
+/// Copies `length` bytes from `offset` of the packet into a new mbuf of `mp`
+///
+/// Unlike DPDK the copy is never chained, packets that don't fit one mbuf of `mp` aren't
+/// copied.
+pub unsafe fn rte_pktmbuf_copy(
+	m: *const rte_mbuf,
+	mp: *mut rte_mempool,
+	offset: u32,
+	length: u32,
+) -> *mut rte_mbuf {
+	if offset > (*m).pkt_len {
+		return ptr::null_mut();
+	}
+	let len = cmp::min((*m).pkt_len - offset, length);
+	let mc = rte_pktmbuf_alloc(mp);
+	if mc.is_null() {
+		return ptr::null_mut();
+	}
+	if len > ((*mc).buf_len - (*mc).data_off) as u32 {
+		rte_pktmbuf_free(mc);
+		return ptr::null_mut();
+	}
+	let mut dst = _pkt_raw_addr(mc);
+	let (mut seg, mut skip, mut left) = (m, offset as usize, len as usize);
+	while left > 0 && !seg.is_null() {
+		let seg_len = (*seg).data_len as usize;
+		if skip < seg_len {
+			let n = cmp::min(seg_len - skip, left);
+			ptr::copy_nonoverlapping(_pkt_raw_addr(seg as *mut _).add(skip), dst, n);
+			dst = dst.add(n);
+			left -= n;
+			skip = 0;
+		} else {
+			skip -= seg_len;
+		}
+		seg = (*seg).next;
+	}
+	(*mc).data_len = (len as usize - left) as u16;
+	(*mc).pkt_len = (*mc).data_len as u32;
+	(*mc).port = (*m).port;
+	(*mc).ol_flags = (*m).ol_flags;
+	(*mc).packet_type = (*m).packet_type;
+	mc
+}
+
+pub unsafe fn _pkt_mbuf_alloc_cache(
+	mp: *mut rte_mempool,
+	_cache: *mut rte_mempool_cache,
+) -> *mut rte_mbuf {
+	rte_pktmbuf_alloc(mp)
+}
+
+pub unsafe fn _pkt_mbuf_free_cache(m: *mut rte_mbuf, _cache: *mut rte_mempool_cache) {
+	rte_pktmbuf_free(m)
+}
+
+/// Address of the first byte of packet data
+pub unsafe fn _pkt_raw_addr(pkt: *mut rte_mbuf) -> *mut u8 {
+	((*pkt).buf_addr as *mut u8).add((*pkt).data_off as usize)
+}
@@ -0,0 +1,86 @@
+//! A pure-Rust stand-in for the parts of `dpdk_sys` the wrappers use, enabled by the `soft` feature
+//!
+//! It lets `Mbuf`, `Mempool`, `Ring`, `Channel`, `Port` and the code built on them run in a
+//! plain `cargo test`, without hugepages or a DPDK install. The crate re-exports it as
+//! `l3enginelib::dpdk_sys` so the wrappers compile unchanged against either backend.
+//!
+//! Everything lives in a single process on the heap:
+//! - mempools hand out `rte_mbuf` laid out buffers from one allocation, through a ring
+//! - rings are bounded multi-producer/multi-consumer rings like `rte_ring`
+//! - ports are created from the `--vdev` EAL arguments. `net_pcap*` ports read their
+//!   `rx_pcap` file and write their `tx_pcap` file, `net_null*` ports drop what they send
+//!   and every other port loops what it sends back to its receive queue. Without any
+//!   `--vdev`, port 0 is a loopback port. `rte_eth_from_rings` adds ports backed by rings.
+//! - lcores are threads, and control messages go to the handler registered in the same process
+//! - LPM tables keep a hash map of rules per prefix length
+
+#![allow(non_camel_case_types)]
+#![allow(non_upper_case_globals)]
+#![allow(clippy::missing_safety_doc)]
+
+mod eal;
+mod ethdev;
+mod lpm;
+mod mbuf;
+mod ring;
+
+pub use eal::*;
+pub use ethdev::*;
+pub use lpm::*;
+pub use mbuf::*;
+pub use ring::*;
+
+use std::{
+	cell::Cell,
+	collections::HashMap,
+	ffi::CStr,
+	os::raw,
+	sync::{Mutex, MutexGuard},
+};
+
+pub type FILE = libc::FILE;
+
+pub const RTE_CACHE_LINE_SIZE: u32 = 64;
+pub const RTE_MAX_LCORE: u32 = 128;
+pub const RTE_ETHER_MAX_LEN: u32 = 1518;
+
+thread_local! {
+	static RTE_ERRNO: Cell<raw::c_int> = const { Cell::new(0) };
+}
+
+/// The per-thread error number set by the failing calls
+pub unsafe fn _rte_errno() -> raw::c_int {
+	RTE_ERRNO.with(|e| e.get())
+}
+
+pub(crate) fn set_errno(errno: raw::c_int) {
+	RTE_ERRNO.with(|e| e.set(errno));
+}
+
+pub unsafe fn rte_strerror(errnum: raw::c_int) -> *const raw::c_char {
+	libc::strerror(errnum)
+}
+
+/// Objects looked up by name, e.g. rings and mempools, stored as addresses
+pub(crate) type Registry = state::Storage<Mutex<HashMap<String, usize>>>;
+
+pub(crate) fn lock(registry: &'static Registry) -> MutexGuard<'static, HashMap<String, usize>> {
+	let map = registry.get_or_set(|| Mutex::new(HashMap::new()));
+	match map.lock() {
+		Ok(guard) => guard,
+		Err(p_err) => p_err.into_inner(),
+	}
+}
+
+pub(crate) unsafe fn name_of(name: *const raw::c_char) -> String {
+	CStr::from_ptr(name).to_string_lossy().into_owned()
+}
+
+/// Copy `name` into a fixed size, nul terminated C array
+pub(crate) fn copy_name<const N: usize>(name: &str) -> [raw::c_char; N] {
+	let mut out = [0 as raw::c_char; N];
+	for (dst, src) in out.iter_mut().zip(name.bytes().take(N - 1)) {
+		*dst = src as raw::c_char;
+	}
+	out
+}
@@ -0,0 +1,264 @@
+//! Bounded multi-producer/multi-consumer rings following `rte_ring`'s head/tail scheme
+//!
+//! A producer reserves slots by moving the producer head, fills them and then publishes them
+//! by moving the producer tail once the producers ahead of it are done. Consumers do the same
+//! with the consumer head and tail.
+
+use std::{
+	ffi::c_void,
+	hint,
+	os::raw,
+	ptr,
+	sync::atomic::{AtomicPtr, AtomicU32, Ordering},
+};
+
+use super::{copy_name, lock, name_of, set_errno, Registry};
+
+pub const RTE_RING_NAMESIZE: usize = 32;
+pub const RING_F_EXACT_SZ: raw::c_uint = 0x0004;
+const RTE_RING_SZ_MASK: u32 = 0x7fff_ffff;
+
+static RINGS: Registry = Registry::new();
+
+#[repr(C)]
+pub struct rte_ring {
+	pub name: [raw::c_char; RTE_RING_NAMESIZE],
+	pub flags: raw::c_int,
+	pub size: u32,
+	pub mask: u32,
+	pub capacity: u32,
+	prod_head: AtomicU32,
+	prod_tail: AtomicU32,
+	cons_head: AtomicU32,
+	cons_tail: AtomicU32,
+	slots: Box<[AtomicPtr<c_void>]>,
+}
+
+impl rte_ring {
+	/// A ring holding up to `count - 1` objects, or `count` with `RING_F_EXACT_SZ`
+	pub(crate) fn with_count(name: &str, count: u32, flags: raw::c_uint) -> Option<Box<Self>> {
+		let (size, capacity) = if flags & RING_F_EXACT_SZ != 0 {
+			(count.checked_add(1)?.checked_next_power_of_two()?, count)
+		} else {
+			(count, count.wrapping_sub(1))
+		};
+		if !size.is_power_of_two() || size > RTE_RING_SZ_MASK {
+			return None;
+		}
+		Some(Box::new(Self {
+			name: copy_name(name),
+			flags: flags as raw::c_int,
+			size,
+			mask: size - 1,
+			capacity,
+			prod_head: AtomicU32::new(0),
+			prod_tail: AtomicU32::new(0),
+			cons_head: AtomicU32::new(0),
+			cons_tail: AtomicU32::new(0),
+			slots: (0..size).map(|_| AtomicPtr::new(ptr::null_mut())).collect(),
+		}))
+	}
+
+	/// Enqueue up to `n` objects, or none at all if `fixed` and they don't all fit
+	pub(crate) unsafe fn enqueue(&self, objs: *const *mut c_void, n: u32, fixed: bool) -> u32 {
+		let mut head = self.prod_head.load(Ordering::Relaxed);
+		let cnt = loop {
+			let free = self
+				.capacity
+				.wrapping_add(self.cons_tail.load(Ordering::Acquire))
+				.wrapping_sub(head);
+			let cnt = match n > free {
+				true if fixed => 0,
+				true => free,
+				false => n,
+			};
+			if cnt == 0 {
+				return 0;
+			}
+			match self.prod_head.compare_exchange_weak(
+				head,
+				head.wrapping_add(cnt),
+				Ordering::AcqRel,
+				Ordering::Relaxed,
+			) {
+				Ok(_) => break cnt,
+				Err(cur) => head = cur,
+			}
+		};
+
+		for i in 0..cnt {
+			let slot = &self.slots[(head.wrapping_add(i) & self.mask) as usize];
+			slot.store(*objs.add(i as usize), Ordering::Relaxed);
+		}
+		// publish in reservation order
+		while self.prod_tail.load(Ordering::Acquire) != head {
+			hint::spin_loop();
+		}
+		self.prod_tail
+			.store(head.wrapping_add(cnt), Ordering::Release);
+		cnt
+	}
+
+	/// Dequeue up to `n` objects, or none at all if `fixed` and there are fewer
+	pub(crate) unsafe fn dequeue(&self, objs: *mut *mut c_void, n: u32, fixed: bool) -> u32 {
+		let mut head = self.cons_head.load(Ordering::Relaxed);
+		let cnt = loop {
+			let entries = self.prod_tail.load(Ordering::Acquire).wrapping_sub(head);
+			let cnt = match n > entries {
+				true if fixed => 0,
+				true => entries,
+				false => n,
+			};
+			if cnt == 0 {
+				return 0;
+			}
+			match self.cons_head.compare_exchange_weak(
+				head,
+				head.wrapping_add(cnt),
+				Ordering::AcqRel,
+				Ordering::Relaxed,
+			) {
+				Ok(_) => break cnt,
+				Err(cur) => head = cur,
+			}
+		};
+
+		for i in 0..cnt {
+			let slot = &self.slots[(head.wrapping_add(i) & self.mask) as usize];
+			*objs.add(i as usize) = slot.load(Ordering::Relaxed);
+		}
+		while self.cons_tail.load(Ordering::Acquire) != head {
+			hint::spin_loop();
+		}
+		self.cons_tail
+			.store(head.wrapping_add(cnt), Ordering::Release);
+		cnt
+	}
+
+	pub(crate) fn count(&self) -> u32 {
+		let prod = self.prod_tail.load(Ordering::Acquire);
+		let cons = self.cons_tail.load(Ordering::Acquire);
+		prod.wrapping_sub(cons).min(self.capacity)
+	}
+
+	fn free_count(&self) -> u32 {
+		self.capacity - self.count()
+	}
+}
+
+pub unsafe fn rte_ring_create(
+	name: *const raw::c_char,
+	count: raw::c_uint,
+	_socket_id: raw::c_int,
+	flags: raw::c_uint,
+) -> *mut rte_ring {
+	let name = name_of(name);
+	let mut rings = lock(&RINGS);
+	if rings.contains_key(&name) {
+		set_errno(libc::EEXIST);
+		return ptr::null_mut();
+	}
+	match rte_ring::with_count(&name, count, flags) {
+		Some(r) => {
+			let r = Box::into_raw(r);
+			rings.insert(name, r as usize);
+			r
+		}
+		None => {
+			set_errno(libc::EINVAL);
+			ptr::null_mut()
+		}
+	}
+}
+
+pub unsafe fn rte_ring_lookup(name: *const raw::c_char) -> *mut rte_ring {
+	match lock(&RINGS).get(&name_of(name)) {
+		Some(r) => *r as *mut rte_ring,
+		None => {
+			set_errno(libc::ENOENT);
+			ptr::null_mut()
+		}
+	}
+}
+
+pub unsafe fn rte_ring_free(r: *mut rte_ring) {
+	if r.is_null() {
+		return;
+	}
+	let name = name_of((*r).name.as_ptr());
+	let mut rings = lock(&RINGS);
+	if rings.get(&name) == Some(&(r as usize)) {
+		rings.remove(&name);
+		drop(Box::from_raw(r));
+	}
+}
+
+pub unsafe fn rte_ring_count(r: *const rte_ring) -> raw::c_uint {
+	(*r).count()
+}
+
+pub unsafe fn rte_ring_enqueue(r: *mut rte_ring, obj: *mut c_void) -> raw::c_int {
+	match (*r).enqueue(&obj, 1, true) {
+		0 => -libc::ENOBUFS,
+		_ => 0,
+	}
+}
+
+pub unsafe fn rte_ring_dequeue(r: *mut rte_ring, obj_p: *mut *mut c_void) -> raw::c_int {
+	match (*r).dequeue(obj_p, 1, true) {
+		0 => -libc::ENOENT,
+		_ => 0,
+	}
+}
+
+pub unsafe fn rte_ring_enqueue_bulk(
+	r: *mut rte_ring,
+	obj_table: *const *mut c_void,
+	n: raw::c_uint,
+	free_space: *mut raw::c_uint,
+) -> raw::c_uint {
+	let cnt = (*r).enqueue(obj_table, n, true);
+	if !free_space.is_null() {
+		*free_space = (*r).free_count();
+	}
+	cnt
+}
+
+pub unsafe fn rte_ring_enqueue_burst(
+	r: *mut rte_ring,
+	obj_table: *const *mut c_void,
+	n: raw::c_uint,
+	free_space: *mut raw::c_uint,
+) -> raw::c_uint {
+	let cnt = (*r).enqueue(obj_table, n, false);
+	if !free_space.is_null() {
+		*free_space = (*r).free_count();
+	}
+	cnt
+}
+
+pub unsafe fn rte_ring_dequeue_bulk(
+	r: *mut rte_ring,
+	obj_table: *mut *mut c_void,
+	n: raw::c_uint,
+	available: *mut raw::c_uint,
+) -> raw::c_uint {
+	let cnt = (*r).dequeue(obj_table, n, true);
+	if !available.is_null() {
+		*available = (*r).count();
+	}
+	cnt
+}
+
+pub unsafe fn rte_ring_dequeue_burst(
+	r: *mut rte_ring,
+	obj_table: *mut *mut c_void,
+	n: raw::c_uint,
+	available: *mut raw::c_uint,
+) -> raw::c_uint {
+	let cnt = (*r).dequeue(obj_table, n, false);
+	if !available.is_null() {
+		*available = (*r).count();
+	}
+	cnt
+}
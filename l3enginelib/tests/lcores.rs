@@ -0,0 +1,34 @@
+//! A launch that fails part way waits for the jobs it started instead of losing them
+
+use l3enginelib::{eal_init, launch, launch_workers, Lcore};
+use std::sync::{
+	atomic::{AtomicUsize, Ordering},
+	mpsc, Arc,
+};
+
+#[test]
+fn failed_launches_wait_for_their_jobs() {
+	let args = ["lcores", "-l", "0-2", "--no-huge", "--no-pci"];
+	eal_init(args.iter().map(|a| a.to_string()).collect()).expect("EAL");
+	let workers = Lcore::workers().collect::<Vec<_>>();
+	assert_eq!(workers.len(), 2);
+
+	// the last worker is busy until released
+	let (release, busy) = mpsc::channel::<()>();
+	let blocker = launch(workers[1], move || busy.recv().is_ok()).expect("launch");
+
+	let ran = Arc::new(AtomicUsize::new(0));
+	let jobs = ran.clone();
+	assert!(
+		launch_workers(move |_| jobs.fetch_add(1, Ordering::SeqCst)).is_err(),
+		"the last worker is busy"
+	);
+	assert_eq!(ran.load(Ordering::SeqCst), 1, "the first worker's job ran");
+	launch(workers[0], || ())
+		.expect("the first worker is idle again")
+		.join()
+		.expect("job");
+
+	release.send(()).unwrap();
+	assert!(blocker.join().expect("job"));
+}
@@ -0,0 +1,60 @@
+//! A port backed by rings receives what is enqueued on its rx rings and sends to its tx rings
+
+use l3enginelib::{eal_init, Mbuf, Mempool, Port, PortConf, Ring, RingType};
+use std::slice;
+
+fn ring(name: &str) -> Ring {
+	Ring::new(RingType::Named(String::from(name)), 0).expect("ring")
+}
+
+fn data(pkt: &Mbuf) -> Vec<u8> {
+	unsafe { slice::from_raw_parts(pkt.data_address(0), pkt.data_len()) }.to_vec()
+}
+
+#[test]
+fn rings() {
+	let args = ["rings", "-l", "0", "--no-huge", "--no-pci"];
+	eal_init(args.iter().map(|a| a.to_string()).collect()).expect("EAL");
+	let mempool =
+		Mempool::with_size("RINGS_MEMPOOL", 1023, 32, Mempool::RX_MBUF_DATA_SIZE).expect("mempool");
+
+	let rx = [ring("ring_rx0"), ring("ring_rx1")];
+	let tx = [ring("ring_tx0"), ring("ring_tx1")];
+	// the rings live until the end of the test, after the port's last use
+	let mut port = unsafe { Port::from_rings("ring_port", &rx, &tx) }.expect("ring port");
+	assert!(
+		unsafe { Port::from_rings("ring_port", &rx, &tx) }.is_err(),
+		"port names are unique"
+	);
+
+	assert!(
+		port.configure_with(&PortConf::new(3), &mempool).is_err(),
+		"a queue without a ring"
+	);
+	// the default offloads are left out, the ring driver has none
+	port.configure_with(&PortConf::new(2), &mempool)
+		.expect("port configuration");
+	port.start().expect("port start");
+
+	let frames = (0..8u8)
+		.map(|i| vec![i; 60 + i as usize])
+		.collect::<Vec<_>>();
+	let mut pkts = frames
+		.iter()
+		.map(|f| Mbuf::from_bytes(f, &mempool).expect("mbuf"))
+		.collect::<Vec<_>>();
+	assert_eq!(rx[1].enqueue_bulk(&mut pkts), frames.len());
+
+	assert!(
+		port.receive(0, 32).is_empty(),
+		"queue 0 has a ring of its own"
+	);
+	let mut pkts = port.receive(1, 32);
+	assert_eq!(pkts.iter().map(data).collect::<Vec<_>>(), frames);
+
+	assert_eq!(port.send(&mut pkts, 0), frames.len());
+	let mut sent = Vec::new();
+	assert_eq!(tx[0].dequeue_burst(&mut sent, 32), frames.len());
+	assert_eq!(sent.iter().map(data).collect::<Vec<_>>(), frames);
+	assert_eq!(tx[1].dequeue_burst(&mut sent, 32), 0);
+}
@@ -0,0 +1,6 @@
+//! The mux hands the packets the engine sends it to the clients serving them
+//!
+//! The `l3enginemux` binary runs it as a DPDK secondary process next to the engine. The
+//! library lets the same code run elsewhere, e.g. next to the engine in a test.
+
+pub mod mux;
@@ -1,39 +1,20 @@
-mod mux;
-
-use crossbeam::{
-    channel::{bounded, Receiver, Sender},
-    sync::ShardedLock,
-    thread,
-};
-use l3enginelib::{Mbuf, Mempool};
-use memenpsf::MemEnpsf;
-use mux::*;
+use l3enginelib::{config::Config, Mp, MpMessage, RteLog};
+use l3enginemux::mux::{self, LocalIPMac, Mux, ServiceMap};
 
 use std::{
-    collections::HashMap,
     fs,
     net::Ipv4Addr,
-    os::unix::net::{UnixListener, UnixStream},
-    ptr,
-    slice::from_raw_parts_mut,
+    process,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    thread::spawn,
 };
 
 use log;
+use tracing_subscriber::EnvFilter;
 
 const SOCK_NAME: &str = "/tmp/fd-passrd.socket";
-const CAP: usize = 20;
-const BURST_SZ: usize = 512;
-const MEMENPSF: &str = "memenpsf";
-const MTU: usize = 1536; // NOTE: Definition in multiple places
-
-// static MEMPOOL: Storage<Mempool> = Storage::new();
-// static SERVICE_MAP: ShardedLock<HashMap<&str, Sender<&mut [u8]>>> =
-//     ShardedLock::new(HashMap::new());
 
 /// Handle Ctrl+C
 fn handle_signal(kr: Arc<AtomicBool>) {
@@ -43,153 +24,90 @@ fn handle_signal(kr: Arc<AtomicBool>) {
     .expect("Error setting Ctrl-C handler");
 }
 
-fn handle_client(name: &str, stream: UnixStream, cons: Receiver<Mbuf>) {
-    let mut dev = MemEnpsf::new(name, CAP, stream);
-    match cons.recv() {
-        Ok(buf) => {
-            let pkt = unsafe { dpdk_sys::_pkt_raw_addr(buf.into_ptr()) };
-            let pkt = unsafe { ptr::read(pkt as *const _ as *const [u8; MTU]) };
-            match dev.xmit_to_client(pkt) {
-                Ok(_) => {}
-                Err(_e) => {
-                    log::error!("Error sending packet");
-                    #[cfg(feature = "debug")]
-                    println!("Error sending packet");
-                }
-            }
+/// Send our events and DPDK's logs through `tracing`, filtered by `RUST_LOG`,
+/// e.g. `RUST_LOG=info,l3enginemux=debug,memenpsf=trace`
+///
+/// `DPDK_LOG` can raise or lower individual DPDK log types, e.g. `DPDK_LOG=eal=debug`
+fn init_logging() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+    if let Err(e) = RteLog::install() {
+        log::error!("failed to forward DPDK logs: {}", e);
+    }
+    if let Ok(spec) = std::env::var("DPDK_LOG") {
+        if let Err(e) = RteLog::parse_levels(&spec) {
+            log::error!("bad DPDK_LOG: {}", e);
+        }
+    }
+}
+
+/// Load the engine's configuration, named like `l3enginebin` takes it, for the EAL's file
+/// prefix and our client id
+///
+/// Exits when the configuration can't be used, before anything is set up.
+fn load_config() -> Config {
+    let path = match Config::path_from_env() {
+        Some(path) => path,
+        None => {
+            log::info!("no configuration given, using the defaults");
+            return Config::default();
+        }
+    };
+    match Config::load(&path) {
+        Ok(config) => {
+            log::info!("loaded configuration from {}", path);
+            config
         }
-        Err(_) => {
-            log::info!("channel has been closed");
-            #[cfg(featuer = "debug")]
-            println!("channel has been closed");
-            return;
+        Err(e) => {
+            log::error!("{}: {}", path, e);
+            process::exit(1);
         }
     }
 }
 
-// fn route_pkts(local: LocalIPMac, pkt: Mbuf, mp: &Mempool) {
-// let buf = unsafe { from_raw_parts_mut(dpdk_sys::_pkt_raw_addr(pkt.get_ptr()), MTU) };
-// let tuple = match FiveTuple::parse_pkt(buf, &local, &vec![0][..]) {
-//     Ok(f) => f,
-//     Err(e) => {
-//         log::error!("Dropping packet because: {:#?}", e);
-//         return;
-//     }
-// };
-// if tuple.ethertype() == FiveTuple::ETHERTYPE_ARP {
-//     tuple.handle_arp(local, mp);
-// }
-// // NOTE: Service name is hardcoded for now
-// let srvc = "dummy";
-// if let Some(sender) = service_map.get(&srvc) {}
-// }
+/// Stop when the engine shuts down
+fn handle_mp(kr: Arc<AtomicBool>) {
+    Mp::register(Box::new(move |msg| {
+        if msg == MpMessage::Shutdown {
+            log::info!("engine is shutting down");
+            kr.store(false, Ordering::SeqCst);
+        }
+        None
+    }))
+    .expect("Error registering control message handler");
+}
 
 fn main() {
+    init_logging();
+    let config = load_config();
+    let client = config.channel.client;
     fs::remove_file(SOCK_NAME).ok();
-    let service_map: ShardedLock<HashMap<&str, Sender<Mbuf>>> = ShardedLock::new(HashMap::new());
-    let _listener_thd = thread::scope(|s| {
-        s.spawn(|_| {
-            let listener = UnixListener::bind(SOCK_NAME).unwrap();
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(stream) => {
-                        let (send, recv) = bounded(BURST_SZ);
-                        let l = match service_map.write() {
-                            Ok(mut map) => {
-                                map.insert("dummy", send);
-                                map.len()
-                            }
-                            Err(p_err) => {
-                                // Handles the case if another thread panicked
-                                // while holding the lock
-                                let mut map = p_err.into_inner();
-                                map.insert("dummy", send);
-                                map.len()
-                            }
-                        };
-                        let name = format!("{}{}", MEMENPSF, l);
-                        spawn(move || {
-                            handle_client(&name[..], stream, recv);
-                        });
-                    }
-                    Err(e) => log::error!("failed to connect: {}", e),
-                }
-            }
-        });
-    })
-    .unwrap();
-    let mux = Mux::new().unwrap(); // fatal failure
-    #[cfg(feature = "debug")]
-    println!("mux created");
-    mux::start();
-    #[cfg(feature = "debug")]
-    println!("mux started");
-
-    let mac = [0x90, 0xe2, 0xba, 0xb2, 0x98, 0x48];
-    let ip = Ipv4Addr::new(10, 10, 1, 1);
-    let local = LocalIPMac::new(ip, mac);
+    let services = Arc::new(ServiceMap::default());
+    // clients may connect before the engine is up, their packets wait until then
+    let _listener_thd = mux::listen(SOCK_NAME, services.clone()).unwrap(); // fatal failure
+    mux::start(&config);
 
-    // handling Ctrl+C
+    // handling Ctrl+C and engine shutdown
     let keep_running = Arc::new(AtomicBool::new(true));
     // let kr = keep_running.clone();
     handle_signal(keep_running.clone());
-    #[cfg(feature = "debug")]
-    println!("main loop starting");
-    while keep_running.load(Ordering::SeqCst) {
-        // receive packets
-        let mut _sz = 0;
-        while mux.in_buf.is_empty() {
-            _sz = mux.recv_from_engine_burst();
-            #[cfg(feature = "debug")]
-            println!("received {} packets", _sz);
-        }
+    handle_mp(keep_running.clone());
 
-        // processing received packets
-        for _ in 0..mux.in_buf.len() {
-            match mux.in_buf.pop() {
-                Some(pkt) => {
-                    // route_pkts(local, pkt, &mux.mempool());
-                    thread::scope(|s| {
-                        s.spawn(|_| {
-                            let buf = unsafe {
-                                from_raw_parts_mut(dpdk_sys::_pkt_raw_addr(pkt.get_ptr()), MTU)
-                            };
-                            let tuple = match FiveTuple::parse_pkt(buf, &local, &vec![0][..]) {
-                                Ok(f) => f,
-                                Err(e) => {
-                                    log::error!("Dropping packet because: {:#?}", e);
-                                    drop(pkt);
-                                    return;
-                                }
-                            };
-                            if tuple.ethertype() == FiveTuple::ETHERTYPE_ARP {
-                                tuple.handle_arp(local, &mux.mempool());
-                            }
-                            // NOTE: Service name is hardcoded for now
-                            let srvc = "dummy";
-                            match service_map.read() {
-                                Ok(map) => {
-                                    // map.get(&srvc).unwrap().to_owned()
-                                    // TODO: remove the unwraps
-                                    let sender = map.get(&srvc).unwrap();
-                                    sender.send(pkt).unwrap();
-                                }
-                                Err(p_err) => {
-                                    let map = p_err.into_inner();
-                                    let sender = map.get(&srvc).unwrap();
-                                    sender.send(pkt).unwrap();
-                                }
-                            };
-                        });
-                    })
-                    .unwrap();
-                }
-                None => break,
-            }
-        }
+    mux::attach(client).unwrap(); // fatal failure
+    mux::heartbeat(client, keep_running.clone());
+    let mux = Mux::new().unwrap(); // fatal failure
+    tracing::debug!("mux created");
+
+    let mac = [0x90, 0xe2, 0xba, 0xb2, 0x98, 0x48];
+    let ip = Ipv4Addr::new(10, 10, 1, 1);
+    let local = LocalIPMac::new(ip, mac);
 
-        // TODO: Send packets to clients or drop them
-        // TODO: Check packets received from clients and send them out
+    let _span = tracing::info_span!("mux", client).entered();
+    tracing::debug!("main loop starting");
+    mux.run(&local, &services, &keep_running);
+    if let Err(e) = mux::detach(client) {
+        log::error!("failed to detach from engine: {}", e);
     }
     // match listener_thd.join() {
     //     Ok(_) => {}
@@ -1,8 +1,169 @@
-//! Client is a representation of a client
+//! The clients of the mux and the services they serve
+//!
+//! A client connects to the mux's Unix socket and hands over the shared memory of a
+//! `MemEnpsf` interface. Each client is served by a thread of its own, which copies the
+//! packets of the client's service into the interface.
+//!
+//! The mux's threads are plain `std::thread`s. Each long-lived one that handles mbufs is a
+//! `MuxThread`, registered with the EAL once and freeing through a mempool cache of its own.
 
-use multiqueue::MPMCReceiver;
+use crossbeam::{
+	channel::{bounded, Receiver, Sender, TrySendError},
+	sync::ShardedLock,
+};
+use l3enginelib::{dpdk_sys, EalThread, Error, Lcore, Mbuf, Mempool, MempoolCache};
+use memenpsf::{MemEnpsf, MTU};
 
-pub(crate) struct Client<'a> {
-	service: &'a str,
-	receiver: MPMCReceiver<Mbuf>,
-}
\ No newline at end of file
+use std::{
+	collections::HashMap,
+	io,
+	os::unix::net::{UnixListener, UnixStream},
+	path::Path,
+	ptr,
+	sync::Arc,
+	thread::{self, JoinHandle},
+};
+
+/// Slots in each queue of a client's interface
+pub const CAP: usize = 20;
+/// Most packets waiting for a client
+const BURST_SZ: usize = 512;
+const MEMENPSF: &str = "memenpsf";
+/// NOTE: Service name is hardcoded for now, every client serves it
+pub const SERVICE: &str = "dummy";
+
+/// The channels to the clients, by the service they serve
+pub type ServiceMap = ShardedLock<HashMap<&'static str, Sender<Mbuf>>>;
+
+/// A long-lived mux thread: registered with the EAL for as long as it lives, and freeing
+/// the mbufs it is done with through a mempool cache of its own
+///
+/// Must be created on the thread it serves.
+pub struct MuxThread {
+	cache: MempoolCache,
+	// unregistered after the cache is flushed
+	_eal: Option<EalThread>,
+}
+
+impl MuxThread {
+	/// Register the calling thread and give it a cache for `mp`
+	pub fn new(mp: &Mempool) -> Result<Self, Error> {
+		Ok(Self::with_cache(MempoolCache::new(mp)?))
+	}
+
+	/// Register the calling thread and give it a cache for the mempool of `mbuf`
+	pub fn for_mbuf(mbuf: &Mbuf) -> Result<Self, Error> {
+		Ok(Self::with_cache(MempoolCache::for_mbuf(mbuf)?))
+	}
+
+	fn with_cache(cache: MempoolCache) -> Self {
+		// the EAL's own threads have an lcore id already
+		let eal = match Lcore::current() {
+			Some(_) => None,
+			None => match EalThread::register() {
+				Ok(eal) => Some(eal),
+				Err(e) => {
+					log::error!("failed to register thread with the EAL: {}", e);
+					None
+				}
+			},
+		};
+		Self { cache, _eal: eal }
+	}
+
+	/// Free `pkt` through the thread's cache
+	#[inline]
+	pub fn free(&mut self, pkt: Mbuf) {
+		self.cache.free(pkt);
+	}
+}
+
+/// Accept clients on the Unix socket at `path`, on a thread of its own
+///
+/// The socket is bound by the time this returns, so clients can connect right away.
+/// A client takes the service over from the one before it.
+pub fn listen<P: AsRef<Path>>(path: P, services: Arc<ServiceMap>) -> io::Result<JoinHandle<()>> {
+	let listener = UnixListener::bind(path)?;
+	thread::Builder::new()
+		.name(String::from("mux-listener"))
+		.spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					Ok(stream) => {
+						let (send, recv) = bounded(BURST_SZ);
+						let l = match services.write() {
+							Ok(mut map) => {
+								map.insert(SERVICE, send);
+								map.len()
+							}
+							Err(p_err) => {
+								// Handles the case if another thread panicked
+								// while holding the lock
+								let mut map = p_err.into_inner();
+								map.insert(SERVICE, send);
+								map.len()
+							}
+						};
+						let name = format!("{}{}", MEMENPSF, l);
+						thread::spawn(move || {
+							handle_client(&name[..], stream, recv);
+						});
+					}
+					Err(e) => log::error!("failed to connect: {}", e),
+				}
+			}
+		})
+}
+
+/// Copy the packets of `cons` into the client's interface until the channel closes
+fn handle_client(name: &str, stream: UnixStream, cons: Receiver<Mbuf>) {
+	let _span = tracing::info_span!("client", name).entered();
+	let mut dev = MemEnpsf::new(name, CAP, stream);
+	// clients may connect before the EAL is up, it is once packets flow
+	let mut thread = None;
+	for buf in cons.iter() {
+		let pkt = unsafe { dpdk_sys::_pkt_raw_addr(buf.get_ptr()) };
+		let pkt = unsafe { ptr::read(pkt as *const _ as *const [u8; MTU]) };
+		// the packet has been copied out
+		let thread = thread.get_or_insert_with(|| {
+			MuxThread::for_mbuf(&buf).map_err(|e| log::error!("no mempool cache: {}", e))
+		});
+		match thread {
+			Ok(thread) => thread.free(buf),
+			Err(()) => drop(buf),
+		}
+		match dev.xmit_to_client(pkt) {
+			Ok(_) => tracing::trace!("sent packet to client"),
+			Err(e) => tracing::error!(error = %e, "error sending packet"),
+		}
+	}
+	tracing::info!("channel has been closed");
+}
+
+/// Hand `pkt` to the client serving `service`
+///
+/// The packet comes back if there is no client or the client is behind, for the caller
+/// to drop.
+pub fn dispatch(services: &ServiceMap, service: &str, pkt: Mbuf) -> Result<(), Mbuf> {
+	let map = match services.read() {
+		Ok(map) => map,
+		Err(p_err) => p_err.into_inner(),
+	};
+	let sender = match map.get(service) {
+		Some(sender) => sender,
+		None => {
+			tracing::debug!(service, "no client, dropping packet");
+			return Err(pkt);
+		}
+	};
+	sender.try_send(pkt).map_err(|e| match e {
+		TrySendError::Full(pkt) => {
+			tracing::debug!(service, "client is behind, dropping packet");
+			pkt
+		}
+		TrySendError::Disconnected(pkt) => {
+			tracing::debug!(service, "client is gone, dropping packet");
+			pkt
+		}
+	})
+}
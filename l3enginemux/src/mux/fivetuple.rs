@@ -6,20 +6,11 @@
 //! 	Service Port
 
 use etherparse::{InternetSlice, LinkSlice, ReadError, SlicedPacket, TransportSlice};
-use l3enginelib::{Mbuf, Mempool};
-use pnet::{
-	datalink::MacAddr,
-	packet::{
-		arp::{ArpHardwareTypes, ArpOperations, MutableArpPacket},
-		ethernet::{EtherTypes, MutableEthernetPacket},
-		MutablePacket, Packet,
-	},
-};
 use std::net::Ipv4Addr;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
-pub(crate) enum FiveTupleErr {
+pub enum FiveTupleErr {
 	#[error("Invalid link")]
 	InvalidLink,
 	#[error("Invalid IP")]
@@ -48,13 +39,13 @@ impl From<ReadError> for FiveTupleErr {
 
 #[derive(Clone, Copy)]
 /// Holds the local IP address and the local MAC
-pub(crate) struct LocalIPMac {
+pub struct LocalIPMac {
 	ip: Ipv4Addr,
 	mac: [u8; 6],
 }
 
 impl LocalIPMac {
-	pub(crate) fn new(ip: Ipv4Addr, mac: [u8; 6]) -> Self {
+	pub fn new(ip: Ipv4Addr, mac: [u8; 6]) -> Self {
 		Self { ip, mac }
 	}
 }
@@ -64,7 +55,8 @@ impl LocalIPMac {
 /// source and destination ip of the packet
 /// the destination port
 /// the ether type
-pub(crate) struct FiveTuple {
+#[derive(Debug)]
+pub struct FiveTuple {
 	src_mac: [u8; 6],
 	src_ip: Ipv4Addr,
 	dst_mac: [u8; 6],
@@ -74,11 +66,7 @@ pub(crate) struct FiveTuple {
 }
 
 impl FiveTuple {
-	pub(crate) fn parse_pkt(
-		pkt: &[u8],
-		local: &LocalIPMac,
-		_ports: &[u16],
-	) -> Result<Self, FiveTupleErr> {
+	pub fn parse_pkt(pkt: &[u8], local: &LocalIPMac, _ports: &[u16]) -> Result<Self, FiveTupleErr> {
 		let sliced_pkt = SlicedPacket::from_ethernet(pkt)?;
 		let linkslice = sliced_pkt.link.ok_or(FiveTupleErr::InvalidLink)?;
 		let ipslice = sliced_pkt.ip.ok_or(FiveTupleErr::InvalidIP)?;
@@ -123,61 +111,41 @@ impl FiveTuple {
 	}
 
 	/// Get the Ethertype
-	pub(crate) fn ethertype(&self) -> u16 {
+	pub fn ethertype(&self) -> u16 {
 		self.ethertype.to_be()
 	}
 
-	/// Convert IP address to u32
-	pub(crate) fn ipaddr_to_u32(ip: &Ipv4Addr) -> u32 {
-		let p = ip.octets();
-		(((p[0] & 0xFF) as u32) << 24)
-			| (((p[1] & 0xFF) as u32) << 16)
-			| (((p[2] & 0xFF) as u32) << 8)
-			| ((p[1] & 0xFF) as u32)
+	/// Get the source MAC
+	pub fn src_mac(&self) -> [u8; 6] {
+		self.src_mac
 	}
 
-	fn arr_to_macaddr(mac: [u8; 6]) -> MacAddr {
-		MacAddr::new(mac[0], mac[1], mac[2], mac[3], mac[4], mac[5])
+	/// Get the source IP
+	pub fn src_ip(&self) -> Ipv4Addr {
+		self.src_ip
 	}
 
-	pub(crate) const ETHERTYPE_ARP: u16 = 0x0806;
-	const ARP_OP_REQUEST: u16 = 1;
-	const ARP_OP_REPLY: u16 = 2;
+	/// Get the destination MAC
+	pub fn dst_mac(&self) -> [u8; 6] {
+		self.dst_mac
+	}
 
-	/// Detect ARP packets and send a reply
-	pub fn handle_arp(&self, local: LocalIPMac, mp: &Mempool) -> Option<Mbuf> {
-		if local.mac != self.dst_mac || local.ip != self.dst_ip {
-			return None;
-		}
+	/// Get the destination IP
+	pub fn dst_ip(&self) -> Ipv4Addr {
+		self.dst_ip
+	}
 
-		if self.ethertype.to_be() == Self::ETHERTYPE_ARP {
-			if local.mac == self.dst_mac || local.ip == self.dst_ip {
-				let mut eth_buf = [0u8; 42];
-				let mut eth_pkt = MutableEthernetPacket::new(&mut eth_buf)?;
-				eth_pkt.set_destination(MacAddr::broadcast());
-				let source_mac = Self::arr_to_macaddr(local.mac);
-				eth_pkt.set_source(source_mac);
-				eth_pkt.set_ethertype(EtherTypes::Arp);
-				let mut arp_buffer = [0u8; 28];
-				let mut arp_packet = MutableArpPacket::new(&mut arp_buffer)?;
-				arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
-				arp_packet.set_protocol_type(EtherTypes::Ipv4);
-				arp_packet.set_hw_addr_len(6);
-				arp_packet.set_proto_addr_len(4);
-				arp_packet.set_operation(ArpOperations::Request);
-				arp_packet.set_sender_hw_addr(source_mac);
-				arp_packet.set_sender_proto_addr(local.ip);
-				arp_packet.set_target_hw_addr(MacAddr::zero());
-				arp_packet.set_target_proto_addr(self.src_ip);
-
-				eth_pkt.set_payload(arp_packet.packet_mut());
-
-				match Mbuf::from_bytes(eth_pkt.packet(), mp) {
-					Ok(out_buf) => return Some(out_buf),
-					Err(_) => {}
-				}
-			}
-		}
-		None
+	/// Get the destination port
+	pub fn dst_port(&self) -> u16 {
+		self.dst_port
+	}
+
+	/// Convert IP address to u32
+	pub fn ipaddr_to_u32(ip: &Ipv4Addr) -> u32 {
+		let p = ip.octets();
+		(((p[0] & 0xFF) as u32) << 24)
+			| (((p[1] & 0xFF) as u32) << 16)
+			| (((p[2] & 0xFF) as u32) << 8)
+			| ((p[1] & 0xFF) as u32)
 	}
 }
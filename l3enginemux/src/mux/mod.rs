@@ -13,97 +13,94 @@
 //! This module performs the basic processing and sends them over to the clients
 //! It will also receive the packets from the clients and send them to main process
 //!
+//! The Mux attaches to the engine over the EAL's multi-process channel before it
+//! looks up the rings shared with the engine
+//!
 //! The Mux uses crossbeam MPMC ArrayQueues but the underlying Ring APIs use vectors.
 //! So recv and transmit functions here perform a conversion
 //! Typically, the alternative is using a lock - std::RWLock or crossbeam::AtomicCell and so on
 
+mod client;
 mod fivetuple;
 
-pub(crate) use fivetuple::*;
+pub use client::*;
+pub use fivetuple::*;
 use l3enginelib::apis::eal_init;
 
 use anyhow::Result;
 use crossbeam::queue::ArrayQueue;
-use l3enginelib::{Channel, Mbuf, MemoryError, Mempool};
+use l3enginelib::{
+	config::Config, dpdk_sys, Channel, Error, Mbuf, MemoryError, Mempool, Mp, MpError, MpMessage,
+	PeerMonitor,
+};
+
+use std::{
+	mem::ManuallyDrop,
+	slice::from_raw_parts,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	thread::{sleep, spawn, JoinHandle},
+};
 
-pub(crate) struct Mux {
-	channel: Channel,                     // communicating with the engine
-	mempool: Mempool,                     // the memory pool for Mbufs
-	pub(crate) in_buf: ArrayQueue<Mbuf>,  // hold the incoming packets
-	pub(crate) out_buf: ArrayQueue<Mbuf>, // hold the outgoing packets
+/// The channel and the mempool are the engine's, the mux only looks them up and never
+/// frees them, so that a mux that comes back finds them again
+pub struct Mux {
+	channel: ManuallyDrop<Channel>, // communicating with the engine
+	mempool: ManuallyDrop<Mempool>, // the memory pool for Mbufs
+	pub in_buf: ArrayQueue<Mbuf>,   // hold the incoming packets
+	pub out_buf: ArrayQueue<Mbuf>,  // hold the outgoing packets
 }
 
 impl<'a> Mux {
 	const G_MEMPOOL_NAME: &'a str = "GLOBAL_MEMPOOL";
 	const BURST_SZ: usize = 512;
 
-	pub(crate) fn new() -> Option<Self> {
+	pub fn new() -> Option<Self> {
 		let channel = Channel::lookup().ok()?;
-		#[cfg(feature = "debug")]
-		{
-			println!("found channel");
-			println!(
-				"packetiser: new : client_to_engine found {:p}",
-				channel.client_to_engine.get_ptr()
-			);
-			println!(
-				"packetiser: new : engine_to_client found {:p}",
-				channel.engine_to_client.get_ptr()
-			);
-		}
 		let mempool = Mempool::lookup(Self::G_MEMPOOL_NAME).ok()?;
-		#[cfg(feature = "debug")]
-		println!("found mempool, address: {:p}", mempool.get_ptr());
+		tracing::debug!(mempool = ?mempool.get_ptr(), "found mempool");
 		let in_buf = ArrayQueue::new(Self::BURST_SZ);
 		let out_buf = ArrayQueue::new(Self::BURST_SZ);
 		Some(Mux {
-			channel,
-			mempool,
+			channel: ManuallyDrop::new(channel),
+			mempool: ManuallyDrop::new(mempool),
 			in_buf,
 			out_buf,
 		})
 	}
 
-	pub(crate) fn mempool(&self) -> &Mempool {
+	pub fn mempool(&self) -> &Mempool {
 		&self.mempool
 	}
 
-	pub(crate) fn recv(&self, buf: &mut Vec<u8>) -> Result<(), MemoryError> {
+	pub fn recv(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
 		let mut pkt = match Mbuf::from_bytes(&buf[..], &self.mempool) {
 			Ok(pkt) => pkt,
-			Err(_) => return Err(MemoryError::NoMem),
+			Err(_) => return Err(Error::new(MemoryError::NoMem, "Mbuf::from_bytes")),
 		};
 		self.channel.receive_from_engine(&mut pkt)
 	}
 
-	pub(crate) fn recv_from_engine_burst(&self) -> usize {
+	pub fn recv_from_engine_burst(&self) -> usize {
 		let len = self.in_buf.capacity() - self.in_buf.len();
 		let mut pkts = Vec::with_capacity(len);
-		for k in 0..len {
-			match Mbuf::new(&self.mempool) {
-				Ok(buf) => pkts.push(buf),
-				Err(_) => {
-					pkts.truncate(k);
-					break;
-				}
-			}
-		}
 		let sz = self.channel.recv_from_engine_burst(&mut pkts, len);
-		pkts.truncate(sz);
 		pkts.drain(..)
 			.for_each(|pkt| self.in_buf.push(pkt).unwrap()); // we should never hit the unwrap
 		sz
 	}
 
-	pub(crate) fn xmit(&self, buf: &Vec<u8>) -> Result<(), MemoryError> {
+	pub fn xmit(&self, buf: &Vec<u8>) -> Result<(), Error> {
 		let pkt = match Mbuf::from_bytes(&buf[..], &self.mempool) {
 			Ok(pkt) => pkt,
-			Err(_) => return Err(MemoryError::NoMem),
+			Err(_) => return Err(Error::new(MemoryError::NoMem, "Mbuf::from_bytes")),
 		};
 		self.channel.send_to_engine(pkt)
 	}
 
-	pub(crate) fn xmit_to_engine_bulk(&self) -> usize {
+	pub fn xmit_to_engine_bulk(&self) -> usize {
 		if self.out_buf.is_empty() {
 			return 0usize;
 		}
@@ -115,19 +112,91 @@ impl<'a> Mux {
 		let sz = self.channel.send_to_engine_bulk(&mut pkts);
 		sz
 	}
+
+	/// Hand the packets from the engine to the clients until `keep_running` is cleared
+	///
+	/// Packets that aren't for `local` are dropped. Runs as a `MuxThread` of the calling
+	/// thread.
+	pub fn run(&self, local: &LocalIPMac, services: &ServiceMap, keep_running: &AtomicBool) {
+		let mut thread = match MuxThread::new(&self.mempool) {
+			Ok(thread) => thread,
+			Err(e) => {
+				log::error!("failed to set up the mux thread: {}", e);
+				return;
+			}
+		};
+		while keep_running.load(Ordering::SeqCst) {
+			// receive packets
+			while self.in_buf.is_empty() && keep_running.load(Ordering::SeqCst) {
+				let sz = self.recv_from_engine_burst();
+				if sz > 0 {
+					tracing::trace!(received = sz, "received packets from engine");
+				}
+			}
+
+			// processing received packets
+			while let Some(pkt) = self.in_buf.pop() {
+				let buf = unsafe {
+					from_raw_parts(dpdk_sys::_pkt_raw_addr(pkt.get_ptr()), pkt.data_len())
+				};
+				let tuple = match FiveTuple::parse_pkt(buf, local, &[0]) {
+					Ok(f) => f,
+					Err(e) => {
+						tracing::debug!(reason = %e, "dropping packet");
+						thread.free(pkt);
+						continue;
+					}
+				};
+				// ARP is answered and learned from by the engine
+				tracing::debug!(
+					service = SERVICE,
+					src = %tuple.src_ip(),
+					dst_port = tuple.dst_port(),
+					"classified packet"
+				);
+				if let Err(pkt) = dispatch(services, SERVICE, pkt) {
+					thread.free(pkt);
+				}
+			}
+
+			// TODO: Check packets received from clients and send them out
+		}
+	}
+}
+
+/// The lcores the mux's EAL runs on
+pub const LCORES: &str = "2-3";
+
+/// Initialise the EAL as a secondary of the engine `config` sets up
+pub fn start(config: &Config) {
+	eal_init(config.secondary_eal_args(LCORES)).unwrap();
+	tracing::debug!("mux started");
+}
+
+/// Announce the client to the engine and wait until its channel is ready
+pub fn attach(client_id: u16) -> Result<(), Error> {
+	match Mp::request(MpMessage::Attach(client_id), Mp::DEFAULT_TIMEOUT)? {
+		MpMessage::ChannelReady(id) if id == client_id => Ok(()),
+		reply => Err(Error::new(
+			MpError::BadMessage,
+			format!("unexpected reply to attach: {:?}", reply),
+		)),
+	}
+}
+
+/// Let the engine know the client is alive until `keep_running` is cleared
+pub fn heartbeat(client_id: u16, keep_running: Arc<AtomicBool>) -> JoinHandle<()> {
+	spawn(move || {
+		while keep_running.load(Ordering::SeqCst) {
+			if let Err(e) = Mp::send(MpMessage::Heartbeat(client_id)) {
+				log::error!("failed to send heartbeat: {}", e);
+			}
+			sleep(PeerMonitor::HEARTBEAT_INTERVAL);
+		}
+	})
 }
 
-pub(crate) fn start() {
-	let args = vec![
-		String::from("-l 2-3"),
-		String::from("-n 4"),
-		String::from("--proc-type=secondary"),
-		String::from("--"),
-		String::from("-n 0"),
-	];
-	#[cfg(feature = "debug")]
-	println!("mux args: {:?}", &args);
-	eal_init(args).unwrap();
-	#[cfg(feature = "debug")]
-	println!("mux started");
+/// Tell the engine the client is going away
+pub fn detach(client_id: u16) -> Result<(), Error> {
+	Mp::send(MpMessage::Detach(client_id))
 }
@@ -0,0 +1,675 @@
+//! Runs the engine and the mux in the test's process, on the EAL's virtual devices
+//!
+//! A test starts a `Harness` on the ports it names, injects the frames it wants them to
+//! receive, attaches the mux and connects a `Client` if it wants them, then lets the
+//! engine poll until what it expects has happened. What left a port is read back with
+//! `Harness::sent`, what reached the client from its interface.
+//!
+//! The ports' MAC addresses are only known once the EAL has created them, so frames for a
+//! port are injected after the harness started. A `net_pcap` port reads its `rx_pcap` files
+//! as it needs packets, and stops at their end, so they are injected before the engine
+//! polls. The engine starts polling when the mux attaches or the test first waits on it.
+//!
+//! The mux runs on threads of its own instead of a secondary process, so the harness tells
+//! the engine's `PeerMonitor` about it directly rather than over the EAL's control channel.
+//! The EAL is initialised once per process, so each test binary runs a single test.
+//!
+//! With the `soft` feature, `cargo test -p l3enginemux --no-default-features --features soft`
+//! needs neither DPDK nor hugepages.
+
+#![allow(dead_code)]
+
+use l3enginelib::{
+	config::Config,
+	eal_init,
+	engine::{Counters, Engine, Forwarding},
+	Channel, EalThread, Lcore, LcoreJoinHandle, Mempool, PeerEvent, PeerMonitor, Port,
+};
+use l3enginemux::mux::{self, LocalIPMac, Mux, ServiceMap, CAP, SERVICE};
+use memenpsf::{MemEnpsf, MTU};
+
+use std::{
+	env,
+	fs::{self, File, OpenOptions},
+	io::{Read, Write},
+	os::unix::{
+		io::{AsRawFd, FromRawFd},
+		net::UnixStream,
+	},
+	path::{Path, PathBuf},
+	process, ptr,
+	str::FromStr,
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Arc,
+	},
+	thread::{self, sleep, JoinHandle},
+	time::{Duration, Instant},
+};
+
+/// How long a test waits for the engine or the mux before it gives up
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+
+/// A directory of its own for the files of test `name`
+fn scratch(name: &str) -> PathBuf {
+	let dir = env::temp_dir().join(format!("l3engine-{}-{}", name, process::id()));
+	fs::create_dir_all(&dir).expect("scratch directory");
+	dir
+}
+
+/// `frames` as the records of a pcap file
+fn pcap_records(frames: &[Vec<u8>]) -> Vec<u8> {
+	let mut buf = Vec::new();
+	for frame in frames {
+		buf.extend_from_slice(&[0; 8]); // timestamp
+		buf.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+		buf.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+		buf.extend_from_slice(frame);
+	}
+	buf
+}
+
+/// Write a pcap file of `frames` at `path`
+fn write_pcap<P: AsRef<Path>>(path: P, frames: &[Vec<u8>]) {
+	let mut buf = Vec::new();
+	buf.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+	buf.extend_from_slice(&2u16.to_le_bytes());
+	buf.extend_from_slice(&4u16.to_le_bytes());
+	buf.extend_from_slice(&[0; 8]); // time zone and accuracy
+	buf.extend_from_slice(&65535u32.to_le_bytes());
+	buf.extend_from_slice(&PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+	buf.extend_from_slice(&pcap_records(frames));
+	fs::write(path, buf).expect("rx_pcap");
+}
+
+/// The frames of the pcap file at `path`, none if there is no such file yet
+fn read_pcap<P: AsRef<Path>>(path: P) -> Vec<Vec<u8>> {
+	let buf = match fs::read(path) {
+		Ok(buf) => buf,
+		Err(_) => return Vec::new(),
+	};
+	let u32_at = |off: usize| {
+		let word = [buf[off], buf[off + 1], buf[off + 2], buf[off + 3]];
+		match buf[0..4] == PCAP_MAGIC.to_le_bytes() {
+			true => u32::from_le_bytes(word),
+			false => u32::from_be_bytes(word),
+		}
+	};
+	let mut frames = Vec::new();
+	let mut off = 24;
+	// a frame may still be being written
+	while off + 16 <= buf.len() {
+		let len = u32_at(off + 8) as usize;
+		off += 16;
+		if off + len > buf.len() {
+			break;
+		}
+		frames.push(buf[off..off + len].to_vec());
+		off += len;
+	}
+	frames
+}
+
+/// The virtual device behind a port of the harness
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dev {
+	/// A `net_pcap` port, receiving what `Harness::inject` gives it and keeping what it
+	/// sends for `Harness::sent`
+	Pcap,
+	/// A `net_ring` port, receiving what it sends
+	Ring,
+	/// A `net_null` port, receiving nothing and dropping what it sends
+	Null,
+}
+
+/// The engine, and the mux once attached, running on threads of the test's process
+pub struct Harness {
+	pub config: &'static Config,
+	pub engine: Arc<Engine>,
+	dir: PathBuf,
+	/// The `rx_pcap` and `tx_pcap` files of the `net_pcap` ports, by queue
+	pcaps: Vec<Option<(Vec<PathBuf>, Vec<PathBuf>)>>,
+	channel_port: usize,
+	monitor: Arc<PeerMonitor>,
+	/// The engine's poll loops, started once the test is ready for what the ports receive
+	main: Option<JoinHandle<()>>,
+	workers: Vec<LcoreJoinHandle<()>>,
+	/// Where the mux takes clients, once one connected or the mux attached
+	services: Option<Arc<ServiceMap>>,
+	mux: Option<MuxThreads>,
+	_mempools: Vec<Mempool>,
+}
+
+struct MuxThreads {
+	keep_running: Arc<AtomicBool>,
+	run: JoinHandle<()>,
+	heartbeat: JoinHandle<()>,
+}
+
+impl Harness {
+	/// Initialise the EAL and start the engine for test `name` on the main lcore only,
+	/// see `with_lcores`
+	pub fn with_ports(name: &str, ports: &[(Dev, Option<&str>)], forward: &str) -> Self {
+		Self::with_lcores(name, "0", ports, forward)
+	}
+
+	/// Initialise the EAL on `lcores` and start the engine for test `name`, set up like
+	/// `l3enginebin` sets it up
+	///
+	/// Port `n` is called `port<n>`, on the device and with the address and prefix length
+	/// given for it, with a queue pair per lcore. `forward` is the body of the
+	/// configuration's `[forward]` table.
+	pub fn with_lcores(
+		name: &str,
+		lcores: &str,
+		ports: &[(Dev, Option<&str>)],
+		forward: &str,
+	) -> Self {
+		let dir = scratch(name);
+		let mut toml = format!(
+			r#"
+			[eal]
+			lcores = "{}"
+			args = ["--no-huge", "--no-pci"]
+
+			[[mempool]]
+			name = "GLOBAL_MEMPOOL"
+			size = 2047
+			cache_size = 32
+			"#,
+			lcores
+		);
+		let queues = Config::from_str(&toml)
+			.unwrap_or_else(|e| panic!("bad lcores: {}", e))
+			.lcores()
+			.len();
+		let mut pcaps = Vec::new();
+		for (n, (dev, ip)) in ports.iter().enumerate() {
+			let devargs = match dev {
+				Dev::Pcap => {
+					// a net_pcap port has a queue per pair of files
+					let (rx, tx): (Vec<_>, Vec<_>) = (0..queues)
+						.map(|q| {
+							(
+								dir.join(format!("rx{}-{}.pcap", n, q)),
+								dir.join(format!("tx{}-{}.pcap", n, q)),
+							)
+						})
+						.unzip();
+					let mut devargs = format!("net_pcap{}", n);
+					for (rx, tx) in rx.iter().zip(&tx) {
+						// the device opens it when it's created, the frames come later
+						write_pcap(rx, &[]);
+						devargs += &format!(",rx_pcap={},tx_pcap={}", rx.display(), tx.display());
+					}
+					pcaps.push(Some((rx, tx)));
+					devargs
+				}
+				Dev::Ring => {
+					pcaps.push(None);
+					format!("net_ring{}", n)
+				}
+				Dev::Null => {
+					pcaps.push(None);
+					format!("net_null{}", n)
+				}
+			};
+			toml += &format!(
+				r#"
+				[[port]]
+				name = "port{}"
+				devargs = "{}"
+				"#,
+				n, devargs
+			);
+			if let Some(ip) = ip {
+				toml += &format!("ip = \"{}\"\n", ip);
+			}
+		}
+		toml += "\n[forward]\n";
+		toml += forward;
+		Self::start(&toml, dir, pcaps)
+	}
+
+	fn start(toml: &str, dir: PathBuf, pcaps: Vec<Option<(Vec<PathBuf>, Vec<PathBuf>)>>) -> Self {
+		// ports keep their device name for the life of the process
+		let config: &'static Config = Box::leak(Box::new(
+			Config::from_str(toml).unwrap_or_else(|e| panic!("bad configuration: {}", e)),
+		));
+		eal_init(config.eal_args()).expect("EAL");
+
+		let mempools = config
+			.mempools
+			.iter()
+			.map(|mpc| mpc.create().expect("mempool"))
+			.collect::<Vec<Mempool>>();
+		let ports = config
+			.ports
+			.iter()
+			.map(|pc| {
+				let mempool = config
+					.mempools
+					.iter()
+					.position(|mpc| mpc.name == pc.mempool)
+					.map(|i| &mempools[i])
+					.unwrap(); // checked when the configuration was loaded
+				let mut port = Port::new(&pc.name, pc.id).expect("port");
+				port.configure_with(&pc.conf, mempool)
+					.expect("port configuration");
+				port.start().expect("port start");
+				port
+			})
+			.collect::<Vec<Port>>();
+		let channel_port = config
+			.ports
+			.iter()
+			.position(|pc| pc.name == config.channel.port)
+			.unwrap(); // checked when the configuration was loaded
+
+		let monitor = Arc::new(PeerMonitor::default());
+		let forwarding = Forwarding::from_config(config, &ports).expect("forwarding");
+		let engine = Arc::new(Engine::new(
+			ports,
+			forwarding,
+			Channel::new().expect("channel"),
+			channel_port,
+			monitor.clone(),
+			config.channel.burst,
+			Lcore::count() as u16,
+		));
+
+		Self {
+			config,
+			engine,
+			dir,
+			pcaps,
+			channel_port,
+			monitor,
+			main: None,
+			workers: Vec::new(),
+			services: None,
+			mux: None,
+			_mempools: mempools,
+		}
+	}
+
+	/// Start the engine's poll loops, unless they run already
+	///
+	/// The test's thread initialised the EAL, so it is the main lcore and launches the
+	/// workers. Queue 0's loop runs on a thread of its own, as an EAL thread.
+	fn run(&mut self) {
+		if self.main.is_some() {
+			return;
+		}
+		self.workers = self.engine.launch_pollers().expect("worker lcores");
+		let engine = self.engine.clone();
+		self.main = Some(thread::spawn(move || {
+			let _eal = EalThread::register().ok();
+			engine.run_main();
+		}));
+	}
+
+	/// The MAC address of port `port`
+	pub fn port_mac(&self, port: usize) -> [u8; 6] {
+		self.engine.ports()[port].mac_addr().expect("port MAC").0
+	}
+
+	/// Have queue 0 of the `net_pcap` port `port` receive `frames` once the engine polls
+	pub fn inject(&self, port: usize, frames: &[Vec<u8>]) {
+		self.inject_queue(port, 0, frames)
+	}
+
+	/// Have queue `queue` of the `net_pcap` port `port` receive `frames` once the engine polls
+	pub fn inject_queue(&self, port: usize, queue: usize, frames: &[Vec<u8>]) {
+		assert!(
+			self.main.is_none(),
+			"frames are injected before the engine polls the port past the end of its rx_pcap"
+		);
+		let (rx, _) = self.pcaps[port].as_ref().expect("not a pcap port");
+		let mut file = OpenOptions::new()
+			.append(true)
+			.open(&rx[queue])
+			.expect("rx_pcap");
+		file.write_all(&pcap_records(frames)).expect("rx_pcap");
+	}
+
+	/// The frames the `net_pcap` port `port` has sent so far, queue by queue
+	pub fn sent(&self, port: usize) -> Vec<Vec<u8>> {
+		let (_, tx) = self.pcaps[port].as_ref().expect("not a pcap port");
+		tx.iter().flat_map(read_pcap).collect()
+	}
+
+	/// The clients of the mux, which takes them on a socket of its own
+	fn services(&mut self) -> Arc<ServiceMap> {
+		let dir = &self.dir;
+		self.services
+			.get_or_insert_with(|| {
+				let socket = dir.join("mux.socket");
+				fs::remove_file(&socket).ok();
+				let services = Arc::new(ServiceMap::default());
+				mux::listen(&socket, services.clone()).expect("mux socket");
+				services
+			})
+			.clone()
+	}
+
+	/// Attach the mux to the engine, starting the engine if it isn't running yet
+	///
+	/// The mux takes the address and MAC of the channel's port as its own. The engine
+	/// starts with the channel ready, so what the ports received for the mux gets to it.
+	pub fn attach_mux(&mut self) {
+		assert!(self.mux.is_none(), "the mux is attached already");
+		let attach = {
+			let (monitor, wait) = (self.monitor.clone(), self.config.channel.attach_wait);
+			thread::spawn(move || monitor.attach(wait))
+		};
+		if self.main.is_none() {
+			// nothing polls yet, so the channel is recovered here, before the first poll
+			let start = Instant::now();
+			let gen = loop {
+				match self.monitor.poll() {
+					Some(PeerEvent::Attached(gen)) => break gen,
+					_ if start.elapsed() < TIMEOUT => sleep(Duration::from_millis(1)),
+					e => panic!("expected the mux's attach, got {:?}", e),
+				}
+			};
+			self.engine.channel().reset();
+			self.monitor.ready(gen);
+			self.run();
+		}
+		assert!(
+			attach.join().expect("attach panicked"),
+			"the engine didn't get the channel ready"
+		);
+
+		let keep_running = Arc::new(AtomicBool::new(true));
+		let heartbeat = {
+			let (monitor, kr) = (self.monitor.clone(), keep_running.clone());
+			thread::spawn(move || {
+				while kr.load(Ordering::SeqCst) {
+					monitor.heartbeat();
+					sleep(PeerMonitor::HEARTBEAT_INTERVAL);
+				}
+			})
+		};
+
+		let iface = self
+			.engine
+			.forwarding()
+			.interface(self.channel_port)
+			.expect("the channel's port needs an ip");
+		let local = LocalIPMac::new(iface.addr, iface.mac.0);
+		let run = {
+			let (services, kr) = (self.services(), keep_running.clone());
+			thread::spawn(move || {
+				let mux = Mux::new().expect("the engine's channel and mempool");
+				mux.run(&local, &services, &kr);
+			})
+		};
+
+		self.mux = Some(MuxThreads {
+			keep_running,
+			run,
+			heartbeat,
+		});
+	}
+
+	/// Stop the mux and detach it from the engine, and wait until the engine noticed
+	pub fn detach_mux(&mut self) {
+		let mux = self.mux.take().expect("the mux isn't attached");
+		mux.keep_running.store(false, Ordering::SeqCst);
+		mux.run.join().expect("mux panicked");
+		mux.heartbeat.join().ok();
+		self.monitor.detach();
+		let monitor = &self.monitor;
+		assert!(
+			wait_until(TIMEOUT, || !monitor.is_alive()),
+			"the engine didn't notice the mux went"
+		);
+	}
+
+	/// Connect a client to the mux and wait until the mux serves it
+	///
+	/// Clients may connect before the mux attaches, as they may to `l3enginemux`.
+	pub fn connect(&mut self) -> Client {
+		let services = self.services();
+		let client = Client::connect(self.dir.join("mux.socket"));
+		let served = wait_until(TIMEOUT, || {
+			let map = match services.read() {
+				Ok(map) => map,
+				Err(p_err) => p_err.into_inner(),
+			};
+			map.contains_key(SERVICE)
+		});
+		assert!(served, "the mux didn't take the client");
+		client
+	}
+
+	/// Let the engine poll its ports until `done` holds or `TIMEOUT` passes, and say
+	/// whether it held
+	pub fn poll_until<F: Fn(&Harness) -> bool>(&mut self, done: F) -> bool {
+		self.run();
+		wait_until(TIMEOUT, || done(self))
+	}
+
+	/// A counter summed over every poll loop, e.g. `h.count(|c| &c.tx)`
+	pub fn count<F: Fn(&Counters) -> &AtomicU64>(&self, counter: F) -> u64 {
+		self.engine
+			.counters()
+			.iter()
+			.map(|c| counter(c).load(Ordering::Relaxed))
+			.sum()
+	}
+
+	/// Stop the mux and the engine, and remove the test's files
+	pub fn stop(mut self) {
+		if self.mux.is_some() {
+			self.detach_mux();
+		}
+		self.engine.stop();
+		if let Some(main) = self.main.take() {
+			main.join().expect("engine panicked");
+		}
+		for worker in self.workers.drain(..) {
+			let lcore = worker.lcore();
+			if worker.join().is_err() {
+				panic!("poll loop on lcore {} panicked", lcore.id());
+			}
+		}
+		fs::remove_dir_all(&self.dir).ok();
+	}
+}
+
+/// Check `cond` until it holds or `timeout` passes, and say whether it held
+pub fn wait_until<F: FnMut() -> bool>(timeout: Duration, mut cond: F) -> bool {
+	let start = Instant::now();
+	while start.elapsed() < timeout {
+		if cond() {
+			return true;
+		}
+		sleep(Duration::from_millis(1));
+	}
+	cond()
+}
+
+/// A client of the mux, on the other side of a `MemEnpsf` interface
+pub struct Client {
+	stream: UnixStream,
+	shm: *mut u8,
+	_memfd: File,
+}
+
+impl Client {
+	/// Connect to the mux at `socket` and hand it the interface's shared memory
+	pub fn connect<P: AsRef<Path>>(socket: P) -> Self {
+		let mut stream = UnixStream::connect(socket).expect("mux socket");
+		let len = MemEnpsf::shm_len(CAP);
+		let memfd = unsafe {
+			let fd = libc::memfd_create(b"memenpsf\0".as_ptr() as *const libc::c_char, 0);
+			assert!(fd >= 0, "memfd_create failed");
+			File::from_raw_fd(fd)
+		};
+		memfd.set_len(len as u64).expect("shared memory");
+		let shm = unsafe {
+			libc::mmap(
+				ptr::null_mut(),
+				len,
+				libc::PROT_READ | libc::PROT_WRITE,
+				libc::MAP_SHARED,
+				memfd.as_raw_fd(),
+				0,
+			)
+		};
+		assert_ne!(shm, libc::MAP_FAILED, "mmap failed");
+		fdpass::send_fd(&mut stream, &[0], &memfd).expect("passing the shared memory");
+		Self {
+			stream,
+			shm: shm as *mut u8,
+			_memfd: memfd,
+		}
+	}
+
+	/// The next frame the mux sent, `MTU` bytes whatever its length, if one comes in time
+	///
+	/// The mux writes a frame into the server to client queue and then sends the queue's
+	/// write and read pointers, the write pointer being the slot it just wrote.
+	pub fn recv(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+		self.stream.set_read_timeout(Some(timeout)).ok()?;
+		let mut pointers = [0u8; 2];
+		self.stream.read_exact(&mut pointers).ok()?;
+		let slot = pointers[0] as usize;
+		assert!(slot < CAP, "write pointer {} out of the queue", slot);
+		let mut frame = vec![0; MTU];
+		unsafe { ptr::copy_nonoverlapping(self.shm.add(slot * MTU), frame.as_mut_ptr(), MTU) };
+		Some(frame)
+	}
+}
+
+impl Drop for Client {
+	fn drop(&mut self) {
+		unsafe { libc::munmap(self.shm as *mut libc::c_void, MemEnpsf::shm_len(CAP)) };
+	}
+}
+
+/// Frames to inject, with valid checksums
+pub mod frame {
+	use std::net::Ipv4Addr;
+
+	pub const ETHERTYPE_IPV4: u16 = 0x0800;
+	pub const ETHERTYPE_ARP: u16 = 0x0806;
+	pub const IP_PROTOCOL_ICMP: u8 = 1;
+	pub const IP_PROTOCOL_TCP: u8 = 6;
+	pub const IP_PROTOCOL_UDP: u8 = 17;
+
+	/// The internet checksum of `buf`
+	pub fn checksum(buf: &[u8]) -> u16 {
+		let mut sum = buf
+			.chunks(2)
+			.map(|w| u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)]) as u32)
+			.sum::<u32>();
+		while sum > 0xffff {
+			sum = (sum & 0xffff) + (sum >> 16);
+		}
+		!(sum as u16)
+	}
+
+	pub fn ether(dst: [u8; 6], src: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+		let mut frame = Vec::with_capacity(14 + payload.len());
+		frame.extend_from_slice(&dst);
+		frame.extend_from_slice(&src);
+		frame.extend_from_slice(&ethertype.to_be_bytes());
+		frame.extend_from_slice(payload);
+		frame
+	}
+
+	/// An IPv4 packet without options
+	pub fn ipv4(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, ttl: u8, payload: &[u8]) -> Vec<u8> {
+		let len = (20 + payload.len()) as u16;
+		let mut pkt = vec![0x45, 0];
+		pkt.extend_from_slice(&len.to_be_bytes());
+		pkt.extend_from_slice(&[0, 1, 0x40, 0]); // id 1, don't fragment
+		pkt.extend_from_slice(&[ttl, proto, 0, 0]);
+		pkt.extend_from_slice(&src.octets());
+		pkt.extend_from_slice(&dst.octets());
+		let sum = checksum(&pkt);
+		pkt[10..12].copy_from_slice(&sum.to_be_bytes());
+		pkt.extend_from_slice(payload);
+		pkt
+	}
+
+	/// The sum of the UDP or TCP `segment` with its pseudo header
+	fn l4_checksum(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, segment: &[u8]) -> u16 {
+		let mut buf = Vec::with_capacity(12 + segment.len());
+		buf.extend_from_slice(&src.octets());
+		buf.extend_from_slice(&dst.octets());
+		buf.extend_from_slice(&[0, proto]);
+		buf.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+		buf.extend_from_slice(segment);
+		checksum(&buf)
+	}
+
+	pub fn udp(src: Ipv4Addr, dst: Ipv4Addr, sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+		let mut seg = Vec::with_capacity(8 + payload.len());
+		seg.extend_from_slice(&sport.to_be_bytes());
+		seg.extend_from_slice(&dport.to_be_bytes());
+		seg.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+		seg.extend_from_slice(&[0, 0]);
+		seg.extend_from_slice(payload);
+		let sum = l4_checksum(src, dst, IP_PROTOCOL_UDP, &seg);
+		seg[6..8].copy_from_slice(&sum.to_be_bytes());
+		seg
+	}
+
+	/// A TCP segment with only the ACK flag set
+	pub fn tcp(src: Ipv4Addr, dst: Ipv4Addr, sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+		let mut seg = Vec::with_capacity(20 + payload.len());
+		seg.extend_from_slice(&sport.to_be_bytes());
+		seg.extend_from_slice(&dport.to_be_bytes());
+		seg.extend_from_slice(&1u32.to_be_bytes()); // sequence number
+		seg.extend_from_slice(&1u32.to_be_bytes()); // acknowledgement number
+		seg.extend_from_slice(&[0x50, 0x10, 0xff, 0xff]); // 20 bytes of header, ACK
+		seg.extend_from_slice(&[0, 0, 0, 0]);
+		seg.extend_from_slice(payload);
+		let sum = l4_checksum(src, dst, IP_PROTOCOL_TCP, &seg);
+		seg[16..18].copy_from_slice(&sum.to_be_bytes());
+		seg
+	}
+
+	pub fn echo_request(id: u16, seq: u16, payload: &[u8]) -> Vec<u8> {
+		let mut msg = vec![8, 0, 0, 0];
+		msg.extend_from_slice(&id.to_be_bytes());
+		msg.extend_from_slice(&seq.to_be_bytes());
+		msg.extend_from_slice(payload);
+		let sum = checksum(&msg);
+		msg[2..4].copy_from_slice(&sum.to_be_bytes());
+		msg
+	}
+
+	/// An ARP request from `sha` at `sip` for `tip`, broadcast
+	pub fn arp_request(sha: [u8; 6], sip: Ipv4Addr, tip: Ipv4Addr) -> Vec<u8> {
+		let mut arp = vec![0, 1, 8, 0, 6, 4, 0, 1];
+		arp.extend_from_slice(&sha);
+		arp.extend_from_slice(&sip.octets());
+		arp.extend_from_slice(&[0; 6]);
+		arp.extend_from_slice(&tip.octets());
+		ether([0xff; 6], sha, ETHERTYPE_ARP, &arp)
+	}
+
+	pub fn ethertype(frame: &[u8]) -> u16 {
+		u16::from_be_bytes([frame[12], frame[13]])
+	}
+
+	/// The IPv4 header of `frame`, which has no VLAN tag
+	pub fn ipv4_hdr(frame: &[u8]) -> &[u8] {
+		&frame[14..34]
+	}
+
+	/// The IPv4 payload of `frame`, which has no VLAN tag or IP options
+	pub fn ipv4_payload(frame: &[u8]) -> &[u8] {
+		let len = u16::from_be_bytes([frame[16], frame[17]]) as usize;
+		&frame[34..14 + len]
+	}
+}
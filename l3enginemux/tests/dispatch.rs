@@ -0,0 +1,41 @@
+//! Packets nobody takes come back to the mux to be dropped, instead of stopping it
+
+use crossbeam::channel::bounded;
+use l3enginelib::{config::Config, eal_init, Mbuf, Mempool};
+use l3enginemux::mux::{dispatch, ServiceMap, SERVICE};
+use std::str::FromStr;
+
+#[test]
+fn packets_nobody_takes_come_back() {
+	let config = Config::from_str(
+		r#"
+		[eal]
+		lcores = "0"
+		args = ["--no-huge", "--no-pci"]
+		"#,
+	)
+	.expect("configuration");
+	eal_init(config.eal_args()).expect("EAL");
+	let mempool = Mempool::new("GLOBAL_MEMPOOL").expect("mempool");
+	let pkt = |b: u8| Mbuf::from_bytes(&[b; 64], &mempool).expect("mbuf");
+	let first_byte = |pkt: &Mbuf| unsafe { *pkt.data_address(0) };
+
+	let services = ServiceMap::default();
+	let back = dispatch(&services, SERVICE, pkt(1)).expect_err("no client yet");
+	assert_eq!(first_byte(&back), 1, "the same packet comes back");
+
+	let (send, recv) = bounded(1);
+	services.write().unwrap().insert(SERVICE, send);
+	assert!(dispatch(&services, SERVICE, pkt(2)).is_ok());
+	let back = dispatch(&services, SERVICE, pkt(3)).expect_err("the client is behind");
+	assert_eq!(first_byte(&back), 3);
+	assert!(
+		dispatch(&services, "other", pkt(4)).is_err(),
+		"nobody serves the service"
+	);
+	assert_eq!(first_byte(&recv.recv().unwrap()), 2);
+
+	drop(recv);
+	let back = dispatch(&services, SERVICE, pkt(5)).expect_err("the client is gone");
+	assert_eq!(first_byte(&back), 5);
+}
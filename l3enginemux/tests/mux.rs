@@ -0,0 +1,117 @@
+//! What the engine hands the mux reaches the client, what it answers itself leaves the port
+
+mod common;
+
+use common::{frame::*, Dev, Harness, TIMEOUT};
+use std::{net::Ipv4Addr, time::Duration};
+
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 10, 1, 1);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 10, 1, 2);
+const PEER_MAC: [u8; 6] = [0x02, 0xaa, 0, 0, 0, 2];
+
+#[test]
+fn engine_and_mux() {
+	let ip = format!("{}/24", LOCAL);
+	let mut h = Harness::with_ports("mux", &[(Dev::Pcap, Some(&ip))], r#"policy = "mux""#);
+	let mac = h.port_mac(0);
+
+	let tcp = (0..3u16)
+		.map(|i| {
+			let seg = tcp(
+				PEER,
+				LOCAL,
+				40000 + i,
+				80,
+				format!("segment {}", i).as_bytes(),
+			);
+			ether(
+				mac,
+				PEER_MAC,
+				ETHERTYPE_IPV4,
+				&ipv4(PEER, LOCAL, IP_PROTOCOL_TCP, 64, &seg),
+			)
+		})
+		.collect::<Vec<_>>();
+	let ping = echo_request(0x1234, 7, b"are you there");
+	let mut frames = vec![
+		arp_request(PEER_MAC, PEER, LOCAL),
+		ether(
+			mac,
+			PEER_MAC,
+			ETHERTYPE_IPV4,
+			&ipv4(PEER, LOCAL, IP_PROTOCOL_ICMP, 64, &ping),
+		),
+	];
+	frames.extend(tcp.iter().cloned());
+	let dgram = udp(PEER, LOCAL, 40000, 53, b"query");
+	frames.push(ether(
+		mac,
+		PEER_MAC,
+		ETHERTYPE_IPV4,
+		&ipv4(PEER, LOCAL, IP_PROTOCOL_UDP, 64, &dgram),
+	));
+	h.inject(0, &frames);
+	let mut client = h.connect();
+	h.attach_mux();
+
+	// UDP goes to the mux too, but the mux turns it away
+	let done = h.poll_until(|h| {
+		h.count(|c| &c.rx) == frames.len() as u64
+			&& h.count(|c| &c.to_mux) == 4
+			&& h.count(|c| &c.tx) == 2
+	});
+	assert!(
+		done,
+		"the engine didn't take in every frame, answer ARP and the ping and hand the mux the rest"
+	);
+
+	// TCP to the engine's address is the mux's, in order
+	for sent in &tcp {
+		let got = client
+			.recv(TIMEOUT)
+			.expect("the client got fewer segments than sent");
+		assert_eq!(&got[..sent.len()], &sent[..]);
+	}
+	assert!(
+		client.recv(Duration::from_millis(200)).is_none(),
+		"the mux handed the client more than the TCP segments"
+	);
+
+	let out = h.sent(0);
+	assert_eq!(out.len(), 2, "only ARP and the ping are answered");
+
+	let reply = out
+		.iter()
+		.find(|f| ethertype(f) == ETHERTYPE_ARP)
+		.expect("ARP reply");
+	assert_eq!(&reply[0..6], &PEER_MAC, "ARP reply to the asker");
+	assert_eq!(&reply[6..12], &mac);
+	let arp = &reply[14..42];
+	assert_eq!(&arp[6..8], &[0, 2], "an ARP reply");
+	assert_eq!(&arp[8..14], &mac, "sender is the port");
+	assert_eq!(&arp[14..18], &LOCAL.octets());
+	assert_eq!(&arp[18..24], &PEER_MAC, "target is the asker");
+	assert_eq!(&arp[24..28], &PEER.octets());
+
+	let pong = out
+		.iter()
+		.find(|f| ethertype(f) == ETHERTYPE_IPV4)
+		.expect("echo reply");
+	assert_eq!(&pong[0..6], &PEER_MAC);
+	assert_eq!(&pong[6..12], &mac);
+	let ip = ipv4_hdr(pong);
+	assert_eq!(ip[9], IP_PROTOCOL_ICMP);
+	assert_eq!(&ip[12..16], &LOCAL.octets());
+	assert_eq!(&ip[16..20], &PEER.octets());
+	assert_eq!(checksum(ip), 0, "IPv4 checksum");
+	let icmp = ipv4_payload(pong);
+	assert_eq!(icmp[0], 0, "an echo reply");
+	assert_eq!(
+		&icmp[4..],
+		&ping[4..],
+		"same identifier, sequence number and data"
+	);
+	assert_eq!(checksum(icmp), 0, "ICMP checksum");
+
+	h.stop();
+}
@@ -0,0 +1,42 @@
+//! Ports paired with each other forward what they receive to each other untouched
+
+mod common;
+
+use common::{frame::*, Dev, Harness};
+use std::net::Ipv4Addr;
+
+#[test]
+fn pairs() {
+	let mut h = Harness::with_ports(
+		"pairs",
+		&[(Dev::Pcap, None), (Dev::Ring, None)],
+		r#"
+		policy = "pairs"
+		pairs = [["port0", "port1"]]
+		"#,
+	);
+
+	// whatever goes out of port1 comes back in, and goes back out of port0
+	let (src, dst) = (Ipv4Addr::new(192, 168, 7, 1), Ipv4Addr::new(192, 168, 7, 2));
+	let frames = (0..8u16)
+		.map(|i| {
+			let dgram = udp(src, dst, 5000 + i, 5000, &[i as u8; 64]);
+			let pkt = ipv4(src, dst, IP_PROTOCOL_UDP, 64, &dgram);
+			ether(
+				[0x02, 0xcc, 0, 0, 0, 2],
+				[0x02, 0xcc, 0, 0, 0, 1],
+				ETHERTYPE_IPV4,
+				&pkt,
+			)
+		})
+		.collect::<Vec<_>>();
+	h.inject(0, &frames);
+
+	let n = frames.len() as u64;
+	let done = h.poll_until(|h| h.count(|c| &c.rx) == 2 * n && h.count(|c| &c.tx) == 2 * n);
+	assert!(done, "the frames didn't make it round the pair");
+	assert_eq!(h.count(|c| &c.dropped), 0);
+	assert_eq!(h.sent(0), frames, "frames come back untouched and in order");
+
+	h.stop();
+}
@@ -0,0 +1,111 @@
+//! The router forwards between the ports' subnets and answers what it can't forward
+
+mod common;
+
+use common::{frame::*, Dev, Harness};
+use std::net::Ipv4Addr;
+
+const HOST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const HOST_MAC: [u8; 6] = [0x02, 0xaa, 0, 0, 0, 2];
+const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+/// Neighbours on the links of port1 and port2
+const NEAR: Ipv4Addr = Ipv4Addr::new(10, 0, 1, 2);
+const NEAR_MAC: [u8; 6] = [0x02, 0xbb, 0, 0, 1, 2];
+const NULL: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+#[test]
+fn router() {
+	let ip0 = format!("{}/24", GATEWAY);
+	let mut h = Harness::with_ports(
+		"router",
+		&[
+			(Dev::Pcap, Some(&ip0)),
+			(Dev::Pcap, Some("10.0.1.1/24")),
+			(Dev::Null, Some("10.0.2.1/24")),
+		],
+		&format!(
+			r#"
+			policy = "router"
+			neighbors = [
+				{{ ip = "{}", mac = "02:aa:00:00:00:02" }},
+				{{ ip = "{}", mac = "02:bb:00:00:01:02" }},
+				{{ ip = "{}", mac = "02:bb:00:00:02:02" }},
+			]
+			"#,
+			HOST, NEAR, NULL
+		),
+	);
+	let mac0 = h.port_mac(0);
+
+	let from_host = |dst: Ipv4Addr, ttl: u8| {
+		let dgram = udp(HOST, dst, 40000, 7, b"hello");
+		ether(
+			mac0,
+			HOST_MAC,
+			ETHERTYPE_IPV4,
+			&ipv4(HOST, dst, IP_PROTOCOL_UDP, ttl, &dgram),
+		)
+	};
+	let routed = from_host(NEAR, 64);
+	let expired = from_host(NEAR, 1);
+	let unroutable = from_host(Ipv4Addr::new(192, 168, 0, 1), 64);
+	let frames = vec![
+		routed.clone(),
+		from_host(NULL, 64),
+		expired.clone(),
+		unroutable.clone(),
+	];
+	h.inject(0, &frames);
+
+	// one packet out of port1 and one into port2's void, two errors back out of port0
+	let done = h.poll_until(|h| {
+		h.count(|c| &c.rx) == frames.len() as u64
+			&& h.count(|c| &c.tx) == 4
+			&& h.count(|c| &c.icmp) == 2
+	});
+	assert!(done, "the router didn't forward or answer every packet");
+
+	let out1 = h.sent(1);
+	assert_eq!(
+		out1.len(),
+		1,
+		"only the packet to port1's link leaves port1"
+	);
+	let fwd = &out1[0];
+	assert_eq!(&fwd[0..6], &NEAR_MAC, "addressed to the next hop");
+	assert_eq!(&fwd[6..12], &h.port_mac(1), "from the egress port");
+	let ip = ipv4_hdr(fwd);
+	assert_eq!(ip[8], 63, "TTL decremented");
+	assert_eq!(checksum(ip), 0, "IPv4 checksum");
+	assert_eq!(
+		ipv4_payload(fwd),
+		ipv4_payload(&routed),
+		"payload untouched"
+	);
+
+	let out0 = h.sent(0);
+	assert_eq!(out0.len(), 2, "only the errors go back out of port0");
+	for (err, (icmp_type, code, about)) in out0
+		.iter()
+		.zip([(11, 0, &expired), (3, 0, &unroutable)].iter())
+	{
+		assert_eq!(&err[0..6], &HOST_MAC, "back to the sender");
+		assert_eq!(&err[6..12], &mac0);
+		let ip = ipv4_hdr(err);
+		assert_eq!(ip[9], IP_PROTOCOL_ICMP);
+		assert_eq!(
+			&ip[12..16],
+			&GATEWAY.octets(),
+			"from the ingress port's address"
+		);
+		assert_eq!(&ip[16..20], &HOST.octets());
+		assert_eq!(checksum(ip), 0, "IPv4 checksum");
+		let icmp = ipv4_payload(err);
+		assert_eq!((icmp[0], icmp[1]), (*icmp_type, *code));
+		assert_eq!(checksum(icmp), 0, "ICMP checksum");
+		// the offending header and the first 8 bytes of its payload
+		assert_eq!(&icmp[8..36], &about[14..42]);
+	}
+
+	h.stop();
+}
@@ -0,0 +1,61 @@
+//! With a worker lcore polling a queue of its own, what it receives for the mux reaches the
+//! client, and the mux can go and come back while the worker keeps polling
+
+mod common;
+
+use common::{frame::*, Dev, Harness, TIMEOUT};
+use std::{net::Ipv4Addr, sync::atomic::Ordering};
+
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 10, 2, 1);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 10, 2, 2);
+const PEER_MAC: [u8; 6] = [0x02, 0xaa, 0, 0, 0, 2];
+
+#[test]
+fn workers() {
+	let ip = format!("{}/24", LOCAL);
+	let mut h = Harness::with_lcores(
+		"workers",
+		"0-1",
+		&[(Dev::Pcap, Some(&ip))],
+		r#"policy = "mux""#,
+	);
+	assert_eq!(h.engine.counters().len(), 2, "a poll loop per lcore");
+	let mac = h.port_mac(0);
+
+	let segment = |i: u16| {
+		let seg = tcp(
+			PEER,
+			LOCAL,
+			40000 + i,
+			80,
+			format!("segment {}", i).as_bytes(),
+		);
+		ether(
+			mac,
+			PEER_MAC,
+			ETHERTYPE_IPV4,
+			&ipv4(PEER, LOCAL, IP_PROTOCOL_TCP, 64, &seg),
+		)
+	};
+	// only the worker polls queue 1
+	let frames = (0..3).map(segment).collect::<Vec<_>>();
+	h.inject_queue(0, 1, &frames);
+	let mut client = h.connect();
+	h.attach_mux();
+
+	let done = h.poll_until(|h| h.engine.counters()[1].to_mux.load(Ordering::Relaxed) == 3);
+	assert!(done, "the worker didn't hand the mux what it received");
+	for sent in &frames {
+		let got = client
+			.recv(TIMEOUT)
+			.expect("the client got fewer segments than sent");
+		assert_eq!(&got[..sent.len()], &sent[..]);
+	}
+
+	// the main loop only recovers the channel once the worker is past its poll, when the
+	// mux goes and when it comes back
+	h.detach_mux();
+	h.attach_mux();
+
+	h.stop();
+}
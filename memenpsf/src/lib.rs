@@ -12,7 +12,7 @@ use std::{
 use ipc_queue::RingBuf;
 use libc::{ftruncate, mmap, MAP_SHARED, PROT_READ, PROT_WRITE};
 
-const MTU: usize = 1536;
+pub const MTU: usize = 1536;
 
 pub struct MemEnpsf<'a> {
     name: &'a str,
@@ -24,13 +24,17 @@ pub struct MemEnpsf<'a> {
 
 impl<'a> MemEnpsf<'a> {
     /// Create a new interface
+    ///
+    /// The client sends the shared memory over `stream`, it holds the server to client
+    /// queue followed by the client to server one, `cap` slots of `MTU` bytes each.
     pub fn new(name: &'a str, cap: usize, mut stream: UnixStream) -> Self {
         let fd = fdpass::recv_fd(&mut stream, vec![0u8]).unwrap();
-        unsafe { ftruncate(fd.as_raw_fd(), cap as i64) };
+        let len = Self::shm_len(cap);
+        unsafe { ftruncate(fd.as_raw_fd(), len as i64) };
         let shm = unsafe {
             mmap(
                 ptr::null_mut(),
-                cap,
+                len,
                 PROT_READ | PROT_WRITE,
                 MAP_SHARED,
                 fd.as_raw_fd(),
@@ -50,6 +54,11 @@ impl<'a> MemEnpsf<'a> {
         }
     }
 
+    /// Bytes of shared memory an interface of `cap` slots per queue uses
+    pub fn shm_len(cap: usize) -> usize {
+        2 * cap * MTU
+    }
+
     pub fn cap(&self) -> usize {
         self.cap
     }
@@ -62,34 +71,58 @@ impl<'a> MemEnpsf<'a> {
         let res = self.c2s_q.pop();
         let buf = self.c2s_q.pointers();
         match self.stream.write(&buf) {
-            Ok(sz) => {
-                println!("sent {} elems", sz);
-            }
-            Err(e) => println!("failed to send: {}", e),
+            Ok(sz) => tracing::trace!(
+                iface = self.name,
+                op = "recv_from_client",
+                sent = sz,
+                "sent pointers"
+            ),
+            Err(e) => tracing::error!(
+                iface = self.name,
+                op = "recv_from_client",
+                error = %e,
+                "failed to send pointers"
+            ),
         };
         res
     }
 
     pub fn xmit_to_client(&mut self, buf: [u8; MTU]) -> Result<()> {
         let res = self.s2c_q.push(buf);
-        let buf = self.c2s_q.pointers();
+        let buf = self.s2c_q.pointers();
         match self.stream.write(&buf) {
-            Ok(sz) => {
-                println!("sent {} elems", sz);
-            }
-            Err(e) => println!("failed to send: {}", e),
+            Ok(sz) => tracing::trace!(
+                iface = self.name,
+                op = "xmit_to_client",
+                sent = sz,
+                "sent pointers"
+            ),
+            Err(e) => tracing::error!(
+                iface = self.name,
+                op = "xmit_to_client",
+                error = %e,
+                "failed to send pointers"
+            ),
         };
         res
     }
 
     pub fn recv_from_srv(&mut self) -> Option<[u8; MTU]> {
         let res = self.s2c_q.pop();
-        let buf = self.c2s_q.pointers();
+        let buf = self.s2c_q.pointers();
         match self.stream.write(&buf) {
-            Ok(sz) => {
-                println!("sent {} elems", sz);
-            }
-            Err(e) => println!("failed to send: {}", e),
+            Ok(sz) => tracing::trace!(
+                iface = self.name,
+                op = "recv_from_srv",
+                sent = sz,
+                "sent pointers"
+            ),
+            Err(e) => tracing::error!(
+                iface = self.name,
+                op = "recv_from_srv",
+                error = %e,
+                "failed to send pointers"
+            ),
         };
         res
     }
@@ -98,11 +131,122 @@ impl<'a> MemEnpsf<'a> {
         let res = self.c2s_q.push(buf);
         let buf = self.c2s_q.pointers();
         match self.stream.write(&buf) {
-            Ok(sz) => {
-                println!("sent {} elems", sz);
-            }
-            Err(e) => println!("failed to send: {}", e),
+            Ok(sz) => tracing::trace!(
+                iface = self.name,
+                op = "xmit_to_srv",
+                sent = sz,
+                "sent pointers"
+            ),
+            Err(e) => tracing::error!(
+                iface = self.name,
+                op = "xmit_to_srv",
+                error = %e,
+                "failed to send pointers"
+            ),
         };
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::Read, os::unix::io::FromRawFd};
+
+    const CAP: usize = 4;
+
+    /// An interface of `CAP` slots, its shared memory as the client maps it and the
+    /// client's end of the stream
+    fn interface() -> (MemEnpsf<'static>, *mut u8, UnixStream) {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let memfd = unsafe {
+            let fd = libc::memfd_create(b"memenpsf\0".as_ptr() as *const libc::c_char, 0);
+            assert!(fd >= 0, "memfd_create failed");
+            File::from_raw_fd(fd)
+        };
+        fdpass::send_fd(&mut client, &[0], &memfd).unwrap();
+        let dev = MemEnpsf::new("test", CAP, server);
+        // sized by the interface
+        assert_eq!(
+            memfd.metadata().unwrap().len(),
+            MemEnpsf::shm_len(CAP) as u64
+        );
+        let shm = unsafe {
+            mmap(
+                ptr::null_mut(),
+                MemEnpsf::shm_len(CAP),
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                memfd.as_raw_fd(),
+                0,
+            )
+        };
+        assert_ne!(shm, libc::MAP_FAILED);
+        (dev, shm as *mut u8, client)
+    }
+
+    fn pointers(client: &mut UnixStream) -> [u8; 2] {
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    fn slot(shm: *mut u8, queue: usize, slot: usize) -> [u8; MTU] {
+        let mut buf = [0; MTU];
+        let at = (queue * CAP + slot) * MTU;
+        unsafe { ptr::copy_nonoverlapping(shm.add(at), buf.as_mut_ptr(), MTU) };
+        buf
+    }
+
+    #[test]
+    fn both_queues_fit_in_the_shared_memory() {
+        let (mut dev, shm, _client) = interface();
+        // a queue of CAP slots holds CAP - 1 packets
+        for i in 1..CAP {
+            dev.xmit_to_client([i as u8; MTU]).unwrap();
+            dev.xmit_to_srv([0x80 | i as u8; MTU]).unwrap();
+        }
+        assert!(
+            dev.xmit_to_client([0; MTU]).is_err(),
+            "server to client queue full"
+        );
+        assert!(
+            dev.xmit_to_srv([0; MTU]).is_err(),
+            "client to server queue full"
+        );
+
+        // the server to client queue, then the client to server one
+        for i in 1..CAP {
+            assert_eq!(slot(shm, 0, i)[..], [i as u8; MTU][..]);
+            assert_eq!(slot(shm, 1, i)[..], [0x80 | i as u8; MTU][..]);
+        }
+        for i in 1..CAP {
+            assert_eq!(dev.recv_from_srv().unwrap()[..], [i as u8; MTU][..]);
+            assert_eq!(
+                dev.recv_from_client().unwrap()[..],
+                [0x80 | i as u8; MTU][..]
+            );
+        }
+        assert!(dev.recv_from_srv().is_none());
+        assert!(dev.recv_from_client().is_none());
+        unsafe { libc::munmap(shm as *mut libc::c_void, MemEnpsf::shm_len(CAP)) };
+    }
+
+    #[test]
+    fn sends_the_pointers_of_the_queue_it_touched() {
+        let (mut dev, shm, mut client) = interface();
+        dev.xmit_to_srv([1; MTU]).unwrap();
+        assert_eq!(pointers(&mut client), [1, 0], "client to server, written");
+        dev.xmit_to_srv([2; MTU]).unwrap();
+        assert_eq!(pointers(&mut client), [2, 0]);
+
+        dev.xmit_to_client([3; MTU]).unwrap();
+        assert_eq!(pointers(&mut client), [1, 0], "server to client, written");
+        dev.recv_from_srv().unwrap();
+        assert_eq!(pointers(&mut client), [1, 1], "server to client, read");
+
+        dev.recv_from_client().unwrap();
+        assert_eq!(pointers(&mut client), [2, 1], "client to server, read");
+        unsafe { libc::munmap(shm as *mut libc::c_void, MemEnpsf::shm_len(CAP)) };
+    }
+}
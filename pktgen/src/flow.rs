@@ -0,0 +1,508 @@
+//! The flows `pktgen` sends and the packets it makes for them
+//!
+//! A flow is given on the command line as `key=value` pairs, e.g.
+//! `proto=udp size=imix rate=100kpps src=10.0.0.1-10.0.0.254 dst=10.1.0.1 dport=9`.
+//! Ranges are stepped through one packet at a time, every field advancing together, and
+//! sizes are cycled through in the order given.
+
+use std::{fmt, net::Ipv4Addr, str::FromStr};
+
+use l3enginelib::{checksum, MacAddr, IP_PROTOCOL_TCP, IP_PROTOCOL_UDP};
+
+use crate::stats::Stamp;
+
+const ETHER_HDR_LEN: usize = 14;
+const IPV4_HDR_LEN: usize = 20;
+const UDP_HDR_LEN: usize = 8;
+const TCP_HDR_LEN: usize = 20;
+/// The FCS the NIC appends, counted in frame sizes like every traffic generator does
+pub(crate) const ETHER_CRC_LEN: usize = 4;
+/// Largest frame a standard MTU allows, FCS included
+const MAX_FRAME: u16 = 1518;
+const TCP_FLAG_PSH: u8 = 0x08;
+const TCP_FLAG_ACK: u8 = 0x10;
+
+/// The simple IMIX: seven 64, four 594 and one 1518 byte frames
+const IMIX: &[(u16, usize)] = &[(64, 7), (594, 4), (1518, 1)];
+
+/// An inclusive range of addresses or ports, stepped through in order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Range {
+	start: u32,
+	len: u32,
+}
+
+impl Range {
+	fn single(value: u32) -> Self {
+		Self {
+			start: value,
+			len: 1,
+		}
+	}
+
+	/// The value of the `n`th packet
+	#[inline]
+	fn nth(&self, n: u64) -> u32 {
+		self.start + (n % self.len as u64) as u32
+	}
+
+	/// Parses "a" or "a-b" with `parse` parsing either end
+	fn parse<F>(s: &str, parse: F) -> Option<Self>
+	where
+		F: Fn(&str) -> Option<u32>,
+	{
+		let (start, end) = match s.find('-') {
+			Some(idx) => (parse(&s[..idx])?, parse(&s[idx + 1..])?),
+			None => {
+				let value = parse(s)?;
+				(value, value)
+			}
+		};
+		if end < start {
+			return None;
+		}
+		Some(Self {
+			start,
+			len: (end - start).checked_add(1)?,
+		})
+	}
+}
+
+/// How fast a flow sends
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rate {
+	/// as fast as the port takes packets
+	Max,
+	/// packets a second
+	Pps(f64),
+	/// bits a second, of the frames with their FCS
+	Bps(f64),
+}
+
+impl FromStr for Rate {
+	type Err = String;
+
+	/// Parses "max", a number of packets a second like "1000" or "1.5mpps", or a bit rate
+	/// like "500mbps" or "10gbps"
+	fn from_str(s: &str) -> Result<Self, String> {
+		let bad = || {
+			format!(
+				"{:?} is not a rate like \"max\", \"100kpps\" or \"1gbps\"",
+				s
+			)
+		};
+		let lower = s.trim().to_ascii_lowercase();
+		if lower == "max" {
+			return Ok(Rate::Max);
+		}
+		let digits = lower
+			.find(|c: char| !c.is_ascii_digit() && c != '.')
+			.unwrap_or(lower.len());
+		let value = lower[..digits].parse::<f64>().map_err(|_| bad())?;
+		let rate = match &lower[digits..] {
+			"" | "pps" => Rate::Pps(value),
+			"kpps" => Rate::Pps(value * 1e3),
+			"mpps" => Rate::Pps(value * 1e6),
+			"bps" => Rate::Bps(value),
+			"kbps" => Rate::Bps(value * 1e3),
+			"mbps" => Rate::Bps(value * 1e6),
+			"gbps" => Rate::Bps(value * 1e9),
+			_ => return Err(bad()),
+		};
+		match rate {
+			Rate::Pps(v) | Rate::Bps(v) if v > 0.0 && v.is_finite() => Ok(rate),
+			_ => Err(bad()),
+		}
+	}
+}
+
+impl fmt::Display for Rate {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Rate::Max => write!(f, "max"),
+			Rate::Pps(pps) => write!(f, "{} pps", pps),
+			Rate::Bps(bps) => write!(f, "{} bps", bps),
+		}
+	}
+}
+
+/// What a flow sends, as given on the command line
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlowSpec {
+	/// `IP_PROTOCOL_UDP` or `IP_PROTOCOL_TCP`
+	pub proto: u8,
+	/// frame sizes with the FCS, cycled through
+	pub sizes: Vec<u16>,
+	pub rate: Rate,
+	/// none for the port's own address
+	pub src_mac: Option<MacAddr>,
+	pub dst_mac: MacAddr,
+	pub src_ip: Range,
+	pub dst_ip: Range,
+	pub src_port: Range,
+	pub dst_port: Range,
+}
+
+impl Default for FlowSpec {
+	fn default() -> Self {
+		Self {
+			proto: IP_PROTOCOL_UDP,
+			sizes: vec![64],
+			rate: Rate::Max,
+			src_mac: None,
+			dst_mac: MacAddr::BROADCAST,
+			src_ip: Range::single(u32::from(Ipv4Addr::new(10, 0, 0, 1))),
+			dst_ip: Range::single(u32::from(Ipv4Addr::new(10, 0, 1, 1))),
+			src_port: Range::single(1024),
+			dst_port: Range::single(9), // discard
+		}
+	}
+}
+
+impl FlowSpec {
+	/// Bytes of a frame before the payload, and so the smallest frame with the FCS
+	fn min_size(&self) -> u16 {
+		(self.payload_offset() + Stamp::LEN + ETHER_CRC_LEN) as u16
+	}
+
+	fn payload_offset(&self) -> usize {
+		ETHER_HDR_LEN
+			+ IPV4_HDR_LEN
+			+ match self.proto {
+				IP_PROTOCOL_TCP => TCP_HDR_LEN,
+				_ => UDP_HDR_LEN,
+			}
+	}
+}
+
+fn parse_ip(s: &str) -> Option<u32> {
+	s.trim().parse::<Ipv4Addr>().ok().map(u32::from)
+}
+
+fn parse_port(s: &str) -> Option<u32> {
+	s.trim().parse::<u16>().ok().map(u32::from)
+}
+
+fn parse_sizes(s: &str) -> Option<Vec<u16>> {
+	if s.trim() == "imix" {
+		let imix = IMIX.iter().flat_map(|&(size, n)| vec![size; n]).collect();
+		return Some(imix);
+	}
+	s.split(',')
+		.map(|size| size.trim().parse().ok())
+		.collect::<Option<Vec<u16>>>()
+		.filter(|sizes| !sizes.is_empty())
+}
+
+impl FromStr for FlowSpec {
+	type Err = String;
+
+	/// Parses space separated `key=value` pairs, the keys left out take the defaults
+	fn from_str(s: &str) -> Result<Self, String> {
+		let mut spec = FlowSpec::default();
+		for pair in s.split_whitespace() {
+			let idx = pair
+				.find('=')
+				.ok_or_else(|| format!("{:?} is not a key=value pair", pair))?;
+			let (key, value) = (&pair[..idx], &pair[idx + 1..]);
+			let bad = |what: &str| format!("{}: {:?} is not {}", key, value, what);
+			match key {
+				"proto" => {
+					spec.proto = match value {
+						"udp" => IP_PROTOCOL_UDP,
+						"tcp" => IP_PROTOCOL_TCP,
+						_ => return Err(bad("udp or tcp")),
+					}
+				}
+				"size" => {
+					spec.sizes = parse_sizes(value)
+						.ok_or_else(|| bad("a size, a list of sizes like 64,1518 or imix"))?
+				}
+				"rate" => spec.rate = value.parse().map_err(|e| format!("{}: {}", key, e))?,
+				"smac" => spec.src_mac = Some(value.parse().map_err(|_| bad("a MAC address"))?),
+				"dmac" => spec.dst_mac = value.parse().map_err(|_| bad("a MAC address"))?,
+				"src" => {
+					spec.src_ip = Range::parse(value, parse_ip)
+						.ok_or_else(|| bad("an address or a range like 10.0.0.1-10.0.0.9"))?
+				}
+				"dst" => {
+					spec.dst_ip = Range::parse(value, parse_ip)
+						.ok_or_else(|| bad("an address or a range like 10.0.0.1-10.0.0.9"))?
+				}
+				"sport" => {
+					spec.src_port = Range::parse(value, parse_port)
+						.ok_or_else(|| bad("a port or a range like 1024-2047"))?
+				}
+				"dport" => {
+					spec.dst_port = Range::parse(value, parse_port)
+						.ok_or_else(|| bad("a port or a range like 1024-2047"))?
+				}
+				_ => {
+					return Err(format!(
+						"unknown key {:?}, expected one of proto, size, rate, smac, dmac, src, dst, sport, dport",
+						key
+					))
+				}
+			}
+		}
+		let min = spec.min_size();
+		if let Some(size) = spec.sizes.iter().find(|&&s| s < min || s > MAX_FRAME) {
+			return Err(format!(
+				"size: {} is out of range, frames of this flow are {} to {} bytes",
+				size, min, MAX_FRAME
+			));
+		}
+		Ok(spec)
+	}
+}
+
+/// A flow being sent, its packets are made in a template patched for each one
+pub struct Flow {
+	spec: FlowSpec,
+	id: u16,
+	template: Vec<u8>,
+	/// packets made so far, the sequence number of the next one
+	seq: u64,
+	/// packets or bits the flow may send now
+	credit: f64,
+	last: u64, // TSC of the last refill
+}
+
+impl Flow {
+	/// Flow `id` of `spec`, sent from `port_mac` unless the spec has a source MAC
+	pub fn new(id: u16, spec: FlowSpec, port_mac: MacAddr) -> Self {
+		let mut template = vec![0; MAX_FRAME as usize - ETHER_CRC_LEN];
+		template[0..6].copy_from_slice(&spec.dst_mac.0);
+		template[6..12].copy_from_slice(&spec.src_mac.unwrap_or(port_mac).0);
+		template[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+		let ip = &mut template[ETHER_HDR_LEN..];
+		ip[0] = 0x45;
+		ip[8] = 64; // TTL
+		ip[9] = spec.proto;
+		if spec.proto == IP_PROTOCOL_TCP {
+			let tcp = &mut template[ETHER_HDR_LEN + IPV4_HDR_LEN..];
+			tcp[12] = (TCP_HDR_LEN as u8 / 4) << 4;
+			tcp[13] = TCP_FLAG_PSH | TCP_FLAG_ACK;
+			tcp[14..16].copy_from_slice(&u16::MAX.to_be_bytes()); // window
+		}
+		Self {
+			spec,
+			id,
+			template,
+			seq: 0,
+			credit: 0.0,
+			last: 0,
+		}
+	}
+
+	#[inline]
+	pub fn spec(&self) -> &FlowSpec {
+		&self.spec
+	}
+
+	/// How many packets the flow may send at `now`, at most `burst`, each `next` takes one
+	///
+	/// The credit of a flow that can't keep up is capped at a burst, so it doesn't make up
+	/// for lost time all at once.
+	pub fn due(&mut self, now: u64, hz: u64, burst: usize) -> usize {
+		let per_sec = match self.spec.rate {
+			Rate::Max => return burst,
+			Rate::Pps(pps) => pps,
+			// the average frame is close enough for pacing
+			Rate::Bps(bps) => bps / (self.avg_size() * 8.0),
+		};
+		if self.last == 0 {
+			self.last = now;
+		}
+		let elapsed = now.saturating_sub(self.last) as f64 / hz as f64;
+		self.last = now;
+		self.credit = (self.credit + elapsed * per_sec).min(burst as f64);
+		self.credit as usize
+	}
+
+	fn avg_size(&self) -> f64 {
+		let sizes = &self.spec.sizes;
+		sizes.iter().map(|&s| s as f64).sum::<f64>() / sizes.len() as f64
+	}
+
+	/// Make the next packet, stamped with its sequence number and `now`, returns its bytes
+	/// without the FCS
+	pub fn next(&mut self, now: u64) -> &[u8] {
+		let n = self.seq;
+		self.seq += 1;
+		self.credit = (self.credit - 1.0).max(0.0);
+		let spec = &self.spec;
+		let size = spec.sizes[(n % spec.sizes.len() as u64) as usize] as usize - ETHER_CRC_LEN;
+		let l4 = ETHER_HDR_LEN + IPV4_HDR_LEN;
+		let payload = spec.payload_offset();
+		let (src_ip, dst_ip) = (spec.src_ip.nth(n), spec.dst_ip.nth(n));
+		let (src_port, dst_port) = (spec.src_port.nth(n) as u16, spec.dst_port.nth(n) as u16);
+		let proto = spec.proto;
+		let pkt = &mut self.template[..size];
+
+		let ip = &mut pkt[ETHER_HDR_LEN..l4];
+		ip[2..4].copy_from_slice(&((size - ETHER_HDR_LEN) as u16).to_be_bytes());
+		ip[4..6].copy_from_slice(&(n as u16).to_be_bytes());
+		ip[10..12].copy_from_slice(&[0, 0]);
+		ip[12..16].copy_from_slice(&src_ip.to_be_bytes());
+		ip[16..20].copy_from_slice(&dst_ip.to_be_bytes());
+		let cksum = checksum(ip);
+		ip[10..12].copy_from_slice(&cksum.to_be_bytes());
+
+		Stamp {
+			flow: self.id,
+			seq: n as u32,
+			tsc: now,
+		}
+		.write(&mut pkt[payload..]);
+
+		let seg_len = size - l4;
+		let l4_hdr = &mut pkt[l4..];
+		l4_hdr[0..2].copy_from_slice(&src_port.to_be_bytes());
+		l4_hdr[2..4].copy_from_slice(&dst_port.to_be_bytes());
+		match proto {
+			IP_PROTOCOL_TCP => {
+				l4_hdr[4..8].copy_from_slice(&(n as u32).to_be_bytes());
+				l4_hdr[16..18].copy_from_slice(&[0, 0]);
+				let cksum = l4_checksum(src_ip, dst_ip, proto, &pkt[l4..]);
+				pkt[l4 + 16..l4 + 18].copy_from_slice(&cksum.to_be_bytes());
+			}
+			_ => {
+				l4_hdr[4..6].copy_from_slice(&(seg_len as u16).to_be_bytes());
+				// optional for UDP over IPv4, and costly to make at line rate
+				l4_hdr[6..8].copy_from_slice(&[0, 0]);
+			}
+		}
+		pkt
+	}
+}
+
+/// The TCP or UDP checksum of `segment`, its own checksum zeroed, with the pseudo header
+fn l4_checksum(src_ip: u32, dst_ip: u32, proto: u8, segment: &[u8]) -> u16 {
+	let mut pseudo = [0; 12];
+	pseudo[0..4].copy_from_slice(&src_ip.to_be_bytes());
+	pseudo[4..8].copy_from_slice(&dst_ip.to_be_bytes());
+	pseudo[9] = proto;
+	pseudo[10..12].copy_from_slice(&(segment.len() as u16).to_be_bytes());
+	// the pseudo header is an even length so the sums add up
+	let sum = !checksum(&pseudo) as u32 + !checksum(segment) as u32;
+	let sum = (sum & 0xffff) + (sum >> 16);
+	!(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ip(s: &str) -> u32 {
+		parse_ip(s).unwrap()
+	}
+
+	#[test]
+	fn parses_ranges() {
+		let ports = Range::parse("1024-1026", parse_port).unwrap();
+		assert_eq!(
+			ports,
+			Range {
+				start: 1024,
+				len: 3
+			}
+		);
+		assert_eq!(
+			(0..4).map(|n| ports.nth(n)).collect::<Vec<_>>(),
+			[1024, 1025, 1026, 1024]
+		);
+		assert_eq!(Range::parse("9", parse_port), Some(Range::single(9)));
+		assert_eq!(
+			Range::parse("0.0.0.0-255.255.255.255", parse_ip),
+			None,
+			"more addresses than a range counts"
+		);
+		for bad in &["2-1", "1-", "-1", "1-2-3", "65536", "x"] {
+			assert_eq!(Range::parse(bad, parse_port), None, "{:?}", bad);
+		}
+	}
+
+	#[test]
+	fn parses_rates() {
+		assert_eq!("max".parse(), Ok(Rate::Max));
+		assert_eq!("MAX".parse(), Ok(Rate::Max));
+		assert_eq!("1000".parse(), Ok(Rate::Pps(1000.0)));
+		assert_eq!("1.5mpps".parse(), Ok(Rate::Pps(1.5e6)));
+		assert_eq!("100kpps".parse(), Ok(Rate::Pps(100e3)));
+		assert_eq!("500mbps".parse(), Ok(Rate::Bps(500e6)));
+		assert_eq!("10gbps".parse(), Ok(Rate::Bps(10e9)));
+		for bad in &["", "0", "0kpps", "fast", "10tbps", "1..5pps", "-1"] {
+			assert!(bad.parse::<Rate>().is_err(), "{:?}", bad);
+		}
+		assert_eq!(
+			"fast".parse::<Rate>(),
+			Err("\"fast\" is not a rate like \"max\", \"100kpps\" or \"1gbps\"".to_string())
+		);
+	}
+
+	#[test]
+	fn parses_flows() {
+		assert_eq!("".parse(), Ok(FlowSpec::default()));
+		let spec: FlowSpec = "proto=tcp size=128,1518 rate=1gbps smac=02:00:00:00:00:01 \
+			dmac=02:00:00:00:00:02 src=10.0.0.1-10.0.0.9 dst=10.1.0.1 sport=1024-2047 dport=80"
+			.parse()
+			.unwrap();
+		assert_eq!(
+			spec,
+			FlowSpec {
+				proto: IP_PROTOCOL_TCP,
+				sizes: vec![128, 1518],
+				rate: Rate::Bps(1e9),
+				src_mac: Some(MacAddr([0x02, 0, 0, 0, 0, 0x01])),
+				dst_mac: MacAddr([0x02, 0, 0, 0, 0, 0x02]),
+				src_ip: Range {
+					start: ip("10.0.0.1"),
+					len: 9
+				},
+				dst_ip: Range::single(ip("10.1.0.1")),
+				src_port: Range {
+					start: 1024,
+					len: 1024
+				},
+				dst_port: Range::single(80),
+			}
+		);
+
+		let imix: FlowSpec = "size=imix".parse().unwrap();
+		assert_eq!(imix.sizes.len(), 12);
+		assert_eq!(imix.sizes.iter().filter(|&&s| s == 64).count(), 7);
+	}
+
+	#[test]
+	fn rejects_bad_flows() {
+		for (spec, err) in &[
+			("proto", "\"proto\" is not a key=value pair"),
+			("proto=icmp", "proto: \"icmp\" is not udp or tcp"),
+			("dmac=02:00:00:00:00", "dmac: \"02:00:00:00:00\" is not a MAC address"),
+			("smac=xx", "smac: \"xx\" is not a MAC address"),
+			("dst=10.0.0.9-10.0.0.1", "dst: \"10.0.0.9-10.0.0.1\" is not an address or a range like 10.0.0.1-10.0.0.9"),
+			("dport=65536", "dport: \"65536\" is not a port or a range like 1024-2047"),
+			("size=", "size: \"\" is not a size, a list of sizes like 64,1518 or imix"),
+			("rate=fast", "rate: \"fast\" is not a rate like \"max\", \"100kpps\" or \"1gbps\""),
+			("ttl=1", "unknown key \"ttl\", expected one of proto, size, rate, smac, dmac, src, dst, sport, dport"),
+		] {
+			assert_eq!(spec.parse::<FlowSpec>(), Err(err.to_string()), "{:?}", spec);
+		}
+	}
+
+	#[test]
+	fn sizes_fit_the_headers_and_the_mtu() {
+		// ethernet, IPv4 and UDP headers, the stamp and the FCS
+		assert!("size=62".parse::<FlowSpec>().is_ok());
+		assert_eq!(
+			"size=61".parse::<FlowSpec>(),
+			Err("size: 61 is out of range, frames of this flow are 62 to 1518 bytes".to_string())
+		);
+		assert_eq!(
+			"proto=tcp size=64".parse::<FlowSpec>(),
+			Err("size: 64 is out of range, frames of this flow are 74 to 1518 bytes".to_string())
+		);
+		assert!("size=1519".parse::<FlowSpec>().is_err());
+	}
+}
@@ -0,0 +1,362 @@
+//! `pktgen` sends UDP and TCP flows out of a port and receives them back, to benchmark the
+//! engine without external tooling
+//!
+//! ```text
+//! pktgen [EAL options] -- [--port N] [--rx-port N] [--duration SECS] [--burst N]
+//!        [--mbufs N] [--flow SPEC]...
+//! ```
+//!
+//! Every flow is described by a `SPEC` of `key=value` pairs, see `flow`. Packets carry a
+//! TSC timestamp, so whatever comes back on the receiving port gives the latency of its
+//! round trip as well as the loss. Throughput is reported every second, and loss and
+//! latency per flow at the end. The generator runs on the main lcore, with one queue pair
+//! on each port it uses.
+
+mod flow;
+mod stats;
+
+use flow::{Flow, FlowSpec, ETHER_CRC_LEN};
+use stats::{FlowStats, Latency, Stamp};
+
+use l3enginelib::{
+	dpdk_sys, eal_cleanup, eal_init, Mbuf, Mempool, Port, PortConf, RteLog, IP_PROTOCOL_TCP,
+	IP_PROTOCOL_UDP,
+};
+use std::{
+	env, process,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+};
+use tracing_subscriber::EnvFilter;
+
+const USAGE: &str = "usage: pktgen [EAL options] -- [--port N] [--rx-port N] [--duration SECS] \
+	[--burst N] [--mbufs N] [--flow SPEC]...
+
+  --port N         send out of port N, 0 by default
+  --rx-port N      receive on port N, the sending port by default
+  --duration SECS  send for SECS seconds, 10 by default, 0 until Ctrl+C
+  --burst N        most packets sent or received at a time, 32 by default
+  --mbufs N        mbufs in the mempool, 8191 by default
+  --flow SPEC      a flow to send, the default flow if none is given; SPEC is
+                   space separated key=value pairs:
+                     proto=udp|tcp     size=64|64,594,1518|imix (frames with FCS)
+                     rate=max|1000|100kpps|1.5mpps|500mbps|10gbps
+                     smac=MAC dmac=MAC (the port's MAC, broadcast by default)
+                     src=IP[-IP] dst=IP[-IP] sport=PORT[-PORT] dport=PORT[-PORT]";
+
+/// How long packets still in flight are waited for once sending stops
+const DRAIN_MS: u64 = 100;
+
+/// What comes after the `--`
+#[derive(Debug)]
+struct Args {
+	port: u16,
+	rx_port: Option<u16>,
+	/// seconds, 0 to send until stopped
+	duration: u64,
+	burst: usize,
+	mbufs: u32,
+	flows: Vec<FlowSpec>,
+}
+
+impl Args {
+	fn parse(args: &[String]) -> Result<Self, String> {
+		let mut parsed = Args {
+			port: 0,
+			rx_port: None,
+			duration: 10,
+			burst: 32,
+			mbufs: 8191,
+			flows: Vec::new(),
+		};
+		let mut args = args.iter();
+		while let Some(arg) = args.next() {
+			let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+			let number = |v: &String| format!("{}: {:?} is not a number", arg, v);
+			match arg.as_str() {
+				"--port" => {
+					let v = value()?;
+					parsed.port = v.parse().map_err(|_| number(v))?;
+				}
+				"--rx-port" => {
+					let v = value()?;
+					parsed.rx_port = Some(v.parse().map_err(|_| number(v))?);
+				}
+				"--duration" => {
+					let v = value()?;
+					parsed.duration = v.parse().map_err(|_| number(v))?;
+				}
+				"--burst" => {
+					let v = value()?;
+					parsed.burst = match v.parse() {
+						Ok(0) | Err(_) => return Err(number(v)),
+						Ok(burst) => burst,
+					};
+				}
+				"--mbufs" => {
+					let v = value()?;
+					parsed.mbufs = v.parse().map_err(|_| number(v))?;
+				}
+				"--flow" => {
+					let spec = value()?
+						.parse()
+						.map_err(|e| format!("flow {}: {}", parsed.flows.len(), e))?;
+					parsed.flows.push(spec);
+				}
+				"-h" | "--help" => return Err(String::new()),
+				_ => return Err(format!("unknown option {:?}", arg)),
+			}
+		}
+		if parsed.flows.is_empty() {
+			parsed.flows.push(FlowSpec::default());
+		}
+		if parsed.flows.len() > u16::MAX as usize {
+			return Err(format!("at most {} flows", u16::MAX));
+		}
+		// the rings of both ports fill up with mbufs
+		let conf = PortConf::new(1);
+		let least = 2 * (conf.rx_desc as u32 + conf.tx_desc as u32);
+		if parsed.mbufs < least {
+			return Err(format!("--mbufs: at least {} are needed", least));
+		}
+		Ok(parsed)
+	}
+}
+
+/// Handle Ctrl+C
+fn handle_signal(running: Arc<AtomicBool>) {
+	ctrlc::set_handler(move || {
+		running.store(false, Ordering::Relaxed);
+	})
+	.expect("Error setting Ctrl-C handler");
+}
+
+/// Send our events and DPDK's logs through `tracing`, filtered by `RUST_LOG`
+///
+/// `DPDK_LOG` can raise or lower individual DPDK log types, e.g. `DPDK_LOG=pmd.net.*=debug`
+fn init_logging() {
+	tracing_subscriber::fmt()
+		.with_env_filter(EnvFilter::from_default_env())
+		.init();
+	if let Err(e) = RteLog::install() {
+		log::error!("failed to forward DPDK logs: {}", e);
+	}
+	if let Ok(spec) = std::env::var("DPDK_LOG") {
+		if let Err(e) = RteLog::parse_levels(&spec) {
+			log::error!("bad DPDK_LOG: {}", e);
+		}
+	}
+}
+
+/// Configure and start port `id` with a queue pair receiving into `mempool`
+fn start_port(id: u16, mempool: &Mempool) -> Port {
+	// ports keep their name for the life of the process
+	let name: &'static str = Box::leak(format!("port{}", id).into_boxed_str());
+	let mut port =
+		Port::new(name, id).unwrap_or_else(|e| panic!("Failed to find port {}: {}", id, e));
+	port.configure_with(&PortConf::new(1), mempool)
+		.unwrap_or_else(|e| panic!("Failed to configure port {}: {}", id, e));
+	port.start().unwrap();
+	port
+}
+
+/// The stamp in the payload of a UDP or TCP packet
+fn stamp(pkt: &Mbuf) -> Option<Stamp> {
+	let ip = pkt.ipv4_hdr()?;
+	let l4 = 14 + ip.hdr_len();
+	let payload = match ip.next_proto_id {
+		IP_PROTOCOL_UDP => l4 + 8,
+		IP_PROTOCOL_TCP => l4 + (pkt.tcp_hdr()?.data_off >> 4) as usize * 4,
+		_ => return None,
+	};
+	let bytes = pkt.read_data_slice::<u8>(payload, Stamp::LEN).ok()?;
+	Stamp::read(unsafe { bytes.as_ref() })
+}
+
+/// Packets and bits a second over `secs` seconds
+fn rates(pkts: u64, bytes: u64, secs: f64) -> (f64, f64) {
+	(pkts as f64 / secs, bytes as f64 * 8.0 / secs / 1e6)
+}
+
+fn main() {
+	init_logging();
+	let argv = env::args().collect::<Vec<_>>();
+	let split = argv.iter().position(|a| a == "--").unwrap_or(argv.len());
+	let args = match Args::parse(argv.get(split + 1..).unwrap_or_default()) {
+		Ok(args) => args,
+		Err(e) => {
+			if !e.is_empty() {
+				eprintln!("{}\n", e);
+			}
+			eprintln!("{}", USAGE);
+			process::exit(2);
+		}
+	};
+
+	eal_init(argv[..split].to_vec()).unwrap();
+	let cache = Mempool::MBUF_CACHE_SIZE.min(args.mbufs / 2);
+	let mempool = Mempool::with_size("PKTGEN_POOL", args.mbufs, cache, Mempool::RX_MBUF_DATA_SIZE)
+		.unwrap_or_else(|e| panic!("Failed to create mempool: {}", e));
+	let tx_port = start_port(args.port, &mempool);
+	let rx_port = match args.rx_port {
+		Some(id) if id != args.port => Some(start_port(id, &mempool)),
+		_ => None,
+	};
+	let rx = rx_port.as_ref().unwrap_or(&tx_port);
+	let port_mac = tx_port.mac_addr().unwrap();
+
+	let mut flows = args
+		.flows
+		.iter()
+		.enumerate()
+		.map(|(id, spec)| Flow::new(id as u16, spec.clone(), port_mac))
+		.collect::<Vec<_>>();
+	let mut stats = vec![FlowStats::default(); flows.len()];
+	for (id, flow) in flows.iter().enumerate() {
+		log::info!("flow {}: {:?}", id, flow.spec());
+	}
+
+	let running = Arc::new(AtomicBool::new(true));
+	handle_signal(running.clone());
+
+	let hz = unsafe { dpdk_sys::rte_get_timer_hz() };
+	let tsc = || unsafe { dpdk_sys::rte_get_tsc_cycles() };
+	let start = tsc();
+	let stop_at = match args.duration {
+		0 => u64::MAX,
+		secs => start + secs * hz,
+	};
+	let mut drain_until = None;
+	let mut tx_pkts = Vec::with_capacity(args.burst);
+	let mut tx_flows = Vec::with_capacity(args.burst); // flow and frame size of each
+	let mut turn = 0;
+	// not counted against any flow
+	let mut tx_full = 0;
+	let mut no_mbufs = 0;
+	let mut rx_other = 0;
+	let mut last_report = (start, 0, 0, 0, 0); // TSC, tx, tx bytes, rx, rx bytes
+
+	log::info!("sending {} flow(s) out of port {}", flows.len(), args.port);
+	loop {
+		let now = tsc();
+		if running.load(Ordering::Relaxed) && now < stop_at {
+			// the flows take turns going first so none starves the others
+			turn = (turn + 1) % flows.len();
+			for i in (turn..flows.len()).chain(0..turn) {
+				let flow = &mut flows[i];
+				let room = args.burst - tx_pkts.len();
+				for _ in 0..flow.due(now, hz, args.burst).min(room) {
+					let bytes = flow.next(now);
+					match Mbuf::from_bytes(bytes, &mempool) {
+						Ok(pkt) => {
+							tx_flows.push((i, bytes.len() + ETHER_CRC_LEN));
+							tx_pkts.push(pkt);
+						}
+						Err(_) => {
+							no_mbufs += 1;
+							break;
+						}
+					}
+				}
+			}
+			let n = tx_port.send(&mut tx_pkts, 0);
+			for &(i, bytes) in &tx_flows[..n] {
+				stats[i].sent(bytes);
+			}
+			tx_full += tx_pkts.len() as u64;
+			tx_pkts.clear(); // dropping them frees them
+			tx_flows.clear();
+		} else {
+			match drain_until {
+				None => drain_until = Some(now + DRAIN_MS * hz / 1000),
+				Some(until) if now >= until => break,
+				Some(_) => {}
+			}
+		}
+
+		let pkts = rx.receive(0, args.burst);
+		let rx_at = tsc(); // not the loop's, packets sent in this round may be back already
+		for pkt in pkts {
+			let bytes = pkt.data_len() + ETHER_CRC_LEN;
+			match stamp(&pkt).filter(|s| (s.flow as usize) < stats.len()) {
+				Some(s) => stats[s.flow as usize].received(&s, bytes, rx_at),
+				None => rx_other += 1,
+			}
+		}
+
+		if now - last_report.0 >= hz {
+			let tx = stats.iter().map(|s| s.tx).sum::<u64>();
+			let tx_bytes = stats.iter().map(|s| s.tx_bytes).sum::<u64>();
+			let rx = stats.iter().map(|s| s.rx).sum::<u64>();
+			let rx_bytes = stats.iter().map(|s| s.rx_bytes).sum::<u64>();
+			let secs = (now - last_report.0) as f64 / hz as f64;
+			let (tx_pps, tx_mbps) = rates(tx - last_report.1, tx_bytes - last_report.2, secs);
+			let (rx_pps, rx_mbps) = rates(rx - last_report.3, rx_bytes - last_report.4, secs);
+			println!(
+				"{:>6.1}s  tx {:>12.0} pps {:>10.1} Mbps  rx {:>12.0} pps {:>10.1} Mbps",
+				(now - start) as f64 / hz as f64,
+				tx_pps,
+				tx_mbps,
+				rx_pps,
+				rx_mbps
+			);
+			last_report = (now, tx, tx_bytes, rx, rx_bytes);
+		}
+	}
+
+	let secs = (tsc() - start) as f64 / hz as f64;
+	let mut total = FlowStats::default();
+	let mut latency = Latency::default();
+	for (id, (flow, s)) in flows.iter().zip(&stats).enumerate() {
+		let spec = flow.spec();
+		println!(
+			"flow {}: {} sizes {:?} rate {}",
+			id,
+			if spec.proto == IP_PROTOCOL_TCP {
+				"tcp"
+			} else {
+				"udp"
+			},
+			spec.sizes,
+			spec.rate
+		);
+		println!(
+			"  tx {} rx {} loss {:.3}% reordered {}, {}",
+			s.tx,
+			s.rx,
+			s.loss(),
+			s.reordered,
+			s.latency.display(hz)
+		);
+		total.tx += s.tx;
+		total.tx_bytes += s.tx_bytes;
+		total.rx += s.rx;
+		total.rx_bytes += s.rx_bytes;
+		total.reordered += s.reordered;
+		latency.merge(&s.latency);
+	}
+	let (tx_pps, tx_mbps) = rates(total.tx, total.tx_bytes, secs);
+	let (rx_pps, rx_mbps) = rates(total.rx, total.rx_bytes, secs);
+	println!(
+		"total over {:.1}s: tx {} ({:.0} pps, {:.1} Mbps) rx {} ({:.0} pps, {:.1} Mbps) loss {:.3}% reordered {}, {}",
+		secs,
+		total.tx,
+		tx_pps,
+		tx_mbps,
+		total.rx,
+		rx_pps,
+		rx_mbps,
+		total.loss(),
+		total.reordered,
+		latency.display(hz)
+	);
+	println!(
+		"not sent: {} tx queue full, {} out of mbufs; received {} other packets",
+		tx_full, no_mbufs, rx_other
+	);
+
+	unsafe { dpdk_sys::_pkt_stop_and_close_ports() };
+	eal_cleanup(vec![mempool]).unwrap();
+}
@@ -0,0 +1,251 @@
+//! What `pktgen` counts: the packets of every flow sent and received back, and how long
+//! they took
+//!
+//! Every packet carries a `Stamp` at the start of its payload with its flow, its sequence
+//! number and the TSC it was made at, so received packets are matched to their flow
+//! without keeping any state per packet.
+
+use std::fmt;
+
+/// The stamp in the payload of every packet `pktgen` sends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stamp {
+	pub flow: u16,
+	pub seq: u32,
+	/// TSC the packet was made at
+	pub tsc: u64,
+}
+
+impl Stamp {
+	/// Bytes of a stamp in the payload
+	pub const LEN: usize = 16;
+	/// Tells stamps apart from whatever else the port receives
+	const MAGIC: u16 = 0x9e47;
+
+	pub fn write(&self, buf: &mut [u8]) {
+		buf[0..2].copy_from_slice(&Self::MAGIC.to_be_bytes());
+		buf[2..4].copy_from_slice(&self.flow.to_be_bytes());
+		buf[4..8].copy_from_slice(&self.seq.to_be_bytes());
+		buf[8..16].copy_from_slice(&self.tsc.to_be_bytes());
+	}
+
+	/// The stamp at the start of `buf`, if there is one
+	pub fn read(buf: &[u8]) -> Option<Self> {
+		if buf.len() < Self::LEN || buf[0..2] != Self::MAGIC.to_be_bytes() {
+			return None;
+		}
+		let mut tsc = [0; 8];
+		tsc.copy_from_slice(&buf[8..16]);
+		Some(Self {
+			flow: u16::from_be_bytes([buf[2], buf[3]]),
+			seq: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+			tsc: u64::from_be_bytes(tsc),
+		})
+	}
+}
+
+/// Latencies in TSC cycles, with a histogram of powers of two for the percentiles
+#[derive(Clone, Debug)]
+pub struct Latency {
+	count: u64,
+	sum: u64,
+	min: u64,
+	max: u64,
+	buckets: [u64; 64], // bucket i counts latencies of 2^i to 2^(i+1) - 1 cycles
+}
+
+impl Default for Latency {
+	fn default() -> Self {
+		Self {
+			count: 0,
+			sum: 0,
+			min: u64::MAX,
+			max: 0,
+			buckets: [0; 64],
+		}
+	}
+}
+
+impl Latency {
+	#[inline]
+	pub fn record(&mut self, cycles: u64) {
+		self.count += 1;
+		self.sum = self.sum.saturating_add(cycles);
+		self.min = self.min.min(cycles);
+		self.max = self.max.max(cycles);
+		let bucket = 63 - cycles.max(1).leading_zeros() as usize;
+		self.buckets[bucket] += 1;
+	}
+
+	/// The latency `p` percent of the packets took at most, rounded up to a power of two
+	pub fn percentile(&self, p: f64) -> Option<u64> {
+		if self.count == 0 {
+			return None;
+		}
+		let rank = ((p / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+		let mut seen = 0;
+		for (i, n) in self.buckets.iter().enumerate() {
+			seen += n;
+			if seen >= rank {
+				// the bucket's upper bound, but never beyond what was seen
+				return Some(((2u64 << i) - 1).min(self.max));
+			}
+		}
+		Some(self.max)
+	}
+
+	pub fn merge(&mut self, other: &Latency) {
+		self.count += other.count;
+		self.sum = self.sum.saturating_add(other.sum);
+		self.min = self.min.min(other.min);
+		self.max = self.max.max(other.max);
+		for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+			*a += b;
+		}
+	}
+
+	/// In microseconds at `hz` TSC cycles a second
+	pub fn display(&self, hz: u64) -> LatencyDisplay<'_> {
+		LatencyDisplay { latency: self, hz }
+	}
+}
+
+pub struct LatencyDisplay<'a> {
+	latency: &'a Latency,
+	hz: u64,
+}
+
+impl fmt::Display for LatencyDisplay<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let l = self.latency;
+		if l.count == 0 {
+			return write!(f, "no latency samples");
+		}
+		let us = |cycles: u64| cycles as f64 * 1e6 / self.hz as f64;
+		write!(
+			f,
+			"latency us min {:.1} avg {:.1} p50 {:.1} p99 {:.1} max {:.1}",
+			us(l.min),
+			us(l.sum / l.count),
+			us(l.percentile(50.0).unwrap_or(0)),
+			us(l.percentile(99.0).unwrap_or(0)),
+			us(l.max)
+		)
+	}
+}
+
+/// What a flow has sent and received
+#[derive(Clone, Debug, Default)]
+pub struct FlowStats {
+	pub tx: u64,
+	pub tx_bytes: u64,
+	pub rx: u64,
+	pub rx_bytes: u64,
+	/// received after a packet sent later than them
+	pub reordered: u64,
+	next_seq: u32, // one past the highest sequence number received
+	pub latency: Latency,
+}
+
+impl FlowStats {
+	/// Count a packet of `bytes` with the FCS sent out
+	#[inline]
+	pub fn sent(&mut self, bytes: usize) {
+		self.tx += 1;
+		self.tx_bytes += bytes as u64;
+	}
+
+	/// Count a packet of `bytes` with the FCS received at `now`
+	#[inline]
+	pub fn received(&mut self, stamp: &Stamp, bytes: usize, now: u64) {
+		self.rx += 1;
+		self.rx_bytes += bytes as u64;
+		// serial number arithmetic (RFC 1982), the sequence numbers wrap after 2^32 packets
+		if (stamp.seq.wrapping_sub(self.next_seq) as i32) < 0 {
+			self.reordered += 1;
+		} else {
+			self.next_seq = stamp.seq.wrapping_add(1);
+		}
+		self.latency.record(now.saturating_sub(stamp.tsc));
+	}
+
+	/// Packets sent but not received, in percent of those sent
+	pub fn loss(&self) -> f64 {
+		if self.tx == 0 {
+			return 0.0;
+		}
+		self.tx.saturating_sub(self.rx) as f64 * 100.0 / self.tx as f64
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn received(stats: &mut FlowStats, seq: u32) {
+		let stamp = Stamp {
+			flow: 0,
+			seq,
+			tsc: 0,
+		};
+		stats.received(&stamp, 64, 0);
+	}
+
+	#[test]
+	fn stamps_round_trip() {
+		let stamp = Stamp {
+			flow: 0xbeef,
+			seq: 0xdead_beef,
+			tsc: u64::MAX - 1,
+		};
+		let mut buf = [0; Stamp::LEN + 4];
+		stamp.write(&mut buf);
+		assert_eq!(Stamp::read(&buf), Some(stamp));
+		assert_eq!(Stamp::read(&buf[..Stamp::LEN]), Some(stamp));
+		assert_eq!(Stamp::read(&buf[..Stamp::LEN - 1]), None, "too short");
+		buf[0] ^= 1;
+		assert_eq!(Stamp::read(&buf), None, "no magic");
+	}
+
+	#[test]
+	fn counts_packets_received_late() {
+		let mut stats = FlowStats::default();
+		for &seq in &[0, 1, 3, 2, 4, 4] {
+			received(&mut stats, seq);
+		}
+		assert_eq!(stats.rx, 6);
+		assert_eq!(stats.rx_bytes, 6 * 64);
+		assert_eq!(stats.reordered, 2);
+	}
+
+	#[test]
+	fn sequence_numbers_wrap_around() {
+		// as if the flow had been running for a while
+		let mut stats = FlowStats {
+			next_seq: u32::MAX - 2,
+			..Default::default()
+		};
+		for seq in u32::MAX - 2..=u32::MAX {
+			received(&mut stats, seq);
+		}
+		for &seq in &[0, 2, 1] {
+			received(&mut stats, seq);
+		}
+		assert_eq!(stats.reordered, 1, "only the 1 after the 2");
+		received(&mut stats, u32::MAX);
+		assert_eq!(stats.reordered, 2);
+	}
+
+	#[test]
+	fn loss_is_a_percentage_of_those_sent() {
+		let mut stats = FlowStats::default();
+		assert_eq!(stats.loss(), 0.0);
+		for seq in 0..4 {
+			stats.sent(64);
+			if seq != 2 {
+				received(&mut stats, seq);
+			}
+		}
+		assert_eq!(stats.loss(), 25.0);
+	}
+}